@@ -0,0 +1,28 @@
+use std::process::Command;
+
+/// Record the git commit and compiler version for `GET /version`
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if std::path::Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs/heads");
+    }
+
+    if let Some(hash) = command_output("git", &["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=RSFTS_GIT_HASH={}", hash);
+    }
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(version) = command_output(&rustc, &["--version"]) {
+        println!("cargo:rustc-env=RSFTS_RUSTC_VERSION={}", version);
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
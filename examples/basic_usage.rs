@@ -45,7 +45,10 @@ fn main() -> anyhow::Result<()> {
             println!("\n{}. {}", i + 1, doc.title);
         }
         println!("   URL: {}", doc.url.as_ref().unwrap_or(&"N/A".to_string()));
-        println!("   Content: {}...", &doc.content[..doc.content.len().min(80)]);
+        println!(
+            "   Content: {}...",
+            &doc.content[..doc.content.len().min(80)]
+        );
     }
 
     // Example 2: Search without ranking
@@ -95,7 +98,8 @@ fn main() -> anyhow::Result<()> {
     let updated_doc = Document::new(
         "1".to_string(),
         "Rust: A Modern Systems Programming Language".to_string(),
-        "Rust is a modern systems programming language focused on safety, speed, and concurrency.".to_string(),
+        "Rust is a modern systems programming language focused on safety, speed, and concurrency."
+            .to_string(),
     );
     engine.upsert_document(updated_doc)?;
     println!("✓ Updated document '1'");
@@ -1,24 +1,83 @@
-use crate::document::Document;
-use crate::engine::{SearchEngine, SearchMode, SearchOptions};
+use crate::document::{DisplayValue, Document, Section};
+use crate::engine::{
+    BatchErrorPolicy, QueryAnalysis, QueryTooLong, ResultGroup, ResultWindowTooLarge, SearchEngine,
+    SearchMode, SearchOptions, SearchResult, SectionMatch, TermCount,
+};
+use crate::feed::{self, FeedItem};
+use crate::geo::GeoFilter;
+use crate::highlight::Highlight;
+use crate::multi::{IndexInUse, MultiEngine};
+use crate::query::QueryParser;
+use crate::queue::IndexQueue;
+use crate::ranking::{AuthorityScale, InvalidScorePolicy, RankingProfile, SortSpec, TieBreak};
+use crate::tokenizer::{AnalyzerOptions, Language};
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    async_trait,
+    extract::{FromRef, FromRequestParts, OriginalUri, Path, Query, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Json, Response},
-    routing::{delete, get, post, put},
+    routing::{delete, get, head, post, put},
     Router,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+/// Decimal places scores are rounded to unless a request asks otherwise
+pub const DEFAULT_SCORE_PRECISION: u32 = 4;
+
+/// Results per page when paging with `page` and no `page_size`
+pub const DEFAULT_PAGE_SIZE: usize = 10;
 
 // ========== Request/Response Types ==========
 
 #[derive(Debug, Deserialize)]
 pub struct InsertDocumentRequest {
-    pub id: String,
+    /// Generated by the engine when absent on `POST /documents`
+    #[serde(default)]
+    pub id: Option<String>,
     pub title: String,
     pub content: String,
     #[serde(default)]
     pub url: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub boost: Option<f64>,
+    /// ISO 639-1 language code, English when absent
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Typed values (booleans, numbers, strings) returned as they are
+    #[serde(default)]
+    pub display: BTreeMap<String, DisplayValue>,
+    /// Further named text sections, searched like the content
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Headed sections (anchor, heading, text) a search can point into
+    #[serde(default)]
+    pub sections: Vec<Section>,
+}
+
+impl InsertDocumentRequest {
+    /// Build a document from the request, storing it under `id`
+    fn into_document(self, id: String) -> Document {
+        let mut doc = Document::new(id, self.title, self.content);
+        if let Some(url) = self.url {
+            doc = doc.with_url(url);
+        }
+        doc.metadata = self.metadata;
+        if let Some(boost) = self.boost {
+            doc = doc.with_boost(boost);
+        }
+        doc.language = self.language;
+        doc.display = self.display;
+        doc.fields = self.fields;
+        doc.sections = self.sections;
+        doc
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,6 +85,16 @@ pub struct BatchInsertRequest {
     pub documents: Vec<InsertDocumentRequest>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BatchInsertParams {
+    #[serde(default)]
+    pub dry_run: Option<bool>,
+    /// "abort" (default) fails the whole batch on a bad document; "skip"
+    /// indexes the rest at once, bypassing the queue, and reports failures
+    #[serde(default)]
+    pub on_error: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SearchRequest {
     pub query: String,
@@ -37,15 +106,537 @@ pub struct SearchRequest {
     pub limit: Option<usize>,
     #[serde(default)]
     pub offset: Option<usize>,
+    /// 1-based page number, an alternative to `offset`/`limit`
+    #[serde(default)]
+    pub page: Option<usize>,
+    /// Results per page with `page` (default 10)
+    #[serde(default)]
+    pub page_size: Option<usize>,
+    #[serde(default)]
+    pub profile: Option<String>, // "bm25" or "smart"
+    /// Comma-separated keys ordering documents with equal scores:
+    /// `boost`, `recency`, `id` (default: id)
+    #[serde(default)]
+    pub tie_break: Option<String>,
+    /// Comma-separated sort levels replacing the score order, each
+    /// `relevance`, `boost`, `recency`, `id` or a metadata field, with an
+    /// optional `:asc` or `:desc`, e.g. `relevance,price:asc`
+    #[serde(default)]
+    pub sort: Option<String>,
+    /// Bonus for documents matching the query in both title and content,
+    /// e.g. 0.2 for 20% (default: none)
+    #[serde(default)]
+    pub field_coverage: Option<f64>,
+    /// Least each matched query term adds to the score, however long the
+    /// document, e.g. 0.5 (default: none)
+    #[serde(default)]
+    pub min_term_score: Option<f64>,
+    /// Fraction of documents above which a term's weight is smoothly
+    /// reduced, e.g. 0.1 (default: none)
+    #[serde(default)]
+    pub common_term_ratio: Option<f64>,
+    /// Weight of document authority (the server's `--authority-field`):
+    /// scores are multiplied by `1 + weight * authority` (default: none)
+    #[serde(default)]
+    pub authority_weight: Option<f64>,
+    /// `linear` or `log`, which uses `ln(1 + authority)` (default: linear)
+    #[serde(default)]
+    pub authority_scale: Option<String>,
+    /// Documents scoring NaN or infinity: `zero` keeps them with score 0,
+    /// `drop` leaves them out (default: zero)
+    #[serde(default)]
+    pub invalid_scores: Option<String>,
+    #[serde(default)]
+    pub include_deleted: Option<bool>,
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lon: Option<f64>,
+    #[serde(default)]
+    pub radius_km: Option<f64>,
+    #[serde(default)]
+    pub sort_by_distance: Option<bool>,
+    #[serde(default)]
+    pub max_df_ratio: Option<f64>,
+    /// Number of related terms to aggregate over the matches
+    #[serde(default)]
+    pub related_terms: Option<usize>,
+    #[serde(default)]
+    pub max_candidates: Option<usize>,
+    /// Skip documents that can't reach the requested page (OR mode)
+    #[serde(default)]
+    pub top_k: Option<bool>,
+    /// "literal", "simple" (default) or "boolean"
+    #[serde(default)]
+    pub parser: Option<String>,
+    #[serde(default)]
+    pub highlight: Option<bool>,
+    /// Name the best-matching section of each result
+    #[serde(default)]
+    pub sections: Option<bool>,
+    /// Markup around highlighted matches; used only when both are given
+    #[serde(default)]
+    pub pre_tag: Option<String>,
+    #[serde(default)]
+    pub post_tag: Option<String>,
+    /// HTML-escape highlighted titles and snippets, but not the tags
+    #[serde(default)]
+    pub escape_html: Option<bool>,
+    /// Query analysis overrides (all default to true)
+    #[serde(default)]
+    pub lowercase: Option<bool>,
+    #[serde(default)]
+    pub stopwords: Option<bool>,
+    #[serde(default)]
+    pub stem: Option<bool>,
+    /// ISO 639-1 code of the language to analyze the query in
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Decimal places to round scores to
+    #[serde(default)]
+    pub score_precision: Option<u32>,
+    /// Return scores with full f64 precision
+    #[serde(default)]
+    pub full_scores: Option<bool>,
+    /// Metadata field to group results by
+    #[serde(default)]
+    pub group_by: Option<String>,
+    /// Documents per group (default 3)
+    #[serde(default)]
+    pub group_size: Option<usize>,
+    /// Only documents updated at or after this time (ms since the Unix epoch)
+    #[serde(default)]
+    pub modified_since: Option<u64>,
+    /// Comma-separated `word:count` pairs: keep only documents containing
+    /// each word at least `count` times, e.g. `rust:3,memory:2`
+    #[serde(default)]
+    pub min_tf: Option<String>,
+    /// Trade relevance for variety among the top results, from 0 (none)
+    /// to 1 (only avoid near-duplicates)
+    #[serde(default)]
+    pub diversity: Option<f64>,
+    /// Milliseconds allowed for ranking, after which the best results
+    /// scored so far are returned with `timed_out` set (default: none)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Include `query_analysis`, how the query was parsed and which terms
+    /// were dropped or added
+    #[serde(default)]
+    pub query_analysis: Option<bool>,
+    /// Comma-separated metadata keys to return (default: all)
+    #[serde(default)]
+    pub metadata_keys: Option<String>,
+    /// Answer 400 when the query has no terms left after analysis
+    #[serde(default)]
+    pub reject_empty: Option<bool>,
+    /// Skip the search cache, for results that must be current
+    #[serde(default)]
+    pub no_cache: Option<bool>,
+}
+
+impl SearchRequest {
+    fn search_options(&self) -> anyhow::Result<SearchOptions> {
+        let mode = match self.mode.as_deref() {
+            Some("or") => SearchMode::Or,
+            _ => SearchMode::And,
+        };
+
+        let profile = parse_profile(self.profile.as_deref());
+        let language = match self.language.as_deref() {
+            Some(code) => Language::from_code(code)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported language '{}'", code))?,
+            None => Language::English,
+        };
+
+        let geo = match (self.lat, self.lon, self.radius_km) {
+            (Some(lat), Some(lon), Some(radius_km)) => {
+                let mut filter = GeoFilter::new(lat, lon, radius_km);
+                filter.sort_by_distance = self.sort_by_distance.unwrap_or(false);
+                Some(filter)
+            }
+            _ => None,
+        };
+
+        let tie_break = match self.tie_break.as_deref() {
+            Some(keys) => Some(
+                keys.split(',')
+                    .map(|key| {
+                        let key = key.trim();
+                        TieBreak::from_name(key)
+                            .ok_or_else(|| anyhow::anyhow!("Unknown tie-break key '{}'", key))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        let sort = match self.sort.as_deref() {
+            Some(specs) => specs
+                .split(',')
+                .map(|spec| {
+                    let spec = spec.trim();
+                    SortSpec::from_name(spec)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid sort level '{}'", spec))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let (limit, offset) = match self.pagination()? {
+            Some((page, page_size)) => (Some(page_size), (page - 1).saturating_mul(page_size)),
+            None => (self.limit.or(Some(10)), self.offset.unwrap_or(0)),
+        };
+
+        let mut options = SearchOptions {
+            mode,
+            use_ranking: self.ranked.unwrap_or(true),
+            limit,
+            offset,
+            include_deleted: self.include_deleted.unwrap_or(false),
+            geo,
+            max_df_ratio: self.max_df_ratio,
+            related_terms: self.related_terms,
+            max_candidates: self.max_candidates,
+            top_k_only: self.top_k.unwrap_or(false),
+            query_parser: match self.parser.as_deref() {
+                Some("literal") => QueryParser::Literal,
+                Some("boolean") => QueryParser::Boolean,
+                _ => QueryParser::Simple,
+            },
+            highlight: self.highlight.unwrap_or(false),
+            sections: self.sections.unwrap_or(false),
+            highlight_tags: match (&self.pre_tag, &self.post_tag) {
+                (Some(pre_tag), Some(post_tag)) => Some((pre_tag.clone(), post_tag.clone())),
+                (None, None) => None,
+                _ => {
+                    tracing::warn!(
+                        "Ignoring highlight tags: pre_tag and post_tag must be given together"
+                    );
+                    None
+                }
+            },
+            escape_html: self.escape_html.unwrap_or(false),
+            analyzer: AnalyzerOptions {
+                lowercase: self.lowercase.unwrap_or(true),
+                remove_stopwords: self.stopwords.unwrap_or(true),
+                stem: self.stem.unwrap_or(true),
+                language,
+            },
+            group_by: self.group_by.clone(),
+            group_size: self.group_size.unwrap_or(3),
+            modified_since: self.modified_since,
+            timeout: self.timeout_ms.map(std::time::Duration::from_millis),
+            min_term_frequencies: self.min_term_frequencies()?,
+            query_analysis: self.query_analysis.unwrap_or(false),
+            no_cache: self.no_cache.unwrap_or(false),
+            sort,
+            ..Default::default()
+        }
+        .with_profile(profile);
+        if let Some(tie_break) = tie_break {
+            options.ranking.tie_break = tie_break;
+        }
+        if let Some(boost) = self.field_coverage {
+            anyhow::ensure!(
+                boost.is_finite() && boost >= 0.0,
+                "field_coverage must be a non-negative number"
+            );
+            options.ranking.field_coverage_boost = boost;
+        }
+        if let Some(floor) = self.min_term_score {
+            anyhow::ensure!(
+                floor.is_finite() && floor >= 0.0,
+                "min_term_score must be a non-negative number"
+            );
+            options.ranking.min_term_contribution = floor;
+        }
+        if let Some(ratio) = self.common_term_ratio {
+            anyhow::ensure!(
+                ratio > 0.0 && ratio <= 1.0,
+                "common_term_ratio must be in (0, 1]"
+            );
+            options.ranking.common_term_ratio = Some(ratio);
+        }
+        if let Some(diversity) = self.diversity {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&diversity),
+                "diversity must be between 0 and 1"
+            );
+            options.diversity = Some(diversity);
+        }
+        if let Some(weight) = self.authority_weight {
+            anyhow::ensure!(
+                weight.is_finite() && weight >= 0.0,
+                "authority_weight must be a non-negative number"
+            );
+            options.ranking.authority_weight = weight;
+        }
+        if let Some(scale) = &self.authority_scale {
+            options.ranking.authority_scale = AuthorityScale::from_name(scale)
+                .ok_or_else(|| anyhow::anyhow!("authority_scale must be linear or log"))?;
+        }
+        if let Some(policy) = &self.invalid_scores {
+            options.ranking.invalid_scores = InvalidScorePolicy::from_name(policy)
+                .ok_or_else(|| anyhow::anyhow!("invalid_scores must be zero or drop"))?;
+        }
+
+        Ok(options)
+    }
+
+    /// Page number and page size when paging by page rather than by offset
+    fn pagination(&self) -> anyhow::Result<Option<(usize, usize)>> {
+        if self.page.is_none() && self.page_size.is_none() {
+            return Ok(None);
+        }
+        anyhow::ensure!(
+            self.offset.is_none() && self.limit.is_none(),
+            "Use either page/page_size or offset/limit, not both"
+        );
+        let page = self.page.unwrap_or(1);
+        let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        anyhow::ensure!(page >= 1, "page starts at 1");
+        anyhow::ensure!(page_size >= 1, "page_size must be at least 1");
+        Ok(Some((page, page_size)))
+    }
+
+    /// Parse `min_tf` into words and thresholds
+    fn min_term_frequencies(&self) -> anyhow::Result<Vec<(String, usize)>> {
+        let Some(min_tf) = &self.min_tf else {
+            return Ok(Vec::new());
+        };
+        min_tf
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (word, count) = pair.rsplit_once(':').ok_or_else(|| {
+                    anyhow::anyhow!("min_tf entries must be word:count, got {:?}", pair)
+                })?;
+                let count = count
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid min_tf count {:?}", count))?;
+                Ok((word.trim().to_string(), count))
+            })
+            .collect()
+    }
+
+    /// Round scores and select metadata of a result as requested
+    fn response(&self, query: String, mut result: SearchResult) -> SearchResponse {
+        if !self.full_scores.unwrap_or(false) {
+            let decimals = self.score_precision.unwrap_or(DEFAULT_SCORE_PRECISION);
+            let group_scores = result
+                .groups
+                .iter_mut()
+                .flatten()
+                .flat_map(|g| g.scores.iter_mut().flatten());
+            for score in result.scores.iter_mut().flatten().chain(group_scores) {
+                *score = round_score(*score, decimals);
+            }
+        }
+
+        let group_docs = result
+            .groups
+            .iter_mut()
+            .flatten()
+            .flat_map(|g| g.documents.iter_mut());
+        select_metadata(
+            result.documents.iter_mut().chain(group_docs),
+            self.metadata_keys.as_deref(),
+        );
+
+        // Already validated by search_options
+        let pagination = self.pagination().ok().flatten();
+
+        SearchResponse {
+            documents: result
+                .documents
+                .into_iter()
+                .map(DocumentResponse::from)
+                .collect(),
+            total: result.total,
+            page: pagination.map(|(page, _)| page),
+            page_size: pagination.map(|(_, page_size)| page_size),
+            total_pages: pagination.map(|(_, page_size)| result.total.div_ceil(page_size)),
+            query,
+            scores: result.scores,
+            related_terms: result.related_terms,
+            candidates_truncated: result.candidates_truncated,
+            total_is_approximate: result.total_is_approximate,
+            empty_query: result.empty_query,
+            timed_out: result.timed_out,
+            highlights: result.highlights,
+            sections: result.sections,
+            groups: result
+                .groups
+                .map(|groups| groups.into_iter().map(GroupResponse::from).collect()),
+            query_analysis: result.query_analysis,
+        }
+    }
+}
+
+/// Default most queries a batch search may hold
+pub const DEFAULT_MAX_BATCH_QUERIES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchSearchRequest {
+    pub queries: Vec<String>,
+    /// Search parameters applied to every query, named as for `GET /search`
+    #[serde(default)]
+    pub options: serde_json::Map<String, serde_json::Value>,
+    /// Threads to spread the queries over (default 1), at most the number
+    /// of CPUs
+    #[serde(default)]
+    pub parallelism: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RerankRequest {
+    pub query: String,
+    /// Documents to rank, e.g. from another retriever
+    pub ids: Vec<String>,
+    /// Search parameters, named as for `GET /search`
+    #[serde(default)]
+    pub options: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExplainRequest {
+    pub query: String,
+    /// Document to explain
+    pub id: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub max_df_ratio: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesParams {
+    /// Milliseconds since the Unix epoch
+    #[serde(default)]
+    pub since: u64,
+    /// Comma-separated metadata keys to return (default: all)
+    #[serde(default)]
+    pub metadata_keys: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentPath {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TermPath {
+    pub term: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RelatedTermsParams {
+    /// Number of terms to return (default 10)
+    #[serde(default)]
+    pub n: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDocumentParams {
+    #[serde(default)]
+    pub include_deleted: Option<bool>,
+    /// Comma-separated metadata keys to return (default: all)
+    #[serde(default)]
+    pub metadata_keys: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TitlePath {
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDocumentsParams {
+    /// Comma-separated document IDs
+    pub ids: String,
+    #[serde(default)]
+    pub include_deleted: Option<bool>,
+    /// Comma-separated metadata keys to return (default: all)
+    #[serde(default)]
+    pub metadata_keys: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetDocumentsResponse {
+    /// One entry per requested ID, in request order; `null` when missing
+    pub documents: Vec<Option<DocumentResponse>>,
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteDocumentParams {
+    #[serde(default)]
+    pub soft: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SearchResponse {
     pub documents: Vec<DocumentResponse>,
     pub total: usize,
+    /// Set when paging with `page`/`page_size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<usize>,
     pub query: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub scores: Option<Vec<f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related_terms: Option<Vec<TermCount>>,
+    /// Only part of the matches were considered (see `max_candidates` and `top_k`)
+    pub candidates_truncated: bool,
+    /// `total` is a lower bound ("1,000+ results") rather than an exact count
+    pub total_is_approximate: bool,
+    /// Only stopwords or punctuation were searched for
+    pub empty_query: bool,
+    /// Ranking ran out of `timeout_ms`; the results are the best found in time
+    pub timed_out: bool,
+    /// Highlighted title and snippet, parallel to `documents`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlights: Option<Vec<Highlight>>,
+    /// Best-matching section, parallel to `documents`; `null` for a
+    /// document where no query term occurs in a section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sections: Option<Vec<Option<SectionMatch>>>,
+    /// Set instead of `documents` when grouping with `group_by`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<GroupResponse>>,
+    /// How the query was read, when requested with `query_analysis`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_analysis: Option<QueryAnalysis>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupResponse {
+    /// Group field value; `null` for documents without the field
+    pub value: Option<String>,
+    pub total: usize,
+    pub documents: Vec<DocumentResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scores: Option<Vec<f64>>,
+}
+
+impl From<ResultGroup> for GroupResponse {
+    fn from(group: ResultGroup) -> Self {
+        Self {
+            value: group.value,
+            total: group.total,
+            documents: group
+                .documents
+                .into_iter()
+                .map(DocumentResponse::from)
+                .collect(),
+            scores: group.scores,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +646,25 @@ pub struct DocumentResponse {
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Last write time in milliseconds since the Unix epoch
+    pub updated_at: u64,
+    /// Changes whenever the title, content, url, metadata, boost or
+    /// language change
+    pub content_hash: String,
+    /// Sorted by key; omitted when empty
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Typed display values; omitted when empty
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub display: BTreeMap<String, DisplayValue>,
+    /// Named text sections; omitted when empty
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Omitted when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<Section>,
 }
 
 impl From<Document> for DocumentResponse {
@@ -64,15 +674,64 @@ impl From<Document> for DocumentResponse {
             title: doc.title,
             content: doc.content,
             url: doc.url,
+            language: doc.language,
+            updated_at: doc.updated_at,
+            content_hash: doc.content_hash,
+            metadata: doc.metadata.into_iter().collect(),
+            display: doc.display,
+            fields: doc.fields,
+            sections: doc.sections,
         }
     }
 }
 
+/// Reply to an insert without an id
+#[derive(Debug, Serialize)]
+pub struct InsertedResponse {
+    /// Id the document was stored under
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionResponse {
+    pub version: &'static str,
+    /// Commit the server was built from, when known
+    pub git_hash: Option<&'static str>,
+    pub rustc_version: Option<&'static str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateIndexRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IndexPath {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexInfo {
+    pub name: String,
+    pub documents: usize,
+    /// Distinct terms in the index
+    pub tokens: usize,
+}
+
 #[derive(Debug, Serialize)]
 pub struct StatsResponse {
     pub total_documents: usize,
+    pub live_documents: usize,
+    /// Soft-deleted documents a purge would remove
+    pub deleted_documents: usize,
     pub total_tokens: usize,
     pub avg_docs_per_token: f64,
+    /// Approximate memory used by the loaded index
+    pub estimated_memory_bytes: usize,
+    /// Size of the database on disk
+    pub disk_bytes: u64,
+    /// Documents waiting in the indexing queue
+    pub pending_queue: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -110,13 +769,323 @@ impl<T> ApiResponse<T> {
     }
 }
 
+// ========== State ==========
+
+/// Shared state of the API handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub engine: Arc<SearchEngine>,
+    /// When set, inserts are queued and applied in the background
+    pub queue: Option<IndexQueue>,
+    /// When set, bounds how many requests are handled at once
+    pub concurrency_limit: Option<ConcurrencyLimit>,
+    /// When set, named indexes are served under `/indexes/:name`
+    pub indexes: Option<Arc<MultiEngine>>,
+    /// Most queries a batch search may hold
+    pub max_batch_queries: usize,
+    /// Bearer token required by destructive admin routes; they are refused
+    /// when unset
+    pub admin_token: Option<String>,
+}
+
+impl AppState {
+    pub fn new(engine: Arc<SearchEngine>) -> Self {
+        Self {
+            engine,
+            queue: None,
+            concurrency_limit: None,
+            indexes: None,
+            max_batch_queries: DEFAULT_MAX_BATCH_QUERIES,
+            admin_token: None,
+        }
+    }
+
+    pub fn with_max_batch_queries(mut self, max: usize) -> Self {
+        self.max_batch_queries = max;
+        self
+    }
+
+    pub fn with_admin_token(mut self, token: String) -> Self {
+        self.admin_token = Some(token);
+        self
+    }
+
+    pub fn with_indexes(mut self, indexes: Arc<MultiEngine>) -> Self {
+        self.indexes = Some(indexes);
+        self
+    }
+
+    pub fn with_queue(mut self, queue: IndexQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    pub fn with_concurrency_limit(mut self, limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Index documents now, or queue them when async indexing is enabled
+    /// (default index only). Returns whether the documents were queued.
+    async fn index(&self, engine: &Arc<SearchEngine>, docs: Vec<Document>) -> anyhow::Result<bool> {
+        match &self.queue {
+            Some(queue) if Arc::ptr_eq(engine, &self.engine) => {
+                queue.enqueue(docs).await?;
+                Ok(true)
+            }
+            _ => {
+                engine.batch_insert(docs)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// The engine a request addresses: the named index for routes under
+/// `/indexes/:name`, the default index otherwise
+///
+/// A missing named index is rejected with 404.
+pub struct TargetEngine(pub Arc<SearchEngine>);
+
+/// Like [`TargetEngine`], but creates a missing named index; used by the
+/// routes that write documents
+pub struct IndexingEngine(pub Arc<SearchEngine>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for TargetEngine {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        resolve_engine(parts, state, false).await.map(Self)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for IndexingEngine {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        resolve_engine(parts, state, true).await.map(Self)
+    }
+}
+
+/// Look up the engine named in the path, creating it when `create` is set
+async fn resolve_engine(
+    parts: &mut Parts,
+    state: &AppState,
+    create: bool,
+) -> Result<Arc<SearchEngine>, Response> {
+    let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+        .await
+        .map(|Path(params)| params)
+        .unwrap_or_default();
+    let Some(name) = params.get("name") else {
+        return Ok(state.engine.clone());
+    };
+
+    let not_found = |message: String| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response()
+    };
+    let Some(indexes) = &state.indexes else {
+        return Err(not_found("Named indexes are not enabled".to_string()));
+    };
+    if !MultiEngine::is_valid_name(name) {
+        let message = format!("Invalid index name '{}'", name);
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response());
+    }
+
+    let engine = if create {
+        indexes.get_or_create(name).map(Some)
+    } else {
+        indexes.get(name)
+    };
+    match engine {
+        Ok(Some(engine)) => Ok(engine),
+        Ok(None) => Err(not_found(format!("Index '{}' not found", name))),
+        Err(err) => Err(AppError(err).into_response()),
+    }
+}
+
+impl FromRef<AppState> for Arc<SearchEngine> {
+    fn from_ref(state: &AppState) -> Self {
+        state.engine.clone()
+    }
+}
+
+/// Limits the number of requests handled at once
+///
+/// Excess requests wait for a free slot, or get 503 Service Unavailable when
+/// `reject_when_busy` is set. The health check is never limited.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+    reject_when_busy: bool,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            reject_when_busy: false,
+        }
+    }
+
+    pub fn rejecting_when_busy(mut self) -> Self {
+        self.reject_when_busy = true;
+        self
+    }
+}
+
+async fn limit_concurrency(
+    State(limit): State<ConcurrencyLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let permit = if limit.reject_when_busy {
+        limit.permits.try_acquire_owned().ok()
+    } else {
+        limit.permits.acquire_owned().await.ok()
+    };
+
+    match permit {
+        Some(_permit) => next.run(request).await,
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(
+                "Server is busy, try again later".to_string(),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// Run each request in a span, for trace export. With the `otel` feature,
+/// a W3C `traceparent` header makes the request join the caller's trace.
+async fn trace_request(request: Request, next: Next) -> Response {
+    let span = tracing::info_span!(
+        "http_request",
+        otel.kind = "server",
+        http.method = %request.method(),
+        url.path = request.uri().path(),
+        http.status_code = tracing::field::Empty,
+    );
+    #[cfg(feature = "otel")]
+    crate::otel::continue_trace(&span, request.headers());
+
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
+/// Run blocking work on the blocking pool, inside the current span
+async fn spawn_blocking_in_span<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, tokio::task::JoinError> {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(work)).await
+}
+
+/// Split a comma-separated query parameter, dropping empty entries
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Keep only the requested metadata keys; `None` keeps them all
+fn select_metadata<'a>(docs: impl IntoIterator<Item = &'a mut Document>, keys: Option<&str>) {
+    let Some(keys) = keys.map(split_list) else {
+        return;
+    };
+    for doc in docs {
+        doc.metadata.retain(|key, _| keys.contains(key));
+    }
+}
+
+fn parse_profile(profile: Option<&str>) -> RankingProfile {
+    match profile {
+        Some("smart") => RankingProfile::SmartRelevance,
+        _ => RankingProfile::Bm25Default,
+    }
+}
+
+/// Round a score to `decimals` decimal places
+fn round_score(score: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals.min(15) as i32);
+    (score * factor).round() / factor
+}
+
+/// Response for a write: 202 Accepted if it was only queued
+fn write_response(queued: bool, message: &str) -> Response {
+    if queued {
+        (
+            StatusCode::ACCEPTED,
+            Json(ApiResponse::success("Queued for indexing")),
+        )
+            .into_response()
+    } else {
+        Json(ApiResponse::success(message)).into_response()
+    }
+}
+
 // ========== Error Handling ==========
 
 struct AppError(anyhow::Error);
 
+/// Error of a well-formed request body that can't be acted on, answered
+/// with 422 like bodies the JSON extractor rejects
+#[derive(Debug)]
+struct UnprocessableBody(&'static str);
+
+impl std::fmt::Display for UnprocessableBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for UnprocessableBody {}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let message = format!("{:#}", self.0);
+        // Errors in what the client asked for
+        if self.0.is::<QueryTooLong>() || self.0.is::<ResultWindowTooLarge>() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(message)),
+            )
+                .into_response();
+        }
+        if self.0.is::<UnprocessableBody>() {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ApiResponse::<()>::error(message)),
+            )
+                .into_response();
+        }
+        if self.0.is::<IndexInUse>() {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::<()>::error(message)),
+            )
+                .into_response();
+        }
         tracing::error!("API error: {}", message);
 
         (
@@ -142,46 +1111,213 @@ async fn health_check() -> impl IntoResponse {
     Json(ApiResponse::success("OK"))
 }
 
-async fn insert_document(
-    State(engine): State<Arc<SearchEngine>>,
-    Json(req): Json<InsertDocumentRequest>,
-) -> Result<impl IntoResponse, AppError> {
-    let mut doc = Document::new(req.id, req.title, req.content);
-    if let Some(url) = req.url {
-        doc = doc.with_url(url);
+async fn version() -> impl IntoResponse {
+    Json(ApiResponse::success(VersionResponse {
+        version: crate::VERSION,
+        git_hash: crate::GIT_HASH,
+        rustc_version: crate::RUSTC_VERSION,
+    }))
+}
+
+/// Response to index management requests when named indexes aren't enabled
+fn indexes_disabled() -> Response {
+    let message = "Named indexes are not enabled".to_string();
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::error(message)),
+    )
+        .into_response()
+}
+
+/// The rejection of a request that doesn't carry the admin token as a
+/// bearer token, if it doesn't
+fn admin_rejection(state: &AppState, headers: &HeaderMap) -> Option<Response> {
+    let Some(expected) = &state.admin_token else {
+        let message =
+            "This operation requires the server to be started with --admin-token".to_string();
+        return Some(
+            (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error(message)),
+            )
+                .into_response(),
+        );
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => None,
+        _ => {
+            let message = "Missing or invalid admin token".to_string();
+            Some(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(ApiResponse::<()>::error(message)),
+                )
+                    .into_response(),
+            )
+        }
     }
+}
 
-    engine.upsert_document(doc)?;
+/// Compare secrets without returning early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn list_indexes(State(state): State<AppState>) -> Result<Response, AppError> {
+    let Some(indexes) = &state.indexes else {
+        return Ok(indexes_disabled());
+    };
+
+    let mut infos = Vec::new();
+    for name in indexes.names()? {
+        if let Some(engine) = indexes.get(&name)? {
+            infos.push(IndexInfo {
+                name,
+                documents: engine.document_count()?,
+                tokens: engine.stats()?.total_tokens,
+            });
+        }
+    }
+
+    Ok(Json(ApiResponse::success(infos)).into_response())
+}
 
-    Ok(Json(ApiResponse::success("Document inserted successfully")))
+async fn create_index(
+    State(state): State<AppState>,
+    Json(req): Json<CreateIndexRequest>,
+) -> Result<Response, AppError> {
+    let Some(indexes) = &state.indexes else {
+        return Ok(indexes_disabled());
+    };
+    if !MultiEngine::is_valid_name(&req.name) {
+        let message = format!("Invalid index name '{}'", req.name);
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response());
+    }
+
+    if indexes.create(&req.name)? {
+        Ok(Json(ApiResponse::success(format!(
+            "Index '{}' created",
+            req.name
+        )))
+        .into_response())
+    } else {
+        Ok(Json(ApiResponse::<()>::error_msg(format!(
+            "Index '{}' already exists",
+            req.name
+        )))
+        .into_response())
+    }
+}
+
+async fn drop_index(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(IndexPath { name }): Path<IndexPath>,
+) -> Result<Response, AppError> {
+    let Some(indexes) = state.indexes.clone() else {
+        return Ok(indexes_disabled());
+    };
+    if let Some(response) = admin_rejection(&state, &headers) {
+        return Ok(response);
+    }
+
+    let dropped = {
+        let name = name.clone();
+        spawn_blocking_in_span(move || indexes.drop_index(&name)).await??
+    };
+    if dropped {
+        Ok(Json(ApiResponse::success(format!("Index '{}' deleted", name))).into_response())
+    } else {
+        Ok(Json(ApiResponse::<()>::error_msg(format!(
+            "Index '{}' not found",
+            name
+        )))
+        .into_response())
+    }
+}
+
+async fn insert_document(
+    State(state): State<AppState>,
+    IndexingEngine(engine): IndexingEngine,
+    Json(req): Json<InsertDocumentRequest>,
+) -> Result<Response, AppError> {
+    let Some(id) = req.id.clone() else {
+        let id = engine.generate_id()?;
+        let queued = state
+            .index(&engine, vec![req.into_document(id.clone())])
+            .await?;
+        let status = if queued {
+            StatusCode::ACCEPTED
+        } else {
+            StatusCode::OK
+        };
+        return Ok((status, Json(ApiResponse::success(InsertedResponse { id }))).into_response());
+    };
+    let queued = state.index(&engine, vec![req.into_document(id)]).await?;
+
+    Ok(write_response(queued, "Document inserted successfully"))
 }
 
 async fn batch_insert(
-    State(engine): State<Arc<SearchEngine>>,
+    State(state): State<AppState>,
+    IndexingEngine(engine): IndexingEngine,
+    Query(params): Query<BatchInsertParams>,
     Json(req): Json<BatchInsertRequest>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     let docs: Vec<Document> = req
         .documents
         .into_iter()
         .map(|d| {
-            let mut doc = Document::new(d.id, d.title, d.content);
-            if let Some(url) = d.url {
-                doc = doc.with_url(url);
-            }
-            doc
+            let id =
+                d.id.clone()
+                    .ok_or(UnprocessableBody("Every document in a batch needs an id"))?;
+            Ok(d.into_document(id))
         })
-        .collect();
+        .collect::<anyhow::Result<_>>()?;
+
+    if params.dry_run.unwrap_or(false) {
+        let summary = engine.validate_documents(&docs);
+        return Ok(Json(ApiResponse::success(summary)).into_response());
+    }
+
+    let on_error = match params.on_error.as_deref() {
+        Some(name) => BatchErrorPolicy::from_name(name)
+            .ok_or_else(|| anyhow::anyhow!("on_error must be abort or skip"))?,
+        None => BatchErrorPolicy::Abort,
+    };
+    if on_error == BatchErrorPolicy::Skip {
+        let report =
+            spawn_blocking_in_span(move || engine.batch_insert_with(docs, on_error)).await??;
+        return Ok(Json(ApiResponse::success(report)).into_response());
+    }
 
-    engine.batch_insert(docs)?;
+    let queued = state.index(&engine, docs).await?;
 
-    Ok(Json(ApiResponse::success("Documents inserted successfully")))
+    Ok(write_response(queued, "Documents inserted successfully"))
 }
 
 async fn get_document(
-    State(engine): State<Arc<SearchEngine>>,
-    Path(id): Path<String>,
+    TargetEngine(engine): TargetEngine,
+    Path(DocumentPath { id }): Path<DocumentPath>,
+    Query(params): Query<GetDocumentParams>,
 ) -> Result<impl IntoResponse, AppError> {
-    if let Some(doc) = engine.get_document(&id)? {
+    let include_deleted = params.include_deleted.unwrap_or(false);
+    let doc = if include_deleted || !engine.is_deleted(&id) {
+        engine.get_document(&id)?
+    } else {
+        None
+    };
+
+    if let Some(mut doc) = doc {
+        select_metadata([&mut doc], params.metadata_keys.as_deref());
         Ok(Json(ApiResponse::success(DocumentResponse::from(doc))))
     } else {
         Ok(Json(ApiResponse::error_msg(format!(
@@ -191,64 +1327,338 @@ async fn get_document(
     }
 }
 
+/// Document existence and content hash (as the ETag) without the body
+async fn head_document(
+    TargetEngine(engine): TargetEngine,
+    Path(DocumentPath { id }): Path<DocumentPath>,
+) -> Result<Response, AppError> {
+    match engine
+        .get_document(&id)?
+        .filter(|_| !engine.is_deleted(&id))
+    {
+        Some(doc) => Ok((
+            [(header::ETAG, format!("\"{}\"", doc.content_hash))],
+            StatusCode::OK,
+        )
+            .into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+async fn get_documents(
+    TargetEngine(engine): TargetEngine,
+    Query(params): Query<GetDocumentsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let include_deleted = params.include_deleted.unwrap_or(false);
+    let ids = split_list(&params.ids);
+
+    let mut missing = Vec::new();
+    let documents = engine
+        .get_documents(&ids)?
+        .into_iter()
+        .zip(&ids)
+        .map(|(doc, id)| {
+            let doc = doc.filter(|_| include_deleted || !engine.is_deleted(id));
+            if doc.is_none() {
+                missing.push(id.clone());
+            }
+            doc.map(|mut doc| {
+                select_metadata([&mut doc], params.metadata_keys.as_deref());
+                DocumentResponse::from(doc)
+            })
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(GetDocumentsResponse {
+        documents,
+        missing,
+    })))
+}
+
+/// Documents whose whole title matches, ignoring case and whitespace runs
+async fn get_by_title(
+    TargetEngine(engine): TargetEngine,
+    Path(TitlePath { title }): Path<TitlePath>,
+) -> Result<impl IntoResponse, AppError> {
+    let documents: Vec<DocumentResponse> = engine
+        .get_by_title(&title)?
+        .into_iter()
+        .map(DocumentResponse::from)
+        .collect();
+    Ok(Json(ApiResponse::success(documents)))
+}
+
+async fn list_changes(
+    TargetEngine(engine): TargetEngine,
+    Query(params): Query<ChangesParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut documents = engine.list_modified_since(params.since)?;
+    select_metadata(&mut documents, params.metadata_keys.as_deref());
+    let documents: Vec<DocumentResponse> =
+        documents.into_iter().map(DocumentResponse::from).collect();
+
+    Ok(Json(ApiResponse::success(documents)))
+}
+
 async fn update_document(
-    State(engine): State<Arc<SearchEngine>>,
-    Path(id): Path<String>,
+    State(state): State<AppState>,
+    IndexingEngine(engine): IndexingEngine,
+    Path(DocumentPath { id }): Path<DocumentPath>,
     Json(req): Json<InsertDocumentRequest>,
+) -> Result<Response, AppError> {
+    let queued = state.index(&engine, vec![req.into_document(id)]).await?;
+
+    Ok(write_response(queued, "Document updated successfully"))
+}
+
+async fn delete_document(
+    TargetEngine(engine): TargetEngine,
+    Path(DocumentPath { id }): Path<DocumentPath>,
+    Query(params): Query<DeleteDocumentParams>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mut doc = Document::new(id, req.title, req.content);
-    if let Some(url) = req.url {
-        doc = doc.with_url(url);
+    if params.soft.unwrap_or(false) {
+        if engine.soft_delete(&id)? {
+            Ok(Json(ApiResponse::success(
+                "Document soft-deleted successfully".to_string(),
+            )))
+        } else {
+            Ok(Json(ApiResponse::error_msg(format!(
+                "Document with id '{}' not found or already deleted",
+                id
+            ))))
+        }
+    } else if engine.delete_document(&id)? {
+        Ok(Json(ApiResponse::success(
+            "Document deleted successfully".to_string(),
+        )))
+    } else {
+        Ok(Json(ApiResponse::error_msg(format!(
+            "Document with id '{}' not found",
+            id
+        ))))
     }
+}
 
-    engine.upsert_document(doc)?;
-
-    Ok(Json(ApiResponse::success("Document updated successfully")))
+async fn restore_document(
+    TargetEngine(engine): TargetEngine,
+    Path(DocumentPath { id }): Path<DocumentPath>,
+) -> Result<impl IntoResponse, AppError> {
+    if engine.restore(&id)? {
+        Ok(Json(ApiResponse::success(
+            "Document restored successfully".to_string(),
+        )))
+    } else {
+        Ok(Json(ApiResponse::error_msg(format!(
+            "Document with id '{}' is not deleted",
+            id
+        ))))
+    }
 }
 
-async fn delete_document(
-    State(engine): State<Arc<SearchEngine>>,
-    Path(id): Path<String>,
+async fn purge_documents(
+    TargetEngine(engine): TargetEngine,
 ) -> Result<impl IntoResponse, AppError> {
-    engine.delete_document(&id)?;
-    Ok(Json(ApiResponse::success("Document deleted successfully")))
+    let purged = engine.purge()?;
+    Ok(Json(ApiResponse::success(format!(
+        "Purged {} documents",
+        purged
+    ))))
+}
+
+async fn reindex(TargetEngine(engine): TargetEngine) -> Result<impl IntoResponse, AppError> {
+    // Long-running; keep it off the async workers so searches proceed
+    let indexed = spawn_blocking_in_span(move || engine.reindex()).await??;
+    Ok(Json(ApiResponse::success(format!(
+        "Reindexed {} documents",
+        indexed
+    ))))
 }
 
 async fn search_documents(
-    State(engine): State<Arc<SearchEngine>>,
+    TargetEngine(engine): TargetEngine,
     Query(req): Query<SearchRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let mode = match req.mode.as_deref() {
-        Some("or") => SearchMode::Or,
-        _ => SearchMode::And,
-    };
+    let options = req.search_options()?;
+    let result = engine.search(&req.query, &options)?;
 
-    let options = SearchOptions {
-        mode,
-        use_ranking: req.ranked.unwrap_or(true),
-        limit: req.limit.or(Some(10)),
-        offset: req.offset.unwrap_or(0),
-    };
+    if result.empty_query && req.reject_empty.unwrap_or(false) {
+        let message = "The query has no searchable terms; please refine it";
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(message.to_string())),
+        )
+            .into_response());
+    }
 
+    Ok(Json(ApiResponse::success(
+        req.response(req.query.clone(), result),
+    ))
+    .into_response())
+}
+
+/// A search as an RSS feed, with a plain text snippet per result
+async fn search_feed(
+    TargetEngine(engine): TargetEngine,
+    OriginalUri(uri): OriginalUri,
+    Query(req): Query<SearchRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut options = req.search_options()?;
+    options.highlight = true;
+    options.highlight_tags = Some((String::new(), String::new()));
     let result = engine.search(&req.query, &options)?;
 
-    let response = SearchResponse {
-        documents: result.documents.into_iter().map(DocumentResponse::from).collect(),
-        total: result.total,
-        query: req.query,
-        scores: result.scores,
-    };
+    let items: Vec<FeedItem> = result
+        .documents
+        .into_iter()
+        .zip(result.highlights.unwrap_or_default())
+        .map(|(doc, highlight)| FeedItem {
+            id: doc.id,
+            title: doc.title,
+            link: doc.url,
+            description: highlight.snippet,
+            updated_at: doc.updated_at,
+        })
+        .collect();
+    let title = format!("Search results for \"{}\"", req.query);
+    let description = format!("Documents matching \"{}\"", req.query);
+    let body = feed::rss(&title, &uri.to_string(), &description, &items);
 
-    Ok(Json(ApiResponse::success(response)))
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        body,
+    ))
+}
+
+async fn search_batch(
+    State(state): State<AppState>,
+    TargetEngine(engine): TargetEngine,
+    Json(req): Json<BatchSearchRequest>,
+) -> Result<Response, AppError> {
+    if req.queries.len() > state.max_batch_queries {
+        let message = format!(
+            "A batch may hold at most {} queries",
+            state.max_batch_queries
+        );
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(message)),
+        )
+            .into_response());
+    }
+    let mut params = req.options;
+    params.insert(
+        "query".to_string(),
+        serde_json::Value::String(String::new()),
+    );
+    let params: SearchRequest = serde_json::from_value(serde_json::Value::Object(params))?;
+    let options = params.search_options()?;
+
+    let queries = req.queries;
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = req.parallelism.unwrap_or(1).clamp(1, cpus);
+    let results = spawn_blocking_in_span(move || {
+        let results = engine.search_batch(&queries, &options, threads)?;
+        let responses: Vec<SearchResponse> = queries
+            .into_iter()
+            .zip(results)
+            .map(|(query, result)| params.response(query, result))
+            .collect();
+        anyhow::Ok(responses)
+    })
+    .await??;
+
+    Ok(Json(ApiResponse::success(results)).into_response())
 }
 
-async fn get_stats(State(engine): State<Arc<SearchEngine>>) -> Result<impl IntoResponse, AppError> {
+async fn rerank(
+    TargetEngine(engine): TargetEngine,
+    Json(req): Json<RerankRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut params = req.options;
+    params.insert(
+        "query".to_string(),
+        serde_json::Value::String(req.query.clone()),
+    );
+    let params: SearchRequest = serde_json::from_value(serde_json::Value::Object(params))?;
+    let options = params.search_options()?;
+
+    let result = engine.rerank(&req.query, &req.ids, &options)?;
+
+    Ok(Json(ApiResponse::success(
+        params.response(req.query, result),
+    )))
+}
+
+async fn score_distribution(
+    TargetEngine(engine): TargetEngine,
+    Query(req): Query<SearchRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = req.search_options()?;
+    let query = req.query.clone();
+    let distribution =
+        spawn_blocking_in_span(move || engine.score_distribution(&query, &options)).await??;
+
+    Ok(Json(ApiResponse::success(distribution)))
+}
+
+async fn term_idfs(
+    TargetEngine(engine): TargetEngine,
+    Query(req): Query<SearchRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = req.search_options()?;
+    Ok(Json(ApiResponse::success(
+        engine.term_idfs(&req.query, &options)?,
+    )))
+}
+
+async fn related_terms(
+    TargetEngine(engine): TargetEngine,
+    Path(TermPath { term }): Path<TermPath>,
+    Query(params): Query<RelatedTermsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(ApiResponse::success(
+        engine.related_terms(&term, params.n.unwrap_or(10)),
+    )))
+}
+
+async fn explain_document(
+    TargetEngine(engine): TargetEngine,
+    Query(req): Query<ExplainRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let options = SearchOptions {
+        max_df_ratio: req.max_df_ratio,
+        ..Default::default()
+    }
+    .with_profile(parse_profile(req.profile.as_deref()));
+
+    match engine.explain_with(&req.query, &req.id, &options)? {
+        Some(explanation) => Ok(Json(ApiResponse::success(explanation))),
+        None => Ok(Json(ApiResponse::error_msg(format!(
+            "Document with id '{}' not found",
+            req.id
+        )))),
+    }
+}
+
+async fn get_stats(
+    State(state): State<AppState>,
+    TargetEngine(engine): TargetEngine,
+) -> Result<impl IntoResponse, AppError> {
     let stats = engine.stats()?;
+    let pending_queue = match &state.queue {
+        Some(queue) if Arc::ptr_eq(&engine, &state.engine) => queue.pending(),
+        _ => 0,
+    };
 
     let response = StatsResponse {
         total_documents: stats.total_documents,
+        live_documents: stats.live_documents,
+        deleted_documents: stats.deleted_documents,
         total_tokens: stats.total_tokens,
         avg_docs_per_token: stats.avg_docs_per_token,
+        estimated_memory_bytes: stats.estimated_memory_bytes,
+        disk_bytes: engine.disk_usage()?,
+        pending_queue,
     };
 
     Ok(Json(ApiResponse::success(response)))
@@ -256,15 +1666,452 @@ async fn get_stats(State(engine): State<Arc<SearchEngine>>) -> Result<impl IntoR
 
 // ========== Router ==========
 
-pub fn create_router(engine: Arc<SearchEngine>) -> Router {
-    Router::new()
-        .route("/health", get(health_check))
-        .route("/documents", post(insert_document))
+pub fn create_router(state: AppState) -> Router {
+    let index_routes = Router::new()
+        .route("/documents", get(get_documents).post(insert_document))
         .route("/documents/batch", post(batch_insert))
         .route("/documents/:id", get(get_document))
+        .route("/documents/:id", head(head_document))
         .route("/documents/:id", put(update_document))
         .route("/documents/:id", delete(delete_document))
+        .route("/documents/:id/restore", post(restore_document))
+        .route("/titles/:title", get(get_by_title))
+        .route("/changes", get(list_changes))
+        .route("/purge", post(purge_documents))
+        .route("/reindex", post(reindex))
         .route("/search", get(search_documents))
-        .route("/stats", get(get_stats))
-        .with_state(engine)
+        .route("/search.rss", get(search_feed))
+        .route("/search/batch", post(search_batch))
+        .route("/search/scores", get(score_distribution))
+        .route("/search/idf", get(term_idfs))
+        .route("/rerank", post(rerank))
+        .route("/explain", get(explain_document))
+        .route("/terms/:term/related", get(related_terms))
+        .route("/stats", get(get_stats));
+
+    // Every per-index route is also served for named indexes
+    let mut router = index_routes
+        .clone()
+        .nest("/indexes/:name", index_routes)
+        .route("/indexes", get(list_indexes).post(create_index))
+        .route("/indexes/:name", delete(drop_index));
+
+    if let Some(limit) = state.concurrency_limit.clone() {
+        router = router.route_layer(middleware::from_fn_with_state(limit, limit_concurrency));
+    }
+
+    router
+        .route("/health", get(health_check))
+        .route("/version", get(version))
+        .layer(middleware::from_fn(trace_request))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Method};
+    use tower::Service;
+
+    #[test]
+    fn test_round_score() {
+        assert_eq!(round_score(1.2345678, 4), 1.2346);
+        assert_eq!(round_score(0.28768207245178085, 4), 0.2877);
+        assert_eq!(round_score(0.28768207245178085, 0), 0.0);
+        assert_eq!(round_score(2.5, 0), 3.0);
+    }
+
+    #[test]
+    fn test_metadata_in_document_response() {
+        let plain = Document::new("1".to_string(), "Title".to_string(), "Content".to_string());
+        let json = serde_json::to_value(DocumentResponse::from(plain.clone())).unwrap();
+        assert!(json.get("metadata").is_none());
+
+        let mut doc = plain
+            .with_metadata("author".to_string(), "Ann".to_string())
+            .with_metadata("year".to_string(), "2024".to_string());
+        select_metadata([&mut doc], Some("year, missing"));
+        let json = serde_json::to_value(DocumentResponse::from(doc)).unwrap();
+        assert_eq!(json["metadata"], serde_json::json!({"year": "2024"}));
+    }
+
+    #[test]
+    fn test_page_pagination() {
+        let request = |params: serde_json::Value| -> SearchRequest {
+            serde_json::from_value(params).unwrap()
+        };
+
+        let options = request(serde_json::json!({"query": "rust", "page": 3, "page_size": 20}))
+            .search_options()
+            .unwrap();
+        assert_eq!((options.offset, options.limit), (40, Some(20)));
+
+        let options = request(serde_json::json!({"query": "rust", "page": 2}))
+            .search_options()
+            .unwrap();
+        assert_eq!((options.offset, options.limit), (10, Some(10)));
+
+        assert!(
+            request(serde_json::json!({"query": "rust", "page": 2, "offset": 5}))
+                .search_options()
+                .is_err()
+        );
+        assert!(request(serde_json::json!({"query": "rust", "page": 0}))
+            .search_options()
+            .is_err());
+    }
+
+    #[test]
+    fn test_sort_levels() {
+        let request = |params: serde_json::Value| -> SearchRequest {
+            serde_json::from_value(params).unwrap()
+        };
+
+        let options = request(serde_json::json!({"query": "mug", "sort": "relevance, price:asc"}))
+            .search_options()
+            .unwrap();
+        let levels = ["relevance", "price"].map(|name| SortSpec::from_name(name).unwrap());
+        assert_eq!(options.sort, levels);
+        assert!(
+            request(serde_json::json!({"query": "mug", "sort": "price:cheapest"}))
+                .search_options()
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_rejects_when_busy() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let limit = ConcurrencyLimit::new(1).rejecting_when_busy();
+        let mut router = create_router(AppState::new(engine).with_concurrency_limit(limit.clone()));
+
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = router.call(request("/stats")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Occupy the only slot
+        let _busy = limit.permits.clone().try_acquire_owned().unwrap();
+
+        let response = router.call(request("/stats")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = router.call(request("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_over_long_query_and_deep_page_are_bad_requests() {
+        let config = crate::engine::EngineConfig {
+            max_query_terms: Some(2),
+            max_result_window: Some(10_000),
+            ..Default::default()
+        };
+        let engine = Arc::new(SearchEngine::in_memory_with_config(config).unwrap());
+        let mut router = create_router(AppState::new(engine));
+
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router
+            .call(request("/search?query=rust+guide"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router
+            .call(request("/search?query=rust+guide+tutorial"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = router
+            .call(request("/search?query=rust&offset=100000"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_head_document_returns_content_hash() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let doc = Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        );
+        let expected = format!("\"{}\"", doc.digest());
+        engine.upsert_document(doc).unwrap();
+        let mut router = create_router(AppState::new(engine));
+
+        let request = |uri: &str| {
+            Request::builder()
+                .method(Method::HEAD)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router.call(request("/documents/1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], expected.as_str());
+
+        let response = router.call(request("/documents/2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert_requires_ids() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let mut router = create_router(AppState::new(engine));
+
+        let missing_id = Request::builder()
+            .method(Method::POST)
+            .uri("/documents/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"documents": [{"title": "Rust", "content": "go"}]}"#,
+            ))
+            .unwrap();
+        let response = router.call(missing_id).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert_skipping_bad_documents() {
+        let config = crate::engine::EngineConfig {
+            empty_documents: crate::engine::EmptyDocumentPolicy::Reject,
+            ..Default::default()
+        };
+        let engine = Arc::new(SearchEngine::in_memory_with_config(config).unwrap());
+        let mut router = create_router(AppState::new(engine.clone()));
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/documents/batch?on_error=skip")
+            .header("content-type", "application/json")
+            .body(Body::from(concat!(
+                r#"{"documents": [{"id": "1", "title": "Rust", "content": "go"}, "#,
+                r#"{"id": "2", "title": "", "content": ""}]}"#
+            )))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["data"]["indexed"], 1);
+        assert_eq!(json["data"]["failures"][0]["id"], "2");
+        assert!(engine.get_document("1").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_search_batch() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        engine
+            .upsert_document(Document::new(
+                "1".to_string(),
+                "Rust".to_string(),
+                "Rust guide".to_string(),
+            ))
+            .unwrap();
+        engine
+            .upsert_document(Document::new(
+                "2".to_string(),
+                "Go".to_string(),
+                "Go guide".to_string(),
+            ))
+            .unwrap();
+        let mut router = create_router(AppState::new(engine));
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/search/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"queries": ["go", "guide", "missing"], "options": {"limit": 1}, "parallelism": 2}"#,
+            ))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let results = json["data"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["query"], "go");
+        assert_eq!(results[0]["documents"][0]["id"], "2");
+        assert_eq!(
+            (
+                results[1]["total"].as_u64(),
+                results[1]["documents"].as_array().unwrap().len()
+            ),
+            (Some(2), 1)
+        );
+        assert_eq!(results[2]["total"], 0);
+
+        let mut router = create_router(
+            AppState::new(Arc::new(SearchEngine::in_memory().unwrap())).with_max_batch_queries(2),
+        );
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/search/batch")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"queries": ["go", "guide", "missing"], "parallelism": 1000000}"#,
+            ))
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_typed_display_fields() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let mut router = create_router(AppState::new(engine.clone()));
+
+        let insert = Request::builder()
+            .method(Method::PUT)
+            .uri("/documents/1")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"title": "Rust book", "content": "Guide",
+                    "display": {"price": 39.5, "pages": 560, "in_stock": true, "format": "paperback"}}"#,
+            ))
+            .unwrap();
+        let response = router.call(insert).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stored = engine.get_document("1").unwrap().unwrap();
+        assert_eq!(stored.display["pages"], DisplayValue::Integer(560));
+
+        let request = Request::builder()
+            .uri("/documents/1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["data"]["display"],
+            serde_json::json!({"price": 39.5, "pages": 560, "in_stock": true, "format": "paperback"})
+        );
+
+        let nested = Request::builder()
+            .method(Method::PUT)
+            .uri("/documents/2")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"title": "T", "content": "C", "display": {"tags": ["a"]}}"#,
+            ))
+            .unwrap();
+        let response = router.call(nested).await.unwrap();
+        assert!(response.status().is_client_error());
+    }
+
+    #[tokio::test]
+    async fn test_named_index_routes() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let indexes = Arc::new(MultiEngine::in_memory(Default::default()));
+        let mut router = create_router(AppState::new(engine.clone()).with_indexes(indexes.clone()));
+
+        let insert = Request::builder()
+            .method(Method::POST)
+            .uri("/indexes/acme/documents")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"id": "1", "title": "Rust", "content": "Rust guide"}"#,
+            ))
+            .unwrap();
+        let response = router.call(insert).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let acme = indexes.get("acme").unwrap().unwrap();
+        assert!(acme.get_document("1").unwrap().is_some());
+        assert!(engine.get_document("1").unwrap().is_none());
+
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router
+            .call(request("/indexes/acme/documents/1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = router
+            .call(request("/indexes/missing/search?query=rust"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Only document writes create an index
+        let purge = Request::builder()
+            .method(Method::POST)
+            .uri("/indexes/missing/purge")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.call(purge).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(indexes.get("missing").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_index_management_routes() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let indexes = Arc::new(MultiEngine::in_memory(Default::default()));
+        let state = AppState::new(engine).with_indexes(indexes.clone());
+        let mut router = create_router(state.clone().with_admin_token("s3cret".to_string()));
+
+        let create = Request::builder()
+            .method(Method::POST)
+            .uri("/indexes")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"name": "acme"}"#))
+            .unwrap();
+        let response = router.call(create).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .call(
+                Request::builder()
+                    .uri("/indexes")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json["data"],
+            serde_json::json!([{"name": "acme", "documents": 0, "tokens": 0}])
+        );
+
+        let drop = |token: Option<&str>| {
+            let mut request = Request::builder()
+                .method(Method::DELETE)
+                .uri("/indexes/acme");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let response = router.call(drop(Some("wrong"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = create_router(state)
+            .call(drop(Some("s3cret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(indexes.get("acme").unwrap().is_some());
+
+        let held = indexes.get("acme").unwrap().unwrap();
+        let response = router.call(drop(Some("s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        std::mem::drop(held);
+
+        let response = router.call(drop(Some("s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(indexes.get("acme").unwrap().is_none());
+    }
 }
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Values kept for a fixed time after they are stored
+///
+/// Entries aren't invalidated by anything but age (or [`clear`](Self::clear)),
+/// so a value can be up to `ttl` out of date. Once `capacity` entries are
+/// held, expired ones are dropped and then the oldest, to make room.
+pub struct TtlCache<V> {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, V)>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The value stored under `key`, unless it has expired
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &str, now: Instant) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((stored, value)) if now.duration_since(*stored) < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, value: V) {
+        self.insert_at(key, value, Instant::now());
+    }
+
+    fn insert_at(&self, key: String, value: V, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (stored, _)| now.duration_since(*stored) < self.ttl);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (stored, _))| *stored)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, (now, value));
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Entries held, including expired ones not yet dropped
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_expire_and_oldest_is_evicted() {
+        let cache = TtlCache::new(Duration::from_secs(10), 2);
+        let start = Instant::now();
+
+        cache.insert_at("a".to_string(), 1, start);
+        assert_eq!(cache.get_at("a", start + Duration::from_secs(9)), Some(1));
+        assert_eq!(cache.get_at("a", start + Duration::from_secs(10)), None);
+        assert!(cache.is_empty());
+
+        cache.insert_at("a".to_string(), 1, start);
+        cache.insert_at("b".to_string(), 2, start + Duration::from_secs(1));
+        cache.insert_at("c".to_string(), 3, start + Duration::from_secs(2));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_at("a", start + Duration::from_secs(2)), None);
+        assert_eq!(cache.get_at("b", start + Duration::from_secs(2)), Some(2));
+
+        // Expired entries make room before live ones are evicted
+        cache.insert_at("d".to_string(), 4, start + Duration::from_secs(11));
+        assert_eq!(cache.get_at("c", start + Duration::from_secs(11)), Some(3));
+        assert_eq!(cache.get_at("d", start + Duration::from_secs(11)), Some(4));
+    }
+}
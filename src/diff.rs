@@ -0,0 +1,149 @@
+use crate::document::Document;
+use anyhow::Result;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+
+/// One difference between two document stores
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum DocumentDiff {
+    OnlyInA(String),
+    OnlyInB(String),
+    /// Same id, different title, content, url, metadata or boost
+    Changed(String),
+}
+
+/// Counts of differences between two document stores
+#[derive(Debug, Default, Serialize)]
+pub struct DiffSummary {
+    pub only_in_a: usize,
+    pub only_in_b: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+impl DiffSummary {
+    pub fn is_identical(&self) -> bool {
+        self.only_in_a == 0 && self.only_in_b == 0 && self.changed == 0
+    }
+}
+
+/// Compare two streams of documents sorted by id, reporting each difference
+/// to `on_diff` as it is found
+///
+/// Only one document from each side is held at a time, so corpora of any
+/// size can be compared. `updated_at` is ignored since reindexing changes it.
+pub fn diff_documents<A, B>(
+    a: A,
+    b: B,
+    mut on_diff: impl FnMut(DocumentDiff),
+) -> Result<DiffSummary>
+where
+    A: Iterator<Item = Result<Document>>,
+    B: Iterator<Item = Result<Document>>,
+{
+    let mut a = a.peekable();
+    let mut b = b.peekable();
+    let mut summary = DiffSummary::default();
+
+    loop {
+        let order = match (peek(&mut a)?, peek(&mut b)?) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(doc_a), Some(doc_b)) => doc_a.id.cmp(&doc_b.id),
+        };
+
+        match order {
+            Ordering::Less => {
+                let doc = a.next().unwrap()?;
+                summary.only_in_a += 1;
+                on_diff(DocumentDiff::OnlyInA(doc.id));
+            }
+            Ordering::Greater => {
+                let doc = b.next().unwrap()?;
+                summary.only_in_b += 1;
+                on_diff(DocumentDiff::OnlyInB(doc.id));
+            }
+            Ordering::Equal => {
+                let doc_a = a.next().unwrap()?;
+                let doc_b = b.next().unwrap()?;
+                if same_content(&doc_a, &doc_b) {
+                    summary.unchanged += 1;
+                } else {
+                    summary.changed += 1;
+                    on_diff(DocumentDiff::Changed(doc_a.id));
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Peek at the next document, surfacing a read error instead of the document
+fn peek<I>(iter: &mut Peekable<I>) -> Result<Option<&Document>>
+where
+    I: Iterator<Item = Result<Document>>,
+{
+    if let Some(Err(_)) = iter.peek() {
+        return Err(iter.next().unwrap().unwrap_err());
+    }
+    Ok(iter.peek().map(|doc| doc.as_ref().unwrap()))
+}
+
+fn same_content(a: &Document, b: &Document) -> bool {
+    a.title == b.title
+        && a.content == b.content
+        && a.url == b.url
+        && a.metadata == b.metadata
+        && a.boost == b.boost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(items: &[(&str, &str)]) -> Vec<Result<Document>> {
+        items
+            .iter()
+            .map(|(id, content)| {
+                Ok(Document::new(
+                    id.to_string(),
+                    "Title".to_string(),
+                    content.to_string(),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_documents() -> Result<()> {
+        let a = docs(&[("1", "same"), ("2", "old"), ("3", "only a")]);
+        let b = docs(&[("1", "same"), ("2", "new"), ("4", "only b")]);
+
+        let mut diffs = Vec::new();
+        let summary = diff_documents(a.into_iter(), b.into_iter(), |d| diffs.push(d))?;
+
+        assert_eq!(
+            diffs,
+            vec![
+                DocumentDiff::Changed("2".to_string()),
+                DocumentDiff::OnlyInA("3".to_string()),
+                DocumentDiff::OnlyInB("4".to_string()),
+            ]
+        );
+        assert_eq!(
+            (
+                summary.only_in_a,
+                summary.only_in_b,
+                summary.changed,
+                summary.unchanged
+            ),
+            (1, 1, 1, 1)
+        );
+        assert!(!summary.is_identical());
+
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use serde::{Deserialize, Serialize};
+use crate::tokenizer::Language;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
 
 /// Document represents a searchable document
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +13,148 @@ pub struct Document {
     pub url: Option<String>,
     #[serde(default)]
     pub metadata: std::collections::HashMap<String, String>,
+    /// Query-independent importance, multiplied into every score
+    #[serde(default = "default_boost")]
+    pub boost: f64,
+    /// When the document was last written, in milliseconds since the Unix
+    /// epoch. Set by the engine on every upsert.
+    #[serde(default)]
+    pub updated_at: u64,
+    /// ISO 639-1 code of the language the document is written in, selecting
+    /// its stemmer; English when absent
+    #[serde(default)]
+    pub language: Option<String>,
+    /// [`Document::digest`] of the stored version. Set by the engine on
+    /// every upsert.
+    #[serde(default)]
+    pub content_hash: String,
+    /// Typed values returned with the document for display (price,
+    /// rating), not indexed or searched
+    #[serde(default)]
+    pub display: BTreeMap<String, DisplayValue>,
+    /// Further named text sections (summary, notes), searched like the
+    /// content and indexed after it in name order
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Headed parts of a long document, in reading order, indexed after
+    /// the named fields; a search can name the one matching best
+    #[serde(default)]
+    pub sections: Vec<Section>,
+}
+
+/// A headed part of a document, which a UI can link to by its anchor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    /// Fragment identifier of the section, such as `installation`
+    pub anchor: String,
+    pub heading: String,
+    pub text: String,
+}
+
+/// A typed display value, serialized as a plain JSON boolean, number or
+/// string
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplayValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// Tagged form of [`DisplayValue`] for binary formats, which can't tell a
+/// plain value's type on their own
+#[derive(Serialize, Deserialize)]
+enum TaggedDisplayValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl DisplayValue {
+    /// Bytes identifying the value and its type, for [`Document::digest`]
+    fn digest_bytes(&self) -> Vec<u8> {
+        let (tag, mut bytes) = match self {
+            DisplayValue::Bool(value) => (b'b', vec![*value as u8]),
+            DisplayValue::Integer(value) => (b'i', value.to_le_bytes().to_vec()),
+            DisplayValue::Float(value) => (b'f', value.to_bits().to_le_bytes().to_vec()),
+            DisplayValue::Text(value) => (b's', value.as_bytes().to_vec()),
+        };
+        bytes.insert(0, tag);
+        bytes
+    }
+}
+
+impl Serialize for DisplayValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            let tagged = match self.clone() {
+                DisplayValue::Bool(value) => TaggedDisplayValue::Bool(value),
+                DisplayValue::Integer(value) => TaggedDisplayValue::Integer(value),
+                DisplayValue::Float(value) => TaggedDisplayValue::Float(value),
+                DisplayValue::Text(value) => TaggedDisplayValue::Text(value),
+            };
+            return tagged.serialize(serializer);
+        }
+        match self {
+            DisplayValue::Bool(value) => serializer.serialize_bool(*value),
+            DisplayValue::Integer(value) => serializer.serialize_i64(*value),
+            DisplayValue::Float(value) => serializer.serialize_f64(*value),
+            DisplayValue::Text(value) => serializer.serialize_str(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DisplayValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Ok(match TaggedDisplayValue::deserialize(deserializer)? {
+                TaggedDisplayValue::Bool(value) => DisplayValue::Bool(value),
+                TaggedDisplayValue::Integer(value) => DisplayValue::Integer(value),
+                TaggedDisplayValue::Float(value) => DisplayValue::Float(value),
+                TaggedDisplayValue::Text(value) => DisplayValue::Text(value),
+            });
+        }
+        deserializer.deserialize_any(DisplayValueVisitor)
+    }
+}
+
+struct DisplayValueVisitor;
+
+impl serde::de::Visitor<'_> for DisplayValueVisitor {
+    type Value = DisplayValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a boolean, number or string")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<DisplayValue, E> {
+        Ok(DisplayValue::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<DisplayValue, E> {
+        Ok(DisplayValue::Integer(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<DisplayValue, E> {
+        Ok(i64::try_from(value).map_or(DisplayValue::Float(value as f64), DisplayValue::Integer))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<DisplayValue, E> {
+        Ok(DisplayValue::Float(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<DisplayValue, E> {
+        Ok(DisplayValue::Text(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<DisplayValue, E> {
+        Ok(DisplayValue::Text(value))
+    }
+}
+
+fn default_boost() -> f64 {
+    1.0
 }
 
 impl Document {
@@ -20,6 +165,13 @@ impl Document {
             content,
             url: None,
             metadata: std::collections::HashMap::new(),
+            boost: default_boost(),
+            updated_at: 0,
+            language: None,
+            content_hash: String::new(),
+            display: BTreeMap::new(),
+            fields: BTreeMap::new(),
+            sections: Vec::new(),
         }
     }
 
@@ -33,9 +185,120 @@ impl Document {
         self
     }
 
-    /// Get the full searchable text (title + content)
+    pub fn with_boost(mut self, boost: f64) -> Self {
+        self.boost = boost;
+        self
+    }
+
+    pub fn with_language(mut self, language: String) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    pub fn with_display(mut self, key: String, value: DisplayValue) -> Self {
+        self.display.insert(key, value);
+        self
+    }
+
+    pub fn with_field(mut self, name: String, text: String) -> Self {
+        self.fields.insert(name, text);
+        self
+    }
+
+    pub fn with_section(mut self, anchor: String, heading: String, text: String) -> Self {
+        self.sections.push(Section {
+            anchor,
+            heading,
+            text,
+        });
+        self
+    }
+
+    /// Every text field in index order: title, content, the named fields,
+    /// then each section's heading and text
+    pub fn text_fields(&self) -> impl Iterator<Item = &str> {
+        [self.title.as_str(), self.content.as_str()]
+            .into_iter()
+            .chain(self.fields.values().map(String::as_str))
+            .chain(
+                self.sections
+                    .iter()
+                    .flat_map(|s| [s.heading.as_str(), s.text.as_str()]),
+            )
+    }
+
+    /// Position of the first section in [`text_fields`](Self::text_fields)
+    pub fn first_section_field(&self) -> usize {
+        2 + self.fields.len()
+    }
+
+    /// The language to analyze the document in, or `None` if its language
+    /// code is not supported
+    pub fn analyzer_language(&self) -> Option<Language> {
+        match &self.language {
+            Some(code) => Language::from_code(code),
+            None => Some(Language::English),
+        }
+    }
+
+    /// Hex digest of everything a client can set on the document: title,
+    /// content, url, metadata, boost, language, display values, named
+    /// text fields and sections
+    ///
+    /// Uses 64-bit FNV-1a, so the value is the same across restarts, builds
+    /// and platforms. Meant for change detection, not security.
+    pub fn digest(&self) -> String {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut metadata: Vec<(&String, &String)> = self.metadata.iter().collect();
+        metadata.sort();
+
+        let mut fields: Vec<&[u8]> = vec![self.title.as_bytes(), self.content.as_bytes()];
+        fields.push(self.url.as_deref().unwrap_or_default().as_bytes());
+        for (key, value) in metadata {
+            fields.push(key.as_bytes());
+            fields.push(value.as_bytes());
+        }
+        let boost = self.boost.to_bits().to_le_bytes();
+        fields.push(&boost);
+        fields.push(self.language.as_deref().unwrap_or_default().as_bytes());
+        // Only hashed when present, so older digests stay valid
+        let display: Vec<Vec<u8>> = self
+            .display
+            .values()
+            .map(DisplayValue::digest_bytes)
+            .collect();
+        for (key, value) in self.display.keys().zip(&display) {
+            fields.push(key.as_bytes());
+            fields.push(value);
+        }
+        for (name, text) in &self.fields {
+            fields.push(name.as_bytes());
+            fields.push(text.as_bytes());
+        }
+        for section in &self.sections {
+            fields.push(section.anchor.as_bytes());
+            fields.push(section.heading.as_bytes());
+            fields.push(section.text.as_bytes());
+        }
+
+        let mut hash = OFFSET_BASIS;
+        for field in fields {
+            // 0xff never occurs in UTF-8, so fields can't run into each other
+            for &byte in field.iter().chain(&[0xff]) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(PRIME);
+            }
+        }
+
+        format!("{:016x}", hash)
+    }
+
+    /// Get the full searchable text (title, content, named fields and
+    /// sections)
     pub fn searchable_text(&self) -> String {
-        format!("{} {}", self.title, self.content)
+        self.text_fields().collect::<Vec<_>>().join(" ")
     }
 }
 
@@ -45,6 +308,45 @@ pub struct DocStats {
     pub id: String,
     pub length: usize,
     pub term_frequencies: std::collections::HashMap<String, usize>,
+    /// Term frequencies within the title only (for title boosting)
+    #[serde(default)]
+    pub title_term_frequencies: std::collections::HashMap<String, usize>,
+    /// Token positions of each term in the analyzed text (for proximity)
+    #[serde(default)]
+    pub positions: std::collections::HashMap<String, Vec<usize>>,
+    /// Distinct words of the analyzed text before stemming, to tell exact
+    /// matches from stem matches
+    #[serde(default)]
+    pub surface_forms: std::collections::HashSet<String>,
+    /// Positions of each word before stemming, for exact phrase matching.
+    /// Only recorded when the engine is configured to.
+    #[serde(default)]
+    pub surface_positions: std::collections::HashMap<String, Vec<usize>>,
+    /// The document's boost, kept here so ranking doesn't load the document
+    #[serde(default = "default_boost")]
+    pub boost: f64,
+    /// The document's `updated_at`, for filtering by modification time
+    #[serde(default)]
+    pub updated_at: u64,
+    /// Number of analyzed title tokens, the title's token positions being
+    /// those below it
+    #[serde(default)]
+    pub title_length: usize,
+    /// Numeric value of the engine's authority metadata field, if the
+    /// document has one (see `EngineConfig::authority_field`)
+    #[serde(default)]
+    pub authority: Option<f64>,
+    /// Token positions of the content, so `content:` matches neither named
+    /// fields nor sections
+    #[serde(default)]
+    pub content_positions: std::ops::Range<usize>,
+    /// Token position at which each section of the document starts, in
+    /// section order (see [`Document::sections`])
+    #[serde(default)]
+    pub section_starts: Vec<usize>,
+    /// The document's metadata, so sorting on it doesn't load the document
+    #[serde(default)]
+    pub metadata: std::collections::HashMap<String, String>,
 }
 
 impl DocStats {
@@ -53,6 +355,17 @@ impl DocStats {
             id,
             length,
             term_frequencies: std::collections::HashMap::new(),
+            title_term_frequencies: std::collections::HashMap::new(),
+            positions: std::collections::HashMap::new(),
+            surface_forms: std::collections::HashSet::new(),
+            surface_positions: std::collections::HashMap::new(),
+            boost: default_boost(),
+            updated_at: 0,
+            title_length: 0,
+            authority: None,
+            content_positions: 0..0,
+            section_starts: Vec::new(),
+            metadata: std::collections::HashMap::new(),
         }
     }
 }
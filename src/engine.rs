@@ -1,14 +1,41 @@
-use crate::document::{Document, DocStats};
+use crate::cache::TtlCache;
+use crate::document::{DocStats, Document};
+use crate::eval::{EvalReport, Judgment, QueryEvaluation};
+use crate::geo::GeoFilter;
+use crate::highlight::{Highlight, Highlighter, DEFAULT_SNIPPET_CHARS};
+use crate::import::ImportSummary;
 use crate::index::InvertedIndex;
-use crate::ranking::{rank_documents, ScoredDocument};
-use crate::storage::Storage;
-use crate::tokenizer::Tokenizer;
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use crate::query::{self, Occur, QueryParser};
+use crate::ranking::{
+    compare_sort_values, diversify, query_idfs, rank_documents, rank_documents_within, rank_top_k,
+    RankBudget, RankingOptions, RankingProfile, ScoreDistribution, ScoreExplanation, ScoringQuery,
+    SortKey, SortSpec, TermIdf, TopK, BM25, DIVERSITY_POOL,
+};
+use crate::storage::{Compression, Durability, RetryPolicy, Storage, DEFAULT_SEGMENT_BYTES};
+use crate::tokenizer::{is_unstemmed_term, AnalyzerOptions, Tokenizer, TokenizerConfig};
+use crate::wal::{Wal, WalOp, WAL_FILE};
+use anyhow::Result;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Most top-ranked matches aggregated for related terms
+pub const MAX_RELATED_TERMS_DOCS: usize = 1000;
+
+/// Metadata key of the terms pruned by `max_postings`
+const PRUNED_TERMS_KEY: &str = "pruned_terms";
+/// Prefix of the metadata keys holding job checkpoints
+const CHECKPOINT_PREFIX: &str = "checkpoint/";
+/// Size at which a write flushes the engine so the write-ahead log can be
+/// truncated
+pub const WAL_CHECKPOINT_BYTES: u64 = 64 << 20;
 
 /// Search mode
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SearchMode {
     /// Match all query terms (AND)
     And,
@@ -23,6 +50,82 @@ pub struct SearchOptions {
     pub use_ranking: bool,
     pub limit: Option<usize>,
     pub offset: usize,
+    pub ranking: RankingOptions,
+    /// Include soft-deleted documents in the results
+    pub include_deleted: bool,
+    /// Keep only documents within a radius of a point
+    pub geo: Option<GeoFilter>,
+    /// Ignore query terms present in more than this fraction of documents
+    /// (corpus-specific stopwords). A query made only of such terms is kept
+    /// as-is.
+    pub max_df_ratio: Option<f64>,
+    /// Return this many of the most frequent non-query terms across the
+    /// matching documents (at most [`MAX_RELATED_TERMS_DOCS`] of them)
+    pub related_terms: Option<usize>,
+    /// Stop gathering OR-mode candidates after this many documents, taking
+    /// those of the rarest terms first. Bounds latency for broad queries at
+    /// the cost of completeness.
+    pub max_candidates: Option<usize>,
+    /// Rank OR queries by scoring only documents that can still reach the
+    /// requested page (MaxScore pruning). The page itself is exact, but
+    /// `total` then counts only the documents scored. Ignored with `geo`,
+    /// `group_by`, `related_terms`, `max_candidates`, `diversity` or without
+    /// ranking.
+    pub top_k_only: bool,
+    /// How the query string is read; `Literal` for untrusted input
+    pub query_parser: QueryParser,
+    /// Return a highlighted title and content snippet for each document
+    pub highlight: bool,
+    /// Name the best-matching section of each document in
+    /// [`SearchResult::sections`]
+    pub sections: bool,
+    /// Markup put before and after each highlighted match, `<em>` and
+    /// `</em>` when `None`
+    pub highlight_tags: Option<(String, String)>,
+    /// HTML-escape the highlighted title and snippet, leaving the tags as
+    /// they are, for documents that contain markup
+    pub escape_html: bool,
+    /// Query analysis steps and language; see [`AnalyzerOptions`] for which
+    /// are useful against an index built with the full pipeline. The
+    /// language should match that of the documents searched for.
+    pub analyzer: AnalyzerOptions,
+    /// Bucket results by this metadata field and return groups instead of
+    /// a flat list; `limit` and `offset` then apply to groups
+    pub group_by: Option<String>,
+    /// Documents kept per group
+    pub group_size: usize,
+    /// Keep only documents updated at or after this time, in milliseconds
+    /// since the Unix epoch
+    pub modified_since: Option<u64>,
+    /// Keep only documents containing each word at least the given number
+    /// of times. Words are analyzed like the query; one analyzing to no term
+    /// (a stopword) is ignored. Filters the matches of the query without
+    /// adding to them.
+    pub min_term_frequencies: Vec<(String, usize)>,
+    /// Re-order the best [`DIVERSITY_POOL`] results to trade relevance for
+    /// variety, from 0.0 (ranking as is) to 1.0 (only avoid
+    /// near-duplicates); see [`diversify`]. Results past the pool stay in
+    /// score order. Needs ranking.
+    pub diversity: Option<f64>,
+    /// Time allowed for ranking. Once it runs out, the best documents
+    /// scored so far are returned and the result is marked `timed_out`.
+    /// While set, only the best `offset + limit` documents are kept as
+    /// candidates are scored, so a document deleted mid-search leaves its
+    /// page short instead of being back-filled. Finding the candidates
+    /// isn't interrupted.
+    pub timeout: Option<Duration>,
+    /// Report how the query was read in
+    /// [`SearchResult::query_analysis`]
+    pub query_analysis: bool,
+    /// Order results by these levels in turn instead of by score and
+    /// `ranking.tie_break`, which still order documents equal on every
+    /// level; applied after geo sorting and diversity. Disables top-k
+    /// pruning.
+    pub sort: Vec<SortSpec>,
+    /// Neither use nor fill the search cache
+    /// ([`EngineConfig::search_cache_ttl`]), for results that must be
+    /// current
+    pub no_cache: bool,
 }
 
 impl Default for SearchOptions {
@@ -32,283 +135,5564 @@ impl Default for SearchOptions {
             use_ranking: true,
             limit: Some(10),
             offset: 0,
+            ranking: RankingOptions::default(),
+            include_deleted: false,
+            geo: None,
+            max_df_ratio: None,
+            related_terms: None,
+            max_candidates: None,
+            top_k_only: false,
+            query_parser: QueryParser::default(),
+            highlight: false,
+            sections: false,
+            highlight_tags: None,
+            escape_html: false,
+            analyzer: AnalyzerOptions::default(),
+            group_by: None,
+            group_size: 3,
+            modified_since: None,
+            min_term_frequencies: Vec::new(),
+            diversity: None,
+            timeout: None,
+            query_analysis: false,
+            sort: Vec::new(),
+            no_cache: false,
         }
     }
 }
 
+impl SearchOptions {
+    /// Replace the ranking options with a pre-tuned profile
+    pub fn with_profile(mut self, profile: RankingProfile) -> Self {
+        self.ranking = profile.options();
+        self
+    }
+}
+
 /// Search result
 #[derive(Debug, Clone)]
 pub struct SearchResult {
     pub documents: Vec<Document>,
     pub total: usize,
     pub scores: Option<Vec<f64>>,
+    pub related_terms: Option<Vec<TermCount>>,
+    /// The candidate set hit `max_candidates` or `top_k_only` skipped
+    /// documents, so the results only cover part of the matches
+    pub candidates_truncated: bool,
+    /// Retrieval stopped early, so `total` is a lower bound rather than an
+    /// exact count
+    pub total_is_approximate: bool,
+    /// The query had no terms left after analysis (only stopwords or
+    /// punctuation), as opposed to matching nothing
+    pub empty_query: bool,
+    /// One entry per document when highlighting was requested
+    pub highlights: Option<Vec<Highlight>>,
+    /// One entry per document when `sections` was requested: the section
+    /// matching best, or `None` when no query term occurs in a section
+    pub sections: Option<Vec<Option<SectionMatch>>>,
+    /// Results grouped by `group_by`; `documents` is empty when set
+    pub groups: Option<Vec<ResultGroup>>,
+    /// Ranking ran out of `timeout`, so the results are the best of the
+    /// documents scored in time
+    pub timed_out: bool,
+    /// How the query was read, when `query_analysis` was requested
+    pub query_analysis: Option<QueryAnalysis>,
+}
+
+/// Matching documents in result order, from
+/// [`SearchEngine::ranked_matches`]
+struct RankedMatches {
+    ids: Vec<String>,
+    /// Scores of `ids` when ranking
+    scores: Option<Vec<f64>>,
+    /// Number of matching documents, which can exceed `ids.len()` when only
+    /// the best are kept
+    total: usize,
+    /// Retrieval stopped early; see [`SearchResult::candidates_truncated`]
+    candidates_truncated: bool,
+    timed_out: bool,
+}
+
+/// Why a document scores what it does for a query
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub query: String,
+    pub doc_id: String,
+    /// Analyzed query terms by role
+    pub optional_terms: Vec<String>,
+    pub required_terms: Vec<String>,
+    pub excluded_terms: Vec<String>,
+    /// The document contains an excluded term, so it never matches
+    pub excluded: bool,
+    pub ranking: RankingOptions,
+    pub doc_stats: DocStats,
+    pub score: ScoreExplanation,
+}
+
+/// The section of a result that matches the query best; see
+/// [`Document::sections`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SectionMatch {
+    /// Position of the section in the document's sections
+    pub index: usize,
+    pub anchor: String,
+    pub heading: String,
+}
+
+/// Top documents sharing a value of the `group_by` field
+#[derive(Debug, Clone)]
+pub struct ResultGroup {
+    /// The field value, or `None` for the bucket of documents without it
+    pub value: Option<String>,
+    /// Number of matching documents in the group
+    pub total: usize,
+    pub documents: Vec<Document>,
+    pub scores: Option<Vec<f64>>,
+}
+
+/// A term and its summed frequency over a set of documents
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TermCount {
+    pub term: String,
+    pub count: usize,
+}
+
+/// How a search read its query, gathered by the stages of query
+/// processing as they run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueryAnalysis {
+    pub parser: QueryParser,
+    /// How plain clauses combine, as set by the search
+    pub mode: Option<SearchMode>,
+    /// Clauses in query order
+    pub clauses: Vec<ClauseAnalysis>,
+    /// Words removed as stopwords
+    pub stopwords: Vec<String>,
+    /// Terms ignored because they were pruned from the index
+    pub pruned_terms: Vec<String>,
+    /// Terms ignored for appearing in too many documents (`max_df_ratio`)
+    pub common_terms: Vec<String>,
+    /// Terms added to the query, such as unstemmed word forms
+    pub expansions: Vec<String>,
+    /// Terms dropped past [`EngineConfig::max_query_terms`]
+    pub truncated_terms: usize,
+}
+
+/// One parsed clause of a query
+#[derive(Debug, Clone, Serialize)]
+pub struct ClauseAnalysis {
+    pub text: String,
+    /// `should` (plain), `must` (`+`) or `must_not` (`-`)
+    pub occur: Occur,
+    /// Field of a `field:value` clause, unless it names no known field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// The field is a keyword field, matched as one exact value
+    pub keyword: bool,
+    pub phrase: bool,
+    pub weight: f64,
+    /// Terms the clause analyzed to
+    pub terms: Vec<String>,
+}
+
+/// Query terms after parsing and analysis
+#[derive(Debug, Default)]
+struct AnalyzedQuery {
+    optional: Vec<String>,
+    required: Vec<String>,
+    excluded: Vec<String>,
+    /// `^weight` of terms whose weight isn't 1.0
+    weights: HashMap<String, f64>,
+    /// Unstemmed query word of each term (the first one, if several stem
+    /// alike)
+    surface_forms: HashMap<String, String>,
+    /// Unstemmed forms of the plain and required words, with
+    /// `index_unstemmed`. They add to the score but never decide a match:
+    /// a document containing a word's exact form contains its stem too.
+    unstemmed: Vec<String>,
+    /// Clauses that need more than a term lookup: `title:` and `content:`
+    /// terms and quoted phrases. Their excluded terms are only here, not in
+    /// `excluded`.
+    constraints: Vec<ClauseConstraint>,
+    analysis: QueryAnalysis,
+    /// Analyzed terms of the whole query, including any truncated
+    total_terms: usize,
+}
+
+/// Text field a query term can be restricted to with `title:` or `content:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Title,
+    Content,
+}
+
+impl TextField {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(TextField::Title),
+            "content" => Some(TextField::Content),
+            _ => None,
+        }
+    }
+
+    /// Whether this field of a document contains the term
+    fn contains(self, stats: &DocStats, term: &str) -> bool {
+        match self {
+            TextField::Title => stats
+                .title_term_frequencies
+                .get(term)
+                .is_some_and(|&tf| tf > 0),
+            TextField::Content => stats.positions.get(term).is_some_and(|positions| {
+                positions
+                    .iter()
+                    .any(|p| stats.content_positions.contains(p))
+            }),
+        }
+    }
+
+    /// Whether a token position of a document lies in this field. Title
+    /// positions come first, so they are those below the title length.
+    fn contains_position(self, stats: &DocStats, position: usize) -> bool {
+        match self {
+            TextField::Title => position < stats.title_length,
+            TextField::Content => stats.content_positions.contains(&position),
+        }
+    }
+}
+
+/// A query clause matched against document statistics rather than the
+/// index: a term restricted to a field, or a phrase
+#[derive(Debug, Clone)]
+struct ClauseConstraint {
+    /// Analyzed terms in query order; a single term unless a phrase
+    terms: Vec<String>,
+    /// Unstemmed word of each term
+    surface_forms: Vec<String>,
+    field: Option<TextField>,
+    occur: Occur,
+    phrase: bool,
+}
+
+impl ClauseConstraint {
+    /// Whether a document matches the clause. With `exact`, phrases are
+    /// matched on unstemmed words where the document recorded them.
+    fn matches(&self, stats: &DocStats, exact: bool) -> bool {
+        if !self.phrase {
+            return self
+                .field
+                .is_none_or(|field| field.contains(stats, &self.terms[0]));
+        }
+
+        let (words, positions) = if exact && !stats.surface_positions.is_empty() {
+            (&self.surface_forms, &stats.surface_positions)
+        } else {
+            (&self.terms, &stats.positions)
+        };
+        let in_field = |position| {
+            self.field
+                .is_none_or(|field| field.contains_position(stats, position))
+        };
+        let Some(starts) = positions.get(&words[0]) else {
+            return false;
+        };
+
+        starts.iter().any(|&start| {
+            in_field(start)
+                && in_field(start + words.len() - 1)
+                && words[1..].iter().enumerate().all(|(i, word)| {
+                    positions
+                        .get(word)
+                        .is_some_and(|p| p.contains(&(start + i + 1)))
+                })
+        })
+    }
+}
+
+impl AnalyzedQuery {
+    /// Terms that contribute to the score, plain terms, required ones, then
+    /// unstemmed forms, with their weights and surface forms
+    fn scoring_query(&self) -> ScoringQuery {
+        let terms: Vec<String> = self
+            .optional
+            .iter()
+            .chain(&self.required)
+            .chain(&self.unstemmed)
+            .cloned()
+            .collect();
+        let weights = terms
+            .iter()
+            .map(|term| self.weights.get(term).copied().unwrap_or(1.0))
+            .collect();
+        let surface_forms = terms
+            .iter()
+            .map(|term| self.surface_forms.get(term).unwrap_or(term).clone())
+            .collect();
+        ScoringQuery {
+            terms,
+            weights,
+            surface_forms,
+        }
+    }
+
+    /// Drop plain and required terms that appear in more than `max_ratio` of
+    /// all documents, unless that would leave no terms to match.
+    fn drop_common_terms(&mut self, index: &InvertedIndex, max_ratio: f64) {
+        let total = index.total_documents();
+        if total == 0 {
+            return;
+        }
+        let is_rare = |term: &String| index.doc_frequency(term) as f64 / total as f64 <= max_ratio;
+
+        let optional: Vec<String> = self
+            .optional
+            .iter()
+            .filter(|t| is_rare(t))
+            .cloned()
+            .collect();
+        let required: Vec<String> = self
+            .required
+            .iter()
+            .filter(|t| is_rare(t))
+            .cloned()
+            .collect();
+
+        if !optional.is_empty() || !required.is_empty() {
+            self.optional = optional;
+            self.required = required;
+            self.unstemmed.retain(is_rare);
+            self.constraints
+                .retain(|c| c.occur == Occur::MustNot || c.phrase || c.terms.iter().all(is_rare));
+            let common = self
+                .analysis
+                .clauses
+                .iter()
+                .flat_map(|c| &c.terms)
+                .filter(|t| !is_rare(t));
+            self.analysis.common_terms = common.cloned().collect();
+        }
+    }
+
+    /// The query analysis, completed with the search mode
+    fn analysis_for(&self, options: &SearchOptions) -> QueryAnalysis {
+        QueryAnalysis {
+            mode: Some(options.mode),
+            ..self.analysis.clone()
+        }
+    }
+
+    /// Whether a document satisfies the field restrictions and phrases of
+    /// the query. Like other plain clauses, a plain one must match in AND
+    /// mode, while in OR mode some plain clause must match.
+    fn matches_constraints(&self, stats: &DocStats, mode: SearchMode, exact: bool) -> bool {
+        let mut scoped_optional = HashSet::new();
+        let mut optional_hit = false;
+
+        for constraint in &self.constraints {
+            let hit = constraint.matches(stats, exact);
+            match constraint.occur {
+                Occur::Must if !hit => return false,
+                Occur::MustNot if hit => return false,
+                Occur::Should if !hit && matches!(mode, SearchMode::And) => return false,
+                Occur::Should => {
+                    scoped_optional.extend(constraint.terms.iter().map(String::as_str));
+                    optional_hit |= hit;
+                }
+                _ => {}
+            }
+        }
+
+        optional_hit
+            || scoped_optional.is_empty()
+            || matches!(mode, SearchMode::And)
+            || self.optional.iter().any(|term| {
+                !scoped_optional.contains(term.as_str())
+                    && stats.term_frequencies.contains_key(term)
+            })
+    }
+}
+
+/// Engine configuration, fixed at construction
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Flush the index and storage when the engine is dropped
+    pub flush_on_drop: bool,
+    /// Compression for stored documents
+    pub compression: Compression,
+    /// Metadata fields indexed as a single exact, case-insensitive keyword
+    /// and queried with `field:value`
+    pub keyword_fields: Vec<String>,
+    /// Text analysis settings, applied to both documents and queries
+    pub tokenizer: TokenizerConfig,
+    /// Index only document titles; content is stored but not searchable.
+    /// Suited to autocomplete-style corpora. Only affects documents indexed
+    /// after the setting changes.
+    pub title_only: bool,
+    /// Drop a term from the index once it is in more than this many
+    /// documents. Pruned terms are never indexed again and are ignored in
+    /// queries like stopwords, trading recall for a smaller index and cheaper
+    /// intersections.
+    pub max_postings: Option<usize>,
+    /// When writes are flushed to disk; see [`Durability`] for the data-loss
+    /// window of each level
+    pub durability: Durability,
+    /// How document, statistics and index writes that fail transiently are
+    /// retried
+    pub storage_retry: RetryPolicy,
+    /// Size the saved index is split into, in bytes of terms and ids, so
+    /// no single storage value grows with the whole index
+    pub index_segment_bytes: usize,
+    /// Handling of documents with no indexable text
+    pub empty_documents: EmptyDocumentPolicy,
+    /// Serve queries only: every write fails and nothing is flushed, so the
+    /// data directory is left as it was found. The directory must exist.
+    pub read_only: bool,
+    /// Gap left between the token positions of the title and the content,
+    /// so proximity never treats the last title word and the first content
+    /// word as adjacent. Only affects documents indexed after it changes.
+    pub position_increment_gap: usize,
+    /// How [`SearchEngine::generate_id`] makes ids for documents inserted
+    /// without one
+    pub id_generation: IdGeneration,
+    /// Also record where each unstemmed word occurs, so quoted phrases
+    /// match the exact words typed rather than anything with the same stems
+    /// ("university study" no longer matches "universe study"). Costs
+    /// storage; documents indexed before it was enabled are matched on stems
+    /// until reindexed.
+    pub exact_phrases: bool,
+    /// Persist the index from a copy taken after each write instead of
+    /// serializing it under the write lock, so searches aren't blocked while
+    /// a large index is written out. Off by default, since every write then
+    /// clones the whole index and briefly needs memory for a second copy.
+    pub snapshot_index_saves: bool,
+    /// Metadata field holding each document's authority, a precomputed
+    /// importance such as PageRank or an inbound link count. Non-numeric,
+    /// negative or missing values mean no authority. Searches blend it in
+    /// with [`RankingOptions::authority_weight`]; documents indexed before
+    /// it was set have none until reindexed.
+    pub authority_field: Option<String>,
+    /// Index only the first N words of each document, title first, so one
+    /// huge document can't stall ingestion. The rest is stored and returned
+    /// but can't be found. Words are counted as the tokenizer splits text,
+    /// before stopwords are removed.
+    pub max_document_tokens: Option<usize>,
+    /// Record every upsert and delete in a log file in the index directory
+    /// (`wal.log`), synced before the write returns, and replay it when the
+    /// engine is opened. Writes then survive a crash even with
+    /// [`Durability::Async`] or [`Durability::None`], at the cost of one
+    /// `fsync` per write operation (one per batch for batch inserts). The
+    /// log is emptied on every flush, and a write flushes the engine once
+    /// the log reaches [`WAL_CHECKPOINT_BYTES`]. Ignored in memory, when
+    /// read-only and by [`SearchEngine::with_storage`].
+    pub write_ahead_log: bool,
+    /// Most terms a search query may have after analysis, counting plain,
+    /// required, excluded and field terms. A query pasted from a whole
+    /// document would otherwise cost a posting lookup per word. Applies to
+    /// everything that analyzes a query, not just searches: reranking, score
+    /// distributions, term IDFs, explanations and highlighters.
+    pub max_query_terms: Option<usize>,
+    /// What a search does with a query over `max_query_terms`
+    pub long_queries: LongQueryPolicy,
+    /// Most results a search may page through: `offset + limit` over it
+    /// fails with [`ResultWindowTooLarge`], since every result up to the
+    /// end of the page has to be ranked. A search without a limit is only
+    /// checked by its offset. There is no cursor to read further results
+    /// with, so a search past the window has to be narrowed by its query or
+    /// filters. `None`, the default, allows any depth.
+    pub max_result_window: Option<usize>,
+    /// Keep search results for this long and answer a repeated search (the
+    /// same query and options) from them. Writes don't invalidate cached
+    /// results, so for up to the TTL a search may miss documents added
+    /// since, return ones changed or deleted since as they were, and report
+    /// stale totals and scores; only [`SearchEngine::clear`] empties the
+    /// cache. Timed-out results and results of more than
+    /// [`MAX_CACHED_DOCUMENTS`] documents aren't cached. `None` disables
+    /// caching.
+    pub search_cache_ttl: Option<Duration>,
+    /// Most searches the cache holds; the oldest is dropped for a new one
+    pub search_cache_capacity: usize,
+}
+
+/// Default [`EngineConfig::search_cache_capacity`]
+pub const DEFAULT_SEARCH_CACHE_CAPACITY: usize = 1024;
+
+/// Most documents a search result may hold to be cached, so the cache's
+/// size is bounded by its capacity
+pub const MAX_CACHED_DOCUMENTS: usize = 100;
+
+/// Default [`EngineConfig::position_increment_gap`], as in Lucene
+pub const DEFAULT_POSITION_INCREMENT_GAP: usize = 100;
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            flush_on_drop: true,
+            compression: Compression::None,
+            keyword_fields: Vec::new(),
+            tokenizer: TokenizerConfig::default(),
+            title_only: false,
+            max_postings: None,
+            durability: Durability::default(),
+            storage_retry: RetryPolicy::default(),
+            index_segment_bytes: DEFAULT_SEGMENT_BYTES,
+            empty_documents: EmptyDocumentPolicy::default(),
+            read_only: false,
+            position_increment_gap: DEFAULT_POSITION_INCREMENT_GAP,
+            id_generation: IdGeneration::default(),
+            exact_phrases: false,
+            snapshot_index_saves: false,
+            authority_field: None,
+            max_document_tokens: None,
+            write_ahead_log: false,
+            max_query_terms: None,
+            long_queries: LongQueryPolicy::default(),
+            max_result_window: None,
+            search_cache_ttl: None,
+            search_cache_capacity: DEFAULT_SEARCH_CACHE_CAPACITY,
+        }
+    }
+}
+
+/// Line format of [`SearchEngine::export_terms`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermExportFormat {
+    /// `term<TAB>doc_frequency`, with tabs, newlines and backslashes in
+    /// terms escaped as `\t`, `\n` and `\\`
+    Tsv,
+    /// `{"term": ..., "doc_frequency": ...}`
+    Jsonl,
+}
+
+/// Scheme for generated document ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdGeneration {
+    /// Increasing numbers ("1", "2", ...) from a counter kept in storage,
+    /// so ids sort by insertion order
+    #[default]
+    Sequential,
+    /// Random UUID-formatted ids, which reveal nothing about insertion order
+    Random,
+}
+
+/// What a batch insert does when one of its documents can't be indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchErrorPolicy {
+    /// Fail the whole call at the first bad document. Documents before it
+    /// may already be applied but aren't committed.
+    #[default]
+    Abort,
+    /// Index the other documents and report the failures
+    Skip,
+}
+
+impl BatchErrorPolicy {
+    /// Parse a policy name as used in the API: `abort` or `skip`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "abort" => Some(BatchErrorPolicy::Abort),
+            "skip" => Some(BatchErrorPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of [`SearchEngine::batch_insert_with`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchReport {
+    /// Documents applied, including any ignored by
+    /// [`EmptyDocumentPolicy::Skip`]
+    pub indexed: usize,
+    pub failures: Vec<DocumentFailure>,
+}
+
+/// A document of a batch that couldn't be indexed
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentFailure {
+    /// Position of the document in the batch
+    pub position: usize,
+    pub id: String,
+    pub error: String,
+}
+
+/// What to do with documents that have no indexable text
+///
+/// A document whose title and content analyze to no tokens (empty, or only
+/// stopwords and punctuation) can't be found by text queries and pulls the
+/// average document length used by BM25 towards zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyDocumentPolicy {
+    /// Store and index the document anyway; it is reachable by id and by
+    /// keyword fields
+    #[default]
+    Index,
+    /// Ignore the write, leaving any existing version of the document as is
+    Skip,
+    /// Fail the write with an error
+    Reject,
+}
+
+/// Handling of search queries with more terms than
+/// [`EngineConfig::max_query_terms`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongQueryPolicy {
+    /// Fail the search with [`QueryTooLong`]
+    #[default]
+    Reject,
+    /// Search with the first `max_query_terms` terms, dropping the rest
+    Truncate,
+}
+
+/// Error of a search whose query has more terms than
+/// [`EngineConfig::max_query_terms`], with [`LongQueryPolicy::Reject`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTooLong {
+    /// Terms in the analyzed query
+    pub terms: usize,
+    pub max_terms: usize,
+}
+
+impl std::fmt::Display for QueryTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The query has {} terms, more than the maximum of {}",
+            self.terms, self.max_terms
+        )
+    }
+}
+
+impl std::error::Error for QueryTooLong {}
+
+/// Error of a search paging past [`EngineConfig::max_result_window`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultWindowTooLarge {
+    /// `offset + limit` of the search
+    pub window: usize,
+    pub max_result_window: usize,
+}
+
+impl std::fmt::Display for ResultWindowTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Result window is too large: offset + limit is {}, more than the maximum of {}; \
+             refine the query or filter instead of paging this deep",
+            self.window, self.max_result_window
+        )
+    }
+}
+
+impl std::error::Error for ResultWindowTooLarge {}
+
+/// Writes made while a reindex is building its new index, replayed onto it
+/// before the swap
+#[derive(Default)]
+struct RebuildLog {
+    touched: HashSet<String>,
+    cleared: bool,
 }
 
 /// Main search engine
+///
+/// Every write is applied to sled immediately, but sled buffers writes and
+/// only guarantees they are on disk after a flush. With the default
+/// [`Durability::Sync`] each write operation flushes before returning; with
+/// the other levels call [`flush`](Self::flush) or [`close`](Self::close) to
+/// make recent writes durable. With `flush_on_drop` enabled (the default) the
+/// engine also flushes when dropped; errors during that flush can't be
+/// returned and are logged instead.
 pub struct SearchEngine {
+    config: EngineConfig,
     storage: Storage,
     index: Arc<RwLock<InvertedIndex>>,
     doc_stats: Arc<RwLock<HashMap<String, DocStats>>>,
     tokenizer: Tokenizer,
     avg_doc_length: Arc<RwLock<f64>>,
+    /// Sum of the lengths in `doc_stats`, kept up to date on every write so
+    /// the average needn't be recomputed from scratch
+    total_doc_length: Arc<RwLock<usize>>,
+    tombstones: Arc<RwLock<HashSet<String>>>,
+    pruned_terms: Arc<RwLock<HashSet<String>>>,
+    /// At least the largest document boost, for bounding scores in top-k
+    /// retrieval; not lowered on delete
+    max_boost: Arc<RwLock<f64>>,
+    /// At least the largest document authority, like `max_boost`
+    max_authority: Arc<RwLock<f64>>,
+    /// Held shared by every index write and exclusively by the reindex swap,
+    /// so the swap sees no write half-applied
+    write_gate: RwLock<()>,
+    /// Set while a reindex is running
+    rebuild_log: Mutex<Option<RebuildLog>>,
+    /// Held while a snapshot of the index is taken and saved, so an older
+    /// snapshot never overwrites a newer one
+    index_save: Mutex<()>,
+    /// Set with [`EngineConfig::write_ahead_log`]. Writes append to it under
+    /// `write_gate`, so a flush holding the gate exclusively knows every
+    /// logged write is in storage.
+    wal: Option<Wal>,
+    /// Set with [`EngineConfig::search_cache_ttl`]
+    search_cache: Option<TtlCache<SearchResult>>,
 }
 
 impl SearchEngine {
     /// Create a new search engine with storage path
     pub fn new(storage_path: &str) -> Result<Self> {
-        let storage = Storage::open(storage_path)?;
-        let tokenizer = Tokenizer::new();
+        Self::with_config(storage_path, EngineConfig::default())
+    }
+
+    /// Create a new search engine with storage path and configuration
+    pub fn with_config(storage_path: &str, config: EngineConfig) -> Result<Self> {
+        // Opening would otherwise create an empty index
+        anyhow::ensure!(
+            !config.read_only || std::path::Path::new(storage_path).is_dir(),
+            "Index directory {} does not exist",
+            storage_path
+        );
+        let storage = Storage::open_with_durability(storage_path, config.durability)?;
+        let mut engine = Self::with_storage(storage, config)?;
+
+        let wal_path = std::path::Path::new(storage_path).join(WAL_FILE);
+        if engine.config.write_ahead_log && !engine.config.read_only {
+            engine.recover(&wal_path)?;
+            engine.wal = Some(Wal::open(&wal_path)?);
+        } else if wal_path.exists() && !Wal::read(&wal_path)?.is_empty() {
+            tracing::warn!(
+                "{} holds writes that may be missing from the index; open it with the write-ahead log enabled \
+                 and writable to replay them",
+                wal_path.display()
+            );
+        }
+        Ok(engine)
+    }
+
+    /// Create a search engine over an open storage, loading the index it
+    /// holds
+    ///
+    /// Engines over [namespaced](Storage::with_namespace) clones of one
+    /// storage share its database but not their data. The storage's
+    /// durability is kept, and the write-ahead log isn't used.
+    pub fn with_storage(storage: Storage, config: EngineConfig) -> Result<Self> {
+        let storage = storage
+            .with_compression(config.compression)
+            .with_retry(config.storage_retry)
+            .with_segment_bytes(config.index_segment_bytes);
+        let tokenizer = Tokenizer::with_config(config.tokenizer.clone());
 
         // Load or create index
         let index = storage.load_index()?.unwrap_or_else(InvertedIndex::new);
 
         // Load document statistics
-        let stats_vec = storage.get_all_doc_stats()?;
+        let (stats_vec, outdated_stats) = storage.get_all_doc_stats()?;
         let doc_stats: HashMap<String, DocStats> =
             stats_vec.into_iter().map(|s| (s.id.clone(), s)).collect();
 
         // Calculate average document length
-        let avg_doc_length = if doc_stats.is_empty() {
-            0.0
-        } else {
-            doc_stats.values().map(|s| s.length).sum::<usize>() as f64 / doc_stats.len() as f64
+        let total_doc_length: usize = doc_stats.values().map(|s| s.length).sum();
+        let avg_doc_length = average_length(total_doc_length, doc_stats.len());
+
+        let max_boost = max_boost(doc_stats.values());
+        let max_authority = max_authority(doc_stats.values());
+
+        // Load soft-deleted document IDs
+        let tombstones: HashSet<String> = storage.get_all_tombstones()?.into_iter().collect();
+
+        if !config.read_only {
+            storage.ensure_title_index()?;
+        }
+
+        let pruned_terms: HashSet<String> = match storage.get_metadata(PRUNED_TERMS_KEY)? {
+            Some(json) => serde_json::from_str(&json)?,
+            None => HashSet::new(),
         };
+        let search_cache = search_cache(&config);
 
-        Ok(Self {
+        let engine = Self {
+            config,
             storage,
             index: Arc::new(RwLock::new(index)),
             doc_stats: Arc::new(RwLock::new(doc_stats)),
             tokenizer,
             avg_doc_length: Arc::new(RwLock::new(avg_doc_length)),
-        })
+            total_doc_length: Arc::new(RwLock::new(total_doc_length)),
+            tombstones: Arc::new(RwLock::new(tombstones)),
+            pruned_terms: Arc::new(RwLock::new(pruned_terms)),
+            max_boost: Arc::new(RwLock::new(max_boost)),
+            max_authority: Arc::new(RwLock::new(max_authority)),
+            write_gate: RwLock::new(()),
+            rebuild_log: Mutex::new(None),
+            index_save: Mutex::new(()),
+            wal: None,
+            search_cache,
+        };
+        engine.rebuild_doc_stats(&outdated_stats)?;
+        Ok(engine)
+    }
+
+    /// Recompute statistics stored in an older layout from their documents
+    ///
+    /// Statistics gain fields as ranking does, and older ones can't be
+    /// decoded; they are rebuilt once, on open, and saved in the current
+    /// layout unless the engine is read-only.
+    fn rebuild_doc_stats(&self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        tracing::info!(
+            documents = ids.len(),
+            "Rebuilding document statistics stored in an older format"
+        );
+
+        for id in ids {
+            let Some(doc) = self.storage.get_document(id)? else {
+                // Left behind by a deleted document
+                if !self.config.read_only {
+                    self.storage.delete_doc_stats(id)?;
+                }
+                continue;
+            };
+            let (_, doc_stats) = self.analyze_document(&doc);
+            if !self.config.read_only {
+                self.storage.save_doc_stats(&doc_stats)?;
+            }
+            self.doc_stats
+                .write()
+                .unwrap()
+                .insert(id.clone(), doc_stats);
+        }
+
+        let stats_map = self.doc_stats.read().unwrap();
+        let total: usize = stats_map.values().map(|s| s.length).sum();
+        *self.total_doc_length.write().unwrap() = total;
+        *self.avg_doc_length.write().unwrap() = average_length(total, stats_map.len());
+        *self.max_boost.write().unwrap() = max_boost(stats_map.values());
+        *self.max_authority.write().unwrap() = max_authority(stats_map.values());
+        drop(stats_map);
+
+        if self.config.read_only {
+            return Ok(());
+        }
+        self.storage.flush()
+    }
+
+    /// Replay the writes in the write-ahead log, left by a crash, and flush
+    /// them so the log can be emptied
+    ///
+    /// Upserts and deletes are idempotent, so writes that did reach storage
+    /// before the crash are harmlessly applied again.
+    fn recover(&self, wal_path: &std::path::Path) -> Result<()> {
+        let ops = Wal::read(wal_path)?;
+        if !ops.is_empty() {
+            tracing::info!(writes = ops.len(), "Replaying the write-ahead log");
+            for op in ops {
+                match op {
+                    WalOp::Upsert(doc) => self.store_document(*doc)?,
+                    WalOp::Delete(id) => {
+                        self.remove_document(&id)?;
+                    }
+                    WalOp::Clear => self.clear()?,
+                }
+            }
+            self.flush()?;
+        }
+        if wal_path.exists() {
+            Wal::open(wal_path)?.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Open an existing index for queries only; see [`EngineConfig::read_only`]
+    pub fn open_read_only(storage_path: &str) -> Result<Self> {
+        Self::with_config(
+            storage_path,
+            EngineConfig {
+                read_only: true,
+                ..Default::default()
+            },
+        )
     }
 
     /// Create an in-memory search engine (for testing)
     pub fn in_memory() -> Result<Self> {
-        let storage = Storage::in_memory()?;
-        let tokenizer = Tokenizer::new();
+        Self::in_memory_with_config(EngineConfig::default())
+    }
+
+    /// Create an in-memory search engine with configuration
+    pub fn in_memory_with_config(config: EngineConfig) -> Result<Self> {
+        let storage = Storage::in_memory()?
+            .with_compression(config.compression)
+            .with_retry(config.storage_retry)
+            .with_segment_bytes(config.index_segment_bytes);
+        let tokenizer = Tokenizer::with_config(config.tokenizer.clone());
+        let search_cache = search_cache(&config);
 
         Ok(Self {
+            config,
             storage,
             index: Arc::new(RwLock::new(InvertedIndex::new())),
             doc_stats: Arc::new(RwLock::new(HashMap::new())),
             tokenizer,
             avg_doc_length: Arc::new(RwLock::new(0.0)),
+            total_doc_length: Arc::new(RwLock::new(0)),
+            tombstones: Arc::new(RwLock::new(HashSet::new())),
+            pruned_terms: Arc::new(RwLock::new(HashSet::new())),
+            max_boost: Arc::new(RwLock::new(0.0)),
+            max_authority: Arc::new(RwLock::new(0.0)),
+            write_gate: RwLock::new(()),
+            rebuild_log: Mutex::new(None),
+            index_save: Mutex::new(()),
+            wal: None,
+            search_cache,
         })
     }
 
     /// Insert or update a document
+    ///
+    /// Upserting a soft-deleted document restores it.
     pub fn upsert_document(&self, doc: Document) -> Result<()> {
+        let _span = tracing::info_span!("upsert_document", doc_id = %doc.id).entered();
+        self.ensure_writable()?;
+        self.index_document(doc)?;
+        self.commit()
+    }
+
+    /// Make a new id not used by any stored document
+    pub fn generate_id(&self) -> Result<String> {
+        self.ensure_writable()?;
+        loop {
+            let id = match self.config.id_generation {
+                IdGeneration::Sequential => (self.storage.next_sequence()? + 1).to_string(),
+                IdGeneration::Random => random_uuid(),
+            };
+            if self.storage.get_document(&id)?.is_none() {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Store a document under a newly generated id, ignoring `doc.id`, and
+    /// return the id
+    pub fn upsert_document_auto_id(&self, mut doc: Document) -> Result<String> {
+        doc.id = self.generate_id()?;
+        let id = doc.id.clone();
+        self.upsert_document(doc)?;
+        Ok(id)
+    }
+
+    /// Finish a write operation: make its log records durable, then commit
+    /// storage, flushing everything if the log has grown large
+    fn commit(&self) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.sync()?;
+        }
+        self.storage.commit()?;
+        if self
+            .wal
+            .as_ref()
+            .is_some_and(|wal| wal.len() >= WAL_CHECKPOINT_BYTES)
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Append a write to the write-ahead log, if there is one
+    fn log_write(&self, op: impl FnOnce() -> WalOp) -> Result<()> {
+        match &self.wal {
+            Some(wal) => wal.append(&op()),
+            None => Ok(()),
+        }
+    }
+
+    fn index_document(&self, mut doc: Document) -> Result<()> {
+        anyhow::ensure!(
+            doc.boost.is_finite() && doc.boost >= 0.0,
+            "Invalid boost {} for document '{}'",
+            doc.boost,
+            doc.id
+        );
+        anyhow::ensure!(
+            doc.analyzer_language().is_some(),
+            "Unsupported language '{}' for document '{}'",
+            doc.language.as_deref().unwrap_or_default(),
+            doc.id
+        );
+
+        doc.updated_at = now_millis();
+        doc.content_hash = doc.digest();
+        self.store_document(doc)
+    }
+
+    /// Index and store a document whose `updated_at` and `content_hash` are
+    /// already set
+    fn store_document(&self, doc: Document) -> Result<()> {
         let doc_id = doc.id.clone();
-        let searchable_text = doc.searchable_text();
+        let (mut tokens, doc_stats) = self.analyze_document(&doc);
 
-        // Tokenize and analyze
-        let tokens = self.tokenizer.analyze(&searchable_text);
-        let term_frequencies = self.tokenizer.analyze_with_frequencies(&searchable_text);
+        if doc_stats.length == 0 {
+            match self.config.empty_documents {
+                EmptyDocumentPolicy::Index => {}
+                EmptyDocumentPolicy::Skip => {
+                    tracing::debug!("Skipping document '{}' with no indexable text", doc_id);
+                    return Ok(());
+                }
+                EmptyDocumentPolicy::Reject => {
+                    anyhow::bail!("Document '{}' has no indexable text", doc_id)
+                }
+            }
+        }
 
-        // Create document statistics
-        let doc_stats = DocStats {
-            id: doc_id.clone(),
-            length: tokens.len(),
-            term_frequencies,
-        };
+        let _gate = self.write_gate.read().unwrap();
+        self.note_write(&doc_id);
+        self.log_write(|| WalOp::Upsert(Box::new(doc.clone())))?;
 
         // Update index
         {
             let mut index = self.index.write().unwrap();
+            let mut pruned_terms = self.pruned_terms.write().unwrap();
+
+            tokens.retain(|token| !pruned_terms.contains(token));
             index.update_document(&doc_id, &tokens);
 
-            // Persist index
-            self.storage.save_index(&*index)?;
+            if let Some(max_postings) = self.config.max_postings {
+                let over_limit: Vec<String> = tokens
+                    .iter()
+                    .filter(|token| index.doc_frequency(token) > max_postings)
+                    .cloned()
+                    .collect();
+                self.prune_terms(&mut index, &mut pruned_terms, over_limit)?;
+            }
+
+            drop(pruned_terms);
+            self.persist_index(index)?;
         }
 
         // Update document statistics
         {
             let mut stats_map = self.doc_stats.write().unwrap();
-            stats_map.insert(doc_id.clone(), doc_stats.clone());
+            let replaced = stats_map.insert(doc_id.clone(), doc_stats.clone());
 
-            // Recalculate average document length
-            let avg = if stats_map.is_empty() {
-                0.0
-            } else {
-                stats_map.values().map(|s| s.length).sum::<usize>() as f64 / stats_map.len() as f64
-            };
-            *self.avg_doc_length.write().unwrap() = avg;
+            let mut max_boost = self.max_boost.write().unwrap();
+            *max_boost = max_boost.max(doc_stats.boost);
+            if let Some(authority) = doc_stats.authority {
+                let mut max_authority = self.max_authority.write().unwrap();
+                *max_authority = max_authority.max(authority);
+            }
+
+            let mut total = self.total_doc_length.write().unwrap();
+            *total = *total - replaced.map_or(0, |s| s.length) + doc_stats.length;
+            *self.avg_doc_length.write().unwrap() = average_length(*total, stats_map.len());
         }
 
         // Save to storage
         self.storage.save_document(&doc)?;
         self.storage.save_doc_stats(&doc_stats)?;
 
-        Ok(())
-    }
-
-    /// Batch insert documents (more efficient)
-    pub fn batch_insert(&self, docs: Vec<Document>) -> Result<()> {
-        for doc in docs {
-            self.upsert_document(doc)?;
+        if self.tombstones.write().unwrap().remove(&doc_id) {
+            self.storage.remove_tombstone(&doc_id)?;
         }
-        self.storage.flush()?;
+
         Ok(())
     }
 
-    /// Delete a document
-    pub fn delete_document(&self, doc_id: &str) -> Result<()> {
-        // Remove from index
-        {
-            let mut index = self.index.write().unwrap();
-            index.remove_document(doc_id);
-            self.storage.save_index(&*index)?;
+    /// Index tokens and statistics of a document, before pruning
+    fn analyze_document(&self, doc: &Document) -> (Vec<String>, DocStats) {
+        let fields = self.indexed_text(doc);
+        let title = fields[0];
+        let searchable_text = fields.join(" ");
+
+        // Tokenize and analyze
+        let analyzer = AnalyzerOptions::for_language(doc.analyzer_language().unwrap_or_default());
+        let pairs = self
+            .tokenizer
+            .analyze_with_surface_forms(&searchable_text, &analyzer);
+        let unstemmed = self.tokenizer.unstemmed_terms(&pairs, &analyzer);
+        let (mut tokens, surface_forms): (Vec<String>, HashSet<String>) = pairs.into_iter().unzip();
+        let mut term_frequencies = self
+            .tokenizer
+            .analyze_with_frequencies(&searchable_text, &analyzer);
+        let mut title_term_frequencies = self.tokenizer.analyze_with_frequencies(title, &analyzer);
+        let length = tokens.len();
+
+        // Unstemmed forms are extra terms, not extra words of the text
+        if !unstemmed.is_empty() {
+            let title_pairs = self.tokenizer.analyze_with_surface_forms(title, &analyzer);
+            for term in self.tokenizer.unstemmed_terms(&title_pairs, &analyzer) {
+                *title_term_frequencies.entry(term).or_insert(0) += 1;
+            }
+            for term in unstemmed {
+                *term_frequencies.entry(term.clone()).or_insert(0) += 1;
+                tokens.push(term);
+            }
         }
 
-        // Remove from statistics
-        {
-            let mut stats_map = self.doc_stats.write().unwrap();
-            stats_map.remove(doc_id);
+        // Positions are counted per field, with a gap between fields
+        let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut surface_positions: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut section_starts = Vec::new();
+        let mut title_length = 0;
+        let mut content_positions = 0..0;
+        let mut start = 0;
+        for (i, field) in fields.iter().enumerate() {
+            // Fields alternate heading and text from the first section on
+            if let Some(0) = i.checked_sub(doc.first_section_field()).map(|n| n % 2) {
+                section_starts.push(start);
+            }
+            let field_tokens = self.tokenizer.analyze_with_surface_forms(field, &analyzer);
+            match i {
+                0 => title_length = field_tokens.len(),
+                1 => content_positions = start..start + field_tokens.len(),
+                _ => {}
+            }
+            for (offset, (token, surface)) in field_tokens.iter().enumerate() {
+                positions
+                    .entry(token.clone())
+                    .or_default()
+                    .push(start + offset);
+                if self.config.exact_phrases {
+                    surface_positions
+                        .entry(surface.clone())
+                        .or_default()
+                        .push(start + offset);
+                }
+            }
+            start += field_tokens.len() + self.config.position_increment_gap;
+        }
 
-            // Recalculate average document length
-            let avg = if stats_map.is_empty() {
-                0.0
-            } else {
-                stats_map.values().map(|s| s.length).sum::<usize>() as f64 / stats_map.len() as f64
-            };
-            *self.avg_doc_length.write().unwrap() = avg;
+        // Keyword fields are indexed but don't count towards the text length
+        for field in &self.config.keyword_fields {
+            if let Some(value) = doc.metadata.get(field) {
+                let keyword = self.tokenizer.analyze_keyword(field, value);
+                term_frequencies.insert(keyword.clone(), 1);
+                tokens.push(keyword);
+            }
         }
 
-        // Remove from storage
-        self.storage.delete_document(doc_id)?;
-        self.storage.delete_doc_stats(doc_id)?;
+        // Create document statistics
+        let doc_stats = DocStats {
+            id: doc.id.clone(),
+            length,
+            term_frequencies,
+            title_term_frequencies,
+            positions,
+            surface_forms,
+            surface_positions,
+            boost: doc.boost,
+            updated_at: doc.updated_at,
+            title_length,
+            authority: self.document_authority(doc),
+            content_positions,
+            section_starts,
+            metadata: doc.metadata.clone(),
+        };
 
-        Ok(())
+        (tokens, doc_stats)
     }
 
-    /// Get a document by ID
-    pub fn get_document(&self, doc_id: &str) -> Result<Option<Document>> {
-        self.storage.get_document(doc_id)
+    /// The text fields to index, title first, as far as they are indexed;
+    /// see [`EngineConfig::title_only`] and
+    /// [`EngineConfig::max_document_tokens`]
+    fn indexed_text<'a>(&self, doc: &'a Document) -> Vec<&'a str> {
+        let fields = doc.text_fields().take(if self.config.title_only {
+            1
+        } else {
+            usize::MAX
+        });
+        let Some(max_tokens) = self.config.max_document_tokens else {
+            return fields.collect();
+        };
+
+        let mut remaining = max_tokens;
+        let mut truncated = false;
+        let fields = fields
+            .map(|field| {
+                let (kept, words) = self.tokenizer.take_words(field, remaining);
+                remaining -= words;
+                truncated |= kept.len() < field.len();
+                kept
+            })
+            .collect();
+        if truncated {
+            tracing::warn!(doc_id = %doc.id, max_tokens, "Indexing only the start of a long document");
+        }
+        fields
     }
 
-    /// Search for documents
-    pub fn search(&self, query: &str, options: &SearchOptions) -> Result<SearchResult> {
-        // Tokenize query
-        let query_tokens = self.tokenizer.analyze(query);
+    /// The document's value of [`EngineConfig::authority_field`], if it is a
+    /// finite, non-negative number
+    fn document_authority(&self, doc: &Document) -> Option<f64> {
+        let field = self.config.authority_field.as_ref()?;
+        let authority: f64 = doc.metadata.get(field)?.trim().parse().ok()?;
+        (authority.is_finite() && authority >= 0.0).then_some(authority)
+    }
 
-        if query_tokens.is_empty() {
-            return Ok(SearchResult {
-                documents: Vec::new(),
-                total: 0,
-                scores: None,
-            });
+    /// Save the index after a write, releasing the write lock
+    ///
+    /// Serializing a large index takes a while. With
+    /// [`EngineConfig::snapshot_index_saves`] the lock is released first and
+    /// a copy is saved instead; the copy is taken under `index_save`, so it
+    /// includes this write and saves land in order.
+    fn persist_index(&self, index: std::sync::RwLockWriteGuard<'_, InvertedIndex>) -> Result<()> {
+        if !self.config.snapshot_index_saves {
+            return self.storage.save_index(&index);
         }
+        drop(index);
 
-        // Find matching documents
-        let candidate_ids = {
-            let index = self.index.read().unwrap();
-            match options.mode {
-                SearchMode::And => index.search_and(&query_tokens),
-                SearchMode::Or => index.search_or(&query_tokens),
-            }
-        };
-
-        let total = candidate_ids.len();
+        let _save = self.index_save.lock().unwrap();
+        let snapshot = self.index.read().unwrap().clone();
+        self.storage.save_index(&snapshot)
+    }
 
-        // Rank documents if requested
-        let (sorted_ids, scores) = if options.use_ranking {
-            let index = self.index.read().unwrap();
-            let stats_map = self.doc_stats.read().unwrap();
-            let avg_length = *self.avg_doc_length.read().unwrap();
+    /// Drop terms from the index and remember them as pruned
+    fn prune_terms(
+        &self,
+        index: &mut InvertedIndex,
+        pruned_terms: &mut HashSet<String>,
+        terms: Vec<String>,
+    ) -> Result<()> {
+        if terms.is_empty() {
+            return Ok(());
+        }
+        for term in &terms {
+            index.remove_token(term);
+        }
+        pruned_terms.extend(terms);
+        self.storage
+            .save_metadata(PRUNED_TERMS_KEY, &serde_json::to_string(&*pruned_terms)?)
+    }
 
-            let scored_docs = rank_documents(&query_tokens, &candidate_ids, &stats_map, &*index, avg_length);
+    /// Remove terms found in fewer than `min_df` documents from the index
+    ///
+    /// Terms that occur once or twice (typos, OCR noise) make up much of a
+    /// vocabulary but rarely matter to queries. Keyword field terms are
+    /// kept. Unlike `max_postings`, removed terms aren't remembered:
+    /// documents indexed later add them back, as does a reindex. Returns the
+    /// number of terms removed.
+    pub fn prune_rare_terms(&self, min_df: usize) -> Result<usize> {
+        self.ensure_writable()?;
+        let _gate = self.write_gate.read().unwrap();
 
-            let ids: Vec<String> = scored_docs.iter().map(|sd| sd.doc_id.clone()).collect();
-            let scores: Vec<f64> = scored_docs.iter().map(|sd| sd.score).collect();
+        let mut index = self.index.write().unwrap();
+        let rare: Vec<String> = index
+            .all_tokens()
+            .into_iter()
+            .filter(|term| index.doc_frequency(term) < min_df && !self.is_keyword_term(term))
+            .cloned()
+            .collect();
+        for term in &rare {
+            index.remove_token(term);
+        }
+        self.persist_index(index)?;
+        self.storage.commit()?;
 
-            (ids, Some(scores))
-        } else {
-            (candidate_ids, None)
-        };
+        Ok(rare.len())
+    }
 
-        // Apply pagination
-        let start = options.offset;
-        let end = if let Some(limit) = options.limit {
-            (start + limit).min(sorted_ids.len())
+    /// Whether a term was produced by a keyword field
+    fn is_keyword_term(&self, term: &str) -> bool {
+        self.config.keyword_fields.iter().any(|field| {
+            term.strip_prefix(field.as_str())
+                .is_some_and(|rest| rest.starts_with(':'))
+        })
+    }
+
+    /// Record a write to replay onto an index being rebuilt
+    fn note_write(&self, doc_id: &str) {
+        if let Some(log) = self.rebuild_log.lock().unwrap().as_mut() {
+            log.touched.insert(doc_id.to_string());
+        }
+    }
+
+    /// Batch insert documents (more efficient)
+    pub fn batch_insert(&self, docs: Vec<Document>) -> Result<()> {
+        self.batch_insert_with(docs, BatchErrorPolicy::Abort)
+            .map(|_| ())
+    }
+
+    /// Batch insert documents, handling documents that fail as the policy
+    /// says
+    ///
+    /// With [`BatchErrorPolicy::Skip`] the call fails only if the batch as
+    /// a whole can't be written, such as on a read-only engine or a failed
+    /// commit.
+    pub fn batch_insert_with(
+        &self,
+        docs: Vec<Document>,
+        on_error: BatchErrorPolicy,
+    ) -> Result<BatchReport> {
+        let _span = tracing::info_span!("batch_insert", documents = docs.len()).entered();
+        self.ensure_writable()?;
+        let mut report = BatchReport::default();
+        for (position, doc) in docs.into_iter().enumerate() {
+            let id = doc.id.clone();
+            match self.index_document(doc) {
+                Ok(()) => report.indexed += 1,
+                Err(e) if on_error == BatchErrorPolicy::Skip => {
+                    let error = format!("{:#}", e);
+                    tracing::warn!(position, id = %id, error = %error, "Skipping a document of a batch");
+                    report.failures.push(DocumentFailure {
+                        position,
+                        id,
+                        error,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.commit()?;
+        Ok(report)
+    }
+
+    /// Analyze documents as an import would, without writing anything
+    pub fn validate_documents(&self, docs: &[Document]) -> ImportSummary {
+        ImportSummary::from_documents(docs, &self.tokenizer)
+    }
+
+    /// Delete a document
+    ///
+    /// Returns `false`, changing nothing, if there is no such document.
+    pub fn delete_document(&self, doc_id: &str) -> Result<bool> {
+        let _span = tracing::info_span!("delete_document", doc_id).entered();
+        self.ensure_writable()?;
+        let removed = self.remove_document(doc_id)?;
+        if removed {
+            self.commit()?;
+        }
+        Ok(removed)
+    }
+
+    fn remove_document(&self, doc_id: &str) -> Result<bool> {
+        let _gate = self.write_gate.read().unwrap();
+        let indexed = self.doc_stats.read().unwrap().contains_key(doc_id);
+        if !indexed && self.storage.get_document(doc_id)?.is_none() {
+            return Ok(false);
+        }
+        self.note_write(doc_id);
+        self.log_write(|| WalOp::Delete(doc_id.to_string()))?;
+
+        // Remove from index
+        {
+            let mut index = self.index.write().unwrap();
+            index.remove_document(doc_id);
+            self.persist_index(index)?;
+        }
+
+        // Remove from statistics
+        {
+            let mut stats_map = self.doc_stats.write().unwrap();
+            if let Some(removed) = stats_map.remove(doc_id) {
+                let mut total = self.total_doc_length.write().unwrap();
+                *total -= removed.length;
+                *self.avg_doc_length.write().unwrap() = average_length(*total, stats_map.len());
+            }
+        }
+
+        // Remove from storage
+        self.storage.delete_document(doc_id)?;
+        self.storage.delete_doc_stats(doc_id)?;
+
+        if self.tombstones.write().unwrap().remove(doc_id) {
+            self.storage.remove_tombstone(doc_id)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Soft-delete a document
+    ///
+    /// The document stays in storage and in the index but is excluded from
+    /// search results unless `include_deleted` is set. Returns `false` if the
+    /// document doesn't exist or is already soft-deleted.
+    pub fn soft_delete(&self, doc_id: &str) -> Result<bool> {
+        self.ensure_writable()?;
+        if self.storage.get_document(doc_id)?.is_none() {
+            return Ok(false);
+        }
+
+        if !self.tombstones.write().unwrap().insert(doc_id.to_string()) {
+            return Ok(false);
+        }
+        self.storage.add_tombstone(doc_id)?;
+        self.storage.commit()?;
+
+        Ok(true)
+    }
+
+    /// Restore a soft-deleted document
+    ///
+    /// Returns `false` if the document wasn't soft-deleted.
+    pub fn restore(&self, doc_id: &str) -> Result<bool> {
+        self.ensure_writable()?;
+        if !self.tombstones.write().unwrap().remove(doc_id) {
+            return Ok(false);
+        }
+        self.storage.remove_tombstone(doc_id)?;
+        self.storage.commit()?;
+
+        Ok(true)
+    }
+
+    /// Check whether a document is soft-deleted
+    pub fn is_deleted(&self, doc_id: &str) -> bool {
+        self.tombstones.read().unwrap().contains(doc_id)
+    }
+
+    /// Permanently remove all soft-deleted documents
+    ///
+    /// Returns the number of documents purged.
+    pub fn purge(&self) -> Result<usize> {
+        self.ensure_writable()?;
+        let ids: Vec<String> = self.tombstones.read().unwrap().iter().cloned().collect();
+
+        for id in &ids {
+            self.remove_document(id)?;
+        }
+        self.commit()?;
+
+        Ok(ids.len())
+    }
+
+    /// Rebuild the index and document statistics from stored documents
+    ///
+    /// The new index is built off to the side while searches keep using the
+    /// current one, then swapped in under a brief lock. Writes made during
+    /// the build are replayed onto the new index before the swap. Useful
+    /// after changing analysis settings. Returns the number of documents
+    /// indexed.
+    pub fn reindex(&self) -> Result<usize> {
+        self.ensure_writable()?;
+        {
+            let mut log = self.rebuild_log.lock().unwrap();
+            anyhow::ensure!(log.is_none(), "A reindex is already running");
+            *log = Some(RebuildLog::default());
+        }
+
+        let result = self.build_and_swap_index();
+        *self.rebuild_log.lock().unwrap() = None;
+        result
+    }
+
+    /// Whether a search can rank with [`rank_top_k`] instead of scoring
+    /// every candidate
+    fn can_prune(options: &SearchOptions) -> bool {
+        options.top_k_only
+            && matches!(options.mode, SearchMode::Or)
+            && options.use_ranking
+            && options.geo.is_none()
+            && options.group_by.is_none()
+            && options.related_terms.is_none()
+            && options.max_candidates.is_none()
+            && options.diversity.is_none()
+            && options.sort.is_empty()
+    }
+
+    /// Rank the best `k` matches of an OR query
+    fn rank_top_k(
+        &self,
+        analyzed: &AnalyzedQuery,
+        query: &ScoringQuery,
+        k: usize,
+        options: &SearchOptions,
+        deadline: Option<Instant>,
+    ) -> RankedMatches {
+        let index = self.index.read().unwrap();
+        let stats_map = self.doc_stats.read().unwrap();
+        let tombstones = self.tombstones.read().unwrap();
+        let avg_length = *self.avg_doc_length.read().unwrap();
+        let top_k = TopK {
+            k,
+            max_boost: *self.max_boost.read().unwrap(),
+            max_authority: *self.max_authority.read().unwrap(),
+            deadline,
+        };
+        let min_tfs = self.analyze_min_term_frequencies(options);
+
+        let accept = |id: &str| {
+            let Some(stats) = stats_map.get(id) else {
+                return false;
+            };
+            let has = |token: &String| stats.term_frequencies.contains_key(token);
+            (analyzed.optional.is_empty() || analyzed.optional.iter().any(has))
+                && analyzed.required.iter().all(has)
+                && !analyzed.excluded.iter().any(has)
+                && analyzed.matches_constraints(stats, options.mode, self.config.exact_phrases)
+                && (options.include_deleted || !tombstones.contains(id))
+                && options
+                    .modified_since
+                    .is_none_or(|since| stats.updated_at >= since)
+                && meets_min_term_frequencies(stats, &min_tfs)
+        };
+
+        let (scored_docs, skipped, timed_out) = rank_top_k(
+            query,
+            top_k,
+            &index,
+            &stats_map,
+            avg_length,
+            &options.ranking,
+            accept,
+        );
+        let (ids, scores): (Vec<String>, Vec<f64>) = scored_docs
+            .into_iter()
+            .map(|sd| (sd.doc_id, sd.score))
+            .unzip();
+        RankedMatches {
+            total: ids.len(),
+            ids,
+            scores: Some(scores),
+            candidates_truncated: skipped,
+            timed_out,
+        }
+    }
+
+    fn build_and_swap_index(&self) -> Result<usize> {
+        let mut pruned_terms = self.pruned_terms.read().unwrap().clone();
+        let mut index = InvertedIndex::new();
+        let mut stats_map = HashMap::new();
+
+        for doc in self.storage.iter_documents()? {
+            let doc = doc?;
+            let (mut tokens, doc_stats) = self.analyze_document(&doc);
+            tokens.retain(|token| !pruned_terms.contains(token));
+            index.add_document(&doc.id, &tokens);
+            self.storage.save_doc_stats(&doc_stats)?;
+            stats_map.insert(doc.id, doc_stats);
+        }
+
+        // Wait for in-flight writes, then replay those made during the build
+        let _gate = self.write_gate.write().unwrap();
+        let log = self.rebuild_log.lock().unwrap().take().unwrap_or_default();
+        anyhow::ensure!(!log.cleared, "The index was cleared during the reindex");
+
+        for id in &log.touched {
+            index.remove_document(id);
+            stats_map.remove(id);
+            match self.storage.get_document(id)? {
+                Some(doc) => {
+                    let (mut tokens, doc_stats) = self.analyze_document(&doc);
+                    tokens.retain(|token| !pruned_terms.contains(token));
+                    index.add_document(id, &tokens);
+                    self.storage.save_doc_stats(&doc_stats)?;
+                    stats_map.insert(id.clone(), doc_stats);
+                }
+                None => self.storage.delete_doc_stats(id)?,
+            }
+        }
+
+        if let Some(max_postings) = self.config.max_postings {
+            let over_limit: Vec<String> = index
+                .all_tokens()
+                .into_iter()
+                .filter(|token| index.doc_frequency(token) > max_postings)
+                .cloned()
+                .collect();
+            self.prune_terms(&mut index, &mut pruned_terms, over_limit)?;
+        }
+
+        let count = stats_map.len();
+        let total_length: usize = stats_map.values().map(|s| s.length).sum();
+
+        self.storage.save_index(&index)?;
+        *self.index.write().unwrap() = index;
+        *self.pruned_terms.write().unwrap() = pruned_terms;
+        *self.doc_stats.write().unwrap() = stats_map;
+        *self.total_doc_length.write().unwrap() = total_length;
+        *self.avg_doc_length.write().unwrap() = average_length(total_length, count);
+        *self.max_boost.write().unwrap() = max_boost(self.doc_stats.read().unwrap().values());
+        *self.max_authority.write().unwrap() =
+            max_authority(self.doc_stats.read().unwrap().values());
+        self.storage.commit()?;
+
+        Ok(count)
+    }
+
+    /// Get a document by ID
+    pub fn get_document(&self, doc_id: &str) -> Result<Option<Document>> {
+        self.storage.get_document(doc_id)
+    }
+
+    /// Get the documents titled exactly `title`, ignoring case and runs of
+    /// whitespace, in id order
+    ///
+    /// Unlike a title search, the whole title must match: "Rust" finds a
+    /// page named "rust" but not "Rust (programming language)". Soft-deleted
+    /// documents are left out.
+    pub fn get_by_title(&self, title: &str) -> Result<Vec<Document>> {
+        let ids = self.storage.find_ids_by_title(title)?;
+        let tombstones = self.tombstones.read().unwrap();
+
+        let mut documents = Vec::with_capacity(ids.len());
+        for id in ids.iter().filter(|id| !tombstones.contains(*id)) {
+            if let Some(doc) = self.storage.get_document(id)? {
+                documents.push(doc);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Get several documents by ID, in the order requested
+    ///
+    /// Missing IDs yield `None` at their position.
+    pub fn get_documents(&self, doc_ids: &[String]) -> Result<Vec<Option<Document>>> {
+        doc_ids
+            .iter()
+            .map(|id| self.storage.get_document(id))
+            .collect()
+    }
+
+    /// List documents updated at or after `since` (milliseconds since the
+    /// Unix epoch), oldest first
+    ///
+    /// Suited to incremental sync: pass the newest `updated_at` seen so far
+    /// and skip ids already applied, since documents written in the same
+    /// millisecond share a timestamp. Soft-deleted documents are left out.
+    pub fn list_modified_since(&self, since: u64) -> Result<Vec<Document>> {
+        let mut modified: Vec<(u64, String)> = {
+            let stats_map = self.doc_stats.read().unwrap();
+            let tombstones = self.tombstones.read().unwrap();
+            stats_map
+                .values()
+                .filter(|s| s.updated_at >= since && !tombstones.contains(&s.id))
+                .map(|s| (s.updated_at, s.id.clone()))
+                .collect()
+        };
+        modified.sort();
+
+        let mut documents = Vec::with_capacity(modified.len());
+        for (_, id) in modified {
+            if let Some(doc) = self.storage.get_document(&id)? {
+                documents.push(doc);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Iterate over all stored documents in id order
+    pub fn iter_documents(&self) -> Result<impl Iterator<Item = Result<Document>>> {
+        self.storage.iter_documents()
+    }
+
+    /// Stream all documents as CSV rows (id, title, content, url)
+    ///
+    /// Returns the number of documents written.
+    pub fn export_csv<W: std::io::Write>(&self, writer: W) -> Result<usize> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record(["id", "title", "content", "url"])?;
+
+        let mut count = 0;
+        for doc in self.iter_documents()? {
+            let doc = doc?;
+            csv_writer.write_record([
+                doc.id.as_str(),
+                doc.title.as_str(),
+                doc.content.as_str(),
+                doc.url.as_deref().unwrap_or(""),
+            ])?;
+            count += 1;
+        }
+
+        csv_writer.flush()?;
+        Ok(count)
+    }
+
+    /// Stream every indexed term with its document frequency, one per line
+    ///
+    /// Terms come in no particular order and include keyword terms
+    /// (`field:value`) and, with `index_unstemmed`, unstemmed forms
+    /// (`=word`). Writes are held off while the dictionary is written out.
+    /// Returns the number of terms written.
+    pub fn export_terms<W: Write>(&self, writer: W, format: TermExportFormat) -> Result<usize> {
+        let mut writer = std::io::BufWriter::new(writer);
+        let index = self.index.read().unwrap();
+
+        let mut count = 0;
+        for (term, doc_frequency) in index.terms() {
+            match format {
+                TermExportFormat::Tsv => {
+                    writeln!(writer, "{}\t{}", escape_tsv(term), doc_frequency)?
+                }
+                TermExportFormat::Jsonl => {
+                    let line = serde_json::json!({ "term": term, "doc_frequency": doc_frequency });
+                    serde_json::to_writer(&mut writer, &line)?;
+                    writeln!(writer)?;
+                }
+            }
+            count += 1;
+        }
+
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Parse operators and analyze each clause of a query, enforcing
+    /// [`EngineConfig::max_query_terms`]
+    fn analyze_query(
+        &self,
+        query: &str,
+        analyzer: &AnalyzerOptions,
+        parser: QueryParser,
+    ) -> Result<AnalyzedQuery> {
+        let mut analyzed = AnalyzedQuery::default();
+        analyzed.analysis.parser = parser;
+
+        for clause in query::parse_with(query, parser) {
+            let is_keyword = clause
+                .field
+                .as_ref()
+                .is_some_and(|field| self.config.keyword_fields.contains(field));
+            let text_field = clause
+                .field
+                .as_deref()
+                .filter(|_| !is_keyword)
+                .and_then(TextField::from_name);
+            let mut pairs = match &clause.field {
+                Some(field) if self.config.keyword_fields.contains(field) => {
+                    let keyword = self.tokenizer.analyze_keyword(field, &clause.text);
+                    vec![(keyword.clone(), keyword)]
+                }
+                Some(_) if text_field.is_some() => self
+                    .tokenizer
+                    .analyze_with_surface_forms(&clause.text, analyzer),
+                // Not a known field, treat the whole clause as text, such as
+                // a URL kept whole
+                Some(field) => self
+                    .tokenizer
+                    .analyze_with_surface_forms(&format!("{}:{}", field, clause.text), analyzer),
+                None => self
+                    .tokenizer
+                    .analyze_with_surface_forms(&clause.text, analyzer),
+            };
+            analyzed.total_terms += pairs.len();
+            if let Some(max_terms) = self.truncated_query_terms() {
+                let room = max_terms.saturating_sub(analyzed.total_terms - pairs.len());
+                if pairs.len() > room {
+                    analyzed.analysis.truncated_terms += pairs.len() - room;
+                    pairs.truncate(room);
+                    if pairs.is_empty() {
+                        continue;
+                    }
+                }
+            }
+            analyzed.analysis.clauses.push(ClauseAnalysis {
+                text: clause.text.clone(),
+                occur: clause.occur,
+                field: clause
+                    .field
+                    .clone()
+                    .filter(|_| is_keyword || text_field.is_some()),
+                keyword: is_keyword,
+                phrase: clause.phrase,
+                weight: clause.weight,
+                terms: pairs.iter().map(|(term, _)| term.clone()).collect(),
+            });
+            // Phrases are checked against positions, which pruned terms keep
+            let phrase =
+                (clause.phrase && !is_keyword && !pairs.is_empty()).then(|| ClauseConstraint {
+                    terms: pairs.iter().map(|(term, _)| term.clone()).collect(),
+                    surface_forms: pairs.iter().map(|(_, surface)| surface.clone()).collect(),
+                    field: text_field,
+                    occur: clause.occur,
+                    phrase: true,
+                });
+            let unstemmed = match clause.occur {
+                Occur::MustNot => Vec::new(),
+                _ if is_keyword => Vec::new(),
+                _ => self.tokenizer.unstemmed_terms(&pairs, analyzer),
+            };
+            let tokens: Vec<String> = {
+                let pruned_terms = self.pruned_terms.read().unwrap();
+                let (kept, pruned): (Vec<_>, Vec<_>) = pairs
+                    .into_iter()
+                    .partition(|(term, _)| !pruned_terms.contains(term));
+                analyzed
+                    .analysis
+                    .pruned_terms
+                    .extend(pruned.into_iter().map(|(term, _)| term));
+                kept.into_iter()
+                    .map(|(term, surface)| {
+                        analyzed
+                            .surface_forms
+                            .entry(term.clone())
+                            .or_insert(surface);
+                        term
+                    })
+                    .collect()
+            };
+            let unstemmed: Vec<String> = {
+                let pruned_terms = self.pruned_terms.read().unwrap();
+                unstemmed
+                    .into_iter()
+                    .filter(|term| !pruned_terms.contains(term))
+                    .collect()
+            };
+            analyzed
+                .analysis
+                .expansions
+                .extend(unstemmed.iter().cloned());
+            if clause.weight != 1.0 {
+                for token in tokens.iter().chain(&unstemmed) {
+                    analyzed.weights.insert(token.clone(), clause.weight);
+                }
+            }
+            analyzed.unstemmed.extend(unstemmed);
+            let constrained = phrase.is_some() || text_field.is_some();
+            if let Some(phrase) = phrase {
+                analyzed.constraints.push(phrase);
+            } else if text_field.is_some() {
+                analyzed
+                    .constraints
+                    .extend(tokens.iter().map(|term| ClauseConstraint {
+                        terms: vec![term.clone()],
+                        surface_forms: Vec::new(),
+                        field: text_field,
+                        occur: clause.occur,
+                        phrase: false,
+                    }));
+            }
+            match clause.occur {
+                Occur::Should => analyzed.optional.extend(tokens),
+                Occur::Must => analyzed.required.extend(tokens),
+                Occur::MustNot if constrained => {}
+                Occur::MustNot => analyzed.excluded.extend(tokens),
+            }
+        }
+
+        self.check_query_length(&analyzed)?;
+        Ok(analyzed)
+    }
+
+    /// Query length queries are cut to, if over-long queries are truncated
+    fn truncated_query_terms(&self) -> Option<usize> {
+        self.config
+            .max_query_terms
+            .filter(|_| self.config.long_queries == LongQueryPolicy::Truncate)
+    }
+
+    /// Enforce [`EngineConfig::max_query_terms`] on an analyzed query
+    fn check_query_length(&self, analyzed: &AnalyzedQuery) -> Result<()> {
+        let Some(max_terms) = self.config.max_query_terms else {
+            return Ok(());
+        };
+        if analyzed.total_terms <= max_terms {
+            return Ok(());
+        }
+        match self.config.long_queries {
+            LongQueryPolicy::Reject => {
+                tracing::warn!(
+                    terms = analyzed.total_terms,
+                    max_terms,
+                    "Rejecting an over-long query"
+                );
+                Err(QueryTooLong {
+                    terms: analyzed.total_terms,
+                    max_terms,
+                }
+                .into())
+            }
+            LongQueryPolicy::Truncate => {
+                tracing::warn!(
+                    terms = analyzed.total_terms,
+                    max_terms,
+                    "Truncating an over-long query"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Enforce [`EngineConfig::max_result_window`]
+    fn check_result_window(&self, options: &SearchOptions) -> Result<()> {
+        let Some(max_result_window) = self.config.max_result_window else {
+            return Ok(());
+        };
+        let window = options.offset.saturating_add(options.limit.unwrap_or(0));
+        if window <= max_result_window {
+            return Ok(());
+        }
+        tracing::warn!(
+            offset = options.offset,
+            limit = options.limit,
+            "Rejecting a search paging too deep"
+        );
+        Err(ResultWindowTooLarge {
+            window,
+            max_result_window,
+        }
+        .into())
+    }
+
+    /// Words of a query clause that analysis drops as stopwords
+    fn stopwords_in(&self, text: &str, analyzer: &AnalyzerOptions) -> Vec<String> {
+        let words_only = AnalyzerOptions {
+            remove_stopwords: false,
+            stem: false,
+            ..*analyzer
+        };
+        self.tokenizer
+            .analyze_with(text, &words_only)
+            .into_iter()
+            .filter(|word| self.tokenizer.analyze_with(word, analyzer).is_empty())
+            .collect()
+    }
+
+    /// Create a highlighter for the matching (non-excluded) terms of a query
+    pub fn highlighter(&self, query: &str) -> Result<Highlighter<'_>> {
+        self.highlighter_for(query, &SearchOptions::default())
+    }
+
+    /// Create a highlighter that marks what [`search`](Self::search) with
+    /// these options matches: the same terms, analyzed the same way, with
+    /// `highlight_tags` if set
+    pub fn highlighter_for(&self, query: &str, options: &SearchOptions) -> Result<Highlighter<'_>> {
+        let analyzed = self.analyze_search(query, options)?;
+        Ok(self.query_highlighter(analyzed.scoring_query().terms, options))
+    }
+
+    fn query_highlighter(&self, terms: Vec<String>, options: &SearchOptions) -> Highlighter<'_> {
+        let highlighter = Highlighter::new(&self.tokenizer, terms)
+            .with_analyzer(options.analyzer)
+            .with_html_escaping(options.escape_html);
+        match &options.highlight_tags {
+            Some((pre_tag, post_tag)) => highlighter.with_tags(pre_tag.clone(), post_tag.clone()),
+            None => highlighter,
+        }
+    }
+
+    /// The best-matching section of each document, by the positions of the
+    /// query terms within it; see [`BM25::best_section`]
+    fn best_sections(
+        &self,
+        query_tokens: &[String],
+        documents: &[Document],
+        options: &SearchOptions,
+    ) -> Vec<Option<SectionMatch>> {
+        let idfs = query_idfs(query_tokens, &self.index.read().unwrap(), &options.ranking);
+        let stats_map = self.doc_stats.read().unwrap();
+        let bm25 = BM25::default();
+        documents
+            .iter()
+            .map(|doc| {
+                let stats = stats_map.get(&doc.id)?;
+                let index = bm25.best_section(query_tokens, stats, &idfs)?;
+                // The document may have been rewritten since it was fetched
+                let section = doc.sections.get(index)?;
+                Some(SectionMatch {
+                    index,
+                    anchor: section.anchor.clone(),
+                    heading: section.heading.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Parse and analyze a query as a search with these options does
+    fn analyze_search(&self, query: &str, options: &SearchOptions) -> Result<AnalyzedQuery> {
+        let mut analyzed = self.analyze_query(query, &options.analyzer, options.query_parser)?;
+        if let Some(max_ratio) = options.max_df_ratio {
+            let index = self.index.read().unwrap();
+            analyzed.drop_common_terms(&index, max_ratio);
+        }
+        // Finding stopwords re-analyzes every clause, so only when reported
+        if options.query_analysis && options.analyzer.remove_stopwords {
+            let clauses = analyzed
+                .analysis
+                .clauses
+                .iter()
+                .filter(|clause| !clause.keyword);
+            let stopwords = clauses
+                .flat_map(|clause| self.stopwords_in(&clause.text, &options.analyzer))
+                .collect();
+            analyzed.analysis.stopwords = stopwords;
+        }
+        Ok(analyzed)
+    }
+
+    /// Search for documents
+    ///
+    /// Plain query terms are combined according to `options.mode`. A `+term`
+    /// must appear in every result regardless of the mode, and a `-term`
+    /// removes any document containing it (see [`QueryParser`] for other
+    /// syntaxes). Required terms are ranked like
+    /// plain terms; excluded terms never contribute to the score. A query
+    /// made only of excluded terms matches nothing.
+    ///
+    /// Pagination is by offset, so documents added or deleted between two
+    /// page requests can shift results across pages. Within one call, a
+    /// document deleted after matching is left out and the page is filled
+    /// from later results instead; an offset past the end gives an empty
+    /// page.
+    ///
+    /// With [`EngineConfig::search_cache_ttl`] set, a repeated search may be
+    /// answered from the cache and so be out of date; `options.no_cache`
+    /// forces a fresh one.
+    pub fn search(&self, query: &str, options: &SearchOptions) -> Result<SearchResult> {
+        let Some(cache) = self.search_cache.as_ref().filter(|_| !options.no_cache) else {
+            return self.search_uncached(query, options);
+        };
+        let key = search_cache_key(query, options);
+        if let Some(result) = cache.get(&key) {
+            tracing::debug!(query, "Search answered from cache");
+            return Ok(result);
+        }
+        let result = self.search_uncached(query, options)?;
+        if !result.timed_out && result.documents.len() <= MAX_CACHED_DOCUMENTS {
+            cache.insert(key, result.clone());
+        }
+        Ok(result)
+    }
+
+    fn search_uncached(&self, query: &str, options: &SearchOptions) -> Result<SearchResult> {
+        let span = tracing::info_span!("search", query, results = tracing::field::Empty);
+        let _enter = span.enter();
+
+        self.check_result_window(options)?;
+        let analyzed = self.analyze_search(query, options)?;
+
+        let scoring_query = analyzed.scoring_query();
+        let query_tokens = scoring_query.terms.clone();
+        let empty_query = query_tokens.is_empty()
+            && analyzed.excluded.is_empty()
+            && analyzed.constraints.is_empty();
+
+        if query_tokens.is_empty() {
+            span.record("results", 0);
+            return Ok(SearchResult {
+                documents: Vec::new(),
+                total: 0,
+                scores: None,
+                related_terms: None,
+                candidates_truncated: false,
+                total_is_approximate: false,
+                empty_query,
+                highlights: None,
+                sections: None,
+                groups: None,
+                timed_out: false,
+                query_analysis: options
+                    .query_analysis
+                    .then(|| analyzed.analysis_for(options)),
+            });
+        }
+
+        let mut matches = self.ranked_matches(&analyzed, &scoring_query, options)?;
+        if !options.sort.is_empty() {
+            self.sort_matches(&mut matches, &options.sort);
+        }
+
+        let mut result = self.build_result(&query_tokens, matches, options)?;
+        result.query_analysis = options
+            .query_analysis
+            .then(|| analyzed.analysis_for(options));
+        span.record("results", result.total);
+        Ok(result)
+    }
+
+    /// Search for documents containing each word at least the given number
+    /// of times, such as those where a topic is central rather than
+    /// mentioned in passing
+    ///
+    /// The words make up the query, combined according to `options.mode`,
+    /// and each must also meet its threshold; see
+    /// [`SearchOptions::min_term_frequencies`].
+    pub fn search_with_min_tf(
+        &self,
+        terms: &[(&str, usize)],
+        options: &SearchOptions,
+    ) -> Result<SearchResult> {
+        let query = terms
+            .iter()
+            .map(|(word, _)| *word)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let options = SearchOptions {
+            min_term_frequencies: terms
+                .iter()
+                .map(|(word, min_tf)| (word.to_string(), *min_tf))
+                .collect(),
+            query_parser: QueryParser::Literal,
+            ..options.clone()
+        };
+        self.search(&query, &options)
+    }
+
+    /// Analyzed terms of [`SearchOptions::min_term_frequencies`] with their
+    /// thresholds
+    fn analyze_min_term_frequencies(&self, options: &SearchOptions) -> Vec<(String, usize)> {
+        options
+            .min_term_frequencies
+            .iter()
+            .flat_map(|(word, min_tf)| {
+                let terms = self.tokenizer.analyze_with(word, &options.analyzer);
+                terms.into_iter().map(move |term| (term, *min_tf))
+            })
+            .collect()
+    }
+
+    /// Rank a given set of documents against a query, skipping retrieval
+    ///
+    /// For use as a BM25 re-ranker behind another retriever. Every supplied
+    /// id that names a live document is scored and returned, whether or not
+    /// it matches; ids are deduplicated and ties broken as in a search.
+    /// Term weights apply, but `+term` and `-term` don't filter and the
+    /// search mode is ignored. IDF still comes from the whole index.
+    pub fn rerank(
+        &self,
+        query: &str,
+        candidate_ids: &[String],
+        options: &SearchOptions,
+    ) -> Result<SearchResult> {
+        let analyzed = self.analyze_query(query, &options.analyzer, options.query_parser)?;
+        let scoring_query = analyzed.scoring_query();
+
+        let matches = {
+            let index = self.index.read().unwrap();
+            let stats_map = self.doc_stats.read().unwrap();
+            let tombstones = self.tombstones.read().unwrap();
+            let avg_length = *self.avg_doc_length.read().unwrap();
+
+            let mut seen = HashSet::new();
+            let candidates: Vec<String> = candidate_ids
+                .iter()
+                .filter(|id| options.include_deleted || !tombstones.contains(*id))
+                .filter(|id| stats_map.contains_key(*id) && seen.insert(*id))
+                .cloned()
+                .collect();
+
+            let scored_docs = rank_documents(
+                &scoring_query,
+                &candidates,
+                &stats_map,
+                &index,
+                avg_length,
+                &options.ranking,
+            );
+            RankedMatches {
+                total: scored_docs.len(),
+                ids: scored_docs.iter().map(|sd| sd.doc_id.clone()).collect(),
+                scores: Some(scored_docs.iter().map(|sd| sd.score).collect()),
+                candidates_truncated: false,
+                timed_out: false,
+            }
+        };
+
+        let mut result = self.build_result(&scoring_query.terms, matches, options)?;
+        result.empty_query = scoring_query.terms.is_empty();
+        Ok(result)
+    }
+
+    /// Page, group and highlight results given in final order
+    fn build_result(
+        &self,
+        query_tokens: &[String],
+        matches: RankedMatches,
+        options: &SearchOptions,
+    ) -> Result<SearchResult> {
+        let RankedMatches {
+            ids: sorted_ids,
+            scores,
+            total,
+            candidates_truncated,
+            timed_out,
+        } = matches;
+
+        let related_terms = options
+            .related_terms
+            .map(|n| self.frequent_terms(&sorted_ids, query_tokens, n));
+
+        if let Some(field) = &options.group_by {
+            let groups = self.group_results(field, &sorted_ids, scores.as_deref(), options)?;
+            return Ok(SearchResult {
+                documents: Vec::new(),
+                total,
+                scores: None,
+                related_terms,
+                candidates_truncated,
+                total_is_approximate: candidates_truncated,
+                empty_query: false,
+                highlights: None,
+                sections: None,
+                groups: Some(groups),
+                timed_out,
+                query_analysis: None,
+            });
+        }
+
+        // Fetch the page. Documents deleted while the query ran are skipped
+        // and the page is back-filled from the following results.
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let mut documents = Vec::new();
+        let mut page_scores = scores.as_ref().map(|_| Vec::new());
+        let mut vanished = 0;
+
+        for (i, id) in sorted_ids.iter().enumerate().skip(options.offset) {
+            if documents.len() >= limit {
+                break;
+            }
+            match self.storage.get_document(id)? {
+                Some(doc) => {
+                    documents.push(doc);
+                    if let (Some(page_scores), Some(scores)) = (&mut page_scores, &scores) {
+                        page_scores.push(scores[i]);
+                    }
+                }
+                None => vanished += 1,
+            }
+        }
+        let total = total - vanished;
+
+        let highlights = options.highlight.then(|| {
+            let highlighter = self.query_highlighter(query_tokens.to_vec(), options);
+            documents
+                .iter()
+                .map(|doc| highlighter.document(doc, DEFAULT_SNIPPET_CHARS))
+                .collect()
+        });
+        let sections = options
+            .sections
+            .then(|| self.best_sections(query_tokens, &documents, options));
+
+        Ok(SearchResult {
+            documents,
+            total,
+            scores: page_scores,
+            related_terms,
+            candidates_truncated,
+            total_is_approximate: candidates_truncated,
+            empty_query: false,
+            highlights,
+            sections,
+            groups: None,
+            timed_out,
+            query_analysis: None,
+        })
+    }
+
+    /// Matching documents of an analyzed query in result order, with their
+    /// scores when ranking
+    fn ranked_matches(
+        &self,
+        analyzed: &AnalyzedQuery,
+        scoring_query: &ScoringQuery,
+        options: &SearchOptions,
+    ) -> Result<RankedMatches> {
+        let AnalyzedQuery {
+            optional: optional_tokens,
+            required: required_tokens,
+            excluded: excluded_tokens,
+            ..
+        } = analyzed;
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        let mut candidates_truncated = false;
+        let top_k = options
+            .limit
+            .filter(|_| Self::can_prune(options))
+            .map(|limit| options.offset.saturating_add(limit));
+        let matches = if let Some(k) = top_k {
+            self.rank_top_k(analyzed, scoring_query, k, options, deadline)
         } else {
-            sorted_ids.len()
+            // Find matching documents
+            let candidate_ids = {
+                let index = self.index.read().unwrap();
+
+                let mut candidates: Option<HashSet<String>> = if optional_tokens.is_empty() {
+                    None
+                } else {
+                    let ids = match (options.mode, options.max_candidates) {
+                        (SearchMode::And, _) => index.search_and(optional_tokens),
+                        (SearchMode::Or, None) => index.search_or(optional_tokens),
+                        (SearchMode::Or, Some(max)) => {
+                            let (ids, truncated) = index.search_or_limited(optional_tokens, max);
+                            candidates_truncated = truncated;
+                            ids
+                        }
+                    };
+                    Some(ids.into_iter().collect())
+                };
+
+                if !required_tokens.is_empty() {
+                    let required: HashSet<String> =
+                        index.search_and(required_tokens).into_iter().collect();
+                    candidates = Some(match candidates {
+                        None => required,
+                        Some(c) => c.intersection(&required).cloned().collect(),
+                    });
+                }
+
+                let mut candidates = candidates.unwrap_or_default();
+                for id in index.search_or(excluded_tokens) {
+                    candidates.remove(&id);
+                }
+
+                if !options.include_deleted {
+                    let tombstones = self.tombstones.read().unwrap();
+                    candidates.retain(|id| !tombstones.contains(id));
+                }
+
+                if let Some(since) = options.modified_since {
+                    let stats_map = self.doc_stats.read().unwrap();
+                    candidates
+                        .retain(|id| stats_map.get(id).is_some_and(|s| s.updated_at >= since));
+                }
+
+                let min_tfs = self.analyze_min_term_frequencies(options);
+                if !min_tfs.is_empty() {
+                    let stats_map = self.doc_stats.read().unwrap();
+                    candidates.retain(|id| {
+                        stats_map
+                            .get(id)
+                            .is_some_and(|s| meets_min_term_frequencies(s, &min_tfs))
+                    });
+                }
+
+                if !analyzed.constraints.is_empty() {
+                    let stats_map = self.doc_stats.read().unwrap();
+                    candidates.retain(|id| {
+                        stats_map.get(id).is_some_and(|s| {
+                            analyzed.matches_constraints(s, options.mode, self.config.exact_phrases)
+                        })
+                    });
+                }
+
+                candidates.into_iter().collect::<Vec<String>>()
+            };
+
+            // Rank documents if requested
+            if options.use_ranking {
+                let index = self.index.read().unwrap();
+                let stats_map = self.doc_stats.read().unwrap();
+                let avg_length = *self.avg_doc_length.read().unwrap();
+
+                // Everything past the page is needed to filter or group
+                let keep = options
+                    .limit
+                    .filter(|_| {
+                        deadline.is_some()
+                            && options.sort.is_empty()
+                            && options.geo.is_none()
+                            && options.group_by.is_none()
+                            && options.related_terms.is_none()
+                    })
+                    .map(|limit| options.offset.saturating_add(limit));
+                let budget = RankBudget { keep, deadline };
+                let (scored_docs, timed_out) = rank_documents_within(
+                    scoring_query,
+                    &candidate_ids,
+                    &stats_map,
+                    &index,
+                    avg_length,
+                    &options.ranking,
+                    budget,
+                );
+
+                RankedMatches {
+                    total: if keep.is_some() {
+                        candidate_ids.len()
+                    } else {
+                        scored_docs.len()
+                    },
+                    ids: scored_docs.iter().map(|sd| sd.doc_id.clone()).collect(),
+                    scores: Some(scored_docs.iter().map(|sd| sd.score).collect()),
+                    candidates_truncated,
+                    timed_out,
+                }
+            } else {
+                RankedMatches {
+                    total: candidate_ids.len(),
+                    ids: candidate_ids,
+                    scores: None,
+                    candidates_truncated,
+                    timed_out: false,
+                }
+            }
+        };
+
+        // Apply geo filter
+        let mut matches = match &options.geo {
+            Some(geo) => {
+                let (ids, scores) = self.apply_geo_filter(geo, matches.ids, matches.scores)?;
+                RankedMatches {
+                    total: ids.len(),
+                    ids,
+                    scores,
+                    ..matches
+                }
+            }
+            None => matches,
+        };
+
+        if let (Some(lambda), Some(scores)) = (options.diversity, &mut matches.scores) {
+            let stats_map = self.doc_stats.read().unwrap();
+            diversify(&mut matches.ids, scores, &stats_map, lambda, DIVERSITY_POOL);
+        }
+
+        Ok(matches)
+    }
+
+    /// Reorder matches by [`SearchOptions::sort`], keeping their current
+    /// order among documents equal on every level. Documents without
+    /// statistics come last on boost, recency and metadata levels.
+    fn sort_matches(&self, matches: &mut RankedMatches, sort: &[SortSpec]) {
+        let stats_map = self.doc_stats.read().unwrap();
+        let stats: Vec<Option<&DocStats>> =
+            matches.ids.iter().map(|id| stats_map.get(id)).collect();
+        let direct = |ordering: Ordering, descending: bool| {
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+
+        let mut order: Vec<usize> = (0..matches.ids.len()).collect();
+        order.sort_by(|&a, &b| {
+            for spec in sort {
+                // Missing values come last either way, so each level is
+                // directed on its own
+                let ordering = match &spec.key {
+                    SortKey::Relevance => match &matches.scores {
+                        Some(scores) => direct(scores[a].total_cmp(&scores[b]), spec.descending),
+                        None => Ordering::Equal,
+                    },
+                    SortKey::Id => direct(matches.ids[a].cmp(&matches.ids[b]), spec.descending),
+                    SortKey::Boost | SortKey::Recency => match (stats[a], stats[b]) {
+                        (Some(a_stats), Some(b_stats)) => {
+                            let ordering = match spec.key {
+                                SortKey::Boost => a_stats.boost.total_cmp(&b_stats.boost),
+                                _ => a_stats.updated_at.cmp(&b_stats.updated_at),
+                            };
+                            direct(ordering, spec.descending)
+                        }
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    },
+                    SortKey::Metadata(field) => {
+                        let value = |i: usize| stats[i].and_then(|stats| stats.metadata.get(field));
+                        compare_sort_values(
+                            value(a).map(String::as_str),
+                            value(b).map(String::as_str),
+                            spec.descending,
+                        )
+                    }
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+
+        matches.ids = order
+            .iter()
+            .map(|&i| std::mem::take(&mut matches.ids[i]))
+            .collect();
+        if let Some(scores) = &mut matches.scores {
+            *scores = order.iter().map(|&i| scores[i]).collect();
+        }
+    }
+
+    /// Distribution of the scores of every document matching a query, to
+    /// help choose a score threshold
+    ///
+    /// Ranking is always on and pagination, grouping and top-k pruning are
+    /// ignored, so the whole candidate set is scored.
+    pub fn score_distribution(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<ScoreDistribution> {
+        let analyzed = self.analyze_search(query, options)?;
+        let scoring_query = analyzed.scoring_query();
+        if scoring_query.terms.is_empty() {
+            return Ok(ScoreDistribution::default());
+        }
+
+        let options = SearchOptions {
+            use_ranking: true,
+            top_k_only: false,
+            timeout: None,
+            ..options.clone()
+        };
+        let matches = self.ranked_matches(&analyzed, &scoring_query, &options)?;
+
+        Ok(ScoreDistribution::from_scores(
+            &matches.scores.unwrap_or_default(),
+        ))
+    }
+
+    /// Document frequency and IDF of each scored query term, in query order
+    ///
+    /// Terms are analyzed the way [`search`](Self::search) would with these
+    /// options; excluded terms and those dropped by `max_df_ratio` aren't
+    /// scored, so they aren't listed.
+    pub fn term_idfs(&self, query: &str, options: &SearchOptions) -> Result<Vec<TermIdf>> {
+        let analyzed = self.analyze_search(query, options)?;
+        let scored: HashSet<&String> = analyzed.optional.iter().chain(&analyzed.required).collect();
+        let mut terms: Vec<String> = Vec::new();
+        for term in analyzed
+            .analysis
+            .clauses
+            .iter()
+            .flat_map(|clause| &clause.terms)
+        {
+            if scored.contains(term) && !terms.contains(term) {
+                terms.push(term.clone());
+            }
+        }
+
+        let index = self.index.read().unwrap();
+        let idfs = query_idfs(&terms, &index, &options.ranking);
+        Ok(terms
+            .into_iter()
+            .zip(idfs)
+            .map(|(term, idf)| TermIdf {
+                doc_frequency: index.doc_frequency(&term),
+                term,
+                idf,
+            })
+            .collect())
+    }
+
+    /// Run several queries with the same options, returning their results in
+    /// query order
+    ///
+    /// Queries are spread over up to `threads` threads. Each query takes the
+    /// index locks on its own, so a write may land between two queries.
+    pub fn search_batch(
+        &self,
+        queries: &[String],
+        options: &SearchOptions,
+        threads: usize,
+    ) -> Result<Vec<SearchResult>> {
+        if threads <= 1 || queries.len() <= 1 {
+            return queries
+                .iter()
+                .map(|query| self.search(query, options))
+                .collect();
+        }
+
+        let chunk_size = queries.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = queries
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|query| self.search(query, options))
+                            .collect()
+                    })
+                })
+                .collect();
+
+            let mut results = Vec::with_capacity(queries.len());
+            for handle in handles {
+                let chunk_results: Result<Vec<SearchResult>> =
+                    handle.join().expect("search thread panicked");
+                results.extend(chunk_results?);
+            }
+            Ok(results)
+        })
+    }
+
+    /// Run judged queries and measure how well the results match the
+    /// judgments
+    ///
+    /// The first `options.limit` results of each query (10 if unset) are
+    /// counted; grouping and highlighting are turned off.
+    pub fn evaluate(&self, judgments: &[Judgment], options: &SearchOptions) -> Result<EvalReport> {
+        let k = options.limit.unwrap_or(10);
+        let options = SearchOptions {
+            limit: Some(k),
+            offset: 0,
+            group_by: None,
+            highlight: false,
+            related_terms: None,
+            ..options.clone()
+        };
+
+        let mut queries = Vec::with_capacity(judgments.len());
+        for judgment in judgments {
+            let result = self.search(&judgment.query, &options)?;
+            let ids: Vec<String> = result.documents.into_iter().map(|doc| doc.id).collect();
+            queries.push(QueryEvaluation::new(judgment, &ids, k));
+        }
+
+        Ok(EvalReport::new(k, queries))
+    }
+
+    /// Explain how a document scores for a query with default options
+    pub fn explain(&self, query: &str, doc_id: &str) -> Result<Option<Explanation>> {
+        self.explain_with(query, doc_id, &SearchOptions::default())
+    }
+
+    /// Explain how a document scores for a query, analyzing and ranking it
+    /// the way [`search`](Self::search) would with these options. Returns
+    /// `None` if the document isn't indexed.
+    pub fn explain_with(
+        &self,
+        query: &str,
+        doc_id: &str,
+        options: &SearchOptions,
+    ) -> Result<Option<Explanation>> {
+        let Some(doc_stats) = self.doc_stats.read().unwrap().get(doc_id).cloned() else {
+            return Ok(None);
+        };
+
+        let mut analyzed = self.analyze_query(query, &options.analyzer, options.query_parser)?;
+        let index = self.index.read().unwrap();
+        if let Some(max_ratio) = options.max_df_ratio {
+            analyzed.drop_common_terms(&index, max_ratio);
+        }
+
+        let scoring_query = analyzed.scoring_query();
+        let avg_length = *self.avg_doc_length.read().unwrap();
+        let score = BM25::default().explain(
+            &scoring_query,
+            &doc_stats,
+            &index,
+            avg_length,
+            &options.ranking,
+        );
+
+        let excluded = analyzed
+            .excluded
+            .iter()
+            .any(|term| doc_stats.term_frequencies.contains_key(term));
+
+        Ok(Some(Explanation {
+            query: query.to_string(),
+            doc_id: doc_id.to_string(),
+            optional_terms: analyzed.optional,
+            required_terms: analyzed.required,
+            excluded_terms: analyzed.excluded,
+            excluded,
+            ranking: options.ranking.clone(),
+            doc_stats,
+            score,
+        }))
+    }
+
+    /// Bucket ranked results by a metadata field, keeping the best
+    /// `group_size` documents of each. Groups are ordered by their best
+    /// result and paginated with `offset`/`limit`. Every match is loaded to
+    /// read its field, so this costs more than a flat search.
+    fn group_results(
+        &self,
+        field: &str,
+        ids: &[String],
+        scores: Option<&[f64]>,
+        options: &SearchOptions,
+    ) -> Result<Vec<ResultGroup>> {
+        let mut groups: Vec<ResultGroup> = Vec::new();
+        let mut group_index: HashMap<Option<String>, usize> = HashMap::new();
+
+        for (i, id) in ids.iter().enumerate() {
+            let Some(doc) = self.storage.get_document(id)? else {
+                continue;
+            };
+            let value = doc.metadata.get(field).cloned();
+            let pos = *group_index.entry(value.clone()).or_insert_with(|| {
+                groups.push(ResultGroup {
+                    value,
+                    total: 0,
+                    documents: Vec::new(),
+                    scores: scores.map(|_| Vec::new()),
+                });
+                groups.len() - 1
+            });
+
+            let group = &mut groups[pos];
+            group.total += 1;
+            if group.documents.len() < options.group_size {
+                group.documents.push(doc);
+                if let (Some(group_scores), Some(scores)) = (&mut group.scores, scores) {
+                    group_scores.push(scores[i]);
+                }
+            }
+        }
+
+        Ok(groups
+            .into_iter()
+            .skip(options.offset)
+            .take(options.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Terms that most often appear in the same documents as `term`, for
+    /// bootstrapping synonym lists
+    ///
+    /// `term` is analyzed like a query. Each related term is counted once per
+    /// live document containing it and every token of `term`, over at most
+    /// [`MAX_RELATED_TERMS_DOCS`] such documents.
+    pub fn related_terms(&self, term: &str, n: usize) -> Vec<TermCount> {
+        let tokens = self.tokenizer.analyze(term);
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        let ids = self.index.read().unwrap().search_and(&tokens);
+        let stats_map = self.doc_stats.read().unwrap();
+        let tombstones = self.tombstones.read().unwrap();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+
+        let live = ids.iter().filter(|id| !tombstones.contains(*id));
+        for stats in live
+            .take(MAX_RELATED_TERMS_DOCS)
+            .filter_map(|id| stats_map.get(id))
+        {
+            for other in stats.term_frequencies.keys() {
+                if !tokens.contains(other) && !is_unstemmed_term(other) {
+                    *counts.entry(other.as_str()).or_default() += 1;
+                }
+            }
+        }
+
+        top_term_counts(counts, n)
+    }
+
+    /// Sum term frequencies over the first matches and return the `n` most
+    /// frequent terms that aren't part of the query
+    fn frequent_terms(&self, ids: &[String], query_tokens: &[String], n: usize) -> Vec<TermCount> {
+        let stats_map = self.doc_stats.read().unwrap();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+
+        for stats in ids
+            .iter()
+            .take(MAX_RELATED_TERMS_DOCS)
+            .filter_map(|id| stats_map.get(id))
+        {
+            for (term, &tf) in &stats.term_frequencies {
+                if !query_tokens.contains(term) && !is_unstemmed_term(term) {
+                    *counts.entry(term.as_str()).or_default() += tf;
+                }
+            }
+        }
+
+        top_term_counts(counts, n)
+    }
+
+    /// Keep only documents within the filter's radius, optionally sorted by distance
+    ///
+    /// Documents without valid `lat`/`lon` metadata are dropped.
+    fn apply_geo_filter(
+        &self,
+        geo: &GeoFilter,
+        ids: Vec<String>,
+        scores: Option<Vec<f64>>,
+    ) -> Result<(Vec<String>, Option<Vec<f64>>)> {
+        let mut hits = Vec::new();
+        for (i, id) in ids.into_iter().enumerate() {
+            if let Some(doc) = self.storage.get_document(&id)? {
+                if let Some(distance) = geo.matches(&doc) {
+                    hits.push((id, scores.as_ref().map(|s| s[i]), distance));
+                }
+            }
+        }
+
+        if geo.sort_by_distance {
+            hits.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        }
+
+        let scores = scores.map(|_| hits.iter().filter_map(|h| h.1).collect());
+        let ids = hits.into_iter().map(|h| h.0).collect();
+
+        Ok((ids, scores))
+    }
+
+    /// Get index statistics
+    pub fn stats(&self) -> Result<crate::index::IndexStats> {
+        let mut stats = self.index.read().unwrap().stats();
+        stats.deleted_documents = self.tombstones.read().unwrap().len();
+        stats.live_documents = stats
+            .total_documents
+            .saturating_sub(stats.deleted_documents);
+        Ok(stats)
+    }
+
+    /// Terms dropped from the index by `max_postings`, sorted
+    pub fn pruned_terms(&self) -> Vec<String> {
+        let mut terms: Vec<String> = self.pruned_terms.read().unwrap().iter().cloned().collect();
+        terms.sort();
+        terms
+    }
+
+    /// Progress saved by a long-running job such as an import, if any
+    pub fn checkpoint(&self, name: &str) -> Result<Option<String>> {
+        self.storage
+            .get_metadata(&format!("{CHECKPOINT_PREFIX}{name}"))
+    }
+
+    /// Save a job's progress, durable together with the writes before it
+    pub fn save_checkpoint(&self, name: &str, value: &str) -> Result<()> {
+        self.ensure_writable()?;
+        self.storage
+            .save_metadata(&format!("{CHECKPOINT_PREFIX}{name}"), value)?;
+        self.commit()
+    }
+
+    /// Forget a job's progress once it has finished
+    pub fn clear_checkpoint(&self, name: &str) -> Result<()> {
+        self.ensure_writable()?;
+        self.storage
+            .delete_metadata(&format!("{CHECKPOINT_PREFIX}{name}"))?;
+        self.commit()
+    }
+
+    /// Size of the engine's database on disk, in bytes
+    pub fn disk_usage(&self) -> Result<u64> {
+        self.storage.size_on_disk()
+    }
+
+    /// Get total document count
+    pub fn document_count(&self) -> Result<usize> {
+        self.storage.count_documents()
+    }
+
+    /// Flush all changes to disk, emptying the write-ahead log
+    pub fn flush(&self) -> Result<()> {
+        if self.config.read_only {
+            return Ok(());
+        }
+        // No write may be half-applied when the log is emptied
+        let _gate = self.wal.as_ref().map(|_| self.write_gate.write().unwrap());
+        {
+            let index = self.index.read().unwrap();
+            self.storage.save_index(&index)?;
+        }
+        self.storage.flush()?;
+        if let Some(wal) = &self.wal {
+            wal.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Flush all changes and close the engine, reporting any flush error
+    ///
+    /// Once it returns, the data directory can be opened again, unless
+    /// another engine shares its database; see [`Storage::close`].
+    pub fn close(mut self) -> Result<()> {
+        // Already flushed here, nothing left for Drop to do
+        self.config.flush_on_drop = false;
+        self.flush()?;
+        let storage = self.storage.clone();
+        drop(self);
+        storage.close()
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        anyhow::ensure!(!self.config.read_only, "The index is open read-only");
+        Ok(())
+    }
+
+    /// Clear all data
+    pub fn clear(&self) -> Result<()> {
+        self.ensure_writable()?;
+        let gate = self.write_gate.read().unwrap();
+        if let Some(log) = self.rebuild_log.lock().unwrap().as_mut() {
+            log.cleared = true;
+        }
+        self.log_write(|| WalOp::Clear)?;
+        {
+            let mut index = self.index.write().unwrap();
+            *index = InvertedIndex::new();
+        }
+        {
+            let mut stats = self.doc_stats.write().unwrap();
+            stats.clear();
+            *self.total_doc_length.write().unwrap() = 0;
+            *self.avg_doc_length.write().unwrap() = 0.0;
+        }
+        self.tombstones.write().unwrap().clear();
+        self.pruned_terms.write().unwrap().clear();
+        *self.max_boost.write().unwrap() = 0.0;
+        *self.max_authority.write().unwrap() = 0.0;
+        self.storage.clear()?;
+        if let Some(cache) = &self.search_cache {
+            cache.clear();
+        }
+        drop(gate);
+        self.commit()
+    }
+}
+
+/// Escape the characters that would break a TSV line
+fn escape_tsv(text: &str) -> std::borrow::Cow<'_, str> {
+    if !text.contains(['\\', '\t', '\n', '\r']) {
+        return text.into();
+    }
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .into()
+}
+
+/// The `n` highest counts, ties broken alphabetically
+fn top_term_counts(counts: HashMap<&str, usize>, n: usize) -> Vec<TermCount> {
+    let mut terms: Vec<TermCount> = counts
+        .into_iter()
+        .map(|(term, count)| TermCount {
+            term: term.to_string(),
+            count,
+        })
+        .collect();
+    terms.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+    terms.truncate(n);
+
+    terms
+}
+
+fn search_cache(config: &EngineConfig) -> Option<TtlCache<SearchResult>> {
+    config
+        .search_cache_ttl
+        .map(|ttl| TtlCache::new(ttl, config.search_cache_capacity))
+}
+
+/// Key of a search in the search cache. `SearchOptions` derives `Debug`,
+/// which prints every field, so searches differing in any option get
+/// different keys; a hand-written `Debug` would have to keep doing so.
+fn search_cache_key(query: &str, options: &SearchOptions) -> String {
+    format!("{query}\u{0}{options:?}")
+}
+
+/// Average document length, 0.0 for an empty corpus
+fn average_length(total: usize, count: usize) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+fn max_boost<'a>(stats: impl Iterator<Item = &'a DocStats>) -> f64 {
+    stats.map(|s| s.boost).fold(0.0, f64::max)
+}
+
+/// Whether a document contains every term at least as often as required
+fn meets_min_term_frequencies(stats: &DocStats, min_tfs: &[(String, usize)]) -> bool {
+    min_tfs
+        .iter()
+        .all(|(term, min_tf)| stats.term_frequencies.get(term).copied().unwrap_or(0) >= *min_tf)
+}
+
+fn max_authority<'a>(stats: impl Iterator<Item = &'a DocStats>) -> f64 {
+    stats.filter_map(|s| s.authority).fold(0.0, f64::max)
+}
+
+/// A version 4 UUID, using the randomly keyed hasher of the standard library
+/// as the random source
+fn random_uuid() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    let random_u64 = || {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos()),
+        );
+        hasher.finish()
+    };
+    let bits = (u128::from(random_u64()) << 64 | u128::from(random_u64()))
+        & !(0xf000 << 64 | 0xc << 60)
+        | 0x4000 << 64
+        | 0x8 << 60;
+
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl Drop for SearchEngine {
+    fn drop(&mut self) {
+        if self.config.flush_on_drop {
+            if let Err(err) = self.flush() {
+                tracing::error!("Failed to flush search engine on drop: {:#}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Language;
+
+    #[test]
+    fn test_engine_insert_and_search() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        let doc1 = Document::new(
+            "1".to_string(),
+            "Rust Programming".to_string(),
+            "Rust is a systems programming language".to_string(),
+        );
+
+        let doc2 = Document::new(
+            "2".to_string(),
+            "Go Programming".to_string(),
+            "Go is a simple programming language".to_string(),
+        );
+
+        engine.upsert_document(doc1)?;
+        engine.upsert_document(doc2)?;
+
+        let results = engine.search("programming language", &SearchOptions::default())?;
+        assert_eq!(results.total, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outdated_doc_stats_are_rebuilt() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust borrow checker".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Goroutines".to_string(),
+        ))?;
+
+        // Statistics as the first release wrote them: id, length and term
+        // frequencies only
+        let legacy = (
+            "1".to_string(),
+            4usize,
+            HashMap::from([("rust".to_string(), 2usize)]),
+        );
+        engine
+            .storage
+            .save_raw_doc_stats("1", &bincode::serialize(&legacy)?)?;
+
+        // As on open: current statistics load, older ones are rebuilt
+        let (stats, outdated) = engine.storage.get_all_doc_stats()?;
+        assert_eq!(outdated, vec!["1"]);
+        *engine.doc_stats.write().unwrap() = stats.into_iter().map(|s| (s.id.clone(), s)).collect();
+        engine.rebuild_doc_stats(&outdated)?;
+
+        let stats = engine.doc_stats.read().unwrap()["1"].clone();
+        assert_eq!(stats.term_frequencies["borrow"], 1);
+        assert!(stats.positions.contains_key("checker"));
+        assert_eq!(engine.search("borrow", &SearchOptions::default())?.total, 1);
+        // Saved in the current layout
+        assert!(engine.storage.get_doc_stats("1")?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_required_and_excluded_terms() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Python Programming".to_string(),
+            "Python is a programming language".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go Programming".to_string(),
+            "Go is a simple programming language".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Go Board Game".to_string(),
+            "Go is an ancient board game".to_string(),
+        ))?;
+
+        let or_options = SearchOptions {
+            mode: SearchMode::Or,
+            ..Default::default()
+        };
+
+        // (python OR go) AND programming
+        let results = engine.search("python go +programming", &or_options)?;
+        let mut ids: Vec<_> = results.documents.iter().map(|d| d.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "2"]);
+
+        // (python OR go) AND NOT programming
+        let results = engine.search("python go -programming", &or_options)?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].id, "3");
+
+        // Required terms alone act as an AND query
+        let results = engine.search("+go +programming", &or_options)?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].id, "2");
+
+        // Exclusion wins over requirement
+        let results = engine.search("+programming -python", &or_options)?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].id, "2");
+
+        // Only excluded terms match nothing
+        let results = engine.search("-python", &or_options)?;
+        assert_eq!(results.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_csv_escapes_fields() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        engine.upsert_document(
+            Document::new(
+                "1".to_string(),
+                "Quotes, \"commas\"".to_string(),
+                "line one\nline two".to_string(),
+            )
+            .with_url("https://example.com".to_string()),
+        )?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Plain".to_string(),
+            "Text".to_string(),
+        ))?;
+
+        let mut out = Vec::new();
+        let count = engine.export_csv(&mut out)?;
+        assert_eq!(count, 2);
+
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+        let rows: Vec<csv::StringRecord> =
+            reader.records().collect::<std::result::Result<_, _>>()?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][1], "Quotes, \"commas\"");
+        assert_eq!(&rows[0][2], "line one\nline two");
+        assert_eq!(&rows[0][3], "https://example.com");
+        assert_eq!(&rows[1][3], "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespaced_engines_share_storage() -> Result<()> {
+        let storage = Storage::in_memory()?;
+        let articles = SearchEngine::with_storage(
+            storage.clone().with_namespace("articles"),
+            EngineConfig::default(),
+        )?;
+        let products = SearchEngine::with_storage(
+            storage.clone().with_namespace("products"),
+            EngineConfig::default(),
+        )?;
+
+        articles.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        products.upsert_document(Document::new(
+            "2".to_string(),
+            "Crab".to_string(),
+            "Rust colored crab".to_string(),
+        ))?;
+        articles.flush()?;
+        products.flush()?;
+
+        let ids = |engine: &SearchEngine| -> Result<Vec<String>> {
+            let result = engine.search("rust", &SearchOptions::default())?;
+            Ok(result.documents.into_iter().map(|doc| doc.id).collect())
+        };
+        assert_eq!(ids(&articles)?, vec!["1"]);
+        assert_eq!(ids(&products)?, vec!["2"]);
+        assert!(products.get_document("1")?.is_none());
+
+        // A reopened engine loads only its own namespace
+        let reopened = SearchEngine::with_storage(
+            storage.with_namespace("articles"),
+            EngineConfig::default(),
+        )?;
+        assert_eq!(ids(&reopened)?, vec!["1"]);
+        assert_eq!(reopened.stats()?.total_documents, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoints() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        assert_eq!(engine.checkpoint("import")?, None);
+
+        engine.save_checkpoint("import", "1000")?;
+        engine.save_checkpoint("import", "2000")?;
+        assert_eq!(engine.checkpoint("import")?.as_deref(), Some("2000"));
+        assert_eq!(engine.checkpoint("other")?, None);
+
+        engine.clear_checkpoint("import")?;
+        assert_eq!(engine.checkpoint("import")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_terms() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            keyword_fields: vec!["tag".to_string()],
+            ..Default::default()
+        })?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Memory".to_string(),
+        ))?;
+        engine.upsert_document(
+            Document::new("2".to_string(), "Rust".to_string(), "Threads".to_string())
+                .with_metadata("tag".to_string(), "a\tb".to_string()),
+        )?;
+
+        let mut out = Vec::new();
+        assert_eq!(engine.export_terms(&mut out, TermExportFormat::Tsv)?, 4);
+        let mut lines: Vec<&str> = std::str::from_utf8(&out)?.lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["memori\t1", "rust\t2", "tag:a\\tb\t1", "thread\t1"]
+        );
+
+        let mut out = Vec::new();
+        engine.export_terms(&mut out, TermExportFormat::Jsonl)?;
+        let mut terms: Vec<(String, usize)> = std::str::from_utf8(&out)?
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                (
+                    value["term"].as_str().unwrap().to_string(),
+                    value["doc_frequency"].as_u64().unwrap() as usize,
+                )
+            })
+            .collect();
+        terms.sort();
+        assert_eq!(terms[2], ("tag:a\tb".to_string(), 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_soft_delete_restore_and_purge() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust Programming".to_string(),
+            "Rust is a systems language".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Rust Book".to_string(),
+            "Learn rust".to_string(),
+        ))?;
+
+        assert!(engine.soft_delete("1")?);
+        assert!(!engine.soft_delete("1")?);
+        assert!(!engine.soft_delete("missing")?);
+        assert!(engine.is_deleted("1"));
+
+        // Hidden from search but still retrievable
+        let results = engine.search("rust", &SearchOptions::default())?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].id, "2");
+        assert!(engine.get_document("1")?.is_some());
+
+        let with_deleted = SearchOptions {
+            include_deleted: true,
+            ..Default::default()
+        };
+        assert_eq!(engine.search("rust", &with_deleted)?.total, 2);
+
+        // Restore brings it back
+        assert!(engine.restore("1")?);
+        assert!(!engine.restore("1")?);
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 2);
+
+        // Purge removes tombstoned documents for good
+        engine.soft_delete("2")?;
+        assert_eq!(engine.purge()?, 1);
+        assert!(engine.get_document("2")?.is_none());
+        assert!(!engine.is_deleted("2"));
+        assert_eq!(engine.search("rust", &with_deleted)?.total, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_geo_filter() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        let place = |id: &str, lat: &str, lon: &str| {
+            Document::new(
+                id.to_string(),
+                format!("Cafe {}", id),
+                "Coffee shop".to_string(),
+            )
+            .with_metadata("lat".to_string(), lat.to_string())
+            .with_metadata("lon".to_string(), lon.to_string())
+        };
+
+        // Around central Paris
+        engine.upsert_document(place("near", "48.8570", "2.3530"))?;
+        engine.upsert_document(place("mid", "48.8700", "2.3300"))?;
+        engine.upsert_document(place("far", "51.5074", "-0.1278"))?;
+        engine.upsert_document(Document::new(
+            "nowhere".to_string(),
+            "Cafe nowhere".to_string(),
+            "Coffee shop".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            geo: Some(GeoFilter::new(48.8566, 2.3522, 5.0).sorted_by_distance()),
+            ..Default::default()
+        };
+        let results = engine.search("coffee", &options)?;
+
+        assert_eq!(results.total, 2);
+        let ids: Vec<_> = results.documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "mid"]);
+        assert_eq!(results.scores.map(|s| s.len()), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_close_persists_documents() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsfts-close-{}", std::process::id()));
+        let path = dir.to_str().unwrap();
+
+        let engine = SearchEngine::new(path)?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust Programming".to_string(),
+            "Rust is fast".to_string(),
+        ))?;
+        engine.close()?;
+
+        let reopened = SearchEngine::new(path)?;
+        assert_eq!(reopened.search("rust", &SearchOptions::default())?.total, 1);
+        drop(reopened);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_df_ratio_drops_ubiquitous_terms() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        for (id, content) in [
+            ("1", "data pipelines in rust"),
+            ("2", "data science with python"),
+            ("3", "data warehouse design"),
+            ("4", "rust data structures"),
+        ] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                String::new(),
+                content.to_string(),
+            ))?;
+        }
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            max_df_ratio: Some(0.5),
+            ..Default::default()
+        };
+
+        // "data" is in every document and is ignored
+        let results = engine.search("data rust", &options)?;
+        assert_eq!(results.total, 2);
+
+        // A query made only of common terms still works
+        let results = engine.search("data", &options)?;
+        assert_eq!(results.total, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_related_terms() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+
+        for (id, content) in [
+            ("1", "rust ownership borrowing"),
+            ("2", "rust borrowing lifetimes"),
+            ("3", "python generators"),
+        ] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                String::new(),
+                content.to_string(),
+            ))?;
+        }
+
+        let options = SearchOptions {
+            related_terms: Some(2),
+            ..Default::default()
+        };
+        let results = engine.search("rust", &options)?;
+        let related = results.related_terms.unwrap();
+
+        assert_eq!(related.len(), 2);
+        assert_eq!(related[0].term, "borrow");
+        assert_eq!(related[0].count, 2);
+        assert!(related.iter().all(|t| t.term != "rust"));
+
+        let results = engine.search("rust", &SearchOptions::default())?;
+        assert!(results.related_terms.is_none());
+
+        let related = engine.related_terms("Rust", 5);
+        assert_eq!(related[0].term, "borrow");
+        assert_eq!(related[0].count, 2);
+        assert_eq!(related.len(), 3);
+        engine.soft_delete("2")?;
+        assert_eq!(engine.related_terms("rust", 1)[0].count, 1);
+        assert!(engine.related_terms("the", 5).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_candidates_truncates_or_queries() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for i in 0..20 {
+            engine.upsert_document(Document::new(
+                i.to_string(),
+                String::new(),
+                "common words".to_string(),
+            ))?;
+        }
+        engine.upsert_document(Document::new(
+            "rare".to_string(),
+            String::new(),
+            "rare common".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            max_candidates: Some(5),
+            ..Default::default()
+        };
+        let results = engine.search("common rare", &options)?;
+        assert!(results.candidates_truncated);
+        assert!(results.total_is_approximate);
+        assert_eq!(results.total, 5);
+        assert_eq!(results.documents[0].id, "rare");
+
+        let results = engine.search(
+            "common rare",
+            &SearchOptions {
+                max_candidates: None,
+                ..options
+            },
+        )?;
+        assert!(!results.candidates_truncated);
+        assert!(!results.total_is_approximate);
+        assert_eq!(results.total, 21);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_k_only_matches_full_ranking() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for i in 0..50 {
+            engine.upsert_document(Document::new(
+                i.to_string(),
+                String::new(),
+                "common words".to_string(),
+            ))?;
+        }
+        for (id, content) in [
+            ("r1", "rare common"),
+            ("r2", "rare rare filler"),
+            ("r3", "rare filler text here"),
+        ] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                String::new(),
+                content.to_string(),
+            ))?;
+        }
+        engine.soft_delete("r2")?;
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let full = engine.search("rare common", &options)?;
+        let pruned = engine.search(
+            "rare common",
+            &SearchOptions {
+                top_k_only: true,
+                ..options
+            },
+        )?;
+
+        let ids = |r: &SearchResult| r.documents.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&pruned), ids(&full));
+        assert_eq!(pruned.scores, full.scores);
+        assert_eq!(full.total, 52);
+        assert!(pruned.total < full.total);
+        assert!(pruned.candidates_truncated);
+        assert!(pruned.total_is_approximate);
+        assert!(!full.total_is_approximate);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_highlights_title_and_content() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust Programming".to_string(),
+            "Why rust is fast".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            highlight: true,
+            ..Default::default()
+        };
+        let results = engine.search("rust", &options)?;
+        let highlights = results.highlights.unwrap();
+
+        assert_eq!(highlights[0].title, "<em>Rust</em> Programming");
+        assert_eq!(highlights[0].snippet, "Why <em>rust</em> is fast");
+
+        let options = SearchOptions {
+            highlight_tags: Some(("**".to_string(), "**".to_string())),
+            ..options
+        };
+        let highlights = engine.search("rust", &options)?.highlights.unwrap();
+        assert_eq!(highlights[0].title, "**Rust** Programming");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlights_keep_document_casing() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "NASA".to_string(),
+            "Launches by NASA and ESA".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            highlight: true,
+            ..Default::default()
+        };
+        let result = engine.search("nasa", &options)?;
+        assert_eq!(result.documents[0].title, "NASA");
+        let highlight = &result.highlights.unwrap()[0];
+        assert_eq!(highlight.title, "<em>NASA</em>");
+        assert_eq!(highlight.snippet, "Launches by <em>NASA</em> and ESA");
+        assert_eq!(highlight.matched_words, vec!["NASA"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlights_inflected_forms() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Running Notes".to_string(),
+            "The runner runs, then ran; running daily".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            highlight: true,
+            ..Default::default()
+        };
+        let highlight = &engine.search("running", &options)?.highlights.unwrap()[0];
+        assert_eq!(highlight.title, "<em>Running</em> Notes");
+        assert_eq!(
+            highlight.snippet,
+            "The runner <em>runs</em>, then ran; <em>running</em> daily"
+        );
+
+        // The CLI's highlighter marks the same words
+        let highlighter = engine.highlighter_for("running", &options)?;
+        assert_eq!(
+            highlighter.snippet("The runner runs, then ran; running daily", 160),
+            highlight.snippet
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_analyzer_overrides() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "rust is running".to_string(),
+        ))?;
+
+        let unstemmed = SearchOptions {
+            analyzer: AnalyzerOptions {
+                stem: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(engine.search("rust", &unstemmed)?.total, 1);
+        assert_eq!(engine.search("running", &unstemmed)?.total, 0);
+        assert_eq!(
+            engine.search("running", &SearchOptions::default())?.total,
+            1
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_boost() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "plain".to_string(),
+            String::new(),
+            "rust guide".to_string(),
+        ))?;
+        engine.upsert_document(
+            Document::new(
+                "canonical".to_string(),
+                String::new(),
+                "rust guide".to_string(),
+            )
+            .with_boost(2.0),
+        )?;
+
+        let results = engine.search("rust", &SearchOptions::default())?;
+        assert_eq!(results.documents[0].id, "canonical");
+        let scores = results.scores.unwrap();
+        assert!((scores[0] - 2.0 * scores[1]).abs() < 1e-9);
+
+        let invalid =
+            Document::new("bad".to_string(), String::new(), "x".to_string()).with_boost(f64::NAN);
+        assert!(engine.upsert_document(invalid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_title_only_indexing() -> Result<()> {
+        let config = EngineConfig {
+            title_only: true,
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust Mug".to_string(),
+            "Ceramic coffee mug".to_string(),
+        ))?;
+
+        let results = engine.search("mug", &SearchOptions::default())?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].content, "Ceramic coffee mug");
+        assert_eq!(engine.search("coffee", &SearchOptions::default())?.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_weights() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "r".to_string(),
+            String::new(),
+            "rust rust guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "t".to_string(),
+            String::new(),
+            "tutorial tutorial guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "x".to_string(),
+            String::new(),
+            "unrelated".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            ..Default::default()
+        };
+        let results = engine.search("rust tutorial^3", &options)?;
+        assert_eq!(results.documents[0].id, "t");
+
+        let results = engine.search("rust^3 tutorial", &options)?;
+        assert_eq!(results.documents[0].id, "r");
+
+        // Invalid weights fall back to 1.0
+        let plain = engine.search("rust tutorial", &options)?;
+        let invalid = engine.search("rust^abc tutorial^-1", &options)?;
+        assert_eq!(plain.scores, invalid.scores);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_postings_prunes_common_terms() -> Result<()> {
+        let config = EngineConfig {
+            max_postings: Some(2),
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+
+        for (id, content) in [
+            ("1", "data rust"),
+            ("2", "data python"),
+            ("3", "data go"),
+            ("4", "data rust"),
+        ] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                String::new(),
+                content.to_string(),
+            ))?;
+        }
+
+        assert_eq!(engine.pruned_terms(), vec!["data".to_string()]);
+        assert_eq!(engine.index.read().unwrap().doc_frequency("data"), 0);
+
+        // The pruned term is ignored, like a stopword
+        assert_eq!(
+            engine.search("data rust", &SearchOptions::default())?.total,
+            2
+        );
+        assert_eq!(engine.search("data", &SearchOptions::default())?.total, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_metadata_field() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for (id, site, content) in [
+            ("a1", Some("a.com"), "rust rust rust"),
+            ("a2", Some("a.com"), "rust rust"),
+            ("a3", Some("a.com"), "rust"),
+            ("b1", Some("b.com"), "rust"),
+            ("x", None, "rust tips"),
+        ] {
+            let mut doc = Document::new(id.to_string(), String::new(), content.to_string());
+            if let Some(site) = site {
+                doc = doc.with_metadata("site".to_string(), site.to_string());
+            }
+            engine.upsert_document(doc)?;
+        }
+
+        let options = SearchOptions {
+            group_by: Some("site".to_string()),
+            group_size: 2,
+            ..Default::default()
+        };
+        let results = engine.search("rust", &options)?;
+        assert!(results.documents.is_empty());
+        assert_eq!(results.total, 5);
+
+        let groups = results.groups.unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].value.as_deref(), Some("a.com"));
+        assert_eq!(groups[0].total, 3);
+        let ids: Vec<&str> = groups[0].documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["a1", "a2"]);
+        assert_eq!(groups[0].scores.as_ref().unwrap().len(), 2);
+        assert!(groups
+            .iter()
+            .any(|g| g.value.is_none() && g.documents[0].id == "x"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pagination_skips_vanished_documents() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for i in 0..5 {
+            engine.upsert_document(Document::new(
+                i.to_string(),
+                String::new(),
+                "rust".to_string(),
+            ))?;
+        }
+
+        // Simulate a delete racing with the query: indexed, but no longer stored
+        engine.storage.delete_document("0")?;
+        engine.storage.delete_document("3")?;
+
+        let options = SearchOptions {
+            limit: Some(3),
+            ..Default::default()
+        };
+        let results = engine.search("rust", &options)?;
+        assert_eq!(results.documents.len(), 3);
+        assert_eq!(results.scores.unwrap().len(), 3);
+        assert!(results.documents.iter().all(|d| d.id != "0" && d.id != "3"));
+
+        let past_end = SearchOptions {
+            offset: 10,
+            ..options
+        };
+        let results = engine.search("rust", &past_end)?;
+        assert!(results.documents.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_matches_search_score() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "rust systems programming".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            String::new(),
+            "go programming".to_string(),
+        ))?;
+
+        let options = SearchOptions::default().with_profile(RankingProfile::SmartRelevance);
+        let results = engine.search("rust^2 programming -java", &options)?;
+        let explanation = engine
+            .explain_with("rust^2 programming -java", "1", &options)?
+            .unwrap();
+
+        assert_eq!(explanation.optional_terms, vec!["rust", "program"]);
+        assert_eq!(explanation.excluded_terms, vec!["java"]);
+        assert!(!explanation.excluded);
+        assert_eq!(explanation.score.terms[0].weight, 2.0);
+        assert!((explanation.score.score - results.scores.unwrap()[0]).abs() < 1e-12);
+
+        assert!(engine.explain("rust", "missing")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_modified_since() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "old".to_string(),
+            "Rust".to_string(),
+            "Old rust post".to_string(),
+        ))?;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        engine.upsert_document(Document::new(
+            "new".to_string(),
+            "Rust".to_string(),
+            "New rust post".to_string(),
+        ))?;
+
+        let old = engine.get_document("old")?.unwrap();
+        let new = engine.get_document("new")?.unwrap();
+        assert!(new.updated_at > old.updated_at);
+
+        let ids: Vec<_> = engine
+            .list_modified_since(old.updated_at)?
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        assert_eq!(ids, vec!["old", "new"]);
+        let ids: Vec<_> = engine
+            .list_modified_since(new.updated_at)?
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        assert_eq!(ids, vec!["new"]);
+
+        let options = SearchOptions {
+            modified_since: Some(new.updated_at),
+            ..Default::default()
+        };
+        let results = engine.search("rust", &options)?;
+        assert_eq!(results.total, 1);
+        assert_eq!(results.documents[0].id, "new");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_documents() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "empty".to_string(),
+            String::new(),
+            String::new(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+
+        let results = engine.search("rust", &SearchOptions::default())?;
+        assert_eq!(results.total, 1);
+        assert!(results.scores.unwrap().iter().all(|s| s.is_finite()));
+        assert!(engine.get_document("empty")?.is_some());
+
+        let config = EngineConfig {
+            empty_documents: EmptyDocumentPolicy::Skip,
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        engine.batch_insert(vec![
+            Document::new("empty".to_string(), String::new(), "the and".to_string()),
+            Document::new(
+                "1".to_string(),
+                "Rust".to_string(),
+                "Rust guide".to_string(),
+            ),
+        ])?;
+        assert!(engine.get_document("empty")?.is_none());
+        assert_eq!(engine.document_count()?, 1);
+
+        let config = EngineConfig {
+            empty_documents: EmptyDocumentPolicy::Reject,
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        let empty = Document::new("empty".to_string(), String::new(), String::new());
+        assert!(engine.upsert_document(empty).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword_only_document_scores_finite() -> Result<()> {
+        let config = EngineConfig {
+            keyword_fields: vec!["sku".to_string()],
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        let doc = Document::new("1".to_string(), String::new(), String::new())
+            .with_metadata("sku".to_string(), "ABC-1".to_string());
+        engine.upsert_document(doc)?;
+
+        let results = engine.search("sku:ABC-1", &SearchOptions::default())?;
+        assert_eq!(results.total, 1);
+        assert!(results.scores.unwrap()[0].is_finite());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_match_boost() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "stem".to_string(),
+            "Post".to_string(),
+            "she runs daily".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "exact".to_string(),
+            "Post".to_string(),
+            "she running daily".to_string(),
+        ))?;
+
+        // Plain BM25 scores both alike
+        let plain = engine.search("running", &SearchOptions::default())?;
+        let scores = plain.scores.unwrap();
+        assert!((scores[0] - scores[1]).abs() < 1e-9);
+
+        let options = SearchOptions::default().with_profile(RankingProfile::SmartRelevance);
+        let results = engine.search("running", &options)?;
+        assert_eq!(results.documents[0].id, "exact");
+        let scores = results.scores.unwrap();
+        assert!(scores[0] > scores[1]);
+
+        let explanation = engine.explain_with("running", "exact", &options)?.unwrap();
+        assert!(explanation.score.terms[0].exact_match);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reindex_rebuilds_from_storage() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Go guide".to_string(),
+        ))?;
+
+        // Storage and index drift apart, as after a crash or analysis change
+        engine.storage.save_document(&Document::new(
+            "3".to_string(),
+            "Rust".to_string(),
+            "More rust".to_string(),
+        ))?;
+        engine.storage.delete_document("2")?;
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 1);
+
+        assert_eq!(engine.reindex()?, 2);
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 2);
+        assert_eq!(engine.search("go", &SearchOptions::default())?.total, 0);
+        assert!(engine.storage.get_doc_stats("3")?.is_some());
+
+        // Writes still work, and a second reindex can run
+        engine.delete_document("3")?;
+        assert_eq!(engine.reindex()?, 1);
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_during_reindex_are_kept() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let doc = |i: usize| {
+            Document::new(
+                i.to_string(),
+                "Common".to_string(),
+                format!("common text {}", i),
+            )
+        };
+        engine.batch_insert((0..200).map(doc).collect())?;
+
+        std::thread::scope(|scope| -> Result<()> {
+            let rebuild = scope.spawn(|| engine.reindex());
+            for i in 200..250 {
+                engine.upsert_document(doc(i))?;
+            }
+            for i in 0..10 {
+                engine.delete_document(&i.to_string())?;
+            }
+            rebuild.join().unwrap()?;
+            Ok(())
+        })?;
+
+        let options = SearchOptions {
+            limit: None,
+            ..Default::default()
+        };
+        assert_eq!(engine.search("common", &options)?.total, 240);
+        assert_eq!(engine.document_count()?, 240);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_document_language() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let doc = Document::new(
+            "fr".to_string(),
+            "Chanteuses".to_string(),
+            "Les chanteuses françaises".to_string(),
+        )
+        .with_language("fr".to_string());
+        engine.upsert_document(doc)?;
+        engine.upsert_document(Document::new(
+            "en".to_string(),
+            "Singers".to_string(),
+            "The singers".to_string(),
+        ))?;
+
+        let french = SearchOptions {
+            analyzer: AnalyzerOptions::for_language(Language::French),
+            ..Default::default()
+        };
+        let results = engine.search("chanteuse", &french)?;
+        assert_eq!(
+            results
+                .documents
+                .iter()
+                .map(|d| d.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["fr"]
+        );
+        assert_eq!(
+            engine.search("chanteuse", &SearchOptions::default())?.total,
+            0
+        );
+        assert_eq!(engine.search("singer", &SearchOptions::default())?.total, 1);
+
+        let unknown = Document::new("x".to_string(), "X".to_string(), "x".to_string())
+            .with_language("xx".to_string());
+        assert!(engine.upsert_document(unknown).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_count_deleted_documents() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for id in ["1", "2", "3"] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                String::new(),
+                "rust".to_string(),
+            ))?;
+        }
+        engine.soft_delete("2")?;
+
+        let stats = engine.stats()?;
+        assert_eq!(
+            (
+                stats.total_documents,
+                stats.live_documents,
+                stats.deleted_documents
+            ),
+            (3, 2, 1)
+        );
+
+        engine.purge()?;
+        let stats = engine.stats()?;
+        assert_eq!(
+            (
+                stats.total_documents,
+                stats.live_documents,
+                stats.deleted_documents
+            ),
+            (2, 2, 0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_engine_rejects_writes() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsfts-read-only-{}", std::process::id()));
+        let path = dir.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(SearchEngine::open_read_only(path).is_err());
+
+        let writer = SearchEngine::new(path)?;
+        writer.upsert_document(Document::new(
+            "1".to_string(),
+            String::new(),
+            "rust".to_string(),
+        ))?;
+        writer.close()?;
+
+        let engine = SearchEngine::open_read_only(path)?;
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 1);
+        assert!(engine
+            .upsert_document(Document::new(
+                "2".to_string(),
+                String::new(),
+                "go".to_string()
+            ))
+            .is_err());
+        assert!(engine.delete_document("1").is_err());
+        assert!(engine.soft_delete("1").is_err());
+        assert!(engine.clear().is_err());
+        assert!(engine.reindex().is_err());
+        engine.close()?;
+
+        assert!(SearchEngine::new(path)?.get_document("1")?.is_some());
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_rare_terms() -> Result<()> {
+        let config = EngineConfig {
+            keyword_fields: vec!["sku".to_string()],
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        for (id, content, sku) in [
+            ("1", "rust guide", "a1"),
+            ("2", "rust tutorail", "b2"),
+            ("3", "rust book", "c3"),
+        ] {
+            let doc = Document::new(id.to_string(), String::new(), content.to_string())
+                .with_metadata("sku".to_string(), sku.to_string());
+            engine.upsert_document(doc)?;
+        }
+
+        assert_eq!(engine.prune_rare_terms(2)?, 3);
+        assert_eq!(
+            engine.search("tutorail", &SearchOptions::default())?.total,
+            0
+        );
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 3);
+        assert_eq!(engine.search("sku:b2", &SearchOptions::default())?.total, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_literal_query_parser() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "report-final.txt".to_string(),
+            String::new(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "report-draft.txt".to_string(),
+            String::new(),
+        ))?;
+
+        // Read as an exclusion, "-final.txt" rules out every .txt file
+        let simple = engine.search("report -final.txt", &SearchOptions::default())?;
+        assert_eq!(simple.total, 0);
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            query_parser: QueryParser::Literal,
+            ..Default::default()
+        };
+        let literal = engine.search("report -final.txt", &options)?;
+        assert_eq!(literal.total, 1);
+        assert_eq!(literal.documents[0].id, "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let doc = Document::new("1".to_string(), "Rust".to_string(), "Fast".to_string());
+        assert_eq!(doc.digest(), "4961b8b559caff5f");
+
+        engine.upsert_document(doc.clone())?;
+        let stored = engine.get_document("1")?.unwrap();
+        assert_eq!(stored.content_hash, doc.digest());
+
+        engine.upsert_document(doc.clone().with_metadata("k".to_string(), "v".to_string()))?;
+        let changed = engine.get_document("1")?.unwrap();
+        assert_ne!(changed.content_hash, stored.content_hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_query_is_flagged() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+
+        let options = SearchOptions::default();
+        assert!(engine.search("the of ?!", &options)?.empty_query);
+        assert!(!engine.search("python", &options)?.empty_query);
+        assert!(!engine.search("-rust", &options)?.empty_query);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_positions_leave_gap_between_fields() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            position_increment_gap: 10,
+            ..Default::default()
+        })?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Learn Rust".to_string(),
+            "Book club".to_string(),
+        ))?;
+
+        let stats_map = engine.doc_stats.read().unwrap();
+        let positions = &stats_map["1"].positions;
+        assert_eq!(positions["rust"], vec![1]);
+        assert_eq!(positions["book"], vec![12]);
+        assert_eq!(positions["club"], vec![13]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_score_distribution() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust rust rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Go and rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Python".to_string(),
+            "Python".to_string(),
+        ))?;
+
+        let options = SearchOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let distribution = engine.score_distribution("rust", &options)?;
+        let scores = engine
+            .search("rust", &SearchOptions::default())?
+            .scores
+            .unwrap();
+
+        assert_eq!(distribution.count, 2);
+        assert_eq!(distribution.max, scores[0]);
+        assert_eq!(distribution.min, scores[1]);
+        assert_eq!(engine.score_distribution("the", &options)?.count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_insert_skips_bad_documents() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            empty_documents: EmptyDocumentPolicy::Reject,
+            ..Default::default()
+        })?;
+        let docs = || {
+            vec![
+                Document::new(
+                    "1".to_string(),
+                    "Rust".to_string(),
+                    "Rust guide".to_string(),
+                ),
+                Document::new("2".to_string(), String::new(), "the".to_string()),
+                Document::new("3".to_string(), "Go".to_string(), "Go guide".to_string())
+                    .with_boost(f64::NAN),
+                Document::new(
+                    "4".to_string(),
+                    "Python".to_string(),
+                    "Python guide".to_string(),
+                ),
+            ]
+        };
+
+        assert!(engine.batch_insert(docs()).is_err());
+
+        let report = engine.batch_insert_with(docs(), BatchErrorPolicy::Skip)?;
+        assert_eq!(report.indexed, 2);
+        let failed: Vec<(usize, &str)> = report
+            .failures
+            .iter()
+            .map(|f| (f.position, f.id.as_str()))
+            .collect();
+        assert_eq!(failed, vec![(1, "2"), (2, "3")]);
+        assert!(report.failures[0].error.contains("no indexable text"));
+        assert_eq!(engine.search("guide", &SearchOptions::default())?.total, 2);
+        assert_eq!(engine.document_count()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_result_window() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            max_result_window: Some(20),
+            ..Default::default()
+        })?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        let page = |offset, limit| SearchOptions {
+            offset,
+            limit,
+            ..Default::default()
+        };
+
+        assert_eq!(engine.search("rust", &page(10, Some(10)))?.total, 1);
+        assert_eq!(engine.search("rust", &page(20, None))?.documents.len(), 0);
+        let err = engine.search("rust", &page(15, Some(10))).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ResultWindowTooLarge>(),
+            Some(&ResultWindowTooLarge {
+                window: 25,
+                max_result_window: 20
+            })
+        );
+        assert!(engine.search("rust", &page(21, None)).is_err());
+
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            max_result_window: None,
+            ..Default::default()
+        })?;
+        assert!(engine.search("rust", &page(100_000, Some(10))).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_level_sort() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let product = |id: &str, content: &str, price: Option<&str>| {
+            let doc = Document::new(id.to_string(), "Mug".to_string(), content.to_string());
+            match price {
+                Some(price) => doc.with_metadata("price".to_string(), price.to_string()),
+                None => doc,
+            }
+        };
+        engine.upsert_document(product("a", "Ceramic mug", Some("12.5")))?;
+        engine.upsert_document(product("b", "Ceramic mug", None))?;
+        engine.upsert_document(product("c", "Ceramic mug", Some("9")))?;
+        engine.upsert_document(product("d", "Ceramic mug", Some("10")))?;
+        engine.upsert_document(product("e", "Ceramic ceramic mug", Some("30")))?;
+
+        let sorted = |specs: &[&str]| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                sort: specs
+                    .iter()
+                    .map(|spec| SortSpec::from_name(spec).unwrap())
+                    .collect(),
+                ..Default::default()
+            };
+            let result = engine.search("ceramic", &options)?;
+            Ok(result.documents.into_iter().map(|doc| doc.id).collect())
+        };
+        // Equally relevant products cheapest first, those without a price last
+        assert_eq!(
+            sorted(&["relevance", "price"])?,
+            vec!["e", "c", "d", "a", "b"]
+        );
+        assert_eq!(
+            sorted(&["relevance", "price:desc"])?,
+            vec!["e", "a", "d", "c", "b"]
+        );
+        assert_eq!(
+            sorted(&["price:desc", "id"])?,
+            vec!["e", "a", "d", "c", "b"]
+        );
+        // Ties on every level keep the tie-break order
+        assert_eq!(sorted(&["relevance"])?, vec!["e", "a", "b", "c", "d"]);
+
+        // Metadata fields named like a key are sorted on with `metadata.`
+        engine.upsert_document(
+            product("f", "Ceramic mug", None).with_metadata("id".to_string(), "1".to_string()),
+        )?;
+        assert_eq!(
+            sorted(&["metadata.id", "id"])?,
+            vec!["f", "a", "b", "c", "d", "e"]
+        );
+
+        assert_eq!(SortSpec::from_name("relevance:up"), None);
+        assert_eq!(SortSpec::from_name("metadata."), None);
+        assert_eq!(
+            SortSpec::from_name("recency:asc"),
+            Some(SortSpec {
+                key: SortKey::Recency,
+                descending: false
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_cache() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            search_cache_ttl: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 1);
+
+        // Cached results stay as they were until they expire
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Rust".to_string(),
+            "Rust book".to_string(),
+        ))?;
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 1);
+        let fresh = SearchOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        assert_eq!(engine.search("rust", &fresh)?.total, 2);
+
+        // Other options are a different search
+        let or = SearchOptions {
+            mode: SearchMode::Or,
+            ..Default::default()
+        };
+        assert_eq!(engine.search("rust", &or)?.total, 2);
+
+        engine.clear()?;
+        assert_eq!(engine.search("rust", &SearchOptions::default())?.total, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_cache_misses() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            search_cache_ttl: Some(Duration::from_secs(60)),
+            ..Default::default()
+        })?;
+        let cached =
+            |engine: &SearchEngine| engine.search_cache.as_ref().map_or(0, |cache| cache.len());
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+
+        // Searches bypassing the cache don't fill it
+        let fresh = SearchOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+        engine.search("rust", &fresh)?;
+        assert_eq!(cached(&engine), 0);
+
+        // Any changed option is a new entry
+        engine.search("rust", &SearchOptions::default())?;
+        let changed = SearchOptions {
+            highlight: true,
+            ..Default::default()
+        };
+        engine.search("rust", &changed)?;
+        assert_eq!(cached(&engine), 2);
+        assert_ne!(
+            search_cache_key("rust", &changed),
+            search_cache_key("rust", &SearchOptions::default())
+        );
+
+        // Large results aren't kept
+        engine.clear()?;
+        for i in 0..=MAX_CACHED_DOCUMENTS {
+            engine.upsert_document(Document::new(
+                i.to_string(),
+                "Rust".to_string(),
+                "Rust guide".to_string(),
+            ))?;
+        }
+        let unbounded = SearchOptions {
+            limit: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            engine.search("rust", &unbounded)?.documents.len(),
+            MAX_CACHED_DOCUMENTS + 1
+        );
+        assert_eq!(cached(&engine), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_query_terms() -> Result<()> {
+        let config = |long_queries| EngineConfig {
+            max_query_terms: Some(3),
+            long_queries,
+            ..Default::default()
+        };
+        let docs = [
+            Document::new(
+                "1".to_string(),
+                "Rust".to_string(),
+                "Rust guide".to_string(),
+            ),
+            Document::new("2".to_string(), "Go".to_string(), "Go tutorial".to_string()),
+        ];
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            query_analysis: true,
+            ..Default::default()
+        };
+
+        let engine = SearchEngine::in_memory_with_config(config(LongQueryPolicy::Reject))?;
+        engine.batch_insert(docs.to_vec())?;
+        // Stopwords don't count
+        assert_eq!(engine.search("the rust and the guide", &options)?.total, 1);
+        let err = engine
+            .search("rust guide -python go", &options)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<QueryTooLong>(),
+            Some(&QueryTooLong {
+                terms: 4,
+                max_terms: 3
+            })
+        );
+        assert!(engine
+            .score_distribution("rust guide -python go", &options)
+            .is_err());
+        assert!(engine.term_idfs("rust guide -python go", &options).is_err());
+        assert!(engine
+            .highlighter_for("rust guide -python go", &options)
+            .is_err());
+
+        let engine = SearchEngine::in_memory_with_config(config(LongQueryPolicy::Truncate))?;
+        engine.batch_insert(docs.to_vec())?;
+        let result = engine.search("rust guide \"python basics\" go", &options)?;
+        assert_eq!(result.total, 1);
+        let analysis = result.query_analysis.unwrap();
+        assert_eq!(analysis.truncated_terms, 2);
+        assert_eq!(
+            analysis.clauses.last().unwrap().terms,
+            vec!["python".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_idfs() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Go and rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Python".to_string(),
+            "Python".to_string(),
+        ))?;
+
+        let idfs = engine.term_idfs(
+            "Guides +rust -python the guide missing",
+            &SearchOptions::default(),
+        )?;
+        let terms: Vec<&str> = idfs.iter().map(|t| t.term.as_str()).collect();
+        assert_eq!(terms, vec!["guid", "rust", "miss"]);
+        assert_eq!(idfs[0].doc_frequency, 1);
+        assert_eq!(idfs[1].doc_frequency, 2);
+        assert_eq!(idfs[2].doc_frequency, 0);
+        assert_eq!(idfs[2].idf, 0.0);
+        assert!(idfs[0].idf > idfs[1].idf);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rerank() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Go".to_string(),
+            "Go guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Rust".to_string(),
+            "Rust rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        engine.soft_delete("3")?;
+
+        let ids: Vec<String> = ["1", "missing", "2", "3", "2"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let result = engine.rerank("rust", &ids, &SearchOptions::default())?;
+
+        let ranked: Vec<&str> = result.documents.iter().map(|doc| doc.id.as_str()).collect();
+        assert_eq!(ranked, vec!["2", "1"]);
+        assert_eq!(result.total, 2);
+        assert_eq!(result.scores.unwrap()[1], 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generated_ids() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Taken".to_string(),
+            "Taken".to_string(),
+        ))?;
+
+        let first = engine.upsert_document_auto_id(Document::new(
+            String::new(),
+            "A".to_string(),
+            "A".to_string(),
+        ))?;
+        let second = engine.upsert_document_auto_id(Document::new(
+            String::new(),
+            "B".to_string(),
+            "B".to_string(),
+        ))?;
+        assert_ne!(first, "1");
+        assert!(first.parse::<u64>()? < second.parse::<u64>()?);
+        assert_eq!(engine.get_document(&second)?.unwrap().title, "B");
+
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            id_generation: IdGeneration::Random,
+            ..Default::default()
+        })?;
+        let id = engine.generate_id()?;
+        assert_eq!((id.len(), &id[14..15]), (36, "4"));
+        assert_ne!(id, engine.generate_id()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_missing_document_is_noop() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Go language guide".to_string(),
+        ))?;
+        assert_eq!(*engine.avg_doc_length.read().unwrap(), 3.5);
+
+        assert!(!engine.delete_document("missing")?);
+        assert_eq!(engine.stats()?.total_documents, 2);
+        assert_eq!(*engine.avg_doc_length.read().unwrap(), 3.5);
+
+        assert!(engine.delete_document("2")?);
+        assert_eq!(*engine.avg_doc_length.read().unwrap(), 3.0);
+        assert!(!engine.delete_document("2")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_field_restricted_terms() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Memory safety".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Safety".to_string(),
+            "Rust and safety".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Go".to_string(),
+            "Garbage collection".to_string(),
+        ))?;
+
+        let ids = |query: &str, mode: SearchMode| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                mode,
+                ..Default::default()
+            };
+            let mut ids: Vec<String> = engine
+                .search(query, &options)?
+                .documents
+                .into_iter()
+                .map(|d| d.id)
+                .collect();
+            ids.sort();
+            Ok(ids)
         };
 
-        let page_ids = &sorted_ids[start..end];
-        let page_scores = scores.as_ref().map(|s| s[start..end].to_vec());
+        assert_eq!(ids("title:rust", SearchMode::And)?, vec!["1"]);
+        assert_eq!(ids("content:rust", SearchMode::And)?, vec!["2"]);
+        assert_eq!(
+            ids("title:rust^2 content:safety", SearchMode::And)?,
+            vec!["1"]
+        );
+        assert_eq!(
+            ids("title:rust^2 content:safety", SearchMode::Or)?,
+            vec!["1", "2"]
+        );
+        assert_eq!(ids("title:rust go", SearchMode::Or)?, vec!["1", "3"]);
+        assert_eq!(
+            ids(r#"safety -title:"safety""#, SearchMode::And)?,
+            vec!["1"]
+        );
+        assert_eq!(ids(r#"+content:"rust safety""#, SearchMode::Or)?, vec!["2"]);
 
-        // Fetch documents
-        let mut documents = Vec::new();
-        for id in page_ids {
-            if let Some(doc) = self.storage.get_document(id)? {
-                documents.push(doc);
-            }
+        let plain = engine
+            .search("title:rust", &SearchOptions::default())?
+            .scores
+            .unwrap()[0];
+        let boosted = engine
+            .search("title:rust^2", &SearchOptions::default())?
+            .scores
+            .unwrap()[0];
+        assert!((boosted - 2.0 * plain).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_phrase_queries() -> Result<()> {
+        let docs = [
+            ("1", "Notes", "The university study group"),
+            ("2", "Notes", "A universe study of stars"),
+            ("3", "University", "Study hall"),
+            ("4", "Notes", "Study at the university"),
+        ];
+        let ids = |engine: &SearchEngine, query: &str| -> Result<Vec<String>> {
+            let mut ids: Vec<String> = engine
+                .search(query, &SearchOptions::default())?
+                .documents
+                .into_iter()
+                .map(|d| d.id)
+                .collect();
+            ids.sort();
+            Ok(ids)
+        };
+
+        let engine = SearchEngine::in_memory()?;
+        for (id, title, content) in docs {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                title.to_string(),
+                content.to_string(),
+            ))?;
         }
+        // Stems match, word order and field boundaries count
+        assert_eq!(ids(&engine, r#""university study""#)?, vec!["1", "2"]);
+        assert_eq!(
+            ids(&engine, r#"study -"university study""#)?,
+            vec!["3", "4"]
+        );
+        assert_eq!(ids(&engine, r#"content:"study group""#)?, vec!["1"]);
+        assert_eq!(
+            ids(&engine, r#"title:"study group""#)?,
+            Vec::<String>::new()
+        );
 
-        Ok(SearchResult {
-            documents,
-            total,
-            scores: page_scores,
-        })
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            exact_phrases: true,
+            ..Default::default()
+        })?;
+        for (id, title, content) in docs {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                title.to_string(),
+                content.to_string(),
+            ))?;
+        }
+        assert_eq!(ids(&engine, r#""university study""#)?, vec!["1"]);
+        assert_eq!(ids(&engine, r#""universe""#)?, vec!["2"]);
+        assert_eq!(ids(&engine, "universe")?, vec!["1", "2", "3", "4"]);
+
+        Ok(())
     }
 
-    /// Get index statistics
-    pub fn stats(&self) -> Result<crate::index::IndexStats> {
-        let index = self.index.read().unwrap();
-        Ok(index.stats())
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust rust guide".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Go".to_string(),
+            "Go and rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Go".to_string(),
+            "Go guide".to_string(),
+        ))?;
+
+        let judgments = vec![
+            Judgment {
+                query: "rust".to_string(),
+                relevant: vec!["2".to_string()],
+            },
+            Judgment {
+                query: "go".to_string(),
+                relevant: vec!["2".to_string(), "3".to_string()],
+            },
+        ];
+        let options = SearchOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let report = engine.evaluate(&judgments, &options)?;
+
+        assert_eq!(report.k, 2);
+        assert_eq!(report.queries[0].first_relevant_rank, Some(2));
+        assert_eq!(report.queries[1].recall, 1.0);
+        assert_eq!(report.mrr, 0.75);
+        assert_eq!(report.precision_at_k, 0.75);
+
+        Ok(())
     }
 
-    /// Get total document count
-    pub fn document_count(&self) -> Result<usize> {
-        self.storage.count_documents()
+    #[test]
+    fn test_snapshot_saves_keep_latest_index() -> Result<()> {
+        let engine = Arc::new(SearchEngine::in_memory_with_config(EngineConfig {
+            snapshot_index_saves: true,
+            ..Default::default()
+        })?);
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let engine = engine.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    for i in 0..25 {
+                        let id = format!("{}-{}", t, i);
+                        engine.upsert_document(Document::new(
+                            id,
+                            "Rust".to_string(),
+                            "Rust guide".to_string(),
+                        ))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+
+        let saved = engine.storage.load_index()?.unwrap();
+        assert_eq!(saved.doc_frequency("rust"), 100);
+
+        engine.delete_document("0-0")?;
+        assert_eq!(
+            engine.storage.load_index()?.unwrap().doc_frequency("rust"),
+            99
+        );
+
+        Ok(())
     }
 
-    /// Flush all changes to disk
-    pub fn flush(&self) -> Result<()> {
-        self.storage.flush()
+    #[test]
+    fn test_get_by_title() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "A language".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Rust (programming language)".to_string(),
+            "Rust".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "3".to_string(),
+            "Main  Page".to_string(),
+            "Welcome".to_string(),
+        ))?;
+
+        let ids = |title: &str| -> Result<Vec<String>> {
+            Ok(engine
+                .get_by_title(title)?
+                .into_iter()
+                .map(|d| d.id)
+                .collect())
+        };
+        assert_eq!(ids("rust")?, vec!["1"]);
+        assert_eq!(ids(" main page ")?, vec!["3"]);
+        assert!(ids("Main")?.is_empty());
+
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Iron oxide".to_string(),
+            "Rust".to_string(),
+        ))?;
+        assert!(ids("Rust")?.is_empty());
+        assert_eq!(ids("iron OXIDE")?, vec!["1"]);
+
+        engine.soft_delete("3")?;
+        assert!(ids("Main Page")?.is_empty());
+        engine.delete_document("1")?;
+        assert!(ids("Iron oxide")?.is_empty());
+
+        Ok(())
     }
 
-    /// Clear all data
-    pub fn clear(&self) -> Result<()> {
-        {
-            let mut index = self.index.write().unwrap();
-            *index = InvertedIndex::new();
-        }
-        {
-            let mut stats = self.doc_stats.write().unwrap();
-            stats.clear();
+    #[test]
+    fn test_index_unstemmed() -> Result<()> {
+        let config = EngineConfig {
+            tokenizer: TokenizerConfig {
+                index_unstemmed: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Notes".to_string(),
+            "The universe is big".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Notes".to_string(),
+            "A university in town".to_string(),
+        ))?;
+
+        // Both share the stem "univers"; the exact word ranks first
+        let results = engine.search("university", &SearchOptions::default())?;
+        let ids: Vec<&str> = results.documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "1"]);
+        let results = engine.search("universe", &SearchOptions::default())?;
+        assert_eq!(results.documents[0].id, "1");
+
+        let stats = engine.doc_stats.read().unwrap()["2"].clone();
+        assert_eq!(stats.length, 3);
+        assert_eq!(stats.term_frequencies.get("=university"), Some(&1));
+
+        // Coordination counts words, not their two forms
+        let smart = SearchOptions::default().with_profile(RankingProfile::SmartRelevance);
+        let explanation = engine
+            .explain_with("university town", "2", &smart)?
+            .unwrap();
+        assert_eq!(explanation.score.coordination, 1.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_title_phrases_with_unstemmed_terms() -> Result<()> {
+        let config = EngineConfig {
+            tokenizer: TokenizerConfig {
+                index_unstemmed: true,
+                ..Default::default()
+            },
+            position_increment_gap: 0,
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Running shoes".to_string(),
+            "shoes running".to_string(),
+        ))?;
+
+        // Unstemmed forms add title terms but not title positions
+        assert_eq!(engine.doc_stats.read().unwrap()["1"].title_length, 2);
+        assert_eq!(
+            engine
+                .search("title:\"running shoes\"", &SearchOptions::default())?
+                .total,
+            1
+        );
+        assert_eq!(
+            engine
+                .search("title:\"shoes running\"", &SearchOptions::default())?
+                .total,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_authority_field() -> Result<()> {
+        let config = EngineConfig {
+            authority_field: Some("pagerank".to_string()),
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        let doc = |id: &str, pagerank: &str| {
+            Document::new(
+                id.to_string(),
+                "Rust".to_string(),
+                "Rust ownership".to_string(),
+            )
+            .with_metadata("pagerank".to_string(), pagerank.to_string())
+        };
+        engine.upsert_document(doc("1", "0.5"))?;
+        engine.upsert_document(doc("2", "4"))?;
+        engine.upsert_document(doc("3", "not a number"))?;
+        assert_eq!(engine.doc_stats.read().unwrap()["3"].authority, None);
+
+        let ids = |options: &SearchOptions| -> Result<Vec<String>> {
+            let results = engine.search("rust", options)?;
+            Ok(results.documents.into_iter().map(|d| d.id).collect())
+        };
+        assert_eq!(ids(&SearchOptions::default())?, vec!["1", "2", "3"]);
+
+        let mut options = SearchOptions::default();
+        options.ranking.authority_weight = 1.0;
+        assert_eq!(ids(&options)?, vec!["2", "1", "3"]);
+        options.limit = Some(1);
+        assert_eq!(ids(&options)?, vec!["2"]);
+
+        let explanation = engine.explain_with("rust", "2", &options)?.unwrap();
+        assert_eq!(explanation.score.authority, 5.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_timeout() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let docs = (0..200)
+            .map(|i| Document::new(i.to_string(), "Rust".to_string(), "rust ".repeat(i % 7 + 1)))
+            .collect();
+        engine.batch_insert(docs)?;
+
+        let options = SearchOptions {
+            limit: Some(5),
+            offset: 2,
+            ..Default::default()
+        };
+        let full = engine.search("rust", &options)?;
+
+        // Time to spare: the same page, keeping only the best while scoring
+        let timed = engine.search(
+            "rust",
+            &SearchOptions {
+                timeout: Some(Duration::from_secs(60)),
+                ..options.clone()
+            },
+        )?;
+        assert!(!timed.timed_out);
+        assert_eq!(timed.total, 200);
+        assert_eq!(timed.scores, full.scores);
+        let ids = |result: &SearchResult| {
+            result
+                .documents
+                .iter()
+                .map(|d| d.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&timed), ids(&full));
+
+        // Out of time: the best of what was scored
+        for top_k_only in [false, true] {
+            let expired = engine.search(
+                "rust",
+                &SearchOptions {
+                    timeout: Some(Duration::ZERO),
+                    mode: SearchMode::Or,
+                    top_k_only,
+                    ..options.clone()
+                },
+            )?;
+            assert!(expired.timed_out);
+            assert_eq!(expired.documents.len(), 5);
         }
-        self.storage.clear()?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_max_document_tokens() -> Result<()> {
+        let config = EngineConfig {
+            max_document_tokens: Some(4),
+            ..Default::default()
+        };
+        let engine = SearchEngine::in_memory_with_config(config)?;
+        let content = "memory safety without garbage collection";
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            content.to_string(),
+        ))?;
+
+        assert_eq!(engine.doc_stats.read().unwrap()["1"].length, 4);
+        assert_eq!(engine.search("safety", &SearchOptions::default())?.total, 1);
+        assert_eq!(
+            engine.search("garbage", &SearchOptions::default())?.total,
+            0
+        );
+        assert_eq!(engine.get_document("1")?.unwrap().content, content);
+        Ok(())
+    }
 
     #[test]
-    fn test_engine_insert_and_search() -> Result<()> {
+    fn test_min_term_frequencies() -> Result<()> {
         let engine = SearchEngine::in_memory()?;
+        let docs = [
+            ("1", "Rust once, memory safety"),
+            ("2", "Rust, Rust and more Rust. Memory."),
+            ("3", "Rust, Rust and more Rust. Memory, memory."),
+        ];
+        for (id, content) in docs {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                "Notes".to_string(),
+                content.to_string(),
+            ))?;
+        }
 
-        let doc1 = Document::new(
+        let ids = |result: SearchResult| {
+            result
+                .documents
+                .into_iter()
+                .map(|d| d.id)
+                .collect::<Vec<_>>()
+        };
+        let mut found = ids(engine.search_with_min_tf(&[("rust", 3)], &SearchOptions::default())?);
+        found.sort();
+        assert_eq!(found, vec!["2", "3"]);
+
+        // Thresholds are per word
+        let found = engine
+            .search_with_min_tf(&[("rust", 3), ("memories", 2)], &SearchOptions::default())?;
+        assert_eq!(ids(found), vec!["3"]);
+
+        // A filter on the matches of another query, on the top-k path too
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            top_k_only: true,
+            min_term_frequencies: vec![("memory".to_string(), 2)],
+            ..Default::default()
+        };
+        assert_eq!(ids(engine.search("safety rust", &options)?), vec!["3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_text_fields() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let doc = Document::new(
             "1".to_string(),
-            "Rust Programming".to_string(),
-            "Rust is a systems programming language".to_string(),
+            "Rust".to_string(),
+            "Ownership rules".to_string(),
+        )
+        .with_field("summary".to_string(), "Borrowing explained".to_string())
+        .with_field("notes".to_string(), "Lifetimes later".to_string());
+        engine.upsert_document(doc.clone())?;
+
+        assert_eq!(
+            engine.search("borrowing", &SearchOptions::default())?.total,
+            1
+        );
+        assert_eq!(
+            engine.search("lifetimes", &SearchOptions::default())?.total,
+            1
+        );
+        // Fields don't run into each other
+        assert_eq!(
+            engine
+                .search("\"rules lifetimes\"", &SearchOptions::default())?
+                .total,
+            0
         );
+        // Named fields aren't part of the content
+        assert_eq!(
+            engine
+                .search("content:borrowing", &SearchOptions::default())?
+                .total,
+            0
+        );
+        assert_eq!(
+            engine
+                .search("content:\"explained\"", &SearchOptions::default())?
+                .total,
+            0
+        );
+        assert_eq!(
+            engine
+                .search("content:ownership", &SearchOptions::default())?
+                .total,
+            1
+        );
+        assert_eq!(engine.get_document("1")?.unwrap().fields, doc.fields);
 
-        let doc2 = Document::new(
+        let plain = Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Ownership rules".to_string(),
+        );
+        assert_ne!(doc.digest(), plain.digest());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_whole_email_and_url() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            tokenizer: TokenizerConfig {
+                keep_urls_and_emails: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        let content = "From Jane.Doe@example.com about (https://example.com/billing).";
+        engine.upsert_document(Document::new(
+            "1".to_string(),
+            "Ticket".to_string(),
+            content.to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
             "2".to_string(),
-            "Go Programming".to_string(),
-            "Go is a simple programming language".to_string(),
+            "Ticket".to_string(),
+            "Jane at example".to_string(),
+        ))?;
+
+        let ids = |query: &str| -> Result<Vec<String>> {
+            let result = engine.search(query, &SearchOptions::default())?;
+            Ok(result.documents.into_iter().map(|doc| doc.id).collect())
+        };
+        assert_eq!(ids("jane.doe@example.com")?, vec!["1"]);
+        assert_eq!(ids("https://example.com/billing")?, vec!["1"]);
+        assert_eq!(ids("content:https://example.com/billing")?, vec!["1"]);
+        assert_eq!(ids("jane example")?, vec!["2"]);
+
+        let highlighter =
+            engine.highlighter_for("jane.doe@example.com", &SearchOptions::default())?;
+        assert_eq!(
+            highlighter.highlight(content),
+            "From <em>Jane.Doe@example.com</em> about (https://example.com/billing)."
         );
+        Ok(())
+    }
 
-        engine.upsert_document(doc1)?;
-        engine.upsert_document(doc2)?;
+    #[test]
+    fn test_best_matching_sections() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        let guide = Document::new(
+            "1".to_string(),
+            "Rust guide".to_string(),
+            "An overview".to_string(),
+        )
+        .with_section(
+            "install".to_string(),
+            "Installation".to_string(),
+            "Run rustup".to_string(),
+        )
+        .with_section(
+            "borrowing".to_string(),
+            "Borrowing".to_string(),
+            "The borrow checker".to_string(),
+        );
+        engine.upsert_document(guide.clone())?;
+        engine.upsert_document(Document::new(
+            "2".to_string(),
+            "Borrow".to_string(),
+            "Flat".to_string(),
+        ))?;
 
-        let results = engine.search("programming language", &SearchOptions::default())?;
-        assert_eq!(results.total, 2);
+        let options = SearchOptions {
+            sections: true,
+            ..Default::default()
+        };
+        let result = engine.search("borrow", &options)?;
+        assert_eq!(result.total, 2);
+        let sections: HashMap<&str, &Option<SectionMatch>> = result
+            .documents
+            .iter()
+            .map(|doc| doc.id.as_str())
+            .zip(result.sections.as_ref().unwrap())
+            .collect();
+        assert_eq!(
+            sections["1"],
+            &Some(SectionMatch {
+                index: 1,
+                anchor: "borrowing".to_string(),
+                heading: "Borrowing".to_string(),
+            })
+        );
+        assert_eq!(sections["2"], &None);
+
+        assert_eq!(
+            engine.search("rustup", &options)?.sections.unwrap()[0]
+                .as_ref()
+                .unwrap()
+                .anchor,
+            "install"
+        );
+        // Sections don't run into each other
+        assert_eq!(
+            engine
+                .search("\"rustup borrowing\"", &SearchOptions::default())?
+                .total,
+            0
+        );
+        assert!(engine
+            .search("rustup", &SearchOptions::default())?
+            .sections
+            .is_none());
+        assert_eq!(engine.get_document("1")?.unwrap().sections, guide.sections);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_analysis() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        for (id, content) in [
+            ("1", "Rust memory safety"),
+            ("2", "Rust and Go"),
+            ("3", "Rust and Java"),
+        ] {
+            engine.upsert_document(Document::new(
+                id.to_string(),
+                "Notes".to_string(),
+                content.to_string(),
+            ))?;
+        }
+
+        let options = SearchOptions {
+            mode: SearchMode::Or,
+            max_df_ratio: Some(0.5),
+            query_analysis: true,
+            ..Default::default()
+        };
+        let result = engine.search("the +rust -java \"memory safety\"", &options)?;
+        let analysis = result.query_analysis.expect("requested");
+        assert!(matches!(analysis.mode, Some(SearchMode::Or)));
+        let occurs: Vec<Occur> = analysis.clauses.iter().map(|c| c.occur).collect();
+        assert_eq!(
+            occurs,
+            vec![Occur::Should, Occur::Must, Occur::MustNot, Occur::Should]
+        );
+        assert!(analysis.clauses[3].phrase);
+        assert_eq!(analysis.clauses[3].terms.len(), 2);
+        assert_eq!(analysis.stopwords, vec!["the"]);
+        // In every document, so dropped by max_df_ratio
+        assert_eq!(analysis.common_terms, vec!["rust"]);
+
+        let json = serde_json::to_value(engine.search("rust", &options)?.query_analysis)?;
+        assert_eq!(json["mode"], "or");
+        assert_eq!(json["clauses"][0]["occur"], "should");
+
+        assert!(engine
+            .search("rust", &SearchOptions::default())?
+            .query_analysis
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_replay() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsfts-wal-replay-{}", std::process::id()));
+        let path = dir.to_str().unwrap();
+        let wal_path = dir.join(WAL_FILE);
+        let config = EngineConfig {
+            write_ahead_log: true,
+            durability: Durability::None,
+            flush_on_drop: false,
+            ..Default::default()
+        };
+        let doc = |id: &str, content: &str| {
+            Document::new(id.to_string(), "Notes".to_string(), content.to_string())
+        };
+
+        let engine = SearchEngine::with_config(path, config.clone())?;
+        engine.batch_insert(vec![doc("1", "rust memory"), doc("2", "rust threads")])?;
+        engine.delete_document("2")?;
+        assert_eq!(Wal::read(&wal_path)?.len(), 3);
+        engine.flush()?;
+        assert!(Wal::read(&wal_path)?.is_empty());
+        engine.upsert_document(doc("3", "rust async"))?;
+        drop(engine);
+
+        // A write logged but never applied, as when the process is killed
+        // between the two
+        let mut pending = doc("4", "rust macros");
+        pending.content_hash = pending.digest();
+        let wal = Wal::open(&wal_path)?;
+        wal.append(&WalOp::Upsert(Box::new(pending)))?;
+        wal.sync()?;
+
+        // sled releases its file lock from a background thread after drop
+        let mut reopened = SearchEngine::with_config(path, config.clone());
+        for _ in 0..50 {
+            if reopened.is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            reopened = SearchEngine::with_config(path, config.clone());
+        }
+        let reopened = reopened?;
+        let mut ids: Vec<String> = reopened
+            .search("rust", &SearchOptions::default())?
+            .documents
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "3", "4"]);
+        assert!(Wal::read(&wal_path)?.is_empty());
+        drop(reopened);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword_fields() -> Result<()> {
+        let engine = SearchEngine::in_memory_with_config(EngineConfig {
+            keyword_fields: vec!["sku".to_string(), "country".to_string()],
+            ..Default::default()
+        })?;
+
+        engine.upsert_document(
+            Document::new(
+                "1".to_string(),
+                "Widget".to_string(),
+                "A small widget".to_string(),
+            )
+            .with_metadata("sku".to_string(), "ABC-123".to_string())
+            .with_metadata("country".to_string(), "US".to_string()),
+        )?;
+        engine.upsert_document(
+            Document::new(
+                "2".to_string(),
+                "Gadget".to_string(),
+                "Model abc 123".to_string(),
+            )
+            .with_metadata("sku".to_string(), "ABC-124".to_string())
+            .with_metadata("country".to_string(), "DE".to_string()),
+        )?;
+
+        let ids = |query: &str| -> Result<Vec<String>> {
+            let results = engine.search(query, &SearchOptions::default())?;
+            Ok(results.documents.into_iter().map(|d| d.id).collect())
+        };
+
+        assert_eq!(ids("sku:abc-123")?, vec!["1"]);
+        assert_eq!(ids("country:us")?, vec!["1"]);
+        assert_eq!(ids("country:US widget")?, vec!["1"]);
+        assert!(ids("sku:abc")?.is_empty());
+
+        // Plain text search doesn't see the keyword value split up
+        assert_eq!(ids("abc 123")?, vec!["2"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_documents_preserves_order() -> Result<()> {
+        let engine = SearchEngine::in_memory()?;
+        engine.upsert_document(Document::new(
+            "a".to_string(),
+            "A".to_string(),
+            "First".to_string(),
+        ))?;
+        engine.upsert_document(Document::new(
+            "b".to_string(),
+            "B".to_string(),
+            "Second".to_string(),
+        ))?;
+
+        let ids = vec![
+            "b".to_string(),
+            "missing".to_string(),
+            "a".to_string(),
+            "b".to_string(),
+        ];
+        let docs = engine.get_documents(&ids)?;
+
+        let found: Vec<Option<&str>> = docs
+            .iter()
+            .map(|d| d.as_ref().map(|d| d.id.as_str()))
+            .collect();
+        assert_eq!(found, vec![Some("b"), None, Some("a"), Some("b")]);
 
         Ok(())
     }
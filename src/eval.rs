@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// A query and the documents a good ranking returns for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Judgment {
+    pub query: String,
+    /// Ids of the relevant documents
+    pub relevant: Vec<String>,
+}
+
+/// Metrics of one judged query, counting the first `k` results
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryEvaluation {
+    pub query: String,
+    /// 1-based rank of the first relevant result, if one was returned
+    pub first_relevant_rank: Option<usize>,
+    pub reciprocal_rank: f64,
+    /// Fraction of the `k` result slots holding a relevant document
+    pub precision: f64,
+    /// Fraction of the relevant documents returned
+    pub recall: f64,
+}
+
+impl QueryEvaluation {
+    /// Score a ranking against a judgment
+    pub fn new(judgment: &Judgment, ranked_ids: &[String], k: usize) -> Self {
+        let relevant: HashSet<&str> = judgment.relevant.iter().map(String::as_str).collect();
+        let top = &ranked_ids[..ranked_ids.len().min(k)];
+
+        let first_relevant_rank = top
+            .iter()
+            .position(|id| relevant.contains(id.as_str()))
+            .map(|i| i + 1);
+        let hits = top
+            .iter()
+            .filter(|id| relevant.contains(id.as_str()))
+            .count();
+
+        Self {
+            query: judgment.query.clone(),
+            first_relevant_rank,
+            reciprocal_rank: first_relevant_rank.map_or(0.0, |rank| 1.0 / rank as f64),
+            precision: if k == 0 { 0.0 } else { hits as f64 / k as f64 },
+            recall: if relevant.is_empty() {
+                0.0
+            } else {
+                hits as f64 / relevant.len() as f64
+            },
+        }
+    }
+}
+
+/// Relevance metrics over a set of judgments, averaged over the queries
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    /// Number of results counted per query
+    pub k: usize,
+    /// Mean reciprocal rank
+    pub mrr: f64,
+    pub precision_at_k: f64,
+    pub recall_at_k: f64,
+    pub queries: Vec<QueryEvaluation>,
+}
+
+impl EvalReport {
+    pub fn new(k: usize, queries: Vec<QueryEvaluation>) -> Self {
+        let mean = |metric: fn(&QueryEvaluation) -> f64| {
+            if queries.is_empty() {
+                0.0
+            } else {
+                queries.iter().map(metric).sum::<f64>() / queries.len() as f64
+            }
+        };
+
+        Self {
+            k,
+            mrr: mean(|q| q.reciprocal_rank),
+            precision_at_k: mean(|q| q.precision),
+            recall_at_k: mean(|q| q.recall),
+            queries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_metrics() {
+        let judgment = Judgment {
+            query: "rust".to_string(),
+            relevant: vec!["2".to_string(), "5".to_string()],
+        };
+        let ranked: Vec<String> = ["1", "2", "3", "5"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        let evaluation = QueryEvaluation::new(&judgment, &ranked, 3);
+        assert_eq!(evaluation.first_relevant_rank, Some(2));
+        assert_eq!(evaluation.reciprocal_rank, 0.5);
+        assert_eq!(evaluation.precision, 1.0 / 3.0);
+        assert_eq!(evaluation.recall, 0.5);
+
+        let missed = QueryEvaluation::new(&judgment, &ranked[..1], 3);
+        let report = EvalReport::new(3, vec![evaluation, missed]);
+        assert_eq!(report.mrr, 0.25);
+        assert_eq!(report.recall_at_k, 0.25);
+    }
+}
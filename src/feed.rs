@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+/// One entry of an RSS feed
+#[derive(Debug, Clone)]
+pub struct FeedItem {
+    pub id: String,
+    pub title: String,
+    pub link: Option<String>,
+    /// Plain text summary
+    pub description: String,
+    /// Milliseconds since the Unix epoch
+    pub updated_at: u64,
+}
+
+/// Render an RSS 2.0 channel
+///
+/// Items keep the order given. The document id is each item's `guid`, so
+/// feed readers recognize an updated document as the same item.
+pub fn rss(title: &str, link: &str, description: &str, items: &[FeedItem]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n",
+    );
+    let _ = writeln!(xml, "<title>{}</title>", escape(title));
+    let _ = writeln!(xml, "<link>{}</link>", escape(link));
+    let _ = writeln!(xml, "<description>{}</description>", escape(description));
+    if let Some(latest) = items.iter().map(|item| item.updated_at).max() {
+        let _ = writeln!(xml, "<lastBuildDate>{}</lastBuildDate>", rfc2822(latest));
+    }
+
+    for item in items {
+        xml.push_str("<item>\n");
+        let _ = writeln!(xml, "<title>{}</title>", escape(&item.title));
+        if let Some(link) = &item.link {
+            let _ = writeln!(xml, "<link>{}</link>", escape(link));
+        }
+        let _ = writeln!(
+            xml,
+            "<guid isPermaLink=\"false\">{}</guid>",
+            escape(&item.id)
+        );
+        let _ = writeln!(
+            xml,
+            "<description>{}</description>",
+            escape(&item.description)
+        );
+        let _ = writeln!(xml, "<pubDate>{}</pubDate>", rfc2822(item.updated_at));
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// Escape text for XML character data and attribute values, dropping
+/// control characters XML 1.0 doesn't allow
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A Unix time in milliseconds as an RFC 2822 date in GMT, as RSS uses
+fn rfc2822(millis: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = millis / 1000;
+    let days = secs / 86_400;
+    let (hour, minute, second) = (secs % 86_400 / 3600, secs % 3600 / 60, secs % 60);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// Year, month (1-12) and day of a count of days since 1970-01-01
+/// (Howard Hinnant's `civil_from_days`)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc2822() {
+        assert_eq!(rfc2822(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(rfc2822(951_782_400_000), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(rfc2822(1_700_000_000_123), "Tue, 14 Nov 2023 22:13:20 GMT");
+    }
+
+    #[test]
+    fn test_rss_escapes_text() {
+        let item = FeedItem {
+            id: "1".to_string(),
+            title: "Fish & <Chips>".to_string(),
+            link: None,
+            description: "Say \"hi\"\u{0}".to_string(),
+            updated_at: 0,
+        };
+        let xml = rss(
+            "Search: fish",
+            "/search.rss?query=fish&limit=5",
+            "Results",
+            &[item],
+        );
+        assert!(xml.contains("<title>Fish &amp; &lt;Chips&gt;</title>"));
+        assert!(xml.contains("<description>Say &quot;hi&quot;</description>"));
+        assert!(xml.contains("<link>/search.rss?query=fish&amp;limit=5</link>"));
+        // Only the channel has a link
+        assert_eq!(xml.matches("<link>").count(), 1);
+    }
+}
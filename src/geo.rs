@@ -0,0 +1,96 @@
+use crate::document::Document;
+
+/// Mean Earth radius in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Metadata keys holding a document's coordinates
+pub const LAT_KEY: &str = "lat";
+pub const LON_KEY: &str = "lon";
+
+/// Keep only documents within a radius of a point
+#[derive(Debug, Clone)]
+pub struct GeoFilter {
+    pub lat: f64,
+    pub lon: f64,
+    pub radius_km: f64,
+    /// Order results by distance instead of by score
+    pub sort_by_distance: bool,
+}
+
+impl GeoFilter {
+    pub fn new(lat: f64, lon: f64, radius_km: f64) -> Self {
+        Self {
+            lat,
+            lon,
+            radius_km,
+            sort_by_distance: false,
+        }
+    }
+
+    pub fn sorted_by_distance(mut self) -> Self {
+        self.sort_by_distance = true;
+        self
+    }
+
+    /// Distance from the center to a document, if it has valid coordinates
+    pub fn distance_km(&self, doc: &Document) -> Option<f64> {
+        let (lat, lon) = coordinates(doc)?;
+        Some(haversine_km(self.lat, self.lon, lat, lon))
+    }
+
+    /// Distance to a document if it lies within the radius
+    pub fn matches(&self, doc: &Document) -> Option<f64> {
+        self.distance_km(doc).filter(|&d| d <= self.radius_km)
+    }
+}
+
+/// Parse a document's `lat`/`lon` metadata
+pub fn coordinates(doc: &Document) -> Option<(f64, f64)> {
+    let lat: f64 = doc.metadata.get(LAT_KEY)?.trim().parse().ok()?;
+    let lon: f64 = doc.metadata.get(LON_KEY)?.trim().parse().ok()?;
+
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+        Some((lat, lon))
+    } else {
+        None
+    }
+}
+
+/// Great-circle distance between two points in kilometers
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine() {
+        // Paris to London is roughly 344 km
+        let d = haversine_km(48.8566, 2.3522, 51.5074, -0.1278);
+        assert!((d - 343.5).abs() < 1.0);
+        assert_eq!(haversine_km(10.0, 10.0, 10.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn test_coordinates_require_valid_metadata() {
+        let doc = Document::new("1".to_string(), "Cafe".to_string(), "Coffee".to_string());
+        assert!(coordinates(&doc).is_none());
+
+        let doc = doc.with_metadata("lat".to_string(), "48.85".to_string());
+        assert!(coordinates(&doc).is_none());
+
+        let doc = doc.with_metadata("lon".to_string(), "not a number".to_string());
+        assert!(coordinates(&doc).is_none());
+
+        let doc = doc.with_metadata("lon".to_string(), "2.35".to_string());
+        assert_eq!(coordinates(&doc), Some((48.85, 2.35)));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::document::Document;
+use crate::tokenizer::{AnalyzerOptions, Language, Tokenizer};
+use serde::Serialize;
+use std::collections::HashSet;
+
+pub const DEFAULT_PRE_TAG: &str = "<em>";
+pub const DEFAULT_POST_TAG: &str = "</em>";
+
+/// Content snippet length used for search results
+pub const DEFAULT_SNIPPET_CHARS: usize = 160;
+
+/// Highlighted display fields of a matching document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Highlight {
+    /// The full title with matches wrapped
+    pub title: String,
+    /// A content excerpt around the first match
+    pub snippet: String,
+    /// Distinct matching words of the title and content as the document
+    /// writes them, in order of appearance: "NASA" for a query "nasa"
+    pub matched_words: Vec<String>,
+}
+
+/// Marks query term matches in text
+///
+/// Each word of the text is analyzed with the same tokenizer and analyzer
+/// options as the query, so inflected forms ("runs" for a query "running")
+/// match while the original surface form is what gets wrapped. Matching
+/// ignores case but the text is never rewritten, so a query "nasa" wraps
+/// "NASA" as written.
+pub struct Highlighter<'a> {
+    tokenizer: &'a Tokenizer,
+    terms: HashSet<String>,
+    analyzer: AnalyzerOptions,
+    pre_tag: String,
+    post_tag: String,
+    escape_html: bool,
+}
+
+impl<'a> Highlighter<'a> {
+    /// Create a highlighter for analyzed query terms
+    pub fn new(tokenizer: &'a Tokenizer, terms: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            tokenizer,
+            terms: terms.into_iter().collect(),
+            analyzer: AnalyzerOptions::default(),
+            pre_tag: DEFAULT_PRE_TAG.to_string(),
+            post_tag: DEFAULT_POST_TAG.to_string(),
+            escape_html: false,
+        }
+    }
+
+    /// Analyze words in the language the query terms were analyzed in
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.analyzer.language = language;
+        self
+    }
+
+    /// Analyze words as the query terms were analyzed, so with stemming
+    /// off a query "running" doesn't highlight "runs"
+    pub fn with_analyzer(mut self, analyzer: AnalyzerOptions) -> Self {
+        self.analyzer = analyzer;
+        self
+    }
+
+    pub fn with_tags(mut self, pre_tag: String, post_tag: String) -> Self {
+        self.pre_tag = pre_tag;
+        self.post_tag = post_tag;
+        self
+    }
+
+    /// Escape `&`, `<`, `>` and `"` in the highlighted title and snippet,
+    /// but not in the tags, so the output can be put into a page as HTML
+    /// whatever the document contains
+    pub fn with_html_escaping(mut self, escape_html: bool) -> Self {
+        self.escape_html = escape_html;
+        self
+    }
+
+    /// Byte ranges of the words in text that match a query term
+    pub fn match_spans(&self, text: &str) -> Vec<(usize, usize)> {
+        self.tokenizer
+            .word_spans(text)
+            .into_iter()
+            .filter(|&(start, end)| {
+                self.tokenizer
+                    .analyze_with(&text[start..end], &self.analyzer)
+                    .iter()
+                    .any(|token| self.terms.contains(token))
+            })
+            .collect()
+    }
+
+    /// Wrap every match in the full text
+    pub fn highlight(&self, text: &str) -> String {
+        let spans = self.match_spans(text);
+        self.mark(text, 0, text.len(), &spans)
+    }
+
+    /// Highlight a document's title and extract a content snippet
+    pub fn document(&self, doc: &Document, snippet_chars: usize) -> Highlight {
+        let title_spans = self.match_spans(&doc.title);
+        let content_spans = self.match_spans(&doc.content);
+
+        let mut matched_words: Vec<String> = Vec::new();
+        let words = title_spans
+            .iter()
+            .map(|&(start, end)| &doc.title[start..end])
+            .chain(
+                content_spans
+                    .iter()
+                    .map(|&(start, end)| &doc.content[start..end]),
+            );
+        for word in words {
+            if !matched_words.iter().any(|w| w == word) {
+                matched_words.push(word.to_string());
+            }
+        }
+
+        Highlight {
+            title: self.mark(&doc.title, 0, doc.title.len(), &title_spans),
+            snippet: self.snippet_with_spans(&doc.content, &content_spans, snippet_chars),
+            matched_words,
+        }
+    }
+
+    /// Extract about `max_chars` characters around the first match, with
+    /// matches wrapped. Falls back to the start of the text if nothing matches.
+    pub fn snippet(&self, text: &str, max_chars: usize) -> String {
+        self.snippet_with_spans(text, &self.match_spans(text), max_chars)
+    }
+
+    /// [`snippet`](Self::snippet) with the matches already found
+    fn snippet_with_spans(&self, text: &str, spans: &[(usize, usize)], max_chars: usize) -> String {
+        let char_starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        let total_chars = char_starts.len();
+
+        if total_chars <= max_chars {
+            return self.mark(text, 0, text.len(), spans);
+        }
+
+        let byte_to_char = |byte: usize| char_starts.partition_point(|&b| b < byte);
+
+        // Center the window on the first match
+        let (match_start, match_len) = spans
+            .first()
+            .map(|&(start, end)| (byte_to_char(start), byte_to_char(end) - byte_to_char(start)))
+            .unwrap_or((0, 0));
+        let start_char = match_start.saturating_sub(max_chars.saturating_sub(match_len) / 2);
+        let end_char = (start_char + max_chars).min(total_chars);
+        let start_char = end_char.saturating_sub(max_chars);
+
+        let mut start = char_starts[start_char];
+        let mut end = char_starts.get(end_char).copied().unwrap_or(text.len());
+
+        // Don't cut words in half at the window edges
+        let words = self.tokenizer.word_spans(text);
+        if let Some(&(_, word_end)) = words.iter().find(|&&(s, e)| s < start && e > start) {
+            start = word_end;
+        }
+        if let Some(&(word_start, _)) = words.iter().find(|&&(s, e)| s < end && e > end) {
+            end = word_start.max(start);
+        }
+
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push_str("...");
+        }
+        snippet.push_str(self.mark(text, start, end, spans).trim());
+        if end < text.len() {
+            snippet.push_str("...");
+        }
+        snippet
+    }
+
+    /// Copy `text[start..end]`, wrapping the spans that fall inside it
+    fn mark(&self, text: &str, start: usize, end: usize, spans: &[(usize, usize)]) -> String {
+        let mut out = String::new();
+        let mut cursor = start;
+
+        for &(span_start, span_end) in spans {
+            if span_start < start || span_end > end {
+                continue;
+            }
+            self.push_text(&mut out, &text[cursor..span_start]);
+            out.push_str(&self.pre_tag);
+            self.push_text(&mut out, &text[span_start..span_end]);
+            out.push_str(&self.post_tag);
+            cursor = span_end;
+        }
+        self.push_text(&mut out, &text[cursor..end]);
+
+        out
+    }
+
+    fn push_text(&self, out: &mut String, text: &str) {
+        if !self.escape_html {
+            out.push_str(text);
+            return;
+        }
+        for c in text.chars() {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(tokenizer: &Tokenizer, query: &str) -> Vec<String> {
+        tokenizer.analyze(query)
+    }
+
+    #[test]
+    fn test_highlight_matches_inflected_forms() {
+        let tokenizer = Tokenizer::new();
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "running"));
+
+        let out = highlighter.highlight("She runs daily; running is her thing.");
+        assert_eq!(
+            out,
+            "She <em>runs</em> daily; <em>running</em> is her thing."
+        );
+
+        // "ran" has a stem of its own, so it isn't a match
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "connections"));
+        let out = highlighter.highlight("Connected, connecting; he ran a connection.");
+        assert_eq!(
+            out,
+            "<em>Connected</em>, <em>connecting</em>; he ran a <em>connection</em>."
+        );
+    }
+
+    #[test]
+    fn test_highlight_follows_query_analyzer() {
+        let tokenizer = Tokenizer::new();
+        let analyzer = AnalyzerOptions {
+            stem: false,
+            remove_stopwords: false,
+            ..Default::default()
+        };
+        let terms = tokenizer.analyze_with("running the", &analyzer);
+        let highlighter = Highlighter::new(&tokenizer, terms).with_analyzer(analyzer);
+
+        let out = highlighter.highlight("The dog runs; running is fun.");
+        assert_eq!(out, "<em>The</em> dog runs; <em>running</em> is fun.");
+
+        let french = AnalyzerOptions::for_language(Language::French);
+        let highlighter =
+            Highlighter::new(&tokenizer, tokenizer.analyze_with("chanteuses", &french))
+                .with_analyzer(french);
+        assert_eq!(
+            highlighter.highlight("Une chanteuse"),
+            "Une <em>chanteuse</em>"
+        );
+    }
+
+    #[test]
+    fn test_snippet_centers_on_first_match() {
+        let tokenizer = Tokenizer::new();
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "rust"))
+            .with_tags("[".to_string(), "]".to_string());
+
+        let text = format!(
+            "{} Rust is great {}",
+            "lorem ipsum ".repeat(20),
+            "dolor sit ".repeat(20)
+        );
+        let snippet = highlighter.snippet(&text, 40);
+
+        assert!(snippet.starts_with("..."));
+        assert!(snippet.ends_with("..."));
+        assert!(snippet.contains("[Rust]"));
+        assert!(snippet.len() < 60);
+    }
+
+    #[test]
+    fn test_snippet_handles_multibyte_text() {
+        let tokenizer = Tokenizer::new();
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "café"));
+
+        let text = "ééééé ".repeat(30) + "café au lait";
+        let snippet = highlighter.snippet(&text, 20);
+        assert!(snippet.contains("<em>café</em>"));
+    }
+
+    #[test]
+    fn test_document_highlights_title() {
+        let tokenizer = Tokenizer::new();
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "rust"));
+
+        let doc = Document::new(
+            "1".to_string(),
+            "Learning Rust".to_string(),
+            "A guide to the language".to_string(),
+        );
+        let highlight = highlighter.document(&doc, DEFAULT_SNIPPET_CHARS);
+        assert_eq!(highlight.title, "Learning <em>Rust</em>");
+        assert_eq!(highlight.snippet, "A guide to the language");
+        assert_eq!(highlight.matched_words, vec!["Rust"]);
+    }
+
+    #[test]
+    fn test_highlight_keeps_original_casing() {
+        let tokenizer = Tokenizer::new();
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "nasa"));
+
+        let doc = Document::new(
+            "1".to_string(),
+            "NASA".to_string(),
+            "Nasa, NASA and nasa all refer to NASA".to_string(),
+        );
+        let highlight = highlighter.document(&doc, DEFAULT_SNIPPET_CHARS);
+        assert_eq!(highlight.title, "<em>NASA</em>");
+        assert_eq!(
+            highlight.snippet,
+            "<em>Nasa</em>, <em>NASA</em> and <em>nasa</em> all refer to <em>NASA</em>"
+        );
+        assert_eq!(highlight.matched_words, vec!["NASA", "Nasa", "nasa"]);
+    }
+
+    #[test]
+    fn test_html_escaping_keeps_tags() {
+        let tokenizer = Tokenizer::new();
+        let content = "<script>alert(\"rust\")</script> Rust & safety";
+
+        let highlighter =
+            Highlighter::new(&tokenizer, terms(&tokenizer, "rust script")).with_html_escaping(true);
+        assert_eq!(
+            highlighter.highlight(content),
+            "&lt;<em>script</em>&gt;alert(&quot;<em>rust</em>&quot;)&lt;/<em>script</em>&gt; <em>Rust</em> &amp; safety"
+        );
+        let snippet = highlighter.snippet(content, 20);
+        assert!(!snippet.contains("<script") && !snippet.contains(" & "));
+        assert!(snippet.contains("<em>"));
+
+        // Off by default
+        let highlighter = Highlighter::new(&tokenizer, terms(&tokenizer, "rust"));
+        assert!(highlighter.highlight(content).starts_with("<script>"));
+    }
+}
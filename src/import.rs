@@ -0,0 +1,248 @@
+use crate::document::Document;
+use crate::tokenizer::{AnalyzerOptions, Tokenizer};
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::BufRead;
+
+/// Number of duplicate ids listed in a summary
+const MAX_REPORTED_DUPLICATES: usize = 20;
+
+/// Result of validating documents before an import
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub total: usize,
+    /// Documents with an id and some title or content
+    pub valid: usize,
+    pub empty_ids: usize,
+    pub empty_titles: usize,
+    pub empty_contents: usize,
+    /// Documents whose id was already seen earlier in the batch
+    pub duplicate_ids: usize,
+    /// The first few duplicated ids
+    pub duplicate_examples: Vec<String>,
+    /// Estimated number of tokens that would be indexed
+    pub estimated_tokens: usize,
+}
+
+impl ImportSummary {
+    /// Run the analysis pipeline over documents without writing anything
+    pub fn from_documents<'a>(
+        docs: impl IntoIterator<Item = &'a Document>,
+        tokenizer: &Tokenizer,
+    ) -> Self {
+        let mut summary = Self::default();
+        let mut seen = HashSet::new();
+
+        for doc in docs {
+            summary.total += 1;
+
+            let empty_title = doc.title.trim().is_empty();
+            let empty_content = doc.content.trim().is_empty();
+            if empty_title {
+                summary.empty_titles += 1;
+            }
+            if empty_content {
+                summary.empty_contents += 1;
+            }
+
+            if doc.id.is_empty() {
+                summary.empty_ids += 1;
+            } else if !seen.insert(doc.id.as_str()) {
+                summary.duplicate_ids += 1;
+                if summary.duplicate_examples.len() < MAX_REPORTED_DUPLICATES {
+                    summary.duplicate_examples.push(doc.id.clone());
+                }
+            }
+
+            let has_text = !empty_title || !empty_content;
+            if !doc.id.is_empty() && has_text {
+                summary.valid += 1;
+            }
+
+            let analyzer =
+                AnalyzerOptions::for_language(doc.analyzer_language().unwrap_or_default());
+            summary.estimated_tokens += tokenizer
+                .analyze_with(&doc.searchable_text(), &analyzer)
+                .len();
+        }
+
+        summary
+    }
+}
+
+/// Streams the `<doc>` entries of a Wikipedia abstracts dump
+/// (`enwiki-latest-abstract.xml`) as documents, without loading the whole
+/// dump
+///
+/// Each document's id is its position in the dump, starting at 0, so a
+/// re-run of the same dump produces the same ids.
+pub struct WikiDocuments<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    next_index: usize,
+}
+
+#[derive(Clone, Copy)]
+enum WikiField {
+    Title,
+    Url,
+    Abstract,
+}
+
+impl<R: BufRead> WikiDocuments<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            next_index: 0,
+        }
+    }
+
+    fn read_document(&mut self) -> Result<Option<Document>> {
+        let (mut title, mut url, mut text) = (String::new(), String::new(), String::new());
+        // Depth below the current <doc>, if inside one
+        let mut depth: Option<usize> = None;
+        let mut field = None;
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Eof => return Ok(None),
+                Event::Start(e) => match depth {
+                    None if e.name().as_ref() == b"doc" => depth = Some(0),
+                    None => {}
+                    Some(d) => {
+                        // Only the doc's own fields, not the <url>s of nested elements
+                        if d == 0 {
+                            field = match e.name().as_ref() {
+                                b"title" => Some(WikiField::Title),
+                                b"url" => Some(WikiField::Url),
+                                b"abstract" => Some(WikiField::Abstract),
+                                _ => None,
+                            };
+                        }
+                        depth = Some(d + 1);
+                    }
+                },
+                Event::End(_) => match depth {
+                    Some(0) => {
+                        let id = self.next_index.to_string();
+                        self.next_index += 1;
+                        let (title, url, text) = (title.trim(), url.trim(), text.trim());
+                        return Ok(Some(
+                            Document::new(id, title.to_string(), text.to_string())
+                                .with_url(url.to_string()),
+                        ));
+                    }
+                    Some(d) => {
+                        field = None;
+                        depth = Some(d - 1);
+                    }
+                    None => {}
+                },
+                Event::Text(e) => {
+                    if let Some(field) = field {
+                        push_field(field, &e.unescape()?, &mut title, &mut url, &mut text);
+                    }
+                }
+                Event::CData(e) => {
+                    if let Some(field) = field {
+                        let data = String::from_utf8_lossy(&e);
+                        push_field(field, &data, &mut title, &mut url, &mut text);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn push_field(
+    field: WikiField,
+    data: &str,
+    title: &mut String,
+    url: &mut String,
+    text: &mut String,
+) {
+    match field {
+        WikiField::Title => title.push_str(data),
+        WikiField::Url => url.push_str(data),
+        WikiField::Abstract => text.push_str(data),
+    }
+}
+
+impl<R: BufRead> Iterator for WikiDocuments<R> {
+    type Item = Result<Document>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_document().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_counts_problems() {
+        let docs = vec![
+            Document::new(
+                "1".to_string(),
+                "Rust".to_string(),
+                "Fast language".to_string(),
+            ),
+            Document::new("2".to_string(), String::new(), "No title here".to_string()),
+            Document::new("3".to_string(), "  ".to_string(), String::new()),
+            Document::new(
+                "1".to_string(),
+                "Rust again".to_string(),
+                "Duplicate".to_string(),
+            ),
+            Document::new(String::new(), "No id".to_string(), "Content".to_string()),
+        ];
+
+        let summary = ImportSummary::from_documents(&docs, &Tokenizer::new());
+
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.valid, 3);
+        assert_eq!(summary.empty_ids, 1);
+        assert_eq!(summary.empty_titles, 2);
+        assert_eq!(summary.empty_contents, 1);
+        assert_eq!(summary.duplicate_ids, 1);
+        assert_eq!(summary.duplicate_examples, vec!["1".to_string()]);
+        assert!(summary.estimated_tokens > 0);
+    }
+
+    #[test]
+    fn test_wiki_documents_stream() -> Result<()> {
+        let xml = r#"<feed>
+<doc>
+<title>Wikipedia: Rust &amp; Cargo</title>
+<url>https://en.wikipedia.org/wiki/Rust</url>
+<abstract>A systems <![CDATA[programming]]> language.</abstract>
+<links><sublink linktype="nav"><anchor>History</anchor><url>https://example.com/nested</url></sublink></links>
+</doc>
+<doc>
+<title>Wikipedia: Empty</title>
+<abstract/>
+</doc>
+</feed>"#;
+
+        let docs: Vec<Document> = WikiDocuments::new(xml.as_bytes()).collect::<Result<_>>()?;
+
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].id, "0");
+        assert_eq!(docs[0].title, "Wikipedia: Rust & Cargo");
+        assert_eq!(
+            docs[0].url.as_deref(),
+            Some("https://en.wikipedia.org/wiki/Rust")
+        );
+        assert_eq!(docs[0].content, "A systems programming language.");
+        assert_eq!(docs[1].id, "1");
+        assert_eq!(docs[1].content, "");
+        Ok(())
+    }
+}
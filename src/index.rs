@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
 
 /// Inverted index: token -> list of document IDs
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -21,7 +22,7 @@ impl InvertedIndex {
         let unique_tokens: HashSet<_> = tokens.iter().collect();
 
         for token in unique_tokens {
-            let doc_list = self.index.entry(token.clone()).or_insert_with(Vec::new);
+            let doc_list = self.index.entry(token.clone()).or_default();
 
             // Only add if not already present
             if !doc_list.contains(&doc_id.to_string()) {
@@ -34,10 +35,15 @@ impl InvertedIndex {
 
     /// Remove a document from the index
     pub fn remove_document(&mut self, doc_id: &str) {
+        let mut found = false;
         for doc_list in self.index.values_mut() {
+            let before = doc_list.len();
             doc_list.retain(|id| id != doc_id);
+            found |= doc_list.len() != before;
+        }
+        if found {
+            self.doc_count = self.doc_count.saturating_sub(1);
         }
-        self.doc_count = self.doc_count.saturating_sub(1);
 
         // Clean up empty entries
         self.index.retain(|_, docs| !docs.is_empty());
@@ -102,11 +108,93 @@ impl InvertedIndex {
         result.into_iter().collect()
     }
 
+    /// OR query that stops collecting after `max` documents, starting with
+    /// the rarest tokens. Returns the documents and whether any were left out.
+    pub fn search_or_limited(&self, tokens: &[String], max: usize) -> (Vec<String>, bool) {
+        let mut tokens: Vec<&String> = tokens.iter().collect();
+        tokens.sort_by_key(|token| self.doc_frequency(token));
+
+        let mut result: HashSet<String> = HashSet::new();
+        for token in tokens {
+            for doc in self.get_documents(token).into_iter().flatten() {
+                if result.len() >= max && !result.contains(doc) {
+                    return (result.into_iter().collect(), true);
+                }
+                result.insert(doc.clone());
+            }
+        }
+
+        (result.into_iter().collect(), false)
+    }
+
+    /// Remove a token and its whole posting list
+    pub fn remove_token(&mut self, token: &str) -> bool {
+        self.index.remove(token).is_some()
+    }
+
     /// Get all tokens in the index
     pub fn all_tokens(&self) -> Vec<&String> {
         self.index.keys().collect()
     }
 
+    /// Every term with its document frequency, in no particular order
+    pub fn terms(&self) -> impl Iterator<Item = (&String, usize)> + '_ {
+        self.index
+            .iter()
+            .map(|(term, postings)| (term, postings.len()))
+    }
+
+    /// Split the posting lists into groups of about `max_bytes` of ids and
+    /// terms each, for storing as separate values. A list longer than that
+    /// on its own gets a group of its own.
+    pub fn segments(&self, max_bytes: usize) -> Vec<Vec<(&String, &Vec<String>)>> {
+        let mut segments = Vec::new();
+        let mut segment = Vec::new();
+        let mut size = 0;
+
+        for (token, docs) in &self.index {
+            let entry_size = token.len() + docs.iter().map(String::len).sum::<usize>();
+            if !segment.is_empty() && size + entry_size > max_bytes {
+                segments.push(std::mem::take(&mut segment));
+                size = 0;
+            }
+            segment.push((token, docs));
+            size += entry_size;
+        }
+        if !segment.is_empty() {
+            segments.push(segment);
+        }
+
+        segments
+    }
+
+    /// Rebuild an index from the posting lists of its [`segments`](Self::segments)
+    pub fn from_segments(
+        segments: impl IntoIterator<Item = Vec<(String, Vec<String>)>>,
+        doc_count: usize,
+    ) -> Self {
+        Self {
+            index: segments.into_iter().flatten().collect(),
+            doc_count,
+        }
+    }
+
+    /// Approximate heap and table memory held by the index, in bytes
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let table = self.index.capacity() * (size_of::<String>() + size_of::<Vec<String>>());
+        let entries: usize = self
+            .index
+            .iter()
+            .map(|(token, docs)| {
+                token.capacity()
+                    + docs.capacity() * size_of::<String>()
+                    + docs.iter().map(String::capacity).sum::<usize>()
+            })
+            .sum();
+
+        size_of::<Self>() + table + entries
+    }
+
     /// Get index statistics
     pub fn stats(&self) -> IndexStats {
         IndexStats {
@@ -117,6 +205,9 @@ impl InvertedIndex {
             } else {
                 self.index.values().map(|v| v.len()).sum::<usize>() as f64 / self.index.len() as f64
             },
+            estimated_memory_bytes: self.estimated_memory_bytes(),
+            live_documents: self.doc_count,
+            deleted_documents: 0,
         }
     }
 }
@@ -126,4 +217,66 @@ pub struct IndexStats {
     pub total_documents: usize,
     pub total_tokens: usize,
     pub avg_docs_per_token: f64,
+    /// Approximate memory used by the loaded index
+    pub estimated_memory_bytes: usize,
+    /// Documents not soft-deleted; the index alone counts all of them
+    pub live_documents: usize,
+    /// Soft-deleted documents waiting for a purge (filled in by the engine)
+    pub deleted_documents: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_count_on_update() {
+        let mut index = InvertedIndex::new();
+        index.update_document("1", &["rust".to_string()]);
+        index.update_document("2", &["go".to_string()]);
+        index.update_document("1", &["rust".to_string(), "fast".to_string()]);
+        assert_eq!(index.total_documents(), 2);
+
+        index.remove_document("missing");
+        assert_eq!(index.total_documents(), 2);
+
+        index.remove_document("1");
+        assert_eq!(index.total_documents(), 1);
+    }
+
+    #[test]
+    fn test_search_or_limited_prefers_rare_tokens() {
+        let mut index = InvertedIndex::new();
+        for id in ["1", "2", "3", "4"] {
+            index.update_document(id, &["common".to_string()]);
+        }
+        index.update_document("5", &["rare".to_string()]);
+
+        let tokens = vec!["common".to_string(), "rare".to_string()];
+        let (docs, truncated) = index.search_or_limited(&tokens, 2);
+        assert!(truncated);
+        assert_eq!(docs.len(), 2);
+        assert!(docs.contains(&"5".to_string()));
+
+        let (docs, truncated) = index.search_or_limited(&tokens, 5);
+        assert!(!truncated);
+        assert_eq!(docs.len(), 5);
+    }
+
+    #[test]
+    fn test_estimated_memory_grows_with_index() {
+        let mut index = InvertedIndex::new();
+        let empty = index.estimated_memory_bytes();
+
+        index.add_document("doc-1", &["rust".to_string(), "search".to_string()]);
+        let one = index.estimated_memory_bytes();
+        assert!(one > empty);
+
+        index.add_document("doc-2", &["rust".to_string()]);
+        assert!(index.estimated_memory_bytes() > one);
+        assert_eq!(
+            index.stats().estimated_memory_bytes,
+            index.estimated_memory_bytes()
+        );
+    }
 }
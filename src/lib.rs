@@ -1,18 +1,50 @@
 // Re-export main components
 pub mod api;
+pub mod cache;
+pub mod diff;
 pub mod document;
 pub mod engine;
+pub mod eval;
+pub mod feed;
+pub mod geo;
+pub mod highlight;
+pub mod import;
 pub mod index;
+pub mod multi;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod query;
+pub mod queue;
 pub mod ranking;
 pub mod storage;
 pub mod tokenizer;
+pub mod wal;
 
 // Re-export commonly used types
-pub use document::Document;
-pub use engine::{SearchEngine, SearchMode, SearchOptions, SearchResult};
+pub use diff::{DiffSummary, DocumentDiff};
+pub use document::{DisplayValue, Document, Section};
+pub use engine::{
+    BatchErrorPolicy, BatchReport, ClauseAnalysis, DocumentFailure, EmptyDocumentPolicy,
+    EngineConfig, Explanation, IdGeneration, LongQueryPolicy, QueryAnalysis, QueryTooLong,
+    ResultGroup, ResultWindowTooLarge, SearchEngine, SearchMode, SearchOptions, SearchResult,
+    SectionMatch, TermCount, TermExportFormat, DEFAULT_SEARCH_CACHE_CAPACITY,
+};
+pub use eval::{EvalReport, Judgment};
+pub use geo::GeoFilter;
+pub use highlight::{Highlight, Highlighter};
+pub use import::ImportSummary;
 pub use index::InvertedIndex;
-pub use storage::Storage;
-pub use tokenizer::Tokenizer;
+pub use multi::{IndexInUse, MultiEngine};
+pub use query::QueryParser;
+pub use queue::IndexQueue;
+pub use ranking::{
+    AuthorityScale, InvalidScorePolicy, RankingOptions, RankingProfile, ScoreDistribution, SortKey,
+    SortSpec, TermIdf, TieBreak,
+};
+pub use storage::{Compression, Durability, RetryPolicy, Storage};
+pub use tokenizer::{
+    AnalyzerOptions, Casing, Language, NumericTokens, TokenFilter, Tokenizer, TokenizerConfig,
+};
 
 // Re-export error types
 pub use anyhow::{Error, Result};
@@ -20,6 +52,12 @@ pub use anyhow::{Error, Result};
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Short git commit the library was built from, when built from a checkout
+pub const GIT_HASH: Option<&str> = option_env!("RSFTS_GIT_HASH");
+
+/// Version of the compiler the library was built with
+pub const RUSTC_VERSION: Option<&str> = option_env!("RSFTS_RUSTC_VERSION");
+
 #[cfg(test)]
 mod tests {
     use super::*;
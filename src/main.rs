@@ -1,7 +1,14 @@
-use clap::{Parser, Subcommand};
-use rsfts::{api, Document, SearchEngine, SearchOptions};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rsfts::queue::DEFAULT_QUEUE_CAPACITY;
+use rsfts::{
+    api, diff, Casing, Document, DocumentDiff, Durability, EmptyDocumentPolicy, EngineConfig,
+    IdGeneration, ImportSummary, IndexQueue, Judgment, LongQueryPolicy, MultiEngine, NumericTokens,
+    RankingProfile, RetryPolicy, SearchEngine, SearchMode, SearchOptions, Storage,
+    TermExportFormat, TokenFilter, Tokenizer, TokenizerConfig, DEFAULT_SEARCH_CACHE_CAPACITY,
+};
+use std::io::IsTerminal;
 use std::sync::Arc;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 #[derive(Parser)]
 #[command(name = "rsfts")]
@@ -14,16 +21,7 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Start HTTP API server
-    Serve {
-        #[arg(short, long, default_value = "127.0.0.1")]
-        host: String,
-
-        #[arg(short, long, default_value = "3000")]
-        port: u16,
-
-        #[arg(short = 'd', long, default_value = "./data")]
-        data_dir: String,
-    },
+    Serve(Box<ServeArgs>),
 
     /// Insert a document (CLI mode)
     Insert {
@@ -72,6 +70,38 @@ enum Commands {
         #[arg(short, long)]
         id: String,
 
+        /// Mark the document deleted instead of removing it
+        #[arg(long)]
+        soft: bool,
+
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Restore a soft-deleted document
+    Restore {
+        #[arg(short, long)]
+        id: String,
+
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Permanently remove all soft-deleted documents
+    Purge {
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Remove terms found in fewer than --min-df documents from the index
+    PruneRareTerms {
+        #[arg(long, default_value = "2")]
+        min_df: usize,
+
+        /// Keyword field the index was served with; its terms are kept (repeatable)
+        #[arg(long = "keyword-field")]
+        keyword_fields: Vec<String>,
+
         #[arg(short = 'd', long, default_value = "./data")]
         data_dir: String,
     },
@@ -82,36 +112,456 @@ enum Commands {
         data_dir: String,
     },
 
+    /// Export all documents to a file
+    Export {
+        #[arg(short, long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+
+        #[arg(short, long)]
+        out: String,
+
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Write the term dictionary with document frequencies, one term per line
+    ExportTerms {
+        #[arg(short, long, value_enum, default_value = "tsv")]
+        format: TermFormat,
+
+        #[arg(short, long)]
+        out: String,
+
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Measure ranking quality against judged queries (MRR, precision@k, recall@k)
+    Evaluate {
+        /// JSON array of {"query": "...", "relevant": ["id", ...]}
+        #[arg(long)]
+        judgments: String,
+
+        /// Results counted per query
+        #[arg(short, long, default_value = "10")]
+        k: usize,
+
+        /// Ranking profile: bm25 or smart
+        #[arg(long, value_enum, default_value = "bm25")]
+        profile: Profile,
+
+        /// Match any query term instead of all of them
+        #[arg(long)]
+        or: bool,
+
+        /// Exit with an error if the MRR is below this value (for CI)
+        #[arg(long)]
+        min_mrr: Option<f64>,
+
+        /// Print the metrics of every query
+        #[arg(long)]
+        details: bool,
+
+        #[arg(short = 'd', long, default_value = "./data")]
+        data_dir: String,
+    },
+
+    /// Compare the documents stored in two data directories
+    Diff {
+        /// First data directory
+        #[arg(long)]
+        a: String,
+
+        /// Second data directory
+        #[arg(long)]
+        b: String,
+
+        /// Print each differing id, not just the summary
+        #[arg(long)]
+        details: bool,
+    },
+
     /// Import documents from Wikipedia XML dump
     ImportWiki {
         #[arg(short, long)]
         file: String,
 
+        /// Parse and analyze the dump and print a summary without writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Skip articles whose title and abstract have no indexable text
+        #[arg(long)]
+        skip_empty: bool,
+
+        /// Import only the first N articles of the dump
+        #[arg(long)]
+        max_docs: Option<usize>,
+
+        /// Ignore the checkpoint of an interrupted import and start from the beginning
+        #[arg(long)]
+        restart: bool,
+
         #[arg(short = 'd', long, default_value = "./data")]
         data_dir: String,
     },
 }
 
+/// Options of the serve command, boxed since they outweigh every other
+/// command's
+#[derive(Args)]
+struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(short, long, default_value = "3000")]
+    port: u16,
+
+    #[arg(short = 'd', long, default_value = "./data")]
+    data_dir: String,
+
+    /// Metadata field to index as an exact keyword (repeatable)
+    #[arg(long = "keyword-field")]
+    keyword_fields: Vec<String>,
+
+    /// Index only titles (content is stored but not searchable)
+    #[arg(long)]
+    title_only: bool,
+
+    /// Leading symbol kept on tokens, e.g. '#' for hashtags (repeatable)
+    #[arg(long = "keep-prefix")]
+    keep_prefixes: Vec<char>,
+
+    /// Split camelCase and snake_case identifiers into their parts
+    #[arg(long)]
+    split_identifiers: bool,
+
+    /// Keep email addresses and URLs whole, so they can be searched for
+    /// exactly (changing it needs a reindex)
+    #[arg(long)]
+    keep_urls_and_emails: bool,
+
+    /// Lowercasing rules: unicode, or turkic for Turkish and Azeri text
+    #[arg(long, value_enum, default_value = "unicode")]
+    casing: CaseLocale,
+
+    /// Token filters to apply, in order: lowercase, stopwords, stem or
+    /// asciifold (changing them needs a reindex)
+    #[arg(long, value_parser = parse_filter, value_delimiter = ',', default_value = "lowercase,stopwords,stem")]
+    filters: Vec<TokenFilter>,
+
+    /// Leave tokens made only of digits out of documents and queries
+    #[arg(long)]
+    drop_numbers: bool,
+
+    /// Leave out only numbers with more than N digits (timestamps, ids)
+    #[arg(long, conflicts_with = "drop_numbers")]
+    max_number_digits: Option<usize>,
+
+    /// Also index unstemmed words, ranking exact word matches above
+    /// stem-only ones (roughly doubles the index size)
+    #[arg(long)]
+    index_unstemmed: bool,
+
+    /// Metadata field with a numeric authority (e.g. PageRank) that
+    /// searches can blend into scores with `authority_weight`
+    #[arg(long)]
+    authority_field: Option<String>,
+
+    /// Index only the first N words of each document (title first), so
+    /// one huge document can't stall ingestion
+    #[arg(long)]
+    max_document_tokens: Option<usize>,
+
+    /// Most terms a search query may have after analysis
+    #[arg(long)]
+    max_query_terms: Option<usize>,
+
+    /// What to do with queries over --max-query-terms
+    #[arg(long, value_enum, default_value = "reject")]
+    long_queries: LongQueries,
+
+    /// Most results a search may page through (offset + limit), e.g.
+    /// 10000 (default: no limit)
+    #[arg(long)]
+    max_result_window: Option<usize>,
+
+    /// Answer a repeated search from results cached for this many
+    /// seconds; may be stale by up to that long (default: no cache)
+    #[arg(long)]
+    search_cache_ttl: Option<u64>,
+
+    /// Most searches the cache holds
+    #[arg(long, default_value_t = DEFAULT_SEARCH_CACHE_CAPACITY)]
+    search_cache_size: usize,
+
+    /// Drop terms from the index once they appear in more than N documents
+    #[arg(long)]
+    max_postings: Option<usize>,
+
+    /// Queue inserts and index them in the background (responds 202)
+    #[arg(long)]
+    async_indexing: bool,
+
+    /// Documents the indexing queue holds before inserts wait
+    #[arg(long, default_value_t = DEFAULT_QUEUE_CAPACITY)]
+    queue_capacity: usize,
+
+    /// What to do with documents that have no indexable text
+    #[arg(long, value_enum, default_value = "index")]
+    empty_documents: EmptyDocuments,
+
+    /// When writes are flushed: every write, periodically, or left to sled
+    #[arg(long, value_enum, default_value = "sync")]
+    durability: DurabilityLevel,
+
+    /// With --durability async, milliseconds between background flushes
+    #[arg(long, default_value = "1000")]
+    flush_interval_ms: u64,
+
+    /// Times a storage write failing with a transient I/O error is
+    /// retried, with backoff, before the request fails
+    #[arg(long, default_value_t = RetryPolicy::default().max_retries)]
+    storage_retries: u32,
+
+    /// Size of the segments the saved index is split into, in MiB
+    #[arg(long, default_value_t = 16)]
+    index_segment_mb: usize,
+
+    /// Log each write to wal.log in the data directory before returning
+    /// and replay it on startup, so --durability async or none loses
+    /// nothing on a crash
+    #[arg(long)]
+    wal: bool,
+
+    /// Match quoted phrases on the exact words, not their stems (uses
+    /// more storage)
+    #[arg(long)]
+    exact_phrases: bool,
+
+    /// Save the index from a copy taken after each write instead of under
+    /// its write lock, so searches don't wait for saves; costs a clone of
+    /// the index per write
+    #[arg(long)]
+    snapshot_index_saves: bool,
+
+    /// Give documents inserted without an id random UUIDs instead of
+    /// increasing numbers
+    #[arg(long)]
+    random_ids: bool,
+
+    /// Serve queries only from an existing data directory; writes fail
+    #[arg(long)]
+    read_only: bool,
+
+    /// Serve named indexes under /indexes/:name, stored in this directory
+    #[arg(long)]
+    indexes_dir: Option<String>,
+
+    /// Export traces to this OpenTelemetry collector over OTLP/HTTP,
+    /// e.g. http://localhost:4318 (needs the `otel` feature)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Bearer token for admin routes such as DELETE /indexes/:name
+    /// (default: $RSFTS_ADMIN_TOKEN); without one they are refused
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// Handle at most this many requests at once; others wait
+    #[arg(long)]
+    max_concurrency: Option<usize>,
+
+    /// With --max-concurrency, answer 503 instead of waiting
+    #[arg(long, requires = "max_concurrency")]
+    reject_when_busy: bool,
+
+    /// Most queries a POST /search/batch request may hold
+    #[arg(long, default_value_t = api::DEFAULT_MAX_BATCH_QUERIES)]
+    max_batch_queries: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TermFormat {
+    Tsv,
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Profile {
+    Bm25,
+    Smart,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CaseLocale {
+    Unicode,
+    Turkic,
+}
+
+fn parse_filter(name: &str) -> Result<TokenFilter, String> {
+    TokenFilter::from_name(name).ok_or_else(|| format!("unknown token filter '{}'", name))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LongQueries {
+    Reject,
+    Truncate,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EmptyDocuments {
+    Index,
+    Skip,
+    Reject,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DurabilityLevel {
+    Sync,
+    Async,
+    None,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     // Initialize tracing
+    let otlp_endpoint = match &cli.command {
+        Commands::Serve(args) => args.otlp_endpoint.clone(),
+        _ => None,
+    };
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "rsfts=info,tower_http=info".into());
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "rsfts=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_filter(env_filter))
+        .with(otlp_layer(otlp_endpoint.as_deref())?)
         .init();
 
-    let cli = Cli::parse();
-
     match cli.command {
-        Commands::Serve {
-            host,
-            port,
-            data_dir,
-        } => {
-            serve(host, port, data_dir).await?;
+        Commands::Serve(args) => {
+            let ServeArgs {
+                host,
+                port,
+                data_dir,
+                keyword_fields,
+                title_only,
+                keep_prefixes,
+                split_identifiers,
+                keep_urls_and_emails,
+                casing,
+                filters,
+                drop_numbers,
+                max_number_digits,
+                index_unstemmed,
+                authority_field,
+                max_document_tokens,
+                max_query_terms,
+                long_queries,
+                max_result_window,
+                search_cache_ttl,
+                search_cache_size,
+                max_postings,
+                async_indexing,
+                queue_capacity,
+                empty_documents,
+                durability,
+                flush_interval_ms,
+                storage_retries,
+                index_segment_mb,
+                wal,
+                exact_phrases,
+                snapshot_index_saves,
+                random_ids,
+                read_only,
+                indexes_dir,
+                otlp_endpoint: _,
+                admin_token,
+                max_concurrency,
+                reject_when_busy,
+                max_batch_queries,
+            } = *args;
+            let durability = match durability {
+                DurabilityLevel::Sync => Durability::Sync,
+                DurabilityLevel::Async => Durability::Async {
+                    flush_every_ms: flush_interval_ms,
+                },
+                DurabilityLevel::None => Durability::None,
+            };
+            let config = EngineConfig {
+                keyword_fields,
+                title_only,
+                max_postings,
+                durability,
+                storage_retry: RetryPolicy {
+                    max_retries: storage_retries,
+                    ..Default::default()
+                },
+                index_segment_bytes: index_segment_mb.saturating_mul(1 << 20),
+                write_ahead_log: wal,
+                read_only,
+                exact_phrases,
+                snapshot_index_saves,
+                authority_field,
+                max_document_tokens,
+                max_query_terms,
+                long_queries: match long_queries {
+                    LongQueries::Reject => LongQueryPolicy::Reject,
+                    LongQueries::Truncate => LongQueryPolicy::Truncate,
+                },
+                max_result_window,
+                search_cache_ttl: search_cache_ttl.map(std::time::Duration::from_secs),
+                search_cache_capacity: search_cache_size,
+                id_generation: if random_ids {
+                    IdGeneration::Random
+                } else {
+                    IdGeneration::Sequential
+                },
+                empty_documents: match empty_documents {
+                    EmptyDocuments::Index => EmptyDocumentPolicy::Index,
+                    EmptyDocuments::Skip => EmptyDocumentPolicy::Skip,
+                    EmptyDocuments::Reject => EmptyDocumentPolicy::Reject,
+                },
+                tokenizer: TokenizerConfig {
+                    keep_prefixes,
+                    split_identifiers,
+                    keep_urls_and_emails,
+                    casing: match casing {
+                        CaseLocale::Unicode => Casing::Unicode,
+                        CaseLocale::Turkic => Casing::Turkic,
+                    },
+                    numeric_tokens: match (drop_numbers, max_number_digits) {
+                        (true, _) => NumericTokens::Drop,
+                        (false, Some(max_digits)) => NumericTokens::DropLongerThan(max_digits),
+                        (false, None) => NumericTokens::Keep,
+                    },
+                    index_unstemmed,
+                    filters,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let options = ServeOptions {
+                queue_capacity: async_indexing.then_some(queue_capacity),
+                indexes_dir,
+                max_batch_queries,
+                admin_token: admin_token.or_else(|| std::env::var("RSFTS_ADMIN_TOKEN").ok()),
+                concurrency_limit: max_concurrency.map(|max| {
+                    let limit = api::ConcurrencyLimit::new(max);
+                    if reject_when_busy {
+                        limit.rejecting_when_busy()
+                    } else {
+                        limit
+                    }
+                }),
+            };
+            serve(host, port, data_dir, config, options).await?;
         }
         Commands::Insert {
             id,
@@ -133,23 +583,142 @@ async fn main() -> anyhow::Result<()> {
         Commands::Get { id, data_dir } => {
             get_document(id, data_dir)?;
         }
-        Commands::Delete { id, data_dir } => {
-            delete_document(id, data_dir)?;
+        Commands::Delete { id, soft, data_dir } => {
+            delete_document(id, soft, data_dir)?;
+        }
+        Commands::Restore { id, data_dir } => {
+            restore_document(id, data_dir)?;
+        }
+        Commands::Purge { data_dir } => {
+            purge_documents(data_dir)?;
+        }
+        Commands::PruneRareTerms {
+            min_df,
+            keyword_fields,
+            data_dir,
+        } => {
+            prune_rare_terms(min_df, keyword_fields, data_dir)?;
         }
         Commands::Stats { data_dir } => {
             show_stats(data_dir)?;
         }
-        Commands::ImportWiki { file, data_dir } => {
-            import_wiki(file, data_dir)?;
+        Commands::Export {
+            format,
+            out,
+            data_dir,
+        } => {
+            export_documents(format, out, data_dir)?;
+        }
+        Commands::ExportTerms {
+            format,
+            out,
+            data_dir,
+        } => {
+            export_terms(format, out, data_dir)?;
+        }
+        Commands::Evaluate {
+            judgments,
+            k,
+            profile,
+            or,
+            min_mrr,
+            details,
+            data_dir,
+        } => {
+            let options = SearchOptions {
+                limit: Some(k),
+                mode: if or { SearchMode::Or } else { SearchMode::And },
+                ..Default::default()
+            }
+            .with_profile(match profile {
+                Profile::Bm25 => RankingProfile::Bm25Default,
+                Profile::Smart => RankingProfile::SmartRelevance,
+            });
+            evaluate(judgments, options, min_mrr, details, data_dir)?;
+        }
+        Commands::Diff { a, b, details } => {
+            diff_indexes(a, b, details)?;
+        }
+        Commands::ImportWiki {
+            file,
+            dry_run,
+            skip_empty,
+            max_docs,
+            restart,
+            data_dir,
+        } => {
+            import_wiki(file, dry_run, skip_empty, max_docs, restart, data_dir)?;
         }
     }
 
     Ok(())
 }
 
-async fn serve(host: String, port: u16, data_dir: String) -> anyhow::Result<()> {
+/// Trace exporter for `--otlp-endpoint`. Storage spans are debug level,
+/// so spans are exported down to debug whatever the log filter.
+#[cfg(feature = "otel")]
+fn otlp_layer<S>(
+    endpoint: Option<&str>,
+) -> anyhow::Result<Option<impl tracing_subscriber::Layer<S>>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+    let filter =
+        tracing_subscriber::filter::Targets::new().with_target("rsfts", tracing::Level::DEBUG);
+    Ok(Some(
+        rsfts::otel::layer(endpoint, "rsfts")?.with_filter(filter),
+    ))
+}
+
+#[cfg(not(feature = "otel"))]
+fn otlp_layer(
+    endpoint: Option<&str>,
+) -> anyhow::Result<Option<tracing_subscriber::layer::Identity>> {
+    anyhow::ensure!(
+        endpoint.is_none(),
+        "--otlp-endpoint needs rsfts to be built with the `otel` feature"
+    );
+    Ok(None)
+}
+
+/// HTTP server settings that don't belong to the engine
+struct ServeOptions {
+    queue_capacity: Option<usize>,
+    concurrency_limit: Option<api::ConcurrencyLimit>,
+    indexes_dir: Option<String>,
+    max_batch_queries: usize,
+    admin_token: Option<String>,
+}
+
+async fn serve(
+    host: String,
+    port: u16,
+    data_dir: String,
+    config: EngineConfig,
+    options: ServeOptions,
+) -> anyhow::Result<()> {
     tracing::info!("Starting search engine with data directory: {}", data_dir);
-    let engine = Arc::new(SearchEngine::new(&data_dir)?);
+    let engine = Arc::new(SearchEngine::with_config(&data_dir, config.clone())?);
+
+    let mut state =
+        api::AppState::new(engine.clone()).with_max_batch_queries(options.max_batch_queries);
+    if let Some(dir) = options.indexes_dir {
+        tracing::info!("Serving named indexes from: {}", dir);
+        state = state.with_indexes(Arc::new(MultiEngine::new(dir, config)));
+    }
+    if let Some(capacity) = options.queue_capacity {
+        tracing::info!("Async indexing enabled (queue capacity {})", capacity);
+        state = state.with_queue(IndexQueue::start(engine, capacity));
+    }
+    if let Some(limit) = options.concurrency_limit {
+        state = state.with_concurrency_limit(limit);
+    }
+    if let Some(token) = options.admin_token.filter(|token| !token.is_empty()) {
+        state = state.with_admin_token(token);
+    }
 
     let addr = format!("{}:{}", host, port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
@@ -157,15 +726,34 @@ async fn serve(host: String, port: u16, data_dir: String) -> anyhow::Result<()>
     tracing::info!("Server listening on http://{}", addr);
     tracing::info!("API Documentation:");
     tracing::info!("  GET    /health              - Health check");
+    tracing::info!("  GET    /version             - Version and build info");
     tracing::info!("  POST   /documents           - Insert a document");
     tracing::info!("  POST   /documents/batch     - Batch insert documents");
+    tracing::info!("  GET    /documents?ids=a,b   - Get several documents");
     tracing::info!("  GET    /documents/:id       - Get a document");
+    tracing::info!("  HEAD   /documents/:id       - Check a document's content hash");
     tracing::info!("  PUT    /documents/:id       - Update a document");
-    tracing::info!("  DELETE /documents/:id       - Delete a document");
+    tracing::info!("  DELETE /documents/:id       - Delete a document (?soft=true to soft-delete)");
+    tracing::info!("  POST   /documents/:id/restore - Restore a soft-deleted document");
+    tracing::info!("  POST   /purge               - Remove all soft-deleted documents");
+    tracing::info!("  POST   /reindex             - Rebuild the index in the background");
     tracing::info!("  GET    /search?query=...    - Search documents");
+    tracing::info!("  POST   /search/batch        - Run many queries with shared options");
+    tracing::info!("  GET    /search/scores?query= - Score distribution of a query's matches");
+    tracing::info!("  POST   /rerank              - Rank a given set of documents");
+    tracing::info!("  GET    /explain?query=&id=  - Explain a document's score");
+    tracing::info!("  GET    /titles/:title       - Documents with an exact title");
+    tracing::info!("  GET    /changes?since=...   - Documents modified since a time");
+    tracing::info!("  GET    /terms/:term/related - Terms co-occurring with a term");
     tracing::info!("  GET    /stats               - Get index statistics");
+    tracing::info!(
+        "  *      /indexes/:name/...   - The routes above on a named index (--indexes-dir)"
+    );
+    tracing::info!("  GET    /indexes             - List named indexes");
+    tracing::info!("  POST   /indexes             - Create a named index");
+    tracing::info!("  DELETE /indexes/:name       - Delete a named index (--admin-token)");
 
-    let app = api::create_router(engine);
+    let app = api::create_router(state);
 
     axum::serve(listener, app).await?;
 
@@ -193,12 +781,18 @@ fn insert_document(
     Ok(())
 }
 
-fn search_documents(query: String, limit: usize, ranked: bool, data_dir: String) -> anyhow::Result<()> {
+fn search_documents(
+    query: String,
+    limit: usize,
+    ranked: bool,
+    data_dir: String,
+) -> anyhow::Result<()> {
     let engine = SearchEngine::new(&data_dir)?;
 
     let options = SearchOptions {
         use_ranking: ranked,
         limit: Some(limit),
+        sections: true,
         ..Default::default()
     };
 
@@ -206,21 +800,39 @@ fn search_documents(query: String, limit: usize, ranked: bool, data_dir: String)
     let result = engine.search(&query, &options)?;
     let duration = start.elapsed();
 
+    // Only emit ANSI codes when writing to a terminal
+    let (pre_tag, post_tag) = if std::io::stdout().is_terminal() {
+        ("\x1b[1;33m", "\x1b[0m")
+    } else {
+        ("", "")
+    };
+    let highlighter = engine.highlighter_for(
+        &query,
+        &SearchOptions {
+            highlight_tags: Some((pre_tag.to_string(), post_tag.to_string())),
+            ..options
+        },
+    )?;
+
     println!("\n🔍 Search Results for: \"{}\"", query);
     println!("Found {} documents in {:?}", result.total, duration);
     println!();
 
     for (i, doc) in result.documents.iter().enumerate() {
+        let title = highlighter.highlight(&doc.title);
         if let Some(scores) = &result.scores {
-            println!("{}. [Score: {:.4}] {}", i + 1, scores[i], doc.title);
+            println!("{}. [Score: {:.4}] {}", i + 1, scores[i], title);
         } else {
-            println!("{}. {}", i + 1, doc.title);
+            println!("{}. {}", i + 1, title);
         }
         println!("   ID: {}", doc.id);
         if let Some(url) = &doc.url {
             println!("   URL: {}", url);
         }
-        println!("   Content: {}...", &doc.content[..doc.content.len().min(100)]);
+        println!("   Content: {}", highlighter.snippet(&doc.content, 100));
+        if let Some(Some(section)) = result.sections.as_ref().map(|sections| &sections[i]) {
+            println!("   Section: {} (#{})", section.heading, section.anchor);
+        }
         println!();
     }
 
@@ -246,10 +858,99 @@ fn get_document(id: String, data_dir: String) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn delete_document(id: String, data_dir: String) -> anyhow::Result<()> {
+fn delete_document(id: String, soft: bool, data_dir: String) -> anyhow::Result<()> {
+    let engine = SearchEngine::new(&data_dir)?;
+    if soft {
+        if engine.soft_delete(&id)? {
+            println!("✓ Document '{}' soft-deleted successfully", id);
+        } else {
+            println!("❌ Document '{}' not found or already deleted", id);
+        }
+    } else if engine.delete_document(&id)? {
+        println!("✓ Document '{}' deleted successfully", id);
+    } else {
+        println!("❌ Document '{}' not found", id);
+    }
+    Ok(())
+}
+
+fn restore_document(id: String, data_dir: String) -> anyhow::Result<()> {
+    let engine = SearchEngine::new(&data_dir)?;
+    if engine.restore(&id)? {
+        println!("✓ Document '{}' restored successfully", id);
+    } else {
+        println!("❌ Document '{}' is not deleted", id);
+    }
+    Ok(())
+}
+
+fn purge_documents(data_dir: String) -> anyhow::Result<()> {
     let engine = SearchEngine::new(&data_dir)?;
-    engine.delete_document(&id)?;
-    println!("✓ Document '{}' deleted successfully", id);
+    let purged = engine.purge()?;
+    println!("✓ Purged {} soft-deleted documents", purged);
+    Ok(())
+}
+
+fn prune_rare_terms(
+    min_df: usize,
+    keyword_fields: Vec<String>,
+    data_dir: String,
+) -> anyhow::Result<()> {
+    let config = EngineConfig {
+        keyword_fields,
+        ..Default::default()
+    };
+    let engine = SearchEngine::with_config(&data_dir, config)?;
+    let removed = engine.prune_rare_terms(min_df)?;
+    println!(
+        "✓ Removed {} terms found in fewer than {} documents",
+        removed, min_df
+    );
+    Ok(())
+}
+
+fn evaluate(
+    judgments_file: String,
+    options: SearchOptions,
+    min_mrr: Option<f64>,
+    details: bool,
+    data_dir: String,
+) -> anyhow::Result<()> {
+    let judgments: Vec<Judgment> = serde_json::from_reader(std::io::BufReader::new(
+        std::fs::File::open(&judgments_file)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", judgments_file, e))?,
+    ))?;
+
+    let engine = SearchEngine::open_read_only(&data_dir)?;
+    let report = engine.evaluate(&judgments, &options)?;
+
+    if details {
+        for query in &report.queries {
+            let rank = query
+                .first_relevant_rank
+                .map_or("-".to_string(), |rank| rank.to_string());
+            println!(
+                "{:<40} first relevant: {:>3}  P@{}: {:.3}  R@{}: {:.3}",
+                query.query, rank, report.k, query.precision, report.k, query.recall
+            );
+        }
+    }
+
+    println!("\n📏 Evaluation of {} queries", report.queries.len());
+    println!("MRR:            {:.4}", report.mrr);
+    println!("Precision@{:<4} {:.4}", report.k, report.precision_at_k);
+    println!("Recall@{:<7} {:.4}", report.k, report.recall_at_k);
+    println!();
+
+    if let Some(min_mrr) = min_mrr {
+        anyhow::ensure!(
+            report.mrr >= min_mrr,
+            "MRR {:.4} is below the minimum {}",
+            report.mrr,
+            min_mrr
+        );
+    }
+
     Ok(())
 }
 
@@ -259,62 +960,200 @@ fn show_stats(data_dir: String) -> anyhow::Result<()> {
 
     println!("\n📊 Index Statistics");
     println!("Total Documents:       {}", stats.total_documents);
+    println!(
+        "Live / Deleted:        {} / {}",
+        stats.live_documents, stats.deleted_documents
+    );
     println!("Total Unique Tokens:   {}", stats.total_tokens);
     println!("Avg Docs per Token:    {:.2}", stats.avg_docs_per_token);
+    println!(
+        "Index Memory (est.):   {}",
+        format_bytes(stats.estimated_memory_bytes as u64)
+    );
+    println!(
+        "Disk Usage:            {}",
+        format_bytes(engine.disk_usage()?)
+    );
     println!();
 
     Ok(())
 }
 
-fn import_wiki(file: String, data_dir: String) -> anyhow::Result<()> {
-    use flate2::read::GzDecoder;
-    use quick_xml::de::from_reader;
-    use serde::Deserialize;
+/// Human-readable byte count
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn export_documents(format: ExportFormat, out: String, data_dir: String) -> anyhow::Result<()> {
     use std::fs::File;
-    use std::io::BufReader;
+    use std::io::BufWriter;
 
-    #[derive(Debug, Deserialize)]
-    struct WikiDoc {
-        #[serde(default)]
-        title: String,
-        #[serde(default)]
-        url: String,
-        #[serde(rename = "abstract", default)]
-        text: String,
+    let engine = SearchEngine::new(&data_dir)?;
+    let writer = BufWriter::new(File::create(&out)?);
+
+    let count = match format {
+        ExportFormat::Csv => engine.export_csv(writer)?,
+    };
+
+    println!("✓ Exported {} documents to {}", count, out);
+
+    Ok(())
+}
+
+fn export_terms(format: TermFormat, out: String, data_dir: String) -> anyhow::Result<()> {
+    let engine = SearchEngine::open_read_only(&data_dir)?;
+    let format = match format {
+        TermFormat::Tsv => TermExportFormat::Tsv,
+        TermFormat::Jsonl => TermExportFormat::Jsonl,
+    };
+    let count = engine.export_terms(std::fs::File::create(&out)?, format)?;
+
+    println!("✓ Exported {} terms to {}", count, out);
+
+    Ok(())
+}
+
+fn diff_indexes(a: String, b: String, details: bool) -> anyhow::Result<()> {
+    for dir in [&a, &b] {
+        anyhow::ensure!(
+            std::path::Path::new(dir).exists(),
+            "Data directory '{}' does not exist",
+            dir
+        );
     }
 
-    #[derive(Debug, Deserialize)]
-    struct Feed {
-        #[serde(rename = "doc", default)]
-        documents: Vec<WikiDoc>,
+    // Documents are compared straight from storage without loading either index
+    let storage_a = Storage::open(&a)?;
+    let storage_b = Storage::open(&b)?;
+
+    let summary = diff::diff_documents(
+        storage_a.iter_documents()?,
+        storage_b.iter_documents()?,
+        |d| {
+            if details {
+                match d {
+                    DocumentDiff::OnlyInA(id) => println!("- {}", id),
+                    DocumentDiff::OnlyInB(id) => println!("+ {}", id),
+                    DocumentDiff::Changed(id) => println!("~ {}", id),
+                }
+            }
+        },
+    )?;
+
+    println!("\n🔍 Diff: {} vs {}", a, b);
+    println!("Only in A:   {}", summary.only_in_a);
+    println!("Only in B:   {}", summary.only_in_b);
+    println!("Changed:     {}", summary.changed);
+    println!("Unchanged:   {}", summary.unchanged);
+    if summary.is_identical() {
+        println!("✓ Document stores are identical");
     }
+    println!();
+
+    Ok(())
+}
+
+/// Documents indexed between import checkpoints
+const IMPORT_CHECKPOINT_DOCS: usize = 10_000;
+
+fn import_wiki(
+    file: String,
+    dry_run: bool,
+    skip_empty: bool,
+    max_docs: Option<usize>,
+    restart: bool,
+    data_dir: String,
+) -> anyhow::Result<()> {
+    use flate2::read::GzDecoder;
+    use rsfts::import::WikiDocuments;
+    use std::fs::File;
+    use std::io::BufReader;
 
     println!("Loading Wikipedia dump from: {}", file);
 
     let f = File::open(&file)?;
     let decoder = GzDecoder::new(f);
-    let reader = BufReader::new(decoder);
+    let limit = max_docs.unwrap_or(usize::MAX);
+    let docs = WikiDocuments::new(BufReader::new(decoder)).take(limit);
 
-    let mut feed: Feed = from_reader(reader)?;
+    if dry_run {
+        let docs: Vec<Document> = docs.collect::<anyhow::Result<_>>()?;
+        println!("Loaded {} documents", docs.len());
+        let summary = ImportSummary::from_documents(&docs, &Tokenizer::new());
+        print_import_summary(&summary);
+        return Ok(());
+    }
 
-    println!("Loaded {} documents", feed.documents.len());
-    println!("Indexing documents...");
+    let config = EngineConfig {
+        empty_documents: if skip_empty {
+            EmptyDocumentPolicy::Skip
+        } else {
+            EmptyDocumentPolicy::Index
+        },
+        ..Default::default()
+    };
+    let engine = SearchEngine::with_config(&data_dir, config)?;
 
-    let engine = SearchEngine::new(&data_dir)?;
+    // Progress is the number of documents of this dump already imported
+    let checkpoint = format!("import-wiki:{}", std::fs::canonicalize(&file)?.display());
+    let start = match engine.checkpoint(&checkpoint)? {
+        Some(_) if restart => 0,
+        Some(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid import checkpoint '{}'", value))?,
+        None => 0,
+    };
+    if start > 0 {
+        println!(
+            "Resuming after {} documents (use --restart to start over)",
+            start
+        );
+    }
 
-    let docs: Vec<Document> = feed
-        .documents
-        .drain(..)
-        .enumerate()
-        .map(|(i, d)| {
-            Document::new(i.to_string(), d.title, d.text).with_url(d.url)
-        })
-        .collect();
+    println!("Indexing documents...");
 
-    let total = docs.len();
-    engine.batch_insert(docs)?;
+    let mut imported = start;
+    let mut batch = Vec::with_capacity(IMPORT_CHECKPOINT_DOCS);
+    for doc in docs.skip(start) {
+        batch.push(doc?);
+        if batch.len() == IMPORT_CHECKPOINT_DOCS {
+            imported += batch.len();
+            engine.batch_insert(std::mem::take(&mut batch))?;
+            engine.save_checkpoint(&checkpoint, &imported.to_string())?;
+            println!("  {} documents imported", imported);
+        }
+    }
+    imported += batch.len();
+    engine.batch_insert(batch)?;
+    engine.clear_checkpoint(&checkpoint)?;
 
-    println!("✓ Successfully imported {} documents", total);
+    println!("✓ Successfully imported {} documents", imported - start);
 
     Ok(())
 }
+
+fn print_import_summary(summary: &ImportSummary) {
+    println!("\n🧪 Dry Run Summary (nothing was written)");
+    println!("Total Documents:       {}", summary.total);
+    println!("Valid Documents:       {}", summary.valid);
+    println!("Empty IDs:             {}", summary.empty_ids);
+    println!("Empty Titles:          {}", summary.empty_titles);
+    println!("Empty Contents:        {}", summary.empty_contents);
+    println!("Duplicate IDs:         {}", summary.duplicate_ids);
+    if !summary.duplicate_examples.is_empty() {
+        println!("  e.g. {}", summary.duplicate_examples.join(", "));
+    }
+    println!("Estimated Tokens:      {}", summary.estimated_tokens);
+    println!();
+}
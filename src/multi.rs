@@ -0,0 +1,246 @@
+use crate::engine::{EngineConfig, SearchEngine};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Longest allowed index name
+const MAX_INDEX_NAME_LEN: usize = 64;
+
+/// Error of dropping an index while requests still hold its engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexInUse(pub String);
+
+impl std::fmt::Display for IndexInUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Index '{}' is in use; retry once its requests have finished",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for IndexInUse {}
+
+/// A set of named, independent indexes sharing one configuration
+///
+/// Each index is a full [`SearchEngine`] with its own inverted index,
+/// statistics and storage, kept in a sub-directory of the root named after
+/// the index. Indexes are opened on first use and stay open.
+pub struct MultiEngine {
+    /// `None` keeps every index in memory
+    root: Option<PathBuf>,
+    config: EngineConfig,
+    engines: RwLock<HashMap<String, Arc<SearchEngine>>>,
+}
+
+impl MultiEngine {
+    /// Manage indexes stored under `root`
+    pub fn new(root: impl Into<PathBuf>, config: EngineConfig) -> Self {
+        Self {
+            root: Some(root.into()),
+            config,
+            engines: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Manage in-memory indexes (for testing)
+    pub fn in_memory(config: EngineConfig) -> Self {
+        Self {
+            root: None,
+            config,
+            engines: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `name` can be used as an index name: 1-64 ASCII letters,
+    /// digits, `-` or `_`
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_INDEX_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Get an existing index, opening it from disk if needed
+    pub fn get(&self, name: &str) -> Result<Option<Arc<SearchEngine>>> {
+        if let Some(engine) = self.engines.read().unwrap().get(name) {
+            return Ok(Some(engine.clone()));
+        }
+        match self.index_dir(name) {
+            Some(_) => self.get_or_create(name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Get an index, creating it if it doesn't exist
+    pub fn get_or_create(&self, name: &str) -> Result<Arc<SearchEngine>> {
+        anyhow::ensure!(Self::is_valid_name(name), "Invalid index name '{}'", name);
+
+        let mut engines = self.engines.write().unwrap();
+        if let Some(engine) = engines.get(name) {
+            return Ok(engine.clone());
+        }
+
+        let engine = Arc::new(self.open(name)?);
+        engines.insert(name.to_string(), engine.clone());
+
+        Ok(engine)
+    }
+
+    /// Create an index, returning `false` if it already exists
+    pub fn create(&self, name: &str) -> Result<bool> {
+        anyhow::ensure!(Self::is_valid_name(name), "Invalid index name '{}'", name);
+
+        // Checked under the lock, so concurrent creations can't both succeed
+        let mut engines = self.engines.write().unwrap();
+        if engines.contains_key(name) || self.index_dir(name).is_some() {
+            return Ok(false);
+        }
+        engines.insert(name.to_string(), Arc::new(self.open(name)?));
+        Ok(true)
+    }
+
+    /// Delete an index and all its data, returning `false` if it doesn't
+    /// exist
+    ///
+    /// Fails with [`IndexInUse`] while requests still hold the index's
+    /// engine, rather than removing files it has open. The index can't be
+    /// opened again until it is gone.
+    pub fn drop_index(&self, name: &str) -> Result<bool> {
+        anyhow::ensure!(!self.config.read_only, "The index is open read-only");
+
+        let mut engines = self.engines.write().unwrap();
+        let dir = self.index_dir(name);
+        match engines.remove(name).map(Arc::try_unwrap) {
+            Some(Ok(engine)) => engine.close()?,
+            Some(Err(engine)) => {
+                engines.insert(name.to_string(), engine);
+                return Err(IndexInUse(name.to_string()).into());
+            }
+            None if dir.is_none() => return Ok(false),
+            None => {}
+        }
+        if let Some(dir) = dir {
+            std::fs::remove_dir_all(dir)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Open the engine of an index, creating it if needed
+    fn open(&self, name: &str) -> Result<SearchEngine> {
+        match &self.root {
+            Some(root) => {
+                let path = root.join(name);
+                let path = path.to_str().ok_or_else(|| {
+                    anyhow::anyhow!("Index path {} is not valid UTF-8", path.display())
+                })?;
+                SearchEngine::with_config(path, self.config.clone())
+            }
+            None => SearchEngine::in_memory_with_config(self.config.clone()),
+        }
+    }
+
+    /// The directory of an index stored on disk, if it exists
+    fn index_dir(&self, name: &str) -> Option<PathBuf> {
+        let root = self.root.as_ref().filter(|_| Self::is_valid_name(name))?;
+        Some(root.join(name)).filter(|dir| dir.is_dir())
+    }
+
+    /// Names of all indexes, open or on disk, sorted
+    pub fn names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.engines.read().unwrap().keys().cloned().collect();
+
+        if let Some(root) = self.root.as_ref().filter(|root| root.is_dir()) {
+            for entry in std::fs::read_dir(root)? {
+                let entry = entry?;
+                if let Some(name) = entry.file_name().to_str() {
+                    if entry.file_type()?.is_dir()
+                        && Self::is_valid_name(name)
+                        && !names.iter().any(|n| n == name)
+                    {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+    use crate::engine::SearchOptions;
+
+    #[test]
+    fn test_indexes_are_independent() -> Result<()> {
+        let multi = MultiEngine::in_memory(EngineConfig::default());
+        assert!(multi.get("acme")?.is_none());
+
+        let acme = multi.get_or_create("acme")?;
+        let globex = multi.get_or_create("globex")?;
+        acme.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+
+        assert_eq!(acme.search("rust", &SearchOptions::default())?.total, 1);
+        assert_eq!(globex.search("rust", &SearchOptions::default())?.total, 0);
+        assert!(Arc::ptr_eq(&multi.get("acme")?.unwrap(), &acme));
+        assert_eq!(multi.names()?, vec!["acme", "globex"]);
+
+        assert!(multi.get_or_create("../escape").is_err());
+        assert!(multi.get_or_create("").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_and_drop() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("rsfts-multi-drop-{}", std::process::id()));
+        let multi = MultiEngine::new(&root, EngineConfig::default());
+
+        assert!(multi.create("acme")?);
+        assert!(!multi.create("acme")?);
+        let acme = multi.get("acme")?.unwrap();
+        acme.upsert_document(Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Rust guide".to_string(),
+        ))?;
+
+        // Not while a request still holds it
+        let err = multi.drop_index("acme").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IndexInUse>(),
+            Some(&IndexInUse("acme".to_string()))
+        );
+        assert_eq!(acme.search("rust", &SearchOptions::default())?.total, 1);
+        drop(acme);
+
+        assert!(multi.drop_index("acme")?);
+        assert!(!multi.drop_index("acme")?);
+        assert!(!root.join("acme").exists());
+        assert!(multi.names()?.is_empty());
+        assert!(multi.create("acme")?);
+        assert_eq!(
+            multi
+                .get("acme")?
+                .unwrap()
+                .search("rust", &SearchOptions::default())?
+                .total,
+            0
+        );
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Export of `tracing` spans as OpenTelemetry traces over OTLP/HTTP
+//!
+//! [`layer`] hands closed spans, with their fields as attributes, to the
+//! OpenTelemetry SDK, whose batch processor sends them to a collector in
+//! the background over HTTP or HTTPS. [`continue_trace`] makes a request's
+//! span join the trace of its W3C `traceparent` header, so requests from
+//! instrumented clients join their traces.
+
+use anyhow::Result;
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Path collectors receive traces on, for endpoints given without one
+const TRACES_PATH: &str = "/v1/traces";
+
+/// A `tracing` layer exporting spans of service `service_name` to
+/// `endpoint`, e.g. `http://localhost:4318` (the path defaults to
+/// `/v1/traces`)
+pub fn layer<S>(endpoint: &str, service_name: &str) -> Result<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_url(endpoint))
+        .build()?;
+    let resource = Resource::builder_empty()
+        .with_service_name(service_name.to_string())
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+
+    Ok(tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name.to_string())))
+}
+
+/// Make a request's span continue the trace named by its `traceparent`
+/// header. Does nothing without a valid header, or when spans aren't
+/// exported.
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    // Fails only when no layer exports spans, leaving nothing to join
+    let _ = span.set_parent(context);
+}
+
+/// The full URL of the traces endpoint of a collector
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    let after_scheme = endpoint
+        .split_once("://")
+        .map_or(endpoint, |(_, rest)| rest);
+    if after_scheme.contains('/') {
+        endpoint.to_string()
+    } else {
+        format!("{}{}", endpoint, TRACES_PATH)
+    }
+}
+
+/// Reads propagated trace context from request headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_continue_trace() {
+        let tracer = SdkTracerProvider::builder().build().tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("http_request");
+            continue_trace(&span, &headers);
+            let trace_id = span.context().span().span_context().trace_id();
+            assert_eq!(trace_id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+            let span = tracing::info_span!("http_request");
+            continue_trace(&span, &HeaderMap::new());
+            let trace_id = span.context().span().span_context().trace_id();
+            assert_ne!(trace_id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        });
+    }
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(
+            traces_url("http://collector:4318"),
+            "http://collector:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("https://collector:4318/"),
+            "https://collector:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("http://collector/otlp/v1/traces"),
+            "http://collector/otlp/v1/traces"
+        );
+    }
+}
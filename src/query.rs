@@ -0,0 +1,352 @@
+use serde::Serialize;
+
+/// How a query clause participates in matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Occur {
+    /// Plain term, combined with the other plain terms using the search mode
+    Should,
+    /// `+term`: the document must contain the term
+    Must,
+    /// `-term`: the document must not contain the term
+    MustNot,
+}
+
+/// How a query string is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryParser {
+    /// The whole input is text whose terms must all match; nothing is an
+    /// operator. Safe for untrusted input full of punctuation, such as
+    /// file names.
+    Literal,
+    /// `+term`, `-term`, `field:value` and `term^weight`; see [`parse`]
+    #[default]
+    Simple,
+    /// Simple syntax plus the uppercase keywords `AND` (both neighbouring
+    /// terms are required), `NOT` (the next term is excluded) and `OR`
+    /// (plain terms are already alternatives, so it changes nothing)
+    Boolean,
+}
+
+/// A single whitespace-separated clause of a query string
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryClause {
+    pub occur: Occur,
+    /// Field prefix of a `field:value` clause
+    pub field: Option<String>,
+    pub text: String,
+    /// Relative importance from a `term^weight` suffix (default 1.0)
+    pub weight: f64,
+    /// Quoted: the words must appear next to each other, in order
+    pub phrase: bool,
+}
+
+impl QueryClause {
+    pub fn new(occur: Occur, text: String) -> Self {
+        Self {
+            occur,
+            field: None,
+            text,
+            weight: 1.0,
+            phrase: false,
+        }
+    }
+
+    pub fn with_field(mut self, field: String) -> Self {
+        self.field = Some(field);
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f64) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn as_phrase(mut self) -> Self {
+        self.phrase = true;
+        self
+    }
+}
+
+/// Split a `text^weight` suffix off. A weight that isn't a finite,
+/// non-negative number is dropped and the default weight used instead.
+fn split_weight(text: &str) -> (&str, f64) {
+    match text.rsplit_once('^') {
+        Some((term, weight)) if !term.is_empty() => (term, parse_weight(weight)),
+        _ => (text, 1.0),
+    }
+}
+
+fn parse_weight(weight: &str) -> f64 {
+    weight
+        .parse::<f64>()
+        .ok()
+        .filter(|w| w.is_finite() && *w >= 0.0)
+        .unwrap_or(1.0)
+}
+
+/// Split `field:value` into its parts; field names are alphanumeric or `_`
+fn split_field(text: &str) -> Option<(&str, &str)> {
+    let (field, value) = text.split_once(':')?;
+
+    if is_field_name(field) && !value.is_empty() {
+        Some((field, value))
+    } else {
+        None
+    }
+}
+
+fn is_field_name(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Split a query string on whitespace, except inside double quotes. An
+/// unclosed quote runs to the end of the input.
+fn split_clauses(input: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+
+    for (i, c) in input.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        match start {
+            Some(s) if c.is_whitespace() && !quoted => {
+                words.push(&input[s..i]);
+                start = None;
+            }
+            None if !c.is_whitespace() || quoted => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(&input[s..]);
+    }
+
+    words
+}
+
+/// Parse `"quoted text"`, optionally after `field:` and before `^weight`,
+/// returning the field, the text between the quotes and the weight. Inside
+/// the quotes, `:` and `^` are plain text.
+fn split_quoted(text: &str) -> Option<(Option<&str>, &str, f64)> {
+    let (field, rest) = match text.split_once(':') {
+        Some((field, rest)) if is_field_name(field) && rest.starts_with('"') => (Some(field), rest),
+        _ => (None, text),
+    };
+    let rest = rest.strip_prefix('"')?;
+
+    let (quoted, weight) = match rest.rsplit_once('"') {
+        Some((quoted, suffix)) => (quoted, suffix.strip_prefix('^').map_or(1.0, parse_weight)),
+        None => (rest, 1.0),
+    };
+    Some((field, quoted, weight))
+}
+
+/// Parse a query string into clauses.
+///
+/// A leading `+` marks a clause as required and a leading `-` marks it as
+/// excluded; everything else is a plain clause. Operators only apply as a
+/// prefix, so `memory-efficient` stays a plain clause. A `field:value`
+/// clause carries its field name separately; it's up to the caller to decide
+/// whether the field exists. A `^weight` suffix (`rust^3`) sets the clause
+/// weight.
+///
+/// Double quotes make a phrase: one clause whose words must appear next to
+/// each other, sharing the operator, field and weight, e.g.
+/// `-title:"draft notes"^2`.
+pub fn parse(input: &str) -> Vec<QueryClause> {
+    split_clauses(input)
+        .into_iter()
+        .filter_map(parse_word)
+        .collect()
+}
+
+/// Parse a query string with the given parser
+pub fn parse_with(input: &str, parser: QueryParser) -> Vec<QueryClause> {
+    match parser {
+        QueryParser::Literal if input.trim().is_empty() => Vec::new(),
+        QueryParser::Literal => vec![QueryClause::new(Occur::Must, input.to_string())],
+        QueryParser::Simple => parse(input),
+        QueryParser::Boolean => parse_boolean(input),
+    }
+}
+
+fn parse_word(word: &str) -> Option<QueryClause> {
+    let (occur, text) = if let Some(rest) = word.strip_prefix('+') {
+        (Occur::Must, rest)
+    } else if let Some(rest) = word.strip_prefix('-') {
+        (Occur::MustNot, rest)
+    } else {
+        (Occur::Should, word)
+    };
+
+    if let Some((field, quoted, weight)) = split_quoted(text) {
+        let quoted = quoted.trim();
+        if quoted.is_empty() {
+            return None;
+        }
+        let clause = QueryClause::new(occur, quoted.to_string())
+            .with_weight(weight)
+            .as_phrase();
+        return Some(match field {
+            Some(field) => clause.with_field(field.to_string()),
+            None => clause,
+        });
+    }
+
+    let (text, weight) = split_weight(text);
+    if text.is_empty() {
+        None
+    } else if let Some((field, value)) = split_field(text) {
+        Some(
+            QueryClause::new(occur, value.to_string())
+                .with_field(field.to_string())
+                .with_weight(weight),
+        )
+    } else {
+        Some(QueryClause::new(occur, text.to_string()).with_weight(weight))
+    }
+}
+
+fn parse_boolean(input: &str) -> Vec<QueryClause> {
+    let mut clauses: Vec<QueryClause> = Vec::new();
+    let mut negate_next = false;
+    let mut require_next = false;
+
+    for word in split_clauses(input) {
+        match word {
+            "NOT" => negate_next = true,
+            "AND" => {
+                if let Some(last) = clauses.last_mut().filter(|c| c.occur == Occur::Should) {
+                    last.occur = Occur::Must;
+                }
+                require_next = true;
+            }
+            "OR" => {}
+            _ => {
+                let Some(mut clause) = parse_word(word) else {
+                    continue;
+                };
+                if negate_next {
+                    clause.occur = Occur::MustNot;
+                } else if require_next && clause.occur == Occur::Should {
+                    clause.occur = Occur::Must;
+                }
+                negate_next = false;
+                require_next = false;
+                clauses.push(clause);
+            }
+        }
+    }
+
+    clauses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_operators() {
+        let clauses = parse("python go +programming -java memory-efficient");
+        assert_eq!(
+            clauses,
+            vec![
+                QueryClause::new(Occur::Should, "python".to_string()),
+                QueryClause::new(Occur::Should, "go".to_string()),
+                QueryClause::new(Occur::Must, "programming".to_string()),
+                QueryClause::new(Occur::MustNot, "java".to_string()),
+                QueryClause::new(Occur::Should, "memory-efficient".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_with_parsers() {
+        assert_eq!(
+            parse_with("-draft +final.txt", QueryParser::Literal),
+            vec![QueryClause::new(
+                Occur::Must,
+                "-draft +final.txt".to_string()
+            )]
+        );
+        assert!(parse_with("  ", QueryParser::Literal).is_empty());
+
+        assert_eq!(
+            parse_with("rust AND go OR python NOT java", QueryParser::Boolean),
+            vec![
+                QueryClause::new(Occur::Must, "rust".to_string()),
+                QueryClause::new(Occur::Must, "go".to_string()),
+                QueryClause::new(Occur::Should, "python".to_string()),
+                QueryClause::new(Occur::MustNot, "java".to_string()),
+            ]
+        );
+        assert_eq!(parse_with("rust AND go", QueryParser::Simple).len(), 3);
+    }
+
+    #[test]
+    fn test_parse_bare_operators_ignored() {
+        assert!(parse("+ - ").is_empty());
+    }
+
+    #[test]
+    fn test_parse_weights() {
+        let clauses = parse("rust^3 +tutorial^0.5 bad^x neg^-2 ^4");
+        assert_eq!(
+            clauses,
+            vec![
+                QueryClause::new(Occur::Should, "rust".to_string()).with_weight(3.0),
+                QueryClause::new(Occur::Must, "tutorial".to_string()).with_weight(0.5),
+                QueryClause::new(Occur::Should, "bad".to_string()),
+                QueryClause::new(Occur::Should, "neg".to_string()),
+                QueryClause::new(Occur::Should, "^4".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted_clauses() {
+        let clauses =
+            parse(r#"title:rust^2 content:safety -title:"exact phrase"^2 "a:b c^3" +"open "#);
+        assert_eq!(
+            clauses,
+            vec![
+                QueryClause::new(Occur::Should, "rust".to_string())
+                    .with_field("title".to_string())
+                    .with_weight(2.0),
+                QueryClause::new(Occur::Should, "safety".to_string())
+                    .with_field("content".to_string()),
+                QueryClause::new(Occur::MustNot, "exact phrase".to_string())
+                    .with_field("title".to_string())
+                    .with_weight(2.0)
+                    .as_phrase(),
+                QueryClause::new(Occur::Should, "a:b c^3".to_string()).as_phrase(),
+                QueryClause::new(Occur::Must, "open".to_string()).as_phrase(),
+            ]
+        );
+        assert_eq!(
+            split_clauses(r#" a "b  c"^2  d"#),
+            vec!["a", r#""b  c"^2"#, "d"]
+        );
+        assert!(parse(r#""" title:"  "^2"#).is_empty());
+    }
+
+    #[test]
+    fn test_parse_field_clauses() {
+        let clauses = parse("+sku:ABC-123 country:US :x y: http://example");
+        assert_eq!(
+            clauses,
+            vec![
+                QueryClause::new(Occur::Must, "ABC-123".to_string()).with_field("sku".to_string()),
+                QueryClause::new(Occur::Should, "US".to_string()).with_field("country".to_string()),
+                QueryClause::new(Occur::Should, ":x".to_string()),
+                QueryClause::new(Occur::Should, "y:".to_string()),
+                QueryClause::new(Occur::Should, "//example".to_string())
+                    .with_field("http".to_string()),
+            ]
+        );
+    }
+}
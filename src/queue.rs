@@ -0,0 +1,113 @@
+use crate::document::Document;
+use crate::engine::SearchEngine;
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Default number of documents the queue holds before senders wait
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Most documents applied in a single batch
+const MAX_BATCH_SIZE: usize = 256;
+
+/// Bounded queue of documents indexed by a background worker
+///
+/// Documents are applied in arrival order through the engine's batch path.
+/// Operations that bypass the queue (such as deletes) may be overtaken by
+/// documents still waiting in it.
+#[derive(Clone)]
+pub struct IndexQueue {
+    sender: mpsc::Sender<Document>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl IndexQueue {
+    /// Spawn the worker on the current tokio runtime
+    pub fn start(engine: Arc<SearchEngine>, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        tokio::spawn(run_worker(engine, receiver, pending.clone()));
+
+        Self { sender, pending }
+    }
+
+    /// Queue documents for indexing, waiting while the queue is full
+    pub async fn enqueue(&self, docs: Vec<Document>) -> Result<()> {
+        for doc in docs {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if self.sender.send(doc).await.is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                return Err(anyhow!("Indexing queue is closed"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Documents accepted but not yet applied to the index
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+}
+
+async fn run_worker(
+    engine: Arc<SearchEngine>,
+    mut receiver: mpsc::Receiver<Document>,
+    pending: Arc<AtomicUsize>,
+) {
+    while let Some(doc) = receiver.recv().await {
+        let mut batch = vec![doc];
+        while batch.len() < MAX_BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(doc) => batch.push(doc),
+                Err(_) => break,
+            }
+        }
+
+        let count = batch.len();
+        let engine = engine.clone();
+        match tokio::task::spawn_blocking(move || engine.batch_insert(batch)).await {
+            Ok(Ok(())) => tracing::debug!("Indexed {} queued documents", count),
+            Ok(Err(e)) => tracing::error!("Failed to index queued documents: {:#}", e),
+            Err(e) => tracing::error!("Indexing worker panicked: {}", e),
+        }
+
+        pending.fetch_sub(count, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SearchOptions;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queued_documents_are_indexed() {
+        let engine = Arc::new(SearchEngine::in_memory().unwrap());
+        let queue = IndexQueue::start(engine.clone(), 2);
+
+        let docs = (0..10)
+            .map(|i| {
+                Document::new(
+                    i.to_string(),
+                    "Queued".to_string(),
+                    "Rust document".to_string(),
+                )
+            })
+            .collect();
+        queue.enqueue(docs).await.unwrap();
+
+        for _ in 0..100 {
+            if queue.pending() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(queue.pending(), 0);
+        let result = engine.search("rust", &SearchOptions::default()).unwrap();
+        assert_eq!(result.total, 10);
+    }
+}
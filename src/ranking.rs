@@ -1,6 +1,10 @@
 use crate::document::DocStats;
 use crate::index::InvertedIndex;
-use std::collections::HashMap;
+use crate::tokenizer::is_unstemmed_term;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time::Instant;
 
 /// BM25 parameters
 pub struct BM25 {
@@ -17,6 +21,267 @@ impl Default for BM25 {
     }
 }
 
+/// Ranking adjustments applied on top of plain BM25
+#[derive(Debug, Clone, Serialize)]
+pub struct RankingOptions {
+    /// Weight of a title occurrence relative to a content occurrence (1.0 = no boost)
+    pub title_boost: f64,
+    /// Scale the score by the fraction of query terms the document matches
+    pub coordination: bool,
+    /// Bonus for query terms appearing close together (0.0 = disabled)
+    pub proximity_boost: f64,
+    /// Use `1 + ln(tf)` instead of the raw term frequency
+    pub log_tf: bool,
+    /// Extra weight for a query term the document contains in exactly the
+    /// form typed, not just the same stem (0.0 = disabled)
+    pub exact_match_boost: f64,
+    /// Bonus for matching query terms in both the title and the content
+    /// rather than in one of them, favouring documents about the query over
+    /// ones that mention it in passing (0.0 = disabled)
+    pub field_coverage_boost: f64,
+    /// Fraction of documents above which a query term's IDF is divided by
+    /// `1 + ln(df / threshold)`, so very common terms count for less
+    /// without being dropped (None = disabled)
+    pub common_term_ratio: Option<f64>,
+    /// Weight of a document's authority (a precomputed importance such as
+    /// PageRank): the score is multiplied by `1 + weight * scaled
+    /// authority`. Documents without one are unchanged (0.0 = disabled).
+    pub authority_weight: f64,
+    /// How authority is scaled before weighting
+    pub authority_scale: AuthorityScale,
+    /// How documents with equal scores are ordered, most significant key
+    /// first. Documents still tied keep an unspecified order.
+    pub tie_break: Vec<TieBreak>,
+    /// What happens to a document whose score comes out NaN or infinite
+    pub invalid_scores: InvalidScorePolicy,
+    /// Least a matched query term contributes to the score, before the
+    /// document-wide factors, so length normalization of a very long
+    /// document can't reduce a match to nearly nothing. Terms weighted
+    /// `^0` get none (0.0 = disabled).
+    pub min_term_contribution: f64,
+}
+
+/// Handling of a score that isn't a finite number, which only pathological
+/// statistics or parameters produce. Either way a warning is logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidScorePolicy {
+    /// Keep the document with a score of 0.0
+    #[default]
+    Zero,
+    /// Leave the document out of the results
+    Drop,
+}
+
+impl InvalidScorePolicy {
+    /// Parse a policy name as used in the API: `zero` or `drop`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zero" => Some(InvalidScorePolicy::Zero),
+            "drop" => Some(InvalidScorePolicy::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// The score to rank a document by, or `None` to drop it, per
+/// [`RankingOptions::invalid_scores`]
+fn checked_score(doc_id: &str, score: f64, options: &RankingOptions) -> Option<f64> {
+    if score.is_finite() {
+        return Some(score);
+    }
+    tracing::warn!(doc_id, score, policy = ?options.invalid_scores, "Ranking produced an invalid score");
+    match options.invalid_scores {
+        InvalidScorePolicy::Zero => Some(0.0),
+        InvalidScorePolicy::Drop => None,
+    }
+}
+
+/// Scaling of document authority in [`RankingOptions::authority_weight`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorityScale {
+    /// The authority as is
+    #[default]
+    Linear,
+    /// `ln(1 + authority)`, for heavy-tailed values such as link counts
+    Log,
+}
+
+impl AuthorityScale {
+    /// Parse a scale name as used in the API: `linear` or `log`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(AuthorityScale::Linear),
+            "log" => Some(AuthorityScale::Log),
+            _ => None,
+        }
+    }
+
+    fn apply(self, authority: f64) -> f64 {
+        match self {
+            AuthorityScale::Linear => authority,
+            AuthorityScale::Log => authority.ln_1p(),
+        }
+    }
+}
+
+/// Key for ordering documents whose scores tie
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TieBreak {
+    /// Higher document boost first
+    Boost,
+    /// Most recently updated first
+    Recency,
+    /// Ascending document id
+    Id,
+}
+
+impl TieBreak {
+    /// Parse a key name as used in the API: `boost`, `recency` or `id`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "boost" => Some(TieBreak::Boost),
+            "recency" => Some(TieBreak::Recency),
+            "id" => Some(TieBreak::Id),
+            _ => None,
+        }
+    }
+}
+
+/// What a level of a multi-level sort compares; see [`SortSpec`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortKey {
+    /// The document's score; documents are equally relevant when unranked
+    Relevance,
+    /// The document's boost
+    Boost,
+    /// When the document was last updated
+    Recency,
+    /// The document id
+    Id,
+    /// A metadata value, compared as a number when both values are numbers
+    /// and as text otherwise (numbers first); documents without it come last
+    /// in either direction
+    Metadata(String),
+}
+
+/// One level of a multi-level sort, compared only between documents equal
+/// on every earlier level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortSpec {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortSpec {
+    /// Parse `key` or `key:asc` / `key:desc`. `relevance`, `boost`,
+    /// `recency` and `id` name those keys and default to the order
+    /// [`TieBreak`] uses (highest score, boost and latest update first,
+    /// ascending id); any other name is a metadata field, ascending by
+    /// default. `metadata.<field>` names a metadata field explicitly, even
+    /// one called like a key.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (key, direction) = match name.rsplit_once(':') {
+            Some((key, direction)) => (key, Some(direction)),
+            None => (name, None),
+        };
+        let key = match (key, key.strip_prefix("metadata.")) {
+            ("", _) | (_, Some("")) => return None,
+            (_, Some(field)) => SortKey::Metadata(field.to_string()),
+            ("relevance", _) => SortKey::Relevance,
+            ("boost", _) => SortKey::Boost,
+            ("recency", _) => SortKey::Recency,
+            ("id", _) => SortKey::Id,
+            (field, _) => SortKey::Metadata(field.to_string()),
+        };
+        let descending = match direction {
+            None => matches!(key, SortKey::Relevance | SortKey::Boost | SortKey::Recency),
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return None,
+        };
+        Some(Self { key, descending })
+    }
+}
+
+/// Order two metadata values for [`SortKey::Metadata`], ascending, with
+/// missing values last
+pub fn compare_sort_values(a: Option<&str>, b: Option<&str>, descending: bool) -> Ordering {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal,
+    };
+    let number = |value: &str| value.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+    let order = match (number(a), number(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    };
+    if descending {
+        order.reverse()
+    } else {
+        order
+    }
+}
+
+impl Default for RankingOptions {
+    fn default() -> Self {
+        RankingProfile::Bm25Default.options()
+    }
+}
+
+/// Pre-tuned combinations of ranking options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RankingProfile {
+    /// Plain BM25 with no adjustments
+    #[default]
+    Bm25Default,
+    /// Title boost, coordination, mild proximity boost, log-scaled tf and a
+    /// small bonus for exact word forms
+    SmartRelevance,
+}
+
+impl RankingProfile {
+    /// The ranking options this profile stands for
+    pub fn options(self) -> RankingOptions {
+        match self {
+            RankingProfile::Bm25Default => RankingOptions {
+                title_boost: 1.0,
+                coordination: false,
+                proximity_boost: 0.0,
+                log_tf: false,
+                exact_match_boost: 0.0,
+                field_coverage_boost: 0.0,
+                common_term_ratio: None,
+                authority_weight: 0.0,
+                authority_scale: AuthorityScale::Linear,
+                tie_break: vec![TieBreak::Id],
+                invalid_scores: InvalidScorePolicy::Zero,
+                min_term_contribution: 0.0,
+            },
+            RankingProfile::SmartRelevance => RankingOptions {
+                title_boost: 2.0,
+                coordination: true,
+                proximity_boost: 0.5,
+                log_tf: true,
+                exact_match_boost: 0.1,
+                field_coverage_boost: 0.0,
+                common_term_ratio: None,
+                authority_weight: 0.0,
+                authority_scale: AuthorityScale::Linear,
+                tie_break: vec![TieBreak::Id],
+                invalid_scores: InvalidScorePolicy::Zero,
+                min_term_contribution: 0.0,
+            },
+        }
+    }
+}
+
 impl BM25 {
     pub fn new(k1: f64, b: f64) -> Self {
         Self { k1, b }
@@ -30,35 +295,507 @@ impl BM25 {
         index: &InvertedIndex,
         avg_doc_length: f64,
     ) -> f64 {
-        let mut score = 0.0;
-        let doc_length = doc_stats.length as f64;
-        let total_docs = index.total_documents() as f64;
+        self.score_with(
+            query_terms,
+            doc_stats,
+            index,
+            avg_doc_length,
+            &RankingOptions::default(),
+        )
+    }
+
+    /// Calculate BM25 score for a document with ranking adjustments
+    pub fn score_with(
+        &self,
+        query_terms: &[String],
+        doc_stats: &DocStats,
+        index: &InvertedIndex,
+        avg_doc_length: f64,
+        options: &RankingOptions,
+    ) -> f64 {
+        let idfs = query_idfs(query_terms, index, options);
+        self.score_with_idfs(query_terms, &[], doc_stats, &idfs, avg_doc_length, options)
+    }
 
-        for term in query_terms {
-            // Get term frequency in document
-            let tf = *doc_stats.term_frequencies.get(term).unwrap_or(&0) as f64;
+    /// Calculate the score using IDF values precomputed with [`query_idfs`]
+    /// (`idfs[i]` belongs to `query_terms[i]`), so ranking many documents
+    /// doesn't recompute them per document. `surface_forms[i]` is the
+    /// unstemmed query word behind `query_terms[i]`, for the exact match
+    /// boost; pass an empty slice when unknown.
+    pub fn score_with_idfs(
+        &self,
+        query_terms: &[String],
+        surface_forms: &[String],
+        doc_stats: &DocStats,
+        idfs: &[f64],
+        avg_doc_length: f64,
+        options: &RankingOptions,
+    ) -> f64 {
+        let mut score = 0.0;
+        let mut matched_terms = 0;
 
-            if tf == 0.0 {
+        for (i, (term, &idf)) in query_terms.iter().zip(idfs).enumerate() {
+            let Some(term_score) = self.term_score(
+                term,
+                idf,
+                surface_forms.get(i),
+                doc_stats,
+                avg_doc_length,
+                options,
+            ) else {
                 continue;
+            };
+            if !is_unstemmed_term(term) {
+                matched_terms += 1;
             }
+            score += term_score.contribution;
+        }
+
+        DocumentFactors::new(query_terms, matched_terms, doc_stats, options).apply(score)
+    }
+
+    /// How a term found in a document adds to its score, or `None` if the
+    /// document doesn't contain it. `weighted_idf` is the term's IDF times
+    /// its query weight.
+    fn term_score(
+        &self,
+        term: &str,
+        weighted_idf: f64,
+        surface_form: Option<&String>,
+        doc_stats: &DocStats,
+        avg_doc_length: f64,
+        options: &RankingOptions,
+    ) -> Option<TermScore> {
+        let tf = doc_stats.term_frequencies.get(term).copied().unwrap_or(0);
+        if tf == 0 {
+            return None;
+        }
+
+        // Title occurrences are already counted once in tf
+        let title_tf = doc_stats
+            .title_term_frequencies
+            .get(term)
+            .copied()
+            .unwrap_or(0);
+        let mut adjusted_tf = tf as f64 + (options.title_boost - 1.0) * title_tf as f64;
+        if options.log_tf {
+            adjusted_tf = 1.0 + adjusted_tf.max(1.0).ln();
+        }
+
+        // Calculate BM25 score component
+        let length_ratio = length_ratio(doc_stats.length as f64, avg_doc_length);
+        let normalized_tf = (adjusted_tf * (self.k1 + 1.0))
+            / (adjusted_tf + self.k1 * (1.0 - self.b + self.b * length_ratio));
+
+        let contribution =
+            weighted_idf * normalized_tf * exact_match_factor(surface_form, doc_stats, options);
+        Some(TermScore {
+            adjusted_tf,
+            normalized_tf,
+            contribution: floored_contribution(contribution, weighted_idf, options),
+        })
+    }
+
+    /// Index of the document section that matches the query best, if any
+    /// query term occurs in a section
+    ///
+    /// Sections are scored on the saturated frequency of each term within
+    /// them, weighted by `idfs` as in [`score_with_idfs`](Self::score_with_idfs),
+    /// without length normalization; ties go to the earlier section.
+    pub fn best_section(
+        &self,
+        query_terms: &[String],
+        doc_stats: &DocStats,
+        idfs: &[f64],
+    ) -> Option<usize> {
+        let starts = &doc_stats.section_starts;
+        let mut scores = vec![0.0; starts.len()];
+        for (term, &idf) in query_terms.iter().zip(idfs) {
+            let mut tfs = vec![0usize; starts.len()];
+            for &position in doc_stats.positions.get(term).into_iter().flatten() {
+                // Sections follow every other field, so this is the one
+                // containing the position
+                if let Some(section) = starts
+                    .partition_point(|&start| start <= position)
+                    .checked_sub(1)
+                {
+                    tfs[section] += 1;
+                }
+            }
+            for (score, tf) in scores.iter_mut().zip(tfs) {
+                let tf = tf as f64;
+                *score += idf * tf * (self.k1 + 1.0) / (tf + self.k1);
+            }
+        }
+
+        scores
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, score)| score > 0.0)
+            .fold(None, |best: Option<(usize, f64)>, (i, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((i, score)),
+            })
+            .map(|(i, _)| i)
+    }
+}
 
-            // Calculate IDF (Inverse Document Frequency)
-            let doc_freq = index.doc_frequency(term) as f64;
-            let idf = if doc_freq > 0.0 {
-                ((total_docs - doc_freq + 0.5) / (doc_freq + 0.5) + 1.0).ln()
+/// A matched term's share of a document's score, from [`BM25::term_score`]
+struct TermScore {
+    adjusted_tf: f64,
+    normalized_tf: f64,
+    contribution: f64,
+}
+
+/// What a document's summed term contributions are multiplied by, each 1.0
+/// when disabled
+struct DocumentFactors {
+    coordination: f64,
+    proximity: f64,
+    field_coverage: f64,
+    authority: f64,
+    boost: f64,
+}
+
+impl DocumentFactors {
+    fn new(
+        query_terms: &[String],
+        matched_terms: usize,
+        doc_stats: &DocStats,
+        options: &RankingOptions,
+    ) -> Self {
+        let coordination = if options.coordination {
+            coordination_factor(matched_terms, query_terms)
+        } else {
+            1.0
+        };
+        let proximity = match options.proximity_boost {
+            boost if boost > 0.0 => min_term_distance(query_terms, doc_stats)
+                .map_or(1.0, |distance| 1.0 + boost / distance as f64),
+            _ => 1.0,
+        };
+
+        Self {
+            coordination,
+            proximity,
+            field_coverage: field_coverage_factor(query_terms, doc_stats, options),
+            authority: authority_factor(doc_stats.authority, options),
+            boost: doc_stats.boost,
+        }
+    }
+
+    /// The document's score given its term score
+    fn apply(&self, term_score: f64) -> f64 {
+        term_score
+            * self.coordination
+            * self.proximity
+            * self.field_coverage
+            * self.authority
+            * self.boost
+    }
+}
+
+/// How one query term contributed to a document's score
+#[derive(Debug, Clone, Serialize)]
+pub struct TermExplanation {
+    pub term: String,
+    /// `^weight` from the query
+    pub weight: f64,
+    /// Occurrences in the whole document and in the title alone
+    pub tf: usize,
+    pub title_tf: usize,
+    /// tf after title boosting and log scaling
+    pub adjusted_tf: f64,
+    pub doc_frequency: usize,
+    /// IDF after common term damping
+    pub idf: f64,
+    /// What the IDF was divided by for a common term (1.0 when not damped)
+    pub damping: f64,
+    /// tf after BM25 saturation and length normalization
+    pub normalized_tf: f64,
+    /// The document contains the term in exactly the form typed
+    pub exact_match: bool,
+    /// `weight * idf * normalized_tf`, times the exact match boost
+    pub contribution: f64,
+}
+
+/// Step-by-step breakdown of a document's score
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreExplanation {
+    pub terms: Vec<TermExplanation>,
+    pub doc_length: usize,
+    pub avg_doc_length: f64,
+    /// Sum of the term contributions
+    pub term_score: f64,
+    /// Coordination factor (1.0 when disabled)
+    pub coordination: f64,
+    pub min_term_distance: Option<usize>,
+    /// Proximity factor (1.0 when disabled or not applicable)
+    pub proximity: f64,
+    /// Number of fields (title, content) containing a query term
+    pub matched_fields: usize,
+    /// Field coverage factor (1.0 when disabled or matching one field)
+    pub field_coverage: f64,
+    /// Authority factor (1.0 when disabled or the document has none)
+    pub authority: f64,
+    pub doc_boost: f64,
+    /// Final score, as used for ranking
+    pub score: f64,
+}
+
+/// Query terms prepared for ranking, with per-term data aligned by index
+#[derive(Debug, Clone, Default)]
+pub struct ScoringQuery {
+    /// Analyzed terms that contribute to the score
+    pub terms: Vec<String>,
+    /// `^weight` of each term
+    pub weights: Vec<f64>,
+    /// Unstemmed query word behind each term
+    pub surface_forms: Vec<String>,
+}
+
+impl ScoringQuery {
+    /// Terms with weight 1.0, each its own surface form
+    pub fn new(terms: Vec<String>) -> Self {
+        Self {
+            weights: vec![1.0; terms.len()],
+            surface_forms: terms.clone(),
+            terms,
+        }
+    }
+}
+
+/// A matched term's contribution raised to
+/// [`RankingOptions::min_term_contribution`], unless its weighted IDF is 0
+fn floored_contribution(contribution: f64, weighted_idf: f64, options: &RankingOptions) -> f64 {
+    if weighted_idf > 0.0 {
+        contribution.max(options.min_term_contribution)
+    } else {
+        contribution
+    }
+}
+
+/// Multiplier for a matched term the document contains verbatim
+fn exact_match_factor(
+    surface_form: Option<&String>,
+    doc_stats: &DocStats,
+    options: &RankingOptions,
+) -> f64 {
+    match surface_form {
+        Some(surface)
+            if options.exact_match_boost > 0.0 && doc_stats.surface_forms.contains(surface) =>
+        {
+            1.0 + options.exact_match_boost
+        }
+        _ => 1.0,
+    }
+}
+
+/// Fraction of the query's words a document matches. Unstemmed forms
+/// aren't words of their own: a document with only the stem of a word still
+/// matches it.
+fn coordination_factor(matched_terms: usize, query_terms: &[String]) -> f64 {
+    let words = query_terms
+        .iter()
+        .filter(|term| !is_unstemmed_term(term))
+        .count();
+    if words == 0 {
+        1.0
+    } else {
+        matched_terms as f64 / words as f64
+    }
+}
+
+/// How many of a document's fields, title and content, contain a query term
+fn matched_fields(query_terms: &[String], doc_stats: &DocStats) -> usize {
+    let (mut title, mut content) = (false, false);
+    for term in query_terms {
+        let tf = doc_stats.term_frequencies.get(term).copied().unwrap_or(0);
+        let title_tf = doc_stats
+            .title_term_frequencies
+            .get(term)
+            .copied()
+            .unwrap_or(0);
+        title |= title_tf > 0;
+        content |= tf > title_tf;
+    }
+    usize::from(title) + usize::from(content)
+}
+
+/// Multiplier for a document matching the query in both fields
+fn field_coverage_factor(
+    query_terms: &[String],
+    doc_stats: &DocStats,
+    options: &RankingOptions,
+) -> f64 {
+    if options.field_coverage_boost > 0.0 && matched_fields(query_terms, doc_stats) == 2 {
+        1.0 + options.field_coverage_boost
+    } else {
+        1.0
+    }
+}
+
+/// Multiplier for a document's authority, which is never negative
+fn authority_factor(authority: Option<f64>, options: &RankingOptions) -> f64 {
+    match authority {
+        Some(authority) if options.authority_weight > 0.0 => {
+            1.0 + options.authority_weight * options.authority_scale.apply(authority.max(0.0))
+        }
+        _ => 1.0,
+    }
+}
+
+/// Document length relative to the average, for BM25 length normalization.
+/// When every indexed document is empty the average is zero; treat such
+/// documents as average length rather than dividing by zero.
+fn length_ratio(doc_length: f64, avg_doc_length: f64) -> f64 {
+    if avg_doc_length > 0.0 {
+        doc_length / avg_doc_length
+    } else {
+        1.0
+    }
+}
+
+/// Inverse document frequency of a term found in `doc_freq` of `total_docs` documents
+pub fn idf(doc_freq: usize, total_docs: usize) -> f64 {
+    if doc_freq == 0 {
+        return 0.0;
+    }
+    let (doc_freq, total_docs) = (doc_freq as f64, total_docs as f64);
+    ((total_docs - doc_freq + 0.5) / (doc_freq + 0.5) + 1.0).ln()
+}
+
+/// Divisor of the IDF of a term found in `doc_freq` of `total_docs`
+/// documents, growing with the log of how far the term is past
+/// [`RankingOptions::common_term_ratio`]
+pub fn common_term_damping(doc_freq: usize, total_docs: usize, options: &RankingOptions) -> f64 {
+    match options.common_term_ratio {
+        Some(ratio) => {
+            let threshold = (ratio * total_docs as f64).max(1.0);
+            let doc_freq = doc_freq as f64;
+            if doc_freq > threshold {
+                1.0 + (doc_freq / threshold).ln()
             } else {
-                0.0
+                1.0
+            }
+        }
+        None => 1.0,
+    }
+}
+
+/// IDF of each query term against the current index, damped for common
+/// terms as configured, in query order
+pub fn query_idfs(
+    query_terms: &[String],
+    index: &InvertedIndex,
+    options: &RankingOptions,
+) -> Vec<f64> {
+    let total_docs = index.total_documents();
+    query_terms
+        .iter()
+        .map(|term| {
+            let doc_freq = index.doc_frequency(term);
+            idf(doc_freq, total_docs) / common_term_damping(doc_freq, total_docs, options)
+        })
+        .collect()
+}
+
+impl BM25 {
+    /// Score a document like [`rank_documents`] does, recording every
+    /// intermediate value
+    pub fn explain(
+        &self,
+        query: &ScoringQuery,
+        doc_stats: &DocStats,
+        index: &InvertedIndex,
+        avg_doc_length: f64,
+        options: &RankingOptions,
+    ) -> ScoreExplanation {
+        let total_docs = index.total_documents();
+        let mut terms = Vec::new();
+        let mut term_score = 0.0;
+        let mut matched_terms = 0;
+
+        let query_terms = &query.terms;
+        for (i, term) in query_terms.iter().enumerate() {
+            let weight = query.weights.get(i).copied().unwrap_or(1.0);
+            let surface_form = query.surface_forms.get(i);
+            let tf = doc_stats.term_frequencies.get(term).copied().unwrap_or(0);
+            let title_tf = doc_stats
+                .title_term_frequencies
+                .get(term)
+                .copied()
+                .unwrap_or(0);
+            let doc_frequency = index.doc_frequency(term);
+            let damping = common_term_damping(doc_frequency, total_docs, options);
+            let idf = idf(doc_frequency, total_docs) / damping;
+
+            let mut explanation = TermExplanation {
+                term: term.clone(),
+                weight,
+                tf,
+                title_tf,
+                adjusted_tf: 0.0,
+                doc_frequency,
+                idf,
+                damping,
+                normalized_tf: 0.0,
+                exact_match: surface_form.is_some_and(|s| doc_stats.surface_forms.contains(s)),
+                contribution: 0.0,
             };
 
-            // Calculate BM25 score component
-            let normalized_tf =
-                (tf * (self.k1 + 1.0)) / (tf + self.k1 * (1.0 - self.b + self.b * (doc_length / avg_doc_length)));
+            if let Some(scored) = self.term_score(
+                term,
+                idf * weight,
+                surface_form,
+                doc_stats,
+                avg_doc_length,
+                options,
+            ) {
+                if !is_unstemmed_term(term) {
+                    matched_terms += 1;
+                }
+                explanation.adjusted_tf = scored.adjusted_tf;
+                explanation.normalized_tf = scored.normalized_tf;
+                explanation.contribution = scored.contribution;
+                term_score += scored.contribution;
+            }
+            terms.push(explanation);
+        }
 
-            score += idf * normalized_tf;
+        let factors = DocumentFactors::new(query_terms, matched_terms, doc_stats, options);
+        ScoreExplanation {
+            terms,
+            doc_length: doc_stats.length,
+            avg_doc_length,
+            term_score,
+            coordination: factors.coordination,
+            min_term_distance: min_term_distance(query_terms, doc_stats),
+            proximity: factors.proximity,
+            matched_fields: matched_fields(query_terms, doc_stats),
+            field_coverage: factors.field_coverage,
+            authority: factors.authority,
+            doc_boost: factors.boost,
+            score: factors.apply(term_score),
         }
+    }
+}
 
-        score
+/// Smallest distance between positions of two different query terms
+fn min_term_distance(query_terms: &[String], doc_stats: &DocStats) -> Option<usize> {
+    let mut positions: Vec<(usize, &str)> = Vec::new();
+    for term in query_terms {
+        if let Some(term_positions) = doc_stats.positions.get(term) {
+            positions.extend(term_positions.iter().map(|&p| (p, term.as_str())));
+        }
     }
+    positions.sort_unstable();
+
+    positions
+        .windows(2)
+        .filter(|pair| pair[0].1 != pair[1].1)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .min()
 }
 
 /// Ranked search result
@@ -74,34 +811,493 @@ impl ScoredDocument {
     }
 }
 
+/// How discriminative an analyzed query term is in the current corpus
+#[derive(Debug, Clone, Serialize)]
+pub struct TermIdf {
+    /// The term as analyzed and looked up in the index
+    pub term: String,
+    pub doc_frequency: usize,
+    /// IDF as scoring uses it, after common-term damping
+    pub idf: f64,
+}
+
+/// Summary of the scores of every document matching a query
+///
+/// Percentiles use the nearest-rank method. All values are 0.0 when nothing
+/// matched.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScoreDistribution {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl ScoreDistribution {
+    pub fn from_scores(scores: &[f64]) -> Self {
+        if scores.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = scores.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        Self {
+            count: sorted.len(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p25: percentile(25.0),
+            p50: percentile(50.0),
+            p75: percentile(75.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+        }
+    }
+}
+
+/// Documents scored between two deadline checks
+const DEADLINE_CHECK_INTERVAL: usize = 64;
+
+/// Limits for [`rank_documents_within`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RankBudget {
+    /// Return only this many of the best documents; at most twice as many
+    /// are held while scoring
+    pub keep: Option<usize>,
+    /// Stop scoring once this has passed and rank the documents scored so far
+    pub deadline: Option<Instant>,
+}
+
 /// Rank documents using BM25
+///
+/// Each term's weight multiplies its contribution; missing weights count
+/// as 1.0.
 pub fn rank_documents(
-    query_terms: &[String],
+    query: &ScoringQuery,
     candidate_docs: &[String],
     doc_stats_map: &HashMap<String, DocStats>,
     index: &InvertedIndex,
     avg_doc_length: f64,
+    options: &RankingOptions,
 ) -> Vec<ScoredDocument> {
+    let budget = RankBudget::default();
+    rank_documents_within(
+        query,
+        candidate_docs,
+        doc_stats_map,
+        index,
+        avg_doc_length,
+        options,
+        budget,
+    )
+    .0
+}
+
+/// Rank documents using BM25 within a [`RankBudget`], returning the ranked
+/// documents and whether the deadline cut scoring short
+///
+/// The best documents are kept as candidates are scored, so an interrupted
+/// pass returns the best of those scored before the deadline (at least the
+/// first [`DEADLINE_CHECK_INTERVAL`]) rather than nothing.
+pub fn rank_documents_within(
+    query: &ScoringQuery,
+    candidate_docs: &[String],
+    doc_stats_map: &HashMap<String, DocStats>,
+    index: &InvertedIndex,
+    avg_doc_length: f64,
+    options: &RankingOptions,
+    budget: RankBudget,
+) -> (Vec<ScoredDocument>, bool) {
     let bm25 = BM25::default();
+    let mut idfs = query_idfs(&query.terms, index, options);
+    for (idf, weight) in idfs.iter_mut().zip(&query.weights) {
+        *idf *= weight;
+    }
+    let keep = budget.keep.unwrap_or(usize::MAX);
     let mut scored_docs = Vec::new();
+    let mut timed_out = false;
 
-    for doc_id in candidate_docs {
+    for (i, doc_id) in candidate_docs.iter().enumerate() {
+        if i > 0
+            && i % DEADLINE_CHECK_INTERVAL == 0
+            && budget.deadline.is_some_and(|d| Instant::now() >= d)
+        {
+            timed_out = true;
+            break;
+        }
         if let Some(doc_stats) = doc_stats_map.get(doc_id) {
-            let score = bm25.score(query_terms, doc_stats, index, avg_doc_length);
+            let score = bm25.score_with_idfs(
+                &query.terms,
+                &query.surface_forms,
+                doc_stats,
+                &idfs,
+                avg_doc_length,
+                options,
+            );
+            if let Some(score) = checked_score(doc_id, score, options) {
+                scored_docs.push(ScoredDocument::new(doc_id.clone(), score));
+            }
+        }
+        // Sorting in batches keeps the tie-break order without a heap
+        // that knows about it
+        if scored_docs.len() >= keep.max(1).saturating_mul(2) {
+            sort_scored(&mut scored_docs, doc_stats_map, &options.tie_break);
+            scored_docs.truncate(keep);
+        }
+    }
+
+    sort_scored(&mut scored_docs, doc_stats_map, &options.tie_break);
+    scored_docs.truncate(keep);
+
+    (scored_docs, timed_out)
+}
+
+/// Number of best results a search re-orders with [`diversify`]
+pub const DIVERSITY_POOL: usize = 100;
+
+/// Re-order the best `pool` results by Maximal Marginal Relevance
+///
+/// Results are picked one at a time, each maximizing
+/// `(1 - lambda) * relevance - lambda * similarity`: relevance is the score
+/// relative to the best one and similarity the largest cosine similarity of
+/// term frequencies to a result already picked. `lambda` 0 keeps the
+/// ranking; 1 only avoids repetition. Results past the pool keep their
+/// places and scores stay with their documents.
+pub fn diversify(
+    ids: &mut [String],
+    scores: &mut [f64],
+    doc_stats_map: &HashMap<String, DocStats>,
+    lambda: f64,
+    pool: usize,
+) {
+    let pool = pool.min(ids.len());
+    if pool < 3 || lambda <= 0.0 {
+        return;
+    }
+    let best = scores[..pool].iter().copied().fold(f64::MIN, f64::max);
+    let relevance = |score: f64| if best > 0.0 { score / best } else { 0.0 };
+
+    let empty = HashMap::new();
+    let vectors: Vec<&HashMap<String, usize>> = ids[..pool]
+        .iter()
+        .map(|id| {
+            doc_stats_map
+                .get(id)
+                .map_or(&empty, |s| &s.term_frequencies)
+        })
+        .collect();
+    let norms: Vec<f64> = vectors
+        .iter()
+        .map(|tf| tf.values().map(|&n| (n * n) as f64).sum::<f64>().sqrt())
+        .collect();
+
+    // max_similarity[i]: similarity of candidate i to the closest picked result
+    let mut max_similarity = vec![0.0_f64; pool];
+    let mut remaining: Vec<usize> = (0..pool).collect();
+    let mut order = Vec::with_capacity(pool);
+
+    while !remaining.is_empty() {
+        let mmr = |i: usize| (1.0 - lambda) * relevance(scores[i]) - lambda * max_similarity[i];
+        // The first of equals wins, keeping the ranking among ties
+        let (position, &picked) = remaining
+            .iter()
+            .enumerate()
+            .reduce(|a, b| if mmr(*b.1) > mmr(*a.1) { b } else { a })
+            .unwrap();
+        remaining.remove(position);
+        order.push(picked);
+
+        for &i in &remaining {
+            let similarity =
+                cosine_similarity(vectors[i], norms[i], vectors[picked], norms[picked]);
+            max_similarity[i] = max_similarity[i].max(similarity);
+        }
+    }
+
+    let picked_ids: Vec<String> = order.iter().map(|&i| ids[i].clone()).collect();
+    let picked_scores: Vec<f64> = order.iter().map(|&i| scores[i]).collect();
+    ids[..pool].clone_from_slice(&picked_ids);
+    scores[..pool].copy_from_slice(&picked_scores);
+}
+
+/// Cosine similarity of two term frequency vectors with their norms
+fn cosine_similarity(
+    a: &HashMap<String, usize>,
+    a_norm: f64,
+    b: &HashMap<String, usize>,
+    b_norm: f64,
+) -> f64 {
+    if a_norm == 0.0 || b_norm == 0.0 {
+        return 0.0;
+    }
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let dot: usize = small
+        .iter()
+        .filter_map(|(term, &n)| large.get(term).map(|&m| n * m))
+        .sum();
+    dot as f64 / (a_norm * b_norm)
+}
+
+/// Sort by score descending, then by the tie-break keys
+fn sort_scored(
+    scored_docs: &mut [ScoredDocument],
+    doc_stats_map: &HashMap<String, DocStats>,
+    tie_break: &[TieBreak],
+) {
+    scored_docs.sort_by(|a, b| {
+        let mut order = b.score.total_cmp(&a.score);
+        for key in tie_break {
+            if order != Ordering::Equal {
+                break;
+            }
+            order = match key {
+                TieBreak::Id => a.doc_id.cmp(&b.doc_id),
+                TieBreak::Boost | TieBreak::Recency => {
+                    let (Some(a_stats), Some(b_stats)) =
+                        (doc_stats_map.get(&a.doc_id), doc_stats_map.get(&b.doc_id))
+                    else {
+                        continue;
+                    };
+                    match key {
+                        TieBreak::Boost => b_stats.boost.total_cmp(&a_stats.boost),
+                        _ => b_stats.updated_at.cmp(&a_stats.updated_at),
+                    }
+                }
+            };
+        }
+        order
+    });
+}
+
+/// Limits for [`rank_top_k`]
+#[derive(Debug, Clone, Copy)]
+pub struct TopK {
+    /// Number of best documents that must be exact
+    pub k: usize,
+    /// Largest document boost in the corpus (an upper bound is enough)
+    pub max_boost: f64,
+    /// Largest document authority in the corpus (an upper bound is enough)
+    pub max_authority: f64,
+    /// Stop scoring once this has passed and rank the documents scored so far
+    pub deadline: Option<Instant>,
+}
+
+/// Score ordered for a min-heap
+#[derive(PartialEq)]
+struct HeapScore(f64);
+
+impl Eq for HeapScore {}
+
+impl PartialOrd for HeapScore {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapScore {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Rank the documents matching any query term, skipping those that can't
+/// reach the top `k` (MaxScore pruning)
+///
+/// Each term gets an upper bound on what it can add to a score. Posting
+/// lists are walked from the highest bound down, scoring each new document
+/// in full; once the bounds of the remaining terms can't beat the k-th best
+/// score, documents found only in those lists are skipped. The first `k`
+/// results are exactly those full ranking would return; later ones are
+/// ranked among the scored documents only. Documents rejected by `accept`
+/// are never scored. Returns the ranked documents, whether any matching
+/// document was skipped and whether the deadline cut scoring short (which
+/// also counts as skipping).
+pub fn rank_top_k(
+    query: &ScoringQuery,
+    top_k: TopK,
+    index: &InvertedIndex,
+    doc_stats_map: &HashMap<String, DocStats>,
+    avg_doc_length: f64,
+    options: &RankingOptions,
+    accept: impl Fn(&str) -> bool,
+) -> (Vec<ScoredDocument>, bool, bool) {
+    let bm25 = BM25::default();
+    let mut idfs = query_idfs(&query.terms, index, options);
+    for (idf, weight) in idfs.iter_mut().zip(&query.weights) {
+        *idf *= weight;
+    }
+
+    // Normalized tf never reaches k1 + 1
+    let mut term_factor = bm25.k1 + 1.0;
+    if options.exact_match_boost > 0.0 {
+        term_factor *= 1.0 + options.exact_match_boost;
+    }
+    // Coordination only lowers scores
+    let mut factor = top_k.max_boost;
+    if options.proximity_boost > 0.0 {
+        factor *= 1.0 + options.proximity_boost;
+    }
+    if options.field_coverage_boost > 0.0 {
+        factor *= 1.0 + options.field_coverage_boost;
+    }
+    factor *= authority_factor(Some(top_k.max_authority), options);
+
+    // A term repeated in the query counts once per occurrence
+    let mut bounds: HashMap<&str, f64> = HashMap::new();
+    for (term, &idf) in query.terms.iter().zip(&idfs) {
+        *bounds.entry(term.as_str()).or_default() +=
+            floored_contribution(idf * term_factor, idf, options) * factor;
+    }
+    let mut terms: Vec<(&str, f64)> = bounds.into_iter().collect();
+    terms.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    // remaining[i]: the most a document found only in terms[i..] can score
+    let mut remaining = vec![0.0; terms.len() + 1];
+    for i in (0..terms.len()).rev() {
+        remaining[i] = remaining[i + 1] + terms[i].1;
+    }
+
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut best: BinaryHeap<Reverse<HeapScore>> = BinaryHeap::new();
+    let mut scored_docs = Vec::new();
+    let mut skipped = false;
+    let mut timed_out = false;
+
+    'terms: for (i, (term, _)) in terms.iter().enumerate() {
+        let Some(postings) = index.get_documents(term) else {
+            continue;
+        };
+
+        // A document that could tie the k-th score is still scored, since
+        // the tie-break might put it first
+        let threshold = best.peek().map(|Reverse(HeapScore(score))| *score);
+        if best.len() >= top_k.k && threshold.is_some_and(|t| remaining[i] < t) {
+            skipped = terms[i..].iter().any(|(term, _)| {
+                index.get_documents(term).is_some_and(|ids| {
+                    ids.iter()
+                        .any(|id| !seen.contains(id.as_str()) && accept(id))
+                })
+            });
+            break;
+        }
+
+        for doc_id in postings {
+            if !seen.insert(doc_id.as_str()) || !accept(doc_id) {
+                continue;
+            }
+            let Some(doc_stats) = doc_stats_map.get(doc_id) else {
+                continue;
+            };
+            if !scored_docs.is_empty()
+                && scored_docs.len() % DEADLINE_CHECK_INTERVAL == 0
+                && top_k.deadline.is_some_and(|d| Instant::now() >= d)
+            {
+                skipped = true;
+                timed_out = true;
+                break 'terms;
+            }
+
+            let score = bm25.score_with_idfs(
+                &query.terms,
+                &query.surface_forms,
+                doc_stats,
+                &idfs,
+                avg_doc_length,
+                options,
+            );
+            let Some(score) = checked_score(doc_id, score, options) else {
+                continue;
+            };
             scored_docs.push(ScoredDocument::new(doc_id.clone(), score));
+
+            best.push(Reverse(HeapScore(score)));
+            if best.len() > top_k.k {
+                best.pop();
+            }
         }
     }
 
-    // Sort by score descending
-    scored_docs.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    sort_scored(&mut scored_docs, doc_stats_map, &options.tie_break);
 
-    scored_docs
+    (scored_docs, skipped, timed_out)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tie_break() {
+        let mut doc_stats_map = HashMap::new();
+        for (id, boost, updated_at) in [
+            ("a", 1.0, 30),
+            ("b", 2.0, 10),
+            ("c", 1.0, 20),
+            ("d", 2.0, 20),
+        ] {
+            let mut doc_stats = DocStats::new(id.to_string(), 1);
+            doc_stats.boost = boost;
+            doc_stats.updated_at = updated_at;
+            doc_stats_map.insert(id.to_string(), doc_stats);
+        }
+
+        let sorted = |tie_break: &[TieBreak]| {
+            let mut scored_docs: Vec<ScoredDocument> =
+                [("d", 1.0), ("c", 1.0), ("e", 2.0), ("b", 1.0), ("a", 1.0)]
+                    .iter()
+                    .map(|(id, score)| ScoredDocument::new(id.to_string(), *score))
+                    .collect();
+            sort_scored(&mut scored_docs, &doc_stats_map, tie_break);
+            scored_docs
+                .into_iter()
+                .map(|sd| sd.doc_id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(&[TieBreak::Id]), vec!["e", "a", "b", "c", "d"]);
+        assert_eq!(
+            sorted(&[TieBreak::Boost, TieBreak::Recency, TieBreak::Id]),
+            vec!["e", "d", "b", "a", "c"]
+        );
+        assert_eq!(
+            sorted(&[TieBreak::Recency, TieBreak::Id]),
+            vec!["e", "a", "c", "d", "b"]
+        );
+        assert_eq!(sorted(&[]), vec!["e", "d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_score_distribution() {
+        let scores: Vec<f64> = (1..=10).rev().map(f64::from).collect();
+        let distribution = ScoreDistribution::from_scores(&scores);
+
+        assert_eq!(distribution.count, 10);
+        assert_eq!(
+            (distribution.min, distribution.max, distribution.mean),
+            (1.0, 10.0, 5.5)
+        );
+        assert_eq!(
+            (
+                distribution.p25,
+                distribution.p50,
+                distribution.p90,
+                distribution.p99
+            ),
+            (3.0, 5.0, 9.0, 10.0)
+        );
+        assert_eq!(ScoreDistribution::from_scores(&[]).count, 0);
+    }
+
     #[test]
     fn test_bm25_score() {
         let bm25 = BM25::default();
@@ -114,4 +1310,398 @@ mod tests {
         let score = bm25.score(&["test".to_string()], &doc_stats, &index, 10.0);
         assert!(score > 0.0);
     }
+
+    #[test]
+    fn test_precomputed_idfs_match_direct_scoring() {
+        let mut index = InvertedIndex::new();
+        index.add_document("doc1", &["rust".to_string(), "fast".to_string()]);
+        index.add_document("doc2", &["rust".to_string()]);
+
+        let mut doc_stats = DocStats::new("doc1".to_string(), 2);
+        doc_stats.term_frequencies.insert("rust".to_string(), 1);
+        doc_stats.term_frequencies.insert("fast".to_string(), 1);
+
+        let query = vec![
+            "rust".to_string(),
+            "fast".to_string(),
+            "missing".to_string(),
+        ];
+        let idfs = query_idfs(&query, &index, &RankingOptions::default());
+        assert_eq!(idfs[2], 0.0);
+
+        let bm25 = BM25::default();
+        let options = RankingOptions::default();
+        let direct = bm25.score_with(&query, &doc_stats, &index, 1.5, &options);
+        let cached = bm25.score_with_idfs(&query, &[], &doc_stats, &idfs, 1.5, &options);
+        assert_eq!(direct, cached);
+    }
+
+    #[test]
+    fn test_explain_matches_ranking() {
+        let mut index = InvertedIndex::new();
+        index.add_document("doc1", &["rust".to_string(), "book".to_string()]);
+        index.add_document("doc2", &["rust".to_string()]);
+
+        let mut doc_stats = DocStats::new("doc1".to_string(), 4);
+        doc_stats.term_frequencies.insert("rust".to_string(), 2);
+        doc_stats.term_frequencies.insert("book".to_string(), 1);
+        doc_stats
+            .title_term_frequencies
+            .insert("rust".to_string(), 1);
+        doc_stats.positions.insert("rust".to_string(), vec![0, 3]);
+        doc_stats.positions.insert("book".to_string(), vec![1]);
+        doc_stats.boost = 1.5;
+        let stats_map: HashMap<String, DocStats> = [("doc1".to_string(), doc_stats.clone())].into();
+
+        let mut query = ScoringQuery::new(vec![
+            "rust".to_string(),
+            "book".to_string(),
+            "missing".to_string(),
+        ]);
+        query.weights = vec![2.0, 1.0, 1.0];
+        let options = RankingProfile::SmartRelevance.options();
+
+        let ranked = rank_documents(
+            &query,
+            &["doc1".to_string()],
+            &stats_map,
+            &index,
+            3.0,
+            &options,
+        );
+        let explanation = BM25::default().explain(&query, &doc_stats, &index, 3.0, &options);
+
+        assert!((explanation.score - ranked[0].score).abs() < 1e-12);
+        assert_eq!(explanation.terms.len(), 3);
+        assert_eq!(explanation.terms[0].weight, 2.0);
+        assert_eq!(explanation.terms[2].contribution, 0.0);
+        assert_eq!(explanation.min_term_distance, Some(1));
+        assert!((explanation.coordination - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(explanation.doc_boost, 1.5);
+    }
+
+    #[test]
+    fn test_smart_relevance_prefers_title_and_proximity() {
+        let mut index = InvertedIndex::new();
+        index.add_document("title", &["rust".to_string(), "book".to_string()]);
+        index.add_document("content", &["rust".to_string(), "book".to_string()]);
+        index.add_document("other", &["python".to_string()]);
+
+        let mut in_title = DocStats::new("title".to_string(), 6);
+        in_title.term_frequencies.insert("rust".to_string(), 1);
+        in_title.term_frequencies.insert("book".to_string(), 1);
+        in_title
+            .title_term_frequencies
+            .insert("rust".to_string(), 1);
+        in_title
+            .title_term_frequencies
+            .insert("book".to_string(), 1);
+        in_title.positions.insert("rust".to_string(), vec![0]);
+        in_title.positions.insert("book".to_string(), vec![1]);
+
+        let mut in_content = DocStats::new("content".to_string(), 6);
+        in_content.term_frequencies.insert("rust".to_string(), 1);
+        in_content.term_frequencies.insert("book".to_string(), 1);
+        in_content.positions.insert("rust".to_string(), vec![0]);
+        in_content.positions.insert("book".to_string(), vec![5]);
+
+        let query = vec!["rust".to_string(), "book".to_string()];
+        let bm25 = BM25::default();
+        let smart = RankingProfile::SmartRelevance.options();
+
+        // Plain BM25 can't tell them apart
+        let plain_title = bm25.score(&query, &in_title, &index, 6.0);
+        let plain_content = bm25.score(&query, &in_content, &index, 6.0);
+        assert!((plain_title - plain_content).abs() < 1e-9);
+
+        let smart_title = bm25.score_with(&query, &in_title, &index, 6.0, &smart);
+        let smart_content = bm25.score_with(&query, &in_content, &index, 6.0, &smart);
+        assert!(smart_title > smart_content);
+    }
+
+    #[test]
+    fn test_coordination_penalizes_partial_matches() {
+        let mut index = InvertedIndex::new();
+        index.add_document("doc1", &["rust".to_string()]);
+        index.add_document("doc2", &["book".to_string()]);
+
+        let mut doc_stats = DocStats::new("doc1".to_string(), 1);
+        doc_stats.term_frequencies.insert("rust".to_string(), 1);
+
+        let query = vec!["rust".to_string(), "book".to_string()];
+        let options = RankingOptions {
+            coordination: true,
+            ..Default::default()
+        };
+
+        let bm25 = BM25::default();
+        let plain = bm25.score(&query, &doc_stats, &index, 1.0);
+        let coordinated = bm25.score_with(&query, &doc_stats, &index, 1.0, &options);
+        assert!((coordinated - plain / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_common_term_damping() {
+        let mut index = InvertedIndex::new();
+        for i in 0..10 {
+            let mut tokens = vec!["the".to_string()];
+            if i < 2 {
+                tokens.push("rust".to_string());
+            }
+            index.add_document(&format!("doc{}", i), &tokens);
+        }
+
+        let query = vec!["the".to_string(), "rust".to_string()];
+        let plain = query_idfs(&query, &index, &RankingOptions::default());
+        let options = RankingOptions {
+            common_term_ratio: Some(0.5),
+            ..Default::default()
+        };
+        let damped = query_idfs(&query, &index, &options);
+
+        // "the" is in every document, twice the threshold of 5
+        assert!((damped[0] - plain[0] / (1.0 + 2f64.ln())).abs() < 1e-12);
+        assert!(damped[0] > 0.0);
+        assert_eq!(damped[1], plain[1]);
+        assert_eq!(common_term_damping(5, 10, &options), 1.0);
+    }
+
+    #[test]
+    fn test_invalid_scores() {
+        let mut index = InvertedIndex::new();
+        let mut doc_stats_map = HashMap::new();
+        for (id, boost) in [("good", 1.0), ("bad", f64::NAN)] {
+            index.add_document(id, &["rust".to_string()]);
+            let mut doc_stats = DocStats::new(id.to_string(), 3);
+            doc_stats.term_frequencies.insert("rust".to_string(), 1);
+            doc_stats.boost = boost;
+            doc_stats_map.insert(id.to_string(), doc_stats);
+        }
+        let query = ScoringQuery::new(vec!["rust".to_string()]);
+        let candidates = vec!["bad".to_string(), "good".to_string()];
+
+        let ranked = rank_documents(
+            &query,
+            &candidates,
+            &doc_stats_map,
+            &index,
+            3.0,
+            &RankingOptions::default(),
+        );
+        let ranked: Vec<(&str, f64)> = ranked
+            .iter()
+            .map(|sd| (sd.doc_id.as_str(), sd.score))
+            .collect();
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].0, "good");
+        assert!(ranked[0].1 > 0.0);
+        assert_eq!(ranked[1], ("bad", 0.0));
+
+        let options = RankingOptions {
+            invalid_scores: InvalidScorePolicy::Drop,
+            ..Default::default()
+        };
+        let ranked = rank_documents(&query, &candidates, &doc_stats_map, &index, 3.0, &options);
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|sd| sd.doc_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["good"]
+        );
+
+        let top_k = TopK {
+            k: 1,
+            max_boost: 1.0,
+            max_authority: 0.0,
+            deadline: None,
+        };
+        let (ranked, _, _) =
+            rank_top_k(&query, top_k, &index, &doc_stats_map, 3.0, &options, |_| {
+                true
+            });
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|sd| sd.doc_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["good"]
+        );
+    }
+
+    #[test]
+    fn test_min_term_contribution() {
+        let mut index = InvertedIndex::new();
+        let mut doc_stats_map = HashMap::new();
+        // A huge document with both query terms, a short one with one, and
+        // filler so neither term is common
+        for (id, length, terms) in [
+            ("long", 100_000, vec!["rust", "safety"]),
+            ("short", 5, vec!["rust"]),
+        ] {
+            let terms: Vec<String> = terms.into_iter().map(String::from).collect();
+            index.add_document(id, &terms);
+            let mut doc_stats = DocStats::new(id.to_string(), length);
+            for term in terms {
+                doc_stats.term_frequencies.insert(term, 1);
+            }
+            doc_stats_map.insert(id.to_string(), doc_stats);
+        }
+        for i in 0..8 {
+            index.add_document(&format!("other{}", i), &["go".to_string()]);
+        }
+        let query = ScoringQuery::new(vec!["rust".to_string(), "safety".to_string()]);
+        let candidates = vec!["long".to_string(), "short".to_string()];
+        let avg_length = 1_000.0;
+
+        let plain = RankingOptions::default();
+        let ranked = rank_documents(
+            &query,
+            &candidates,
+            &doc_stats_map,
+            &index,
+            avg_length,
+            &plain,
+        );
+        assert_eq!(ranked[0].doc_id, "short");
+
+        let options = RankingOptions {
+            min_term_contribution: 1.5,
+            ..Default::default()
+        };
+        let ranked = rank_documents(
+            &query,
+            &candidates,
+            &doc_stats_map,
+            &index,
+            avg_length,
+            &options,
+        );
+        assert_eq!(ranked[0].doc_id, "long");
+        assert_eq!(ranked[0].score, 3.0);
+
+        // Absent terms get nothing
+        let short = BM25::default().score_with(
+            &query.terms,
+            &doc_stats_map["short"],
+            &index,
+            avg_length,
+            &options,
+        );
+        let plain =
+            BM25::default().score(&query.terms, &doc_stats_map["short"], &index, avg_length);
+        assert_eq!(short, plain.max(1.5));
+
+        // Pruning accounts for the floor
+        let top_k = TopK {
+            k: 1,
+            max_boost: 1.0,
+            max_authority: 0.0,
+            deadline: None,
+        };
+        let (ranked, _, _) = rank_top_k(
+            &query,
+            top_k,
+            &index,
+            &doc_stats_map,
+            avg_length,
+            &options,
+            |_| true,
+        );
+        assert_eq!(ranked[0].doc_id, "long");
+    }
+
+    #[test]
+    fn test_diversify() {
+        let mut doc_stats_map = HashMap::new();
+        for (id, terms) in [
+            ("a", "rust book"),
+            ("b", "rust book"),
+            ("c", "rust game"),
+            ("d", "go book"),
+        ] {
+            let mut doc_stats = DocStats::new(id.to_string(), 2);
+            for term in terms.split(' ') {
+                doc_stats.term_frequencies.insert(term.to_string(), 1);
+            }
+            doc_stats_map.insert(id.to_string(), doc_stats);
+        }
+        let ranked = || {
+            let ids: Vec<String> = ["a", "b", "c", "d"].into_iter().map(String::from).collect();
+            (ids, vec![4.0, 3.9, 3.0, 1.0])
+        };
+
+        // The duplicate of the best result drops below the different one
+        let (mut ids, mut scores) = ranked();
+        diversify(&mut ids, &mut scores, &doc_stats_map, 0.5, 10);
+        assert_eq!(ids, vec!["a", "c", "b", "d"]);
+        assert_eq!(scores, vec![4.0, 3.0, 3.9, 1.0]);
+
+        let (mut ids, mut scores) = ranked();
+        diversify(&mut ids, &mut scores, &doc_stats_map, 0.7, 10);
+        assert_eq!(ids, vec!["a", "c", "d", "b"]);
+
+        // No diversity, or a pool too small to matter, keeps the ranking
+        let (mut ids, mut scores) = ranked();
+        diversify(&mut ids, &mut scores, &doc_stats_map, 0.0, 10);
+        assert_eq!(ids, ranked().0);
+        diversify(&mut ids, &mut scores, &doc_stats_map, 0.5, 2);
+        assert_eq!(ids, ranked().0);
+    }
+
+    #[test]
+    fn test_field_coverage_boost() {
+        let mut index = InvertedIndex::new();
+        index.add_document("both", &["rust".to_string(), "book".to_string()]);
+        index.add_document("content", &["rust".to_string(), "book".to_string()]);
+
+        let mut both = DocStats::new("both".to_string(), 2);
+        both.term_frequencies.insert("rust".to_string(), 1);
+        both.term_frequencies.insert("book".to_string(), 1);
+        both.title_term_frequencies.insert("rust".to_string(), 1);
+        let mut content = both.clone();
+        content.title_term_frequencies.clear();
+
+        let query = vec!["rust".to_string(), "book".to_string()];
+        let options = RankingOptions {
+            field_coverage_boost: 0.5,
+            ..Default::default()
+        };
+
+        let bm25 = BM25::default();
+        let plain = bm25.score(&query, &both, &index, 2.0);
+        assert!((bm25.score_with(&query, &both, &index, 2.0, &options) - plain * 1.5).abs() < 1e-9);
+        assert_eq!(
+            bm25.score_with(&query, &content, &index, 2.0, &options),
+            plain
+        );
+
+        let explanation = bm25.explain(&ScoringQuery::new(query), &both, &index, 2.0, &options);
+        assert_eq!(explanation.matched_fields, 2);
+        assert_eq!(explanation.field_coverage, 1.5);
+    }
+
+    #[test]
+    fn test_best_section() {
+        let mut stats = DocStats::new("1".to_string(), 30);
+        stats.section_starts = vec![10, 20];
+        stats
+            .positions
+            .insert("rust".to_string(), vec![1, 12, 21, 22]);
+        stats.positions.insert("borrow".to_string(), vec![2, 13]);
+
+        let bm25 = BM25::default();
+        let query = vec!["rust".to_string(), "borrow".to_string()];
+        // The rarer term outweighs a second occurrence of the common one
+        assert_eq!(bm25.best_section(&query, &stats, &[0.1, 2.0]), Some(0));
+        assert_eq!(bm25.best_section(&query, &stats, &[2.0, 0.1]), Some(1));
+        // Matches before the first section don't count
+        stats.positions.insert("intro".to_string(), vec![3]);
+        assert_eq!(
+            bm25.best_section(&["intro".to_string()], &stats, &[1.0]),
+            None
+        );
+        stats.section_starts.clear();
+        assert_eq!(bm25.best_section(&query, &stats, &[1.0, 1.0]), None);
+    }
 }
@@ -1,48 +1,334 @@
-use crate::document::{Document, DocStats};
+use crate::document::{DisplayValue, DocStats, Document};
 use crate::index::InvertedIndex;
 use anyhow::{Context, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
 use sled::Db;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const DOCS_TREE: &str = "documents";
 const STATS_TREE: &str = "doc_stats";
 const INDEX_TREE: &str = "index";
 const METADATA_TREE: &str = "metadata";
+const TOMBSTONES_TREE: &str = "tombstones";
+/// Normalized title, a zero byte and the document id -> nothing
+const TITLES_TREE: &str = "titles";
 
+/// Header of documents stored in the versioned format. Legacy documents are
+/// plain bincode, which starts with the little-endian id length and so can't
+/// begin with these bytes for any realistic id.
+const DOC_MAGIC: [u8; 3] = [0xFF, b'D', b'C'];
+/// Layout of [`Document`] written now. Bump it whenever a field is added
+/// and freeze the previous layout in a `DocumentV<n>` struct, since bincode
+/// can't skip fields it doesn't know.
+const DOC_FORMAT_VERSION: u8 = 8;
+
+/// Header of document statistics stored in the versioned format. Statistics
+/// without it, or of an older version, are in an earlier layout and are
+/// rebuilt from their documents rather than decoded.
+const STATS_MAGIC: [u8; 3] = [0xFF, b'S', b'T'];
+const STATS_FORMAT_VERSION: u8 = 10;
+
+/// Longest [`Storage::close`] waits for the database file to be released
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Key of the whole index in format 1, replaced by segments
+const LEGACY_INDEX_KEY: &[u8] = b"main_index";
+/// Key of the [`IndexHeader`] of a segmented index
+const INDEX_HEADER_KEY: &[u8] = b"header";
+const INDEX_FORMAT_VERSION: u8 = 2;
+/// Default [`Storage::with_segment_bytes`]
+pub const DEFAULT_SEGMENT_BYTES: usize = 16 << 20;
+
+/// Layout of a saved index: posting lists spread over numbered segment
+/// values so no single value grows with the whole index
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexHeader {
+    version: u8,
+    doc_count: usize,
+    segments: u32,
+}
+
+fn segment_key(n: u32) -> Vec<u8> {
+    format!("segment/{:08}", n).into_bytes()
+}
+
+/// Compression applied to stored documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+}
+
+impl Compression {
+    fn codec(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+
+    fn from_codec(codec: u8) -> Result<Self> {
+        match codec {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            other => anyhow::bail!("Unknown document codec: {}", other),
+        }
+    }
+}
+
+/// When writes are flushed to disk
+///
+/// sled applies writes in memory and persists them when flushed, so this
+/// trades throughput against how much a crash can lose. Explicit
+/// [`Storage::flush`] calls flush at every level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Flush before each write operation returns. Acknowledged writes
+    /// survive a crash.
+    #[default]
+    Sync,
+    /// Flush in the background every `flush_every_ms` milliseconds. A crash
+    /// loses at most the writes from that window.
+    Async { flush_every_ms: u64 },
+    /// Never flush explicitly and rely on sled's own background flushing
+    /// (every 500 ms by default). A crash loses whatever sled hadn't written
+    /// out yet. Suited to indexes that can be rebuilt from their source.
+    None,
+}
+
+/// Retrying of storage writes that fail transiently
+///
+/// Only I/O errors that may clear up on their own (interrupted, would
+/// block, timed out) are retried; corruption, bugs and encoding errors fail
+/// at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first; 0 disables retrying
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled before each further one
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run a write, retrying it while it fails transiently
+    fn run<T>(&self, operation: &str, mut write: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            match write() {
+                Err(e) if attempt < self.max_retries && is_transient(&e) => {
+                    attempt += 1;
+                    tracing::warn!(operation, attempt, error = %e, "Retrying storage write after transient error");
+                    std::thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error may clear up if the operation is simply tried again
+fn is_transient(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+
+    let io_error = match error.downcast_ref::<sled::Error>() {
+        Some(sled::Error::Io(e)) => e,
+        Some(_) => return false,
+        None => match error.downcast_ref::<std::io::Error>() {
+            Some(e) => e,
+            None => return false,
+        },
+    };
+    matches!(
+        io_error.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    )
+}
+
+/// Document, statistics and index storage in a sled database
+///
+/// Clones share the database. Give each a different
+/// [namespace](Self::with_namespace) to keep several indexes in one
+/// database.
+#[derive(Clone)]
 pub struct Storage {
-    db: Db,
+    db: Arc<Db>,
+    /// Directory of the database, `None` for an in-memory one
+    path: Option<PathBuf>,
+    /// Prefix of the tree names, empty for the unprefixed default trees
+    namespace: String,
+    compression: Compression,
+    durability: Durability,
+    retry: RetryPolicy,
+    segment_bytes: usize,
 }
 
 impl Storage {
     /// Open or create a storage database
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::open(path).context("Failed to open database")?;
-        Ok(Self { db })
+        Self::open_with_durability(path, Durability::default())
+    }
+
+    /// Open or create a storage database with the given durability level
+    pub fn open_with_durability<P: AsRef<Path>>(path: P, durability: Durability) -> Result<Self> {
+        let mut config = sled::Config::new().path(path.as_ref());
+        if let Durability::Async { flush_every_ms } = durability {
+            config = config.flush_every_ms(Some(flush_every_ms));
+        }
+        let db = config.open().context("Failed to open database")?;
+        Ok(Self {
+            db: Arc::new(db),
+            path: Some(path.as_ref().to_path_buf()),
+            namespace: String::new(),
+            compression: Compression::None,
+            durability,
+            retry: RetryPolicy::default(),
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+        })
     }
 
     /// Create an in-memory database (for testing)
     pub fn in_memory() -> Result<Self> {
         let config = sled::Config::new().temporary(true);
-        let db = config.open().context("Failed to create in-memory database")?;
-        Ok(Self { db })
+        let db = config
+            .open()
+            .context("Failed to create in-memory database")?;
+        Ok(Self {
+            db: Arc::new(db),
+            path: None,
+            namespace: String::new(),
+            compression: Compression::None,
+            durability: Durability::default(),
+            retry: RetryPolicy::default(),
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+        })
+    }
+
+    /// Keep data in trees named `<namespace>/documents` and so on instead of
+    /// `documents`, apart from other namespaces of the same database. The
+    /// empty namespace is the default trees.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Name of one of this storage's trees in the database
+    fn tree_name(&self, tree: &str) -> String {
+        if self.namespace.is_empty() {
+            tree.to_string()
+        } else {
+            format!("{}/{}", self.namespace, tree)
+        }
+    }
+
+    fn tree(&self, tree: &str) -> Result<sled::Tree> {
+        Ok(self.db.open_tree(self.tree_name(tree))?)
+    }
+
+    /// Compress documents written from now on. Documents already stored in
+    /// any format remain readable.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Retry document, statistics and index writes as the policy says
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Save the index in segments of about this many bytes of terms and ids
+    pub fn with_segment_bytes(mut self, segment_bytes: usize) -> Self {
+        self.segment_bytes = segment_bytes.max(1);
+        self
+    }
+
+    fn encode_document(&self, doc: &Document) -> Result<Vec<u8>> {
+        let serialized = bincode::serialize(doc)?;
+
+        let mut encoded = DOC_MAGIC.to_vec();
+        encoded.push(DOC_FORMAT_VERSION);
+        encoded.push(self.compression.codec());
+
+        match self.compression {
+            Compression::None => encoded.extend_from_slice(&serialized),
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(encoded, flate2::Compression::default());
+                encoder.write_all(&serialized)?;
+                encoded = encoder.finish()?;
+            }
+        }
+
+        Ok(encoded)
+    }
+
+    fn decode_document(data: &[u8]) -> Result<Document> {
+        let Some(rest) = data.strip_prefix(&DOC_MAGIC) else {
+            // Legacy format: plain bincode of the first layout
+            return deserialize_document(1, data);
+        };
+
+        let [version, codec, payload @ ..] = rest else {
+            anyhow::bail!("Truncated document header");
+        };
+
+        match Compression::from_codec(*codec)? {
+            Compression::None => deserialize_document(*version, payload),
+            Compression::Deflate => deserialize_document(*version, DeflateDecoder::new(payload)),
+        }
     }
 
     // ========== Document Operations ==========
 
-    /// Save a document
+    /// Save a document, keeping the title lookup in step
     pub fn save_document(&self, doc: &Document) -> Result<()> {
-        let tree = self.db.open_tree(DOCS_TREE)?;
-        let serialized = bincode::serialize(doc)?;
-        tree.insert(doc.id.as_bytes(), serialized)?;
-        Ok(())
+        let _span = tracing::debug_span!("storage.save_document", doc_id = %doc.id).entered();
+        let tree = self.tree(DOCS_TREE)?;
+        let titles = self.tree(TITLES_TREE)?;
+        let encoded = self.encode_document(doc)?;
+        // A retry finds the document already written, so the old title is
+        // remembered from the first attempt
+        let mut old_title = None;
+        self.retry.run("save_document", || {
+            if let Some(old) = tree.insert(doc.id.as_bytes(), encoded.clone())? {
+                old_title.get_or_insert(Self::decode_document(&old)?.title);
+            }
+            if let Some(old_title) = &old_title {
+                titles.remove(title_key(old_title, &doc.id))?;
+            }
+            titles.insert(title_key(&doc.title, &doc.id), &[])?;
+            Ok(())
+        })
     }
 
     /// Get a document by ID
     pub fn get_document(&self, id: &str) -> Result<Option<Document>> {
-        let tree = self.db.open_tree(DOCS_TREE)?;
+        let _span = tracing::debug_span!("storage.get_document", doc_id = id).entered();
+        let tree = self.tree(DOCS_TREE)?;
         if let Some(data) = tree.get(id.as_bytes())? {
-            let doc: Document = bincode::deserialize(&data)?;
-            Ok(Some(doc))
+            Ok(Some(Self::decode_document(&data)?))
         } else {
             Ok(None)
         }
@@ -50,88 +336,241 @@ impl Storage {
 
     /// Delete a document
     pub fn delete_document(&self, id: &str) -> Result<()> {
-        let tree = self.db.open_tree(DOCS_TREE)?;
-        tree.remove(id.as_bytes())?;
+        let _span = tracing::debug_span!("storage.delete_document", doc_id = id).entered();
+        let tree = self.tree(DOCS_TREE)?;
+        if let Some(old) = tree.remove(id.as_bytes())? {
+            let titles = self.tree(TITLES_TREE)?;
+            titles.remove(title_key(&Self::decode_document(&old)?.title, id))?;
+        }
+        Ok(())
+    }
+
+    /// IDs of the documents whose title equals `title` once both are
+    /// normalized (see [`normalize_title`]), in id order
+    pub fn find_ids_by_title(&self, title: &str) -> Result<Vec<String>> {
+        let tree = self.tree(TITLES_TREE)?;
+        let prefix = title_key(title, "");
+        let mut ids = Vec::new();
+
+        for item in tree.scan_prefix(&prefix) {
+            let (key, _) = item?;
+            let id = &key[prefix.len()..];
+            // A longer title that itself contains a zero byte
+            if !id.contains(&0) {
+                ids.push(String::from_utf8(id.to_vec())?);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Build the title lookup of documents stored before it existed
+    ///
+    /// Every stored document has an entry, so an empty lookup next to stored
+    /// documents means it was never built.
+    pub fn ensure_title_index(&self) -> Result<()> {
+        let titles = self.tree(TITLES_TREE)?;
+        if !titles.is_empty() {
+            return Ok(());
+        }
+        for doc in self.iter_documents()? {
+            let doc = doc?;
+            titles.insert(title_key(&doc.title, &doc.id), &[])?;
+        }
         Ok(())
     }
 
     /// Get all documents
     pub fn get_all_documents(&self) -> Result<Vec<Document>> {
-        let tree = self.db.open_tree(DOCS_TREE)?;
-        let mut docs = Vec::new();
+        self.iter_documents()?.collect()
+    }
 
-        for item in tree.iter() {
+    /// Iterate over all documents in id order without loading them all at once
+    pub fn iter_documents(&self) -> Result<impl Iterator<Item = Result<Document>>> {
+        let tree = self.tree(DOCS_TREE)?;
+        Ok(tree.iter().map(|item| {
             let (_, value) = item?;
-            let doc: Document = bincode::deserialize(&value)?;
-            docs.push(doc);
-        }
-
-        Ok(docs)
+            Self::decode_document(&value)
+        }))
     }
 
     /// Count total documents
     pub fn count_documents(&self) -> Result<usize> {
-        let tree = self.db.open_tree(DOCS_TREE)?;
+        let tree = self.tree(DOCS_TREE)?;
         Ok(tree.len())
     }
 
     // ========== Document Statistics Operations ==========
 
+    fn encode_doc_stats(stats: &DocStats) -> Result<Vec<u8>> {
+        let mut encoded = STATS_MAGIC.to_vec();
+        encoded.push(STATS_FORMAT_VERSION);
+        bincode::serialize_into(&mut encoded, stats)?;
+        Ok(encoded)
+    }
+
+    /// Decode statistics, or `None` if they are in an older layout
+    fn decode_doc_stats(data: &[u8]) -> Result<Option<DocStats>> {
+        let Some(rest) = data.strip_prefix(&STATS_MAGIC) else {
+            // Plain bincode from before the versioned format
+            return Ok(None);
+        };
+        let [version, payload @ ..] = rest else {
+            anyhow::bail!("Truncated document statistics header");
+        };
+        match (*version).cmp(&STATS_FORMAT_VERSION) {
+            std::cmp::Ordering::Less => Ok(None),
+            std::cmp::Ordering::Equal => Ok(Some(bincode::deserialize(payload)?)),
+            std::cmp::Ordering::Greater => {
+                anyhow::bail!("Unsupported document statistics version: {}", version)
+            }
+        }
+    }
+
     /// Save document statistics
     pub fn save_doc_stats(&self, stats: &DocStats) -> Result<()> {
-        let tree = self.db.open_tree(STATS_TREE)?;
-        let serialized = bincode::serialize(stats)?;
-        tree.insert(stats.id.as_bytes(), serialized)?;
-        Ok(())
+        let tree = self.tree(STATS_TREE)?;
+        let serialized = Self::encode_doc_stats(stats)?;
+        self.retry.run("save_doc_stats", || {
+            tree.insert(stats.id.as_bytes(), serialized.as_slice())?;
+            Ok(())
+        })
     }
 
-    /// Get document statistics
+    /// Get document statistics; `None` also when they are stored in an
+    /// older layout and need rebuilding
     pub fn get_doc_stats(&self, id: &str) -> Result<Option<DocStats>> {
-        let tree = self.db.open_tree(STATS_TREE)?;
-        if let Some(data) = tree.get(id.as_bytes())? {
-            let stats: DocStats = bincode::deserialize(&data)?;
-            Ok(Some(stats))
-        } else {
-            Ok(None)
+        let tree = self.tree(STATS_TREE)?;
+        match tree.get(id.as_bytes())? {
+            Some(data) => Self::decode_doc_stats(&data),
+            None => Ok(None),
         }
     }
 
-    /// Get all document statistics
-    pub fn get_all_doc_stats(&self) -> Result<Vec<DocStats>> {
-        let tree = self.db.open_tree(STATS_TREE)?;
+    /// Get all document statistics, and the ids of the documents whose
+    /// statistics are stored in an older layout and need rebuilding from
+    /// the documents
+    pub fn get_all_doc_stats(&self) -> Result<(Vec<DocStats>, Vec<String>)> {
+        let tree = self.tree(STATS_TREE)?;
         let mut stats = Vec::new();
+        let mut outdated = Vec::new();
 
         for item in tree.iter() {
-            let (_, value) = item?;
-            let doc_stats: DocStats = bincode::deserialize(&value)?;
-            stats.push(doc_stats);
+            let (key, value) = item?;
+            match Self::decode_doc_stats(&value)? {
+                Some(doc_stats) => stats.push(doc_stats),
+                None => outdated.push(String::from_utf8(key.to_vec())?),
+            }
         }
 
-        Ok(stats)
+        Ok((stats, outdated))
+    }
+
+    /// Store statistics bytes as they are, to stand in for older layouts
+    #[cfg(test)]
+    pub(crate) fn save_raw_doc_stats(&self, id: &str, data: &[u8]) -> Result<()> {
+        self.tree(STATS_TREE)?.insert(id.as_bytes(), data)?;
+        Ok(())
     }
 
     /// Delete document statistics
     pub fn delete_doc_stats(&self, id: &str) -> Result<()> {
-        let tree = self.db.open_tree(STATS_TREE)?;
+        let tree = self.tree(STATS_TREE)?;
+        tree.remove(id.as_bytes())?;
+        Ok(())
+    }
+
+    // ========== Tombstone Operations ==========
+
+    /// Mark a document as soft-deleted
+    pub fn add_tombstone(&self, id: &str) -> Result<()> {
+        let tree = self.tree(TOMBSTONES_TREE)?;
+        tree.insert(id.as_bytes(), &[])?;
+        Ok(())
+    }
+
+    /// Clear the soft-deleted mark of a document
+    pub fn remove_tombstone(&self, id: &str) -> Result<()> {
+        let tree = self.tree(TOMBSTONES_TREE)?;
         tree.remove(id.as_bytes())?;
         Ok(())
     }
 
+    /// Get the IDs of all soft-deleted documents
+    pub fn get_all_tombstones(&self) -> Result<Vec<String>> {
+        let tree = self.tree(TOMBSTONES_TREE)?;
+        let mut ids = Vec::new();
+
+        for item in tree.iter() {
+            let (key, _) = item?;
+            ids.push(String::from_utf8(key.to_vec())?);
+        }
+
+        Ok(ids)
+    }
+
     // ========== Index Operations ==========
 
     /// Save the inverted index
+    ///
+    /// The posting lists are split into segments and written in one atomic
+    /// batch with a header saying how many there are, replacing segments
+    /// left over from a larger index and any index in the old single-value
+    /// format.
     pub fn save_index(&self, index: &InvertedIndex) -> Result<()> {
-        let tree = self.db.open_tree(INDEX_TREE)?;
-        let serialized = bincode::serialize(index)?;
-        tree.insert(b"main_index", serialized)?;
-        tree.flush()?;
-        Ok(())
+        let span = tracing::debug_span!(
+            "storage.save_index",
+            bytes = tracing::field::Empty,
+            segments = tracing::field::Empty
+        )
+        .entered();
+        let tree = self.tree(INDEX_TREE)?;
+
+        let mut batch = sled::Batch::default();
+        let mut bytes = 0;
+        let segments = index.segments(self.segment_bytes);
+        for (n, segment) in segments.iter().enumerate() {
+            let serialized = bincode::serialize(segment)?;
+            bytes += serialized.len();
+            batch.insert(segment_key(n as u32), serialized);
+        }
+        let header = IndexHeader {
+            version: INDEX_FORMAT_VERSION,
+            doc_count: index.total_documents(),
+            segments: segments.len() as u32,
+        };
+        let previous = self.index_header(&tree)?.map_or(0, |h| h.segments);
+        for n in header.segments..previous {
+            batch.remove(segment_key(n));
+        }
+        batch.remove(LEGACY_INDEX_KEY);
+        batch.insert(INDEX_HEADER_KEY, bincode::serialize(&header)?);
+        span.record("bytes", bytes);
+        span.record("segments", header.segments);
+
+        self.retry
+            .run("save_index", || Ok(tree.apply_batch(batch.clone())?))
     }
 
-    /// Load the inverted index
+    /// Load the inverted index, from segments or the old single value
     pub fn load_index(&self) -> Result<Option<InvertedIndex>> {
-        let tree = self.db.open_tree(INDEX_TREE)?;
-        if let Some(data) = tree.get(b"main_index")? {
+        let tree = self.tree(INDEX_TREE)?;
+        if let Some(header) = self.index_header(&tree)? {
+            let mut segments = Vec::with_capacity(header.segments as usize);
+            for n in 0..header.segments {
+                let data = tree.get(segment_key(n))?.with_context(|| {
+                    format!("Index segment {} of {} is missing", n, header.segments)
+                })?;
+                let segment: Vec<(String, Vec<String>)> = bincode::deserialize(&data)?;
+                segments.push(segment);
+            }
+            return Ok(Some(InvertedIndex::from_segments(
+                segments,
+                header.doc_count,
+            )));
+        }
+
+        if let Some(data) = tree.get(LEGACY_INDEX_KEY)? {
             let index: InvertedIndex = bincode::deserialize(&data)?;
             Ok(Some(index))
         } else {
@@ -139,18 +578,37 @@ impl Storage {
         }
     }
 
+    fn index_header(&self, tree: &sled::Tree) -> Result<Option<IndexHeader>> {
+        let Some(data) = tree.get(INDEX_HEADER_KEY)? else {
+            return Ok(None);
+        };
+        let header: IndexHeader = bincode::deserialize(&data)?;
+        anyhow::ensure!(
+            header.version == INDEX_FORMAT_VERSION,
+            "Unsupported index format version {}",
+            header.version
+        );
+        Ok(Some(header))
+    }
+
+    /// Next value of a persistent counter that only ever increases, even
+    /// across restarts (values may be skipped after a crash)
+    pub fn next_sequence(&self) -> Result<u64> {
+        Ok(self.db.generate_id()?)
+    }
+
     // ========== Metadata Operations ==========
 
     /// Save metadata (e.g., average document length)
     pub fn save_metadata(&self, key: &str, value: &str) -> Result<()> {
-        let tree = self.db.open_tree(METADATA_TREE)?;
+        let tree = self.tree(METADATA_TREE)?;
         tree.insert(key.as_bytes(), value.as_bytes())?;
         Ok(())
     }
 
     /// Get metadata
     pub fn get_metadata(&self, key: &str) -> Result<Option<String>> {
-        let tree = self.db.open_tree(METADATA_TREE)?;
+        let tree = self.tree(METADATA_TREE)?;
         if let Some(data) = tree.get(key.as_bytes())? {
             Ok(Some(String::from_utf8(data.to_vec())?))
         } else {
@@ -158,26 +616,256 @@ impl Storage {
         }
     }
 
+    /// Remove metadata
+    pub fn delete_metadata(&self, key: &str) -> Result<()> {
+        let tree = self.tree(METADATA_TREE)?;
+        tree.remove(key.as_bytes())?;
+        Ok(())
+    }
+
+    /// Size of the database files on disk, in bytes, for all namespaces
+    pub fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
     /// Flush all changes to disk
     pub fn flush(&self) -> Result<()> {
+        let _span = tracing::debug_span!("storage.flush").entered();
         self.db.flush()?;
         Ok(())
     }
 
-    /// Clear all data
+    /// Flush and close this handle on the database
+    ///
+    /// Closing the last handle closes the database. sled's background
+    /// threads let go of its file lock shortly after, so this waits until
+    /// they have, and the database can be reopened as soon as it returns.
+    /// While other handles are alive (clones, or storages sharing the
+    /// database under other namespaces), it only flushes: the database stays
+    /// open, and can't be reopened, until the last of them is closed or
+    /// dropped.
+    pub fn close(self) -> Result<()> {
+        self.flush()?;
+        let (Ok(db), Some(path)) = (Arc::try_unwrap(self.db), self.path) else {
+            // Other handles still use the database, or it is in memory
+            return Ok(());
+        };
+        drop(db);
+
+        // The lock sled takes on its main file
+        let file = std::fs::File::open(path.join("db"))?;
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(std::fs::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    anyhow::bail!(
+                        "The database was still locked {:?} after closing",
+                        CLOSE_TIMEOUT
+                    )
+                }
+                Err(std::fs::TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Finish a write operation, flushing if the durability level asks for it
+    pub fn commit(&self) -> Result<()> {
+        match self.durability {
+            Durability::Sync => self.flush(),
+            Durability::Async { .. } | Durability::None => Ok(()),
+        }
+    }
+
+    /// Clear all data of this namespace
     pub fn clear(&self) -> Result<()> {
-        self.db.drop_tree(DOCS_TREE)?;
-        self.db.drop_tree(STATS_TREE)?;
-        self.db.drop_tree(INDEX_TREE)?;
-        self.db.drop_tree(METADATA_TREE)?;
+        self.db.drop_tree(self.tree_name(DOCS_TREE))?;
+        self.db.drop_tree(self.tree_name(STATS_TREE))?;
+        self.db.drop_tree(self.tree_name(INDEX_TREE))?;
+        self.db.drop_tree(self.tree_name(METADATA_TREE))?;
+        self.db.drop_tree(self.tree_name(TOMBSTONES_TREE))?;
+        self.db.drop_tree(self.tree_name(TITLES_TREE))?;
         Ok(())
     }
 }
 
+// ========== Earlier Document Layouts ==========
+
+/// Decode a document stored in layout `version`
+fn deserialize_document<R: Read>(version: u8, reader: R) -> Result<Document> {
+    let mut doc: Document = match version {
+        1 => bincode::deserialize_from::<_, DocumentV1>(reader)?.into(),
+        2 => bincode::deserialize_from::<_, DocumentV2>(reader)?.into(),
+        3 => bincode::deserialize_from::<_, DocumentV3>(reader)?.into(),
+        4 => bincode::deserialize_from::<_, DocumentV4>(reader)?.into(),
+        5 => bincode::deserialize_from::<_, DocumentV5>(reader)?.into(),
+        6 => bincode::deserialize_from::<_, DocumentV6>(reader)?.into(),
+        7 => bincode::deserialize_from::<_, DocumentV7>(reader)?.into(),
+        DOC_FORMAT_VERSION => bincode::deserialize_from(reader)?,
+        _ => anyhow::bail!("Unsupported document format version: {}", version),
+    };
+    // Stored before documents carried their digest
+    if doc.content_hash.is_empty() {
+        doc.content_hash = doc.digest();
+    }
+    Ok(doc)
+}
+
+/// Layout 1, also that of legacy documents without a header. Each later
+/// layout appends one field, and bincode lays nested structs out flat, so
+/// each is the previous one plus that field.
+#[derive(Deserialize)]
+struct DocumentV1 {
+    id: String,
+    title: String,
+    content: String,
+    url: Option<String>,
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct DocumentV2 {
+    v1: DocumentV1,
+    boost: f64,
+}
+
+#[derive(Deserialize)]
+struct DocumentV3 {
+    v2: DocumentV2,
+    updated_at: u64,
+}
+
+#[derive(Deserialize)]
+struct DocumentV4 {
+    v3: DocumentV3,
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DocumentV5 {
+    v4: DocumentV4,
+    content_hash: String,
+}
+
+#[derive(Deserialize)]
+struct DocumentV6 {
+    v5: DocumentV5,
+    display: BTreeMap<String, DisplayValue>,
+}
+
+#[derive(Deserialize)]
+struct DocumentV7 {
+    v6: DocumentV6,
+    fields: BTreeMap<String, String>,
+}
+
+impl From<DocumentV1> for Document {
+    fn from(v1: DocumentV1) -> Self {
+        let mut doc = Document::new(v1.id, v1.title, v1.content);
+        doc.url = v1.url;
+        doc.metadata = v1.metadata;
+        doc
+    }
+}
+
+impl From<DocumentV2> for Document {
+    fn from(v2: DocumentV2) -> Self {
+        Document {
+            boost: v2.boost,
+            ..v2.v1.into()
+        }
+    }
+}
+
+impl From<DocumentV3> for Document {
+    fn from(v3: DocumentV3) -> Self {
+        Document {
+            updated_at: v3.updated_at,
+            ..v3.v2.into()
+        }
+    }
+}
+
+impl From<DocumentV4> for Document {
+    fn from(v4: DocumentV4) -> Self {
+        Document {
+            language: v4.language,
+            ..v4.v3.into()
+        }
+    }
+}
+
+impl From<DocumentV5> for Document {
+    fn from(v5: DocumentV5) -> Self {
+        Document {
+            content_hash: v5.content_hash,
+            ..v5.v4.into()
+        }
+    }
+}
+
+impl From<DocumentV6> for Document {
+    fn from(v6: DocumentV6) -> Self {
+        Document {
+            display: v6.display,
+            ..v6.v5.into()
+        }
+    }
+}
+
+impl From<DocumentV7> for Document {
+    fn from(v7: DocumentV7) -> Self {
+        Document {
+            fields: v7.fields,
+            ..v7.v6.into()
+        }
+    }
+}
+
+/// Lowercase a title and collapse its whitespace, for exact title lookups
+pub fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn title_key(title: &str, id: &str) -> Vec<u8> {
+    let mut key = normalize_title(title).into_bytes();
+    key.push(0);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_outdated_doc_stats_are_reported() -> Result<()> {
+        let storage = Storage::in_memory()?;
+        storage.save_doc_stats(&DocStats::new("current".to_string(), 3))?;
+
+        // Plain bincode of the original layout: id, length, term frequencies
+        let legacy = (
+            "legacy".to_string(),
+            2usize,
+            HashMap::from([("rust".to_string(), 2usize)]),
+        );
+        storage.save_raw_doc_stats("legacy", &bincode::serialize(&legacy)?)?;
+
+        let (stats, outdated) = storage.get_all_doc_stats()?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].length, 3);
+        assert_eq!(outdated, vec!["legacy"]);
+        assert!(storage.get_doc_stats("legacy")?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_storage_document() -> Result<()> {
         let storage = Storage::in_memory()?;
@@ -191,4 +879,180 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_namespaces_share_a_database() -> Result<()> {
+        let storage = Storage::in_memory()?;
+        let a = storage.clone().with_namespace("a");
+        let b = storage.clone().with_namespace("b");
+
+        a.save_document(&Document::new(
+            "1".to_string(),
+            "A".to_string(),
+            "In a".to_string(),
+        ))?;
+        b.save_document(&Document::new(
+            "1".to_string(),
+            "B".to_string(),
+            "In b".to_string(),
+        ))?;
+        a.save_metadata("key", "a")?;
+
+        assert_eq!(a.get_document("1")?.unwrap().title, "A");
+        assert_eq!(b.get_document("1")?.unwrap().title, "B");
+        assert!(storage.get_document("1")?.is_none());
+        assert_eq!(b.get_metadata("key")?, None);
+
+        a.clear()?;
+        assert!(a.get_document("1")?.is_none());
+        assert_eq!(b.count_documents()?, 1);
+        assert!(storage
+            .db
+            .tree_names()
+            .iter()
+            .any(|name| name.as_ref() == b"b/documents"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_documents() -> Result<()> {
+        let storage = Storage::in_memory()?.with_compression(Compression::Deflate);
+        let content = "compressible text ".repeat(100);
+        let doc = Document::new("1".to_string(), "Test".to_string(), content.clone());
+
+        storage.save_document(&doc)?;
+
+        let raw = storage.tree(DOCS_TREE)?.get(b"1")?.unwrap();
+        assert!(raw.len() < content.len() / 4);
+        assert_eq!(storage.get_document("1")?.unwrap().content, content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_levels() -> Result<()> {
+        let levels = [
+            Durability::Sync,
+            Durability::Async { flush_every_ms: 50 },
+            Durability::None,
+        ];
+        for (i, durability) in levels.into_iter().enumerate() {
+            let dir =
+                std::env::temp_dir().join(format!("rsfts-durability-{}-{}", std::process::id(), i));
+            {
+                let storage = Storage::open_with_durability(&dir, durability)?;
+                let doc = Document::new("1".to_string(), "Test".to_string(), "Content".to_string());
+                storage.save_document(&doc)?;
+                storage.commit()?;
+                assert_eq!(storage.get_document("1")?.unwrap().title, "Test");
+            }
+            std::fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        use std::io::{Error, ErrorKind};
+
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::ZERO,
+        };
+        let failing = |kind: ErrorKind, failures: u32| {
+            let mut attempts = 0;
+            let result = policy.run("test", || {
+                attempts += 1;
+                if attempts <= failures {
+                    Err(sled::Error::Io(Error::new(kind, "busy")).into())
+                } else {
+                    Ok(())
+                }
+            });
+            (result.is_ok(), attempts)
+        };
+
+        assert_eq!(failing(ErrorKind::TimedOut, 2), (true, 3));
+        assert_eq!(failing(ErrorKind::TimedOut, 3), (false, 3));
+        // Permanent errors aren't retried
+        assert_eq!(failing(ErrorKind::PermissionDenied, 1), (false, 1));
+        assert!(!is_transient(
+            &sled::Error::Unsupported("old format".to_string()).into()
+        ));
+    }
+
+    #[test]
+    fn test_segmented_index() -> Result<()> {
+        let storage = Storage::in_memory()?.with_segment_bytes(16);
+        let tree = storage.tree(INDEX_TREE)?;
+
+        // An index in the single-value format still loads
+        let mut index = InvertedIndex::new();
+        index.add_document("1", &["rust".to_string()]);
+        tree.insert(LEGACY_INDEX_KEY, bincode::serialize(&index)?)?;
+        assert_eq!(
+            storage.load_index()?.unwrap().get_documents("rust"),
+            Some(&vec!["1".to_string()])
+        );
+
+        for id in ["2", "3", "4"] {
+            index.add_document(id, &[format!("term{}", id), "shared".to_string()]);
+        }
+        storage.save_index(&index)?;
+        assert!(tree.get(LEGACY_INDEX_KEY)?.is_none());
+        assert!(storage.index_header(&tree)?.unwrap().segments > 2);
+
+        let loaded = storage.load_index()?.unwrap();
+        assert_eq!(loaded.total_documents(), 4);
+        assert_eq!(loaded.doc_frequency("shared"), 3);
+        assert_eq!(loaded.get_documents("term3"), Some(&vec!["3".to_string()]));
+
+        // Segments of a larger earlier index don't linger
+        let mut small = InvertedIndex::new();
+        small.add_document("1", &["go".to_string()]);
+        storage.save_index(&small)?;
+        assert_eq!(tree.scan_prefix(b"segment/").count(), 1);
+        assert_eq!(storage.load_index()?.unwrap().all_tokens(), vec!["go"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_documents_still_load() -> Result<()> {
+        let storage = Storage::in_memory()?.with_compression(Compression::Deflate);
+        let (title, content, url) = (
+            "Old".to_string(),
+            "Plain bincode".to_string(),
+            None::<String>,
+        );
+        let metadata = HashMap::from([("year".to_string(), "2023".to_string())]);
+        let v1 = ("legacy".to_string(), title, content, url, metadata);
+
+        // Written before the versioned format existed
+        let tree = storage.tree(DOCS_TREE)?;
+        tree.insert(b"legacy", bincode::serialize(&v1)?)?;
+
+        let doc = storage.get_document("legacy")?.unwrap();
+        assert_eq!(doc.title, "Old");
+        assert_eq!(doc.metadata["year"], "2023");
+        assert_eq!(doc.boost, 1.0);
+        assert_eq!(doc.content_hash, doc.digest());
+        assert_eq!(storage.get_all_documents()?.len(), 1);
+
+        // Layout 3 added boost and updated_at to the first one
+        let v3 = (v1, 2.0f64, 1_700_000_000_000u64);
+        let mut encoded = DOC_MAGIC.to_vec();
+        encoded.extend([3, Compression::None.codec()]);
+        encoded.extend(bincode::serialize(&v3)?);
+        tree.insert(b"legacy", encoded)?;
+
+        let doc = storage.get_document("legacy")?.unwrap();
+        assert_eq!(
+            (doc.title.as_str(), doc.boost, doc.updated_at),
+            ("Old", 2.0, 1_700_000_000_000)
+        );
+        assert!(doc.language.is_none() && doc.fields.is_empty());
+
+        Ok(())
+    }
 }
@@ -31,63 +31,664 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Marks a term as a word's unstemmed form (see
+/// [`TokenizerConfig::index_unstemmed`]). No token can start with it, so
+/// marked terms never collide with stems.
+pub const UNSTEMMED_PREFIX: char = '=';
+
+/// The term indexing the unstemmed form of a word
+pub fn unstemmed_term(surface: &str) -> String {
+    format!("{}{}", UNSTEMMED_PREFIX, surface)
+}
+
+/// Whether a term is an unstemmed form rather than a stem
+pub fn is_unstemmed_term(term: &str) -> bool {
+    term.starts_with(UNSTEMMED_PREFIX)
+}
+
+/// How text is lowercased
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Casing {
+    /// Unicode default case mapping
+    #[default]
+    Unicode,
+    /// Turkish and Azeri rules: `I` lowercases to dotless `ı` and `İ` to a
+    /// plain `i` (the default mapping gives `i` and `i` + combining dot)
+    Turkic,
+}
+
+/// Which tokens made only of digits are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumericTokens {
+    #[default]
+    Keep,
+    /// Drop every purely numeric token, e.g. "500" but not "v2" or "utf8"
+    Drop,
+    /// Drop numeric tokens with more than this many digits, such as
+    /// timestamps and ids, keeping short numbers
+    DropLongerThan(usize),
+}
+
+impl NumericTokens {
+    fn keeps(self, token: &str) -> bool {
+        if !token.chars().all(|c| c.is_numeric()) {
+            return true;
+        }
+        match self {
+            NumericTokens::Keep => true,
+            NumericTokens::Drop => false,
+            NumericTokens::DropLongerThan(max_digits) => token.chars().count() <= max_digits,
+        }
+    }
+}
+
+/// A step of the analysis chain applied to each token after splitting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFilter {
+    /// Lowercase following [`TokenizerConfig::casing`]
+    Lowercase,
+    /// Remove English stopwords (from English text only)
+    Stopwords,
+    /// Reduce words to their stems in the text's language
+    Stem,
+    /// Strip diacritics from Latin letters ("café" -> "cafe", "straße" ->
+    /// "strasse")
+    AsciiFold,
+}
+
+impl TokenFilter {
+    /// Parse a filter name: `lowercase`, `stopwords`, `stem` or `asciifold`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lowercase" => Some(TokenFilter::Lowercase),
+            "stopwords" => Some(TokenFilter::Stopwords),
+            "stem" => Some(TokenFilter::Stem),
+            "asciifold" => Some(TokenFilter::AsciiFold),
+            _ => None,
+        }
+    }
+
+    /// Whether the analyzer options switch this filter off
+    fn is_disabled(self, options: &AnalyzerOptions) -> bool {
+        match self {
+            TokenFilter::Lowercase => !options.lowercase,
+            TokenFilter::Stopwords => !options.remove_stopwords,
+            TokenFilter::Stem => !options.stem,
+            TokenFilter::AsciiFold => false,
+        }
+    }
+}
+
+/// The default filter chain: lowercase, then stopwords, then stem
+pub const DEFAULT_FILTERS: [TokenFilter; 3] = [
+    TokenFilter::Lowercase,
+    TokenFilter::Stopwords,
+    TokenFilter::Stem,
+];
+
+/// Tokenizer configuration
+#[derive(Debug, Clone)]
+pub struct TokenizerConfig {
+    /// Keep apostrophes between letters as part of the word ("don't"), so
+    /// contractions match the stopword list instead of splitting into
+    /// "don" + "t". Typographic apostrophes are normalized to `'`.
+    pub keep_apostrophes: bool,
+    /// Split code identifiers into their parts: `getUserName` also yields
+    /// "get", "user", "name" and `max_token_len` is read as one identifier
+    /// yielding "max", "token", "len". For code search.
+    pub split_identifiers: bool,
+    /// With `split_identifiers`, also keep the whole identifier as a token
+    pub keep_original_identifiers: bool,
+    /// Leading symbols kept as part of the word, e.g. `#` and `@` for
+    /// hashtags and mentions. "#rustlang" then only matches "#rustlang",
+    /// not a bare "rustlang".
+    pub keep_prefixes: Vec<char>,
+    /// Lowercasing rules, applied alike to documents and queries
+    pub casing: Casing,
+    /// Numbers to leave out of documents and queries, for corpora such as
+    /// logs where timestamps and ids swamp the vocabulary
+    pub numeric_tokens: NumericTokens,
+    /// Index each word's lowercased, unstemmed form as a term of its own
+    /// next to its stem, and look up both forms of query words. Matching
+    /// still goes by stems, but the rarer exact forms rank documents with
+    /// the very words typed above ones that only share a stem. Roughly
+    /// doubles the index size of text.
+    pub index_unstemmed: bool,
+    /// Filters applied to each token, in order. Reordering changes what
+    /// each filter sees: stopwords after `Stem` are matched against stems,
+    /// and `AsciiFold` before `Stopwords` lets "à" match the list's "a".
+    /// [`AnalyzerOptions`] can still switch the lowercase, stopword and
+    /// stem filters off. Changing the chain requires a reindex.
+    pub filters: Vec<TokenFilter>,
+    /// Keep email addresses and `http`, `https` or `ftp` URLs whole, as
+    /// one unstemmed token each, so "user@example.com" only matches that
+    /// address rather than "user", "example" and "com" anywhere. Trailing
+    /// punctuation and an unmatched closing parenthesis or bracket aren't
+    /// part of a URL. Changing it requires a reindex.
+    pub keep_urls_and_emails: bool,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            keep_apostrophes: true,
+            split_identifiers: false,
+            keep_original_identifiers: true,
+            keep_prefixes: Vec::new(),
+            casing: Casing::Unicode,
+            numeric_tokens: NumericTokens::Keep,
+            index_unstemmed: false,
+            filters: DEFAULT_FILTERS.to_vec(),
+            keep_urls_and_emails: false,
+        }
+    }
+}
+
+/// Language of a text, choosing its stemmer
+///
+/// The stopword list is English, so stopwords are only removed from
+/// English text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    Arabic,
+    Danish,
+    Dutch,
+    #[default]
+    English,
+    Finnish,
+    French,
+    German,
+    Greek,
+    Hungarian,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Romanian,
+    Russian,
+    Spanish,
+    Swedish,
+    Tamil,
+    Turkish,
+}
+
+impl Language {
+    /// Look up a language by its ISO 639-1 code ("en", "fr", ...),
+    /// ignoring case
+    pub fn from_code(code: &str) -> Option<Self> {
+        let language = match code.trim().to_ascii_lowercase().as_str() {
+            "ar" => Language::Arabic,
+            "da" => Language::Danish,
+            "nl" => Language::Dutch,
+            "en" => Language::English,
+            "fi" => Language::Finnish,
+            "fr" => Language::French,
+            "de" => Language::German,
+            "el" => Language::Greek,
+            "hu" => Language::Hungarian,
+            "it" => Language::Italian,
+            "no" | "nb" => Language::Norwegian,
+            "pt" => Language::Portuguese,
+            "ro" => Language::Romanian,
+            "ru" => Language::Russian,
+            "es" => Language::Spanish,
+            "sv" => Language::Swedish,
+            "ta" => Language::Tamil,
+            "tr" => Language::Turkish,
+            _ => return None,
+        };
+        Some(language)
+    }
+
+    fn algorithm(self) -> Algorithm {
+        match self {
+            Language::Arabic => Algorithm::Arabic,
+            Language::Danish => Algorithm::Danish,
+            Language::Dutch => Algorithm::Dutch,
+            Language::English => Algorithm::English,
+            Language::Finnish => Algorithm::Finnish,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Greek => Algorithm::Greek,
+            Language::Hungarian => Algorithm::Hungarian,
+            Language::Italian => Algorithm::Italian,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Romanian => Algorithm::Romanian,
+            Language::Russian => Algorithm::Russian,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Swedish => Algorithm::Swedish,
+            Language::Tamil => Algorithm::Tamil,
+            Language::Turkish => Algorithm::Turkish,
+        }
+    }
+}
+
+/// Analysis steps applied after splitting text into words
+///
+/// Documents are always indexed with every step enabled, so turning a step
+/// off only makes sense for queries, and only some combinations can match:
+///
+/// - `remove_stopwords: false` is safe but rarely useful: stopwords are never
+///   indexed, so a kept stopword matches nothing (and empties AND queries).
+/// - `stem: false` matches only words whose unstemmed form equals their
+///   indexed stem ("rust", but not "running").
+/// - `lowercase: false` matches only words that are already lowercase, so an
+///   uppercase acronym finds nothing in a lowercased index.
+///
+/// `language` must match the language the searched documents were indexed
+/// with, or stems won't line up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalyzerOptions {
+    pub lowercase: bool,
+    pub remove_stopwords: bool,
+    pub stem: bool,
+    pub language: Language,
+}
+
+impl Default for AnalyzerOptions {
+    fn default() -> Self {
+        Self {
+            lowercase: true,
+            remove_stopwords: true,
+            stem: true,
+            language: Language::English,
+        }
+    }
+}
+
+impl AnalyzerOptions {
+    /// Every analysis step, for text in `language`
+    pub fn for_language(language: Language) -> Self {
+        Self {
+            language,
+            ..Default::default()
+        }
+    }
+}
+
 pub struct Tokenizer {
-    stemmer: Stemmer,
+    config: TokenizerConfig,
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/// Split an identifier at underscores, lower-to-upper case changes
+/// ("getUser"), the end of an uppercase run ("HTTPServer") and changes
+/// between letters and digits ("utf8")
+fn split_identifier(word: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+
+    for piece in word.split('_').filter(|p| !p.is_empty()) {
+        let chars: Vec<(usize, char)> = piece.char_indices().collect();
+        let mut start = 0;
+
+        for k in 1..chars.len() {
+            let (i, c) = chars[k];
+            let prev = chars[k - 1].1;
+            let next = chars.get(k + 1).map(|&(_, n)| n);
+
+            let boundary = (prev.is_lowercase() && c.is_uppercase())
+                || (prev.is_uppercase()
+                    && c.is_uppercase()
+                    && next.is_some_and(char::is_lowercase))
+                || (prev.is_alphanumeric()
+                    && c.is_alphanumeric()
+                    && prev.is_alphabetic() != c.is_alphabetic());
+            if boundary {
+                parts.push(&piece[start..i]);
+                start = i;
+            }
+        }
+        parts.push(&piece[start..]);
+    }
+
+    parts
+}
+
+/// URL schemes recognized by [`TokenizerConfig::keep_urls_and_emails`]
+const URL_SCHEMES: [&str; 3] = ["http://", "https://", "ftp://"];
+
+/// Byte length of the URL or email address at the start of text, if there
+/// is one
+fn url_or_email_len(text: &str) -> Option<usize> {
+    url_len(text).or_else(|| email_len(text))
+}
+
+fn url_len(text: &str) -> Option<usize> {
+    let scheme = URL_SCHEMES.iter().find(|scheme| {
+        text.get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })?;
+    let end = text
+        .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+        .unwrap_or(text.len());
+
+    // "(see https://example.com/a)." ends the URL before ")."
+    let mut url = &text[..end];
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '\u{2019}']);
+        let trimmed = match trimmed.chars().last() {
+            Some(close @ (')' | ']')) => {
+                let open = if close == ')' { '(' } else { '[' };
+                if trimmed.matches(close).count() > trimmed.matches(open).count() {
+                    &trimmed[..trimmed.len() - 1]
+                } else {
+                    trimmed
+                }
+            }
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            break;
+        }
+        url = trimmed;
+    }
+    (url.len() > scheme.len()).then_some(url.len())
+}
+
+fn email_len(text: &str) -> Option<usize> {
+    let local = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')))
+        .unwrap_or(text.len());
+    if local == 0 || !text[local..].starts_with('@') {
+        return None;
+    }
+
+    let rest = &text[local + 1..];
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-')))
+        .unwrap_or(rest.len());
+    // A sentence can end right after the address
+    let domain = rest[..end].trim_end_matches(['.', '-']);
+    if !domain.contains('.') || domain.split('.').any(str::is_empty) {
+        return None;
+    }
+    Some(local + 1 + domain.len())
+}
+
+/// Replace Latin letters with diacritics by their plain ASCII letters
+fn ascii_fold(token: &str) -> String {
+    let mut folded = String::with_capacity(token.len());
+    for c in token.chars() {
+        let plain = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+            'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+            'ď' | 'đ' => "d",
+            'Ď' | 'Đ' => "D",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+            'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+            'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+            'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+            'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+            'ł' | 'ľ' | 'ĺ' | 'ļ' => "l",
+            'Ł' | 'Ľ' | 'Ĺ' | 'Ļ' => "L",
+            'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+            'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+            'ŕ' | 'ř' | 'ŗ' => "r",
+            'Ŕ' | 'Ř' | 'Ŗ' => "R",
+            'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+            'Ś' | 'Ŝ' | 'Ş' | 'Š' | 'Ș' => "S",
+            'ţ' | 'ť' | 'ț' => "t",
+            'Ţ' | 'Ť' | 'Ț' => "T",
+            'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+            'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+            'ý' | 'ÿ' => "y",
+            'Ý' | 'Ÿ' => "Y",
+            'ź' | 'ż' | 'ž' => "z",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ß' => "ss",
+            _ => {
+                folded.push(c);
+                continue;
+            }
+        };
+        folded.push_str(plain);
+    }
+    folded
 }
 
 impl Tokenizer {
     pub fn new() -> Self {
-        Self {
-            stemmer: Stemmer::create(Algorithm::English),
+        Self::with_config(TokenizerConfig::default())
+    }
+
+    pub fn with_config(config: TokenizerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Byte ranges of the words in text, in order
+    pub fn word_spans(&self, text: &str) -> Vec<(usize, usize)> {
+        self.first_word_spans(text, usize::MAX)
+    }
+
+    /// The longest prefix of text holding at most `max_words` words, and the
+    /// number of words in it. Stops reading at the last word kept, so the
+    /// work done doesn't grow with the rest of the text.
+    pub fn take_words<'a>(&self, text: &'a str, max_words: usize) -> (&'a str, usize) {
+        let spans = self.first_word_spans(text, max_words.saturating_add(1));
+        if spans.len() <= max_words {
+            return (text, spans.len());
         }
+        let end = max_words.checked_sub(1).map_or(0, |last| spans[last].1);
+        (&text[..end], max_words)
+    }
+
+    /// Byte ranges of the first `max_words` words in text
+    fn first_word_spans(&self, text: &str, max_words: usize) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        if max_words == 0 {
+            return spans;
+        }
+        let mut start = None;
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            if self.config.keep_urls_and_emails && start.is_none() && c.is_alphanumeric() {
+                if let Some(len) = url_or_email_len(&text[i..]) {
+                    spans.push((i, i + len));
+                    if spans.len() == max_words {
+                        return spans;
+                    }
+                    while chars.next_if(|&(j, _)| j < i + len).is_some() {}
+                    continue;
+                }
+            }
+
+            let starts_prefixed = start.is_none()
+                && self.config.keep_prefixes.contains(&c)
+                && chars
+                    .peek()
+                    .is_some_and(|&(_, next)| next.is_alphanumeric());
+
+            let in_word = c.is_alphanumeric()
+                || starts_prefixed
+                || (self.config.keep_apostrophes
+                    && is_apostrophe(c)
+                    && start.is_some()
+                    && chars
+                        .peek()
+                        .is_some_and(|&(_, next)| next.is_alphanumeric()))
+                || (self.config.split_identifiers
+                    && c == '_'
+                    && start.is_some()
+                    && chars
+                        .peek()
+                        .is_some_and(|&(_, next)| next.is_alphanumeric()));
+
+            if in_word {
+                start.get_or_insert(i);
+            } else if let Some(s) = start.take() {
+                spans.push((s, i));
+                if spans.len() == max_words {
+                    return spans;
+                }
+            }
+        }
+        if let Some(s) = start {
+            spans.push((s, text.len()));
+        }
+
+        spans
     }
 
     /// Tokenize text into words
     fn tokenize(&self, text: &str) -> Vec<String> {
-        text.chars()
-            .fold(vec![String::new()], |mut tokens, c| {
-                if c.is_alphanumeric() {
-                    if let Some(last) = tokens.last_mut() {
-                        last.push(c);
+        let mut tokens = Vec::new();
+
+        for (start, end) in self.word_spans(text) {
+            let word = text[start..end].replace('\u{2019}', "'");
+
+            if self.config.split_identifiers && !self.is_url_or_email(&word) {
+                let parts = split_identifier(&word);
+                if parts.len() > 1 {
+                    if self.config.keep_original_identifiers {
+                        tokens.push(word.clone());
                     }
-                } else if tokens.last().map_or(false, |s| !s.is_empty()) {
-                    tokens.push(String::new());
+                    tokens.extend(parts.into_iter().map(String::from));
+                    continue;
                 }
-                tokens
-            })
-            .into_iter()
-            .filter(|s| !s.is_empty())
-            .collect()
+            }
+            tokens.push(word);
+        }
+
+        tokens.retain(|token| self.config.numeric_tokens.keeps(token));
+        tokens
     }
 
-    /// Convert tokens to lowercase
-    fn lowercase_filter(&self, tokens: Vec<String>) -> Vec<String> {
-        tokens.into_iter().map(|t| t.to_lowercase()).collect()
+    /// Lowercase text following the configured casing rules
+    fn lowercase(&self, text: &str) -> String {
+        match self.config.casing {
+            Casing::Unicode => text.to_lowercase(),
+            Casing::Turkic => text
+                .chars()
+                .map(|c| match c {
+                    'I' => 'ı'.to_string(),
+                    'İ' => 'i'.to_string(),
+                    c => c.to_lowercase().to_string(),
+                })
+                .collect(),
+        }
     }
 
-    /// Remove stopwords
-    fn stopword_filter(&self, tokens: Vec<String>) -> Vec<String> {
-        tokens
-            .into_iter()
-            .filter(|t| !STOPWORDS.contains(t.as_str()))
-            .collect()
+    /// Run the configured filter chain over tokens, returning `(term,
+    /// surface_form)` pairs where the surface form is the token as it was
+    /// before stemming
+    fn apply_filters(
+        &self,
+        tokens: Vec<String>,
+        options: &AnalyzerOptions,
+    ) -> Vec<(String, String)> {
+        let mut pairs: Vec<(String, String)> = tokens.into_iter().map(|t| (t.clone(), t)).collect();
+        let mut stemmed = false;
+
+        for &filter in &self.config.filters {
+            if filter.is_disabled(options) {
+                continue;
+            }
+            match filter {
+                TokenFilter::Lowercase => {
+                    self.map_terms(&mut pairs, stemmed, |t| self.lowercase(t))
+                }
+                TokenFilter::AsciiFold => self.map_terms(&mut pairs, stemmed, ascii_fold),
+                TokenFilter::Stopwords => {
+                    if options.language == Language::English {
+                        pairs.retain(|(term, _)| !STOPWORDS.contains(term.as_str()));
+                    }
+                }
+                TokenFilter::Stem => {
+                    let stemmer = Stemmer::create(options.language.algorithm());
+                    for (term, _) in &mut pairs {
+                        *term = self.stem(&stemmer, std::mem::take(term));
+                    }
+                    stemmed = true;
+                }
+            }
+        }
+        pairs
     }
 
-    /// Apply stemming
-    fn stemmer_filter(&self, tokens: Vec<String>) -> Vec<String> {
-        tokens
-            .into_iter()
-            .map(|t| self.stemmer.stem(&t).to_string())
-            .collect()
+    /// Rewrite each term, and its surface form while not yet stemmed
+    fn map_terms(&self, pairs: &mut [(String, String)], stemmed: bool, f: impl Fn(&str) -> String) {
+        for (term, surface) in pairs {
+            *term = f(term);
+            if !stemmed {
+                surface.clone_from(term);
+            }
+        }
+    }
+
+    /// Whether a token is a URL or email address kept whole
+    fn is_url_or_email(&self, token: &str) -> bool {
+        self.config.keep_urls_and_emails && url_or_email_len(token) == Some(token.len())
+    }
+
+    /// Stem one token; hashtags, mentions, URLs and email addresses are
+    /// names and left as-is
+    fn stem(&self, stemmer: &Stemmer, token: String) -> String {
+        if token.starts_with(self.config.keep_prefixes.as_slice()) || self.is_url_or_email(&token) {
+            token
+        } else {
+            stemmer.stem(&token).to_string()
+        }
     }
 
     /// Full analysis pipeline
     pub fn analyze(&self, text: &str) -> Vec<String> {
+        self.analyze_with(text, &AnalyzerOptions::default())
+    }
+
+    /// Analysis pipeline with some steps switched off
+    pub fn analyze_with(&self, text: &str, options: &AnalyzerOptions) -> Vec<String> {
+        self.analyze_with_surface_forms(text, options)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect()
+    }
+
+    /// Analysis pipeline keeping each term's surface form, the token as it
+    /// was before stemming. Returns `(term, surface_form)` pairs.
+    pub fn analyze_with_surface_forms(
+        &self,
+        text: &str,
+        options: &AnalyzerOptions,
+    ) -> Vec<(String, String)> {
         let tokens = self.tokenize(text);
-        let tokens = self.lowercase_filter(tokens);
-        let tokens = self.stopword_filter(tokens);
-        let tokens = self.stemmer_filter(tokens);
-        tokens
+        self.apply_filters(tokens, options)
+    }
+
+    /// The unstemmed terms to index or query next to the stems of
+    /// `pairs`, as returned by [`Self::analyze_with_surface_forms`]; none
+    /// unless `index_unstemmed` is set and the analysis stems
+    pub fn unstemmed_terms(
+        &self,
+        pairs: &[(String, String)],
+        options: &AnalyzerOptions,
+    ) -> Vec<String> {
+        if !self.config.index_unstemmed || !options.stem {
+            return Vec::new();
+        }
+        pairs
+            .iter()
+            .map(|(_, surface)| unstemmed_term(surface))
+            .collect()
+    }
+
+    /// Analyze a keyword field value: one lowercased token, no splitting,
+    /// stopword filtering or stemming. The field name is part of the token so
+    /// keyword terms never collide with analyzed text.
+    pub fn analyze_keyword(&self, field: &str, value: &str) -> String {
+        format!("{}:{}", field, self.lowercase(value.trim()))
     }
 
     /// Analyze and return unique tokens (for indexing)
@@ -96,9 +697,13 @@ impl Tokenizer {
     }
 
     /// Analyze and count term frequencies
-    pub fn analyze_with_frequencies(&self, text: &str) -> std::collections::HashMap<String, usize> {
+    pub fn analyze_with_frequencies(
+        &self,
+        text: &str,
+        options: &AnalyzerOptions,
+    ) -> std::collections::HashMap<String, usize> {
         let mut frequencies = std::collections::HashMap::new();
-        for token in self.analyze(text) {
+        for token in self.analyze_with(text, options) {
             *frequencies.entry(token).or_insert(0) += 1;
         }
         frequencies
@@ -131,4 +736,272 @@ mod tests {
         assert!(tokens.contains(&"brown".to_string()));
         assert!(!tokens.contains(&"the".to_string()));
     }
+
+    #[test]
+    fn test_analyze_keyword() {
+        let tokenizer = Tokenizer::new();
+        assert_eq!(tokenizer.analyze_keyword("sku", " ABC-123 "), "sku:abc-123");
+    }
+
+    #[test]
+    fn test_contractions_match_stopwords() {
+        let tokenizer = Tokenizer::new();
+        assert_eq!(tokenizer.tokenize("don't stop"), vec!["don't", "stop"]);
+        assert_eq!(
+            tokenizer.tokenize("it\u{2019}s 'quoted'"),
+            vec!["it's", "quoted"]
+        );
+
+        assert!(tokenizer.analyze("don't").is_empty());
+        assert!(tokenizer.analyze("it's").is_empty());
+        assert!(tokenizer.analyze("we've").is_empty());
+        assert_eq!(tokenizer.analyze("it's rust's"), vec!["rust"]);
+    }
+
+    #[test]
+    fn test_apostrophes_can_split_words() {
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            keep_apostrophes: false,
+            ..Default::default()
+        });
+        assert_eq!(tokenizer.tokenize("don't"), vec!["don", "t"]);
+    }
+
+    #[test]
+    fn test_analyze_with_options() {
+        let tokenizer = Tokenizer::new();
+        let options = AnalyzerOptions {
+            lowercase: false,
+            remove_stopwords: false,
+            stem: false,
+            language: Language::English,
+        };
+        assert_eq!(
+            tokenizer.analyze_with("The NASA runs", &options),
+            vec!["The", "NASA", "runs"]
+        );
+
+        let options = AnalyzerOptions {
+            stem: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            tokenizer.analyze_with("The NASA runs", &options),
+            vec!["nasa", "runs"]
+        );
+    }
+
+    #[test]
+    fn test_filter_chain_order() {
+        let with_filters = |filters: Vec<TokenFilter>| {
+            Tokenizer::with_config(TokenizerConfig {
+                filters,
+                ..Default::default()
+            })
+        };
+        assert_eq!(Tokenizer::new().analyze("Doings"), vec!["do"]);
+
+        // Stopwords after stemming also catch words that stem to one
+        let stem_first = with_filters(vec![
+            TokenFilter::Lowercase,
+            TokenFilter::Stem,
+            TokenFilter::Stopwords,
+        ]);
+        assert!(stem_first.analyze("Doings").is_empty());
+
+        // Folding before stopwords lets "À" match "a"; the surface form is
+        // the folded word
+        let fold_first = with_filters(vec![
+            TokenFilter::Lowercase,
+            TokenFilter::AsciiFold,
+            TokenFilter::Stopwords,
+            TokenFilter::Stem,
+        ]);
+        assert_eq!(
+            fold_first.analyze_with_surface_forms("À Café", &AnalyzerOptions::default()),
+            vec![("cafe".to_string(), "cafe".to_string())]
+        );
+        assert_eq!(Tokenizer::new().analyze("À Straße"), vec!["à", "straße"]);
+        assert_eq!(fold_first.analyze("À Straße"), vec!["strass"]);
+
+        // Options still switch filters off
+        let options = AnalyzerOptions {
+            lowercase: false,
+            ..Default::default()
+        };
+        assert_eq!(fold_first.analyze_with("Café", &options), vec!["Cafe"]);
+    }
+
+    #[test]
+    fn test_surface_forms() {
+        let tokenizer = Tokenizer::new();
+        let pairs =
+            tokenizer.analyze_with_surface_forms("Running the runs", &AnalyzerOptions::default());
+        assert_eq!(
+            pairs,
+            vec![
+                ("run".to_string(), "running".to_string()),
+                ("run".to_string(), "runs".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_language_selects_stemmer() {
+        let tokenizer = Tokenizer::new();
+        let french = AnalyzerOptions::for_language(Language::French);
+        assert_eq!(
+            tokenizer.analyze_with("les chanteuses", &french),
+            vec!["le", "chanteux"]
+        );
+        assert_eq!(tokenizer.analyze("the singers"), vec!["singer"]);
+
+        assert_eq!(Language::from_code("DE"), Some(Language::German));
+        assert_eq!(Language::from_code("xx"), None);
+    }
+
+    #[test]
+    fn test_turkic_casing() {
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            casing: Casing::Turkic,
+            ..Default::default()
+        });
+        assert_eq!(tokenizer.analyze("İstanbul"), tokenizer.analyze("istanbul"));
+        assert_eq!(tokenizer.analyze("ISPARTA"), tokenizer.analyze("ısparta"));
+
+        let tokenizer = Tokenizer::new();
+        assert_ne!(tokenizer.analyze("İstanbul"), tokenizer.analyze("istanbul"));
+    }
+
+    #[test]
+    fn test_numeric_tokens() {
+        let text = "GET /api 500 took 1697040000123 ms v2";
+        let tokenizer = Tokenizer::new();
+        assert_eq!(
+            tokenizer.analyze(text),
+            vec!["get", "api", "500", "took", "1697040000123", "ms", "v2"]
+        );
+
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            numeric_tokens: NumericTokens::Drop,
+            ..Default::default()
+        });
+        assert_eq!(
+            tokenizer.analyze(text),
+            vec!["get", "api", "took", "ms", "v2"]
+        );
+
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            numeric_tokens: NumericTokens::DropLongerThan(4),
+            ..Default::default()
+        });
+        assert_eq!(
+            tokenizer.analyze(text),
+            vec!["get", "api", "500", "took", "ms", "v2"]
+        );
+    }
+
+    #[test]
+    fn test_take_words() {
+        let tokenizer = Tokenizer::new();
+        assert_eq!(tokenizer.take_words("one, two three.", 2), ("one, two", 2));
+        assert_eq!(
+            tokenizer.take_words("one, two three.", 3),
+            ("one, two three.", 3)
+        );
+        assert_eq!(tokenizer.take_words("one two", 5), ("one two", 2));
+        assert_eq!(tokenizer.take_words("one two", 0), ("", 0));
+    }
+
+    #[test]
+    fn test_split_identifier() {
+        assert_eq!(split_identifier("getUserName"), vec!["get", "User", "Name"]);
+        assert_eq!(
+            split_identifier("max_token_len"),
+            vec!["max", "token", "len"]
+        );
+        assert_eq!(split_identifier("HTTPServer"), vec!["HTTP", "Server"]);
+        assert_eq!(split_identifier("utf8Decoder"), vec!["utf", "8", "Decoder"]);
+        assert_eq!(split_identifier("plain"), vec!["plain"]);
+    }
+
+    #[test]
+    fn test_identifier_splitting_in_analysis() {
+        let config = TokenizerConfig {
+            split_identifiers: true,
+            ..Default::default()
+        };
+        let tokenizer = Tokenizer::with_config(config.clone());
+        assert_eq!(
+            tokenizer.tokenize("call getUserName"),
+            vec!["call", "getUserName", "get", "User", "Name"]
+        );
+        assert_eq!(
+            tokenizer.analyze("max_token_len"),
+            vec!["max_token_len", "max", "token", "len"]
+        );
+
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            keep_original_identifiers: false,
+            ..config
+        });
+        assert_eq!(tokenizer.analyze("HTTPServer"), vec!["http", "server"]);
+        assert_eq!(tokenizer.analyze("don't"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_keep_prefixes() {
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            keep_prefixes: vec!['#', '@'],
+            ..Default::default()
+        });
+        assert_eq!(
+            tokenizer.analyze("Loving #RustLang, thanks @arterning! a#b # @"),
+            vec!["love", "#rustlang", "thank", "@arterning", "b"]
+        );
+        assert_eq!(tokenizer.analyze("#running"), vec!["#running"]);
+
+        let tokenizer = Tokenizer::new();
+        assert_eq!(tokenizer.analyze("#rustlang"), vec!["rustlang"]);
+    }
+
+    #[test]
+    fn test_keep_urls_and_emails() {
+        let tokenizer = Tokenizer::with_config(TokenizerConfig {
+            keep_urls_and_emails: true,
+            split_identifiers: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            tokenizer.analyze(
+                "Mail First.Last+tickets@Example.co.uk, or see https://example.com/docs/pages."
+            ),
+            vec![
+                "mail",
+                "first.last+tickets@example.co.uk",
+                "see",
+                "https://example.com/docs/pages"
+            ]
+        );
+        // Parentheses around a URL aren't part of it, those inside it are
+        assert_eq!(
+            tokenizer.tokenize(
+                "(https://example.com/a_b) https://en.wikipedia.org/wiki/Rust_(language)!"
+            ),
+            vec![
+                "https://example.com/a_b",
+                "https://en.wikipedia.org/wiki/Rust_(language)"
+            ]
+        );
+        assert_eq!(
+            tokenizer.tokenize("user@localhost a@b. http:// x"),
+            vec!["user", "localhost", "a", "b", "http", "x"]
+        );
+
+        let tokenizer = Tokenizer::new();
+        assert_eq!(
+            tokenizer.analyze("user@example.com"),
+            vec!["user", "exampl", "com"]
+        );
+    }
 }
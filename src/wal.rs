@@ -0,0 +1,195 @@
+use crate::document::Document;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the log file inside the index directory
+pub const WAL_FILE: &str = "wal.log";
+
+/// A write recorded in the log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalOp {
+    /// The document as stored, with `updated_at` and `content_hash` set
+    Upsert(Box<Document>),
+    Delete(String),
+    Clear,
+}
+
+/// Append-only log of the writes not yet known to be flushed to storage
+///
+/// Each record is its length and CRC-32 (both little-endian `u32`) followed
+/// by the bincode-encoded [`WalOp`]. Appends are buffered until
+/// [`sync`](Self::sync), so a batch costs one `fsync`. A crash can leave a
+/// partly written last record; reading stops there.
+pub struct Wal {
+    path: PathBuf,
+    file: Mutex<WalFile>,
+}
+
+struct WalFile {
+    writer: BufWriter<File>,
+    /// Bytes appended, buffered or not
+    len: u64,
+}
+
+impl Wal {
+    /// Open or create the log at `path`, keeping its records
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open write-ahead log {}", path.display()))?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file: Mutex::new(WalFile {
+                writer: BufWriter::new(file),
+                len,
+            }),
+        })
+    }
+
+    /// Read the records of the log at `path`, if there is one
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<WalOp>> {
+        let path = path.as_ref();
+        let mut data = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read write-ahead log {}", path.display()))
+            }
+        };
+        Ok(decode_records(&data))
+    }
+
+    /// Record a write; it is durable once [`sync`](Self::sync) returns
+    pub fn append(&self, op: &WalOp) -> Result<()> {
+        let payload = bincode::serialize(op)?;
+        let mut crc = flate2::Crc::new();
+        crc.update(&payload);
+
+        let mut file = self.file.lock().unwrap();
+        file.writer
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        file.writer.write_all(&crc.sum().to_le_bytes())?;
+        file.writer.write_all(&payload)?;
+        file.len += 8 + payload.len() as u64;
+        Ok(())
+    }
+
+    /// Write buffered records to disk
+    pub fn sync(&self) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.writer.flush()?;
+        file.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    /// Drop every record, once the writes they describe are flushed to
+    /// storage
+    pub fn truncate(&self) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.writer.flush()?;
+        file.writer.get_ref().set_len(0)?;
+        file.writer.get_ref().sync_data()?;
+        file.len = 0;
+        Ok(())
+    }
+
+    /// Size of the log in bytes, including records not yet synced
+    pub fn len(&self) -> u64 {
+        self.file.lock().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Decode records up to the end of the data or the first incomplete or
+/// corrupt one
+fn decode_records(mut data: &[u8]) -> Vec<WalOp> {
+    let mut ops = Vec::new();
+    while !data.is_empty() {
+        let Some(op) = decode_record(&mut data) else {
+            tracing::warn!(
+                bytes = data.len(),
+                "Ignoring a torn or corrupt write-ahead log record and everything after it"
+            );
+            break;
+        };
+        ops.push(op);
+    }
+    ops
+}
+
+fn decode_record(data: &mut &[u8]) -> Option<WalOp> {
+    let header = data.get(..8)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let payload = data.get(8..8 + len)?;
+
+    let mut crc = flate2::Crc::new();
+    crc.update(payload);
+    if crc.sum() != checksum {
+        return None;
+    }
+    let op = bincode::deserialize(payload).ok()?;
+    *data = &data[8 + len..];
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wal_round_trip_and_torn_tail() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rsfts-wal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(WAL_FILE);
+        let _ = std::fs::remove_file(&path);
+
+        let wal = Wal::open(&path)?;
+        let doc = Document::new(
+            "1".to_string(),
+            "Rust".to_string(),
+            "Memory safety".to_string(),
+        );
+        wal.append(&WalOp::Upsert(Box::new(doc)))?;
+        wal.append(&WalOp::Delete("2".to_string()))?;
+        wal.sync()?;
+
+        // A crash in the middle of an append
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&[42, 0, 0, 0, 1, 2])?;
+
+        let ops = Wal::read(&path)?;
+        assert_eq!(ops.len(), 2);
+        assert!(
+            matches!(&ops[0], WalOp::Upsert(doc) if doc.id == "1" && doc.content == "Memory safety")
+        );
+        assert!(matches!(&ops[1], WalOp::Delete(id) if id == "2"));
+
+        wal.truncate()?;
+        assert!(wal.is_empty());
+        assert!(Wal::read(&path)?.is_empty());
+        wal.append(&WalOp::Clear)?;
+        wal.sync()?;
+        assert!(matches!(Wal::read(&path)?[..], [WalOp::Clear]));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}